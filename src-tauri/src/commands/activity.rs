@@ -1,5 +1,7 @@
-// Activity-related Tauri commands (liked tracks + play history)
-use crate::db::{queries, Database};
+// Activity-related Tauri commands (liked tracks, ratings, tags + play history)
+use crate::db::{queries, stats, Database};
+use std::collections::HashMap;
+use std::path::Path;
 use tauri::State;
 
 // ============================================================================
@@ -93,6 +95,242 @@ pub async fn get_liked_tracks(db: State<'_, Database>) -> Result<Vec<queries::Tr
     queries::get_liked_tracks(&conn).map_err(|e| e.to_string())
 }
 
+// ============================================================================
+// Ratings & Tags commands
+// ============================================================================
+
+/// Track metadata for cross-device matching in rating/tag sync payloads
+fn track_sync_payload(conn: &rusqlite::Connection, track_id: i64) -> serde_json::Value {
+    let mut payload = serde_json::json!({});
+    if let Ok(Some(track)) = queries::get_track_by_id(conn, track_id) {
+        let track_hash = queries::build_track_hash_str(
+            track.title.as_deref(),
+            track.artist.as_deref(),
+            track.album.as_deref(),
+        );
+        payload["trackHash"] = serde_json::Value::String(track_hash);
+        payload["title"] = serde_json::json!(track.title);
+        payload["artist"] = serde_json::json!(track.artist);
+        payload["album"] = serde_json::json!(track.album);
+        payload["duration"] = serde_json::json!(track.duration);
+    }
+    payload
+}
+
+fn enqueue_track_tag_change(
+    conn: &rusqlite::Connection,
+    track_id: i64,
+    tag_name: &str,
+    operation: &str,
+) {
+    if !queries::is_logged_in(conn) {
+        return;
+    }
+    let mut payload = track_sync_payload(conn, track_id);
+    payload["tag"] = serde_json::json!(tag_name);
+    let _ = queries::enqueue_sync_change(
+        conn,
+        "track_tag",
+        &format!("local_tag_{}_{}", track_id, tag_name.to_lowercase()),
+        operation,
+        Some(&payload.to_string()),
+    );
+}
+
+/// Set a 0-5 star rating (0 clears it). With `write_to_file`, the rating is also
+/// stored in the file's tags (POPM for MP3, RATING/rate elsewhere).
+#[tauri::command]
+pub async fn set_track_rating(
+    track_id: i64,
+    rating: u8,
+    write_to_file: Option<bool>,
+    db: State<'_, Database>,
+) -> Result<(), String> {
+    if rating > 5 {
+        return Err(format!("Invalid rating: {}", rating));
+    }
+
+    // The file is written first and without the DB lock, so a failed write
+    // leaves the library's rating as it was
+    if write_to_file.unwrap_or(false) {
+        let track = {
+            let conn = db.conn.lock().map_err(|e| e.to_string())?;
+            queries::get_track_by_id(&conn, track_id).ok().flatten()
+        };
+        if let Some(track) = track {
+            let path = Path::new(&track.path);
+            if track.source_type.as_deref().unwrap_or("local") == "local" && path.exists() {
+                if !crate::security::is_safe_path(path)? {
+                    return Err("Track is outside the allowed music folders".to_string());
+                }
+                crate::scanner::rating::write_file_rating(path, rating)?;
+            }
+        }
+    }
+
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    queries::set_track_rating(&conn, track_id, rating).map_err(|e| e.to_string())?;
+
+    // Enqueue sync change
+    if queries::is_logged_in(&conn) {
+        let mut payload = track_sync_payload(&conn, track_id);
+        payload["rating"] = serde_json::json!(rating);
+        let _ = queries::enqueue_sync_change(
+            &conn,
+            "track_rating",
+            &format!("local_rating_{}", track_id),
+            if rating == 0 { "delete" } else { "update" },
+            Some(&payload.to_string()),
+        );
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_track_ratings(db: State<'_, Database>) -> Result<HashMap<i64, u8>, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    queries::get_track_ratings(&conn).map_err(|e| e.to_string())
+}
+
+/// Import ratings stored in file tags for local tracks. Tracks that already have a
+/// rating in the library are skipped unless `overwrite` is set. Returns the number imported.
+#[tauri::command]
+pub async fn import_ratings_from_files(
+    overwrite: Option<bool>,
+    db: State<'_, Database>,
+) -> Result<usize, String> {
+    let overwrite = overwrite.unwrap_or(false);
+
+    // Collect candidates, then read files without holding the DB lock
+    let candidates: Vec<(i64, String)> = {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        let rated = queries::get_track_ratings(&conn).map_err(|e| e.to_string())?;
+        queries::get_all_tracks_lightweight(&conn)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .filter(|t| t.source_type.as_deref().unwrap_or("local") == "local")
+            .filter(|t| overwrite || !rated.contains_key(&t.id))
+            .map(|t| (t.id, t.path))
+            .collect()
+    };
+
+    let found = tauri::async_runtime::spawn_blocking(move || {
+        candidates
+            .into_iter()
+            .filter_map(|(id, path)| {
+                crate::scanner::rating::read_file_rating(Path::new(&path)).map(|r| (id, r))
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    for (track_id, rating) in &found {
+        queries::set_track_rating(&conn, *track_id, *rating).map_err(|e| e.to_string())?;
+    }
+
+    Ok(found.len())
+}
+
+#[tauri::command]
+pub async fn get_all_tags(db: State<'_, Database>) -> Result<Vec<queries::TagInfo>, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    queries::get_all_tags(&conn).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_track_tags(track_id: i64, db: State<'_, Database>) -> Result<Vec<String>, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    queries::get_track_tags(&conn, track_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_track_ids_by_tag(name: String, db: State<'_, Database>) -> Result<Vec<i64>, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    queries::get_track_ids_by_tag(&conn, &name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn add_tag_to_tracks(
+    name: String,
+    track_ids: Vec<i64>,
+    db: State<'_, Database>,
+) -> Result<(), String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Tag name cannot be empty".to_string());
+    }
+
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    let added = queries::add_tag_to_tracks(&conn, &name, &track_ids).map_err(|e| e.to_string())?;
+    for track_id in added {
+        enqueue_track_tag_change(&conn, track_id, &name, "create");
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn remove_tag_from_tracks(
+    name: String,
+    track_ids: Vec<i64>,
+    db: State<'_, Database>,
+) -> Result<(), String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    let removed =
+        queries::remove_tag_from_tracks(&conn, &name, &track_ids).map_err(|e| e.to_string())?;
+    for track_id in removed {
+        enqueue_track_tag_change(&conn, track_id, &name, "delete");
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn rename_tag(
+    tag_id: i64,
+    new_name: String,
+    db: State<'_, Database>,
+) -> Result<(), String> {
+    let new_name = new_name.trim().to_string();
+    if new_name.is_empty() {
+        return Err("Tag name cannot be empty".to_string());
+    }
+
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    let old_name = queries::get_tag_name(&conn, tag_id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Tag not found".to_string())?;
+    let track_ids = queries::get_track_ids_by_tag(&conn, &old_name).map_err(|e| e.to_string())?;
+    queries::rename_tag(&conn, tag_id, &new_name).map_err(|e| e.to_string())?;
+
+    // Tags sync per track by name, so a rename is a remove + add on each track
+    for track_id in track_ids {
+        enqueue_track_tag_change(&conn, track_id, &old_name, "delete");
+        enqueue_track_tag_change(&conn, track_id, &new_name, "create");
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_tag(tag_id: i64, db: State<'_, Database>) -> Result<(), String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    let name = queries::get_tag_name(&conn, tag_id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Tag not found".to_string())?;
+    let track_ids = queries::get_track_ids_by_tag(&conn, &name).map_err(|e| e.to_string())?;
+    queries::delete_tag(&conn, tag_id).map_err(|e| e.to_string())?;
+
+    for track_id in track_ids {
+        enqueue_track_tag_change(&conn, track_id, &name, "delete");
+    }
+
+    Ok(())
+}
+
 // ============================================================================
 // Play History commands
 // ============================================================================
@@ -186,3 +424,25 @@ pub async fn get_stats_summary(db: State<'_, Database>) -> Result<queries::Stats
     let conn = db.conn.lock().map_err(|e| e.to_string())?;
     queries::get_stats_summary(&conn).map_err(|e| e.to_string())
 }
+
+/// Listening stats for a week/month/year/custom range with heatmaps and streaks
+#[tauri::command]
+pub async fn get_listening_stats(
+    range: stats::StatsRange,
+    limit: Option<i32>,
+    db: State<'_, Database>,
+) -> Result<stats::ListeningStats, String> {
+    let resolved = range.resolve()?;
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    stats::get_listening_stats(&conn, &resolved, limit.unwrap_or(10)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_year_in_review(
+    year: i32,
+    utc_offset_minutes: Option<i32>,
+    db: State<'_, Database>,
+) -> Result<stats::YearInReview, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    stats::get_year_in_review(&conn, year, utc_offset_minutes.unwrap_or(0), 10)
+}
@@ -0,0 +1,259 @@
+//! Audio feature analysis
+//!
+//! Decodes local tracks to estimate tempo, key, energy and danceability (see
+//! `scanner::audio_features`). Tracks whose tags already give both BPM and key
+//! keep those values and aren't decoded unless `force` is set. Tags are read
+//! from the file, falling back to those stored at the last scan.
+
+use crate::db::queries::{self, AudioFeatures, FeatureAnalysisTrack};
+use crate::db::Database;
+use crate::scanner::audio_features::{analyse, parse_bpm_tag, read_tempo_tags};
+use rayon::prelude::*;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tauri::{Emitter, State};
+
+/// Tracks analysed per database write
+const ANALYSIS_CHUNK: usize = 32;
+
+/// Only one analysis runs at a time
+static ANALYSIS_RUNNING: AtomicBool = AtomicBool::new(false);
+
+struct RunningGuard;
+
+impl RunningGuard {
+    fn acquire() -> Option<Self> {
+        ANALYSIS_RUNNING
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .ok()
+            .map(|_| RunningGuard)
+    }
+}
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        ANALYSIS_RUNNING.store(false, Ordering::Release);
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct AudioAnalysisResult {
+    /// Tracks decoded and analysed
+    pub analysed: usize,
+    /// Tracks whose BPM and key came from their tags
+    pub from_tags: usize,
+    pub failed: usize,
+}
+
+/// Emitted as `audio-analysis-progress` after each chunk of tracks
+#[derive(Debug, Serialize, Clone)]
+pub struct AudioAnalysisProgress {
+    pub processed: usize,
+    pub total: usize,
+}
+
+enum Outcome {
+    Analysed,
+    FromTags,
+    Failed,
+}
+
+/// Features for one track. Tag values win over estimates unless `force` is
+/// set; either way they fill in what the audio can't give.
+fn track_features(track: &FeatureAnalysisTrack, force: bool) -> (Option<AudioFeatures>, Outcome) {
+    let path = Path::new(&track.path);
+    let (file_bpm, file_key) = read_tempo_tags(path);
+    let tag_bpm = file_bpm
+        .as_deref()
+        .or(track.tag_bpm.as_deref())
+        .and_then(parse_bpm_tag);
+    let tag_key = file_key
+        .or_else(|| track.tag_key.clone())
+        .map(|key| key.trim().to_string())
+        .filter(|key| !key.is_empty());
+    if let (Some(bpm), Some(key), false) = (tag_bpm, &tag_key, force) {
+        let features = AudioFeatures {
+            bpm: Some(bpm),
+            key: Some(key.clone()),
+            energy: None,
+            danceability: None,
+        };
+        return (Some(features), Outcome::FromTags);
+    }
+
+    match analyse(path) {
+        Some(mut features) if force => {
+            features.bpm = features.bpm.or(tag_bpm);
+            features.key = features.key.or(tag_key);
+            (Some(features), Outcome::Analysed)
+        }
+        Some(mut features) => {
+            features.bpm = tag_bpm.or(features.bpm);
+            features.key = tag_key.or(features.key);
+            (Some(features), Outcome::Analysed)
+        }
+        // Keep what the tags give even if the audio can't be decoded
+        None if tag_bpm.is_some() || tag_key.is_some() => {
+            let features = AudioFeatures {
+                bpm: tag_bpm,
+                key: tag_key,
+                energy: None,
+                danceability: None,
+            };
+            (Some(features), Outcome::Failed)
+        }
+        None => (None, Outcome::Failed),
+    }
+}
+
+/// Analyse the given tracks, storing results chunk by chunk
+fn analyse_tracks(
+    db_conn: &Mutex<Connection>,
+    tracks: &[FeatureAnalysisTrack],
+    force: bool,
+    mut on_progress: impl FnMut(AudioAnalysisProgress),
+) -> Result<AudioAnalysisResult, String> {
+    let mut result = AudioAnalysisResult::default();
+    let mut processed = 0;
+
+    for chunk in tracks.chunks(ANALYSIS_CHUNK) {
+        // Decode without holding the database lock
+        let computed: Vec<(i64, Option<AudioFeatures>, Outcome)> = chunk
+            .par_iter()
+            .map(|track| {
+                let (features, outcome) = track_features(track, force);
+                (track.id, features, outcome)
+            })
+            .collect();
+
+        let conn = db_conn.lock().map_err(|e| e.to_string())?;
+        let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+        for (id, features, outcome) in &computed {
+            queries::set_audio_features(&tx, *id, features.as_ref()).map_err(|e| e.to_string())?;
+            match outcome {
+                Outcome::Analysed => result.analysed += 1,
+                Outcome::FromTags => result.from_tags += 1,
+                Outcome::Failed => result.failed += 1,
+            }
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+
+        processed += chunk.len();
+        on_progress(AudioAnalysisProgress {
+            processed,
+            total: tracks.len(),
+        });
+    }
+
+    Ok(result)
+}
+
+/// Estimate BPM, key, energy and danceability for local tracks not analysed
+/// yet (all of them when `force` is set, preferring estimates to BPM and key
+/// tags). Runs in the background, emitting `audio-analysis-progress` as
+/// chunks finish.
+#[tauri::command]
+pub async fn analyse_audio_features(
+    app: tauri::AppHandle,
+    force: Option<bool>,
+    db: State<'_, Database>,
+) -> Result<AudioAnalysisResult, String> {
+    let Some(running) = RunningGuard::acquire() else {
+        return Err("Audio analysis is already running".to_string());
+    };
+    let force = force.unwrap_or(false);
+    let tracks = {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        queries::get_tracks_to_analyse(&conn, force).map_err(|e| e.to_string())?
+    };
+
+    let db_conn = db.conn.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let _running = running;
+        analyse_tracks(&db_conn, &tracks, force, |progress| {
+            let _ = app.emit("audio-analysis-progress", progress);
+        })
+    })
+    .await
+    .map_err(|e| format!("Audio analysis task failed: {}", e))?
+}
+
+/// Features of every analysed track, keyed by track ID
+#[tauri::command]
+pub async fn get_audio_features(
+    db: State<'_, Database>,
+) -> Result<HashMap<i64, AudioFeatures>, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    queries::get_audio_features(&conn).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::queries::{SmartField, SmartOperator, SmartPlaylistRules, SmartRuleNode};
+
+    #[test]
+    fn test_tagged_tracks_skip_decoding_unless_forced() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&conn).unwrap();
+        conn.execute_batch(
+            r#"INSERT INTO tracks (id, path, title, metadata_json) VALUES
+                 (1, '/missing/tagged.flac', 'Tagged', '{"Bpm":"126","InitialKey":"Am"}'),
+                 (2, '/missing/bpm-only.flac', 'Half', '{"Bpm":"90"}'),
+                 (3, '/missing/plain.flac', 'Plain', NULL);"#,
+        )
+        .unwrap();
+        let db_conn = Mutex::new(conn);
+        let run = |force: bool| {
+            let tracks = {
+                let conn = db_conn.lock().unwrap();
+                queries::get_tracks_to_analyse(&conn, force).unwrap()
+            };
+            analyse_tracks(&db_conn, &tracks, force, |_| {}).unwrap()
+        };
+
+        let result = run(false);
+        assert_eq!(
+            (result.analysed, result.from_tags, result.failed),
+            (0, 1, 2)
+        );
+        // Every track is marked, so nothing is left for the next run
+        let again = run(false);
+        assert_eq!(again.analysed + again.from_tags + again.failed, 0);
+
+        let conn = db_conn.lock().unwrap();
+        let features = queries::get_audio_features(&conn).unwrap();
+        assert_eq!(features[&1].bpm, Some(126.0));
+        assert_eq!(features[&1].key.as_deref(), Some("Am"));
+        assert_eq!(features[&2].bpm, Some(90.0));
+        assert!(!features.contains_key(&3));
+
+        let rules = SmartPlaylistRules {
+            root: SmartRuleNode::Rule {
+                field: SmartField::Bpm,
+                operator: SmartOperator::Between,
+                value: serde_json::json!([120, 130]),
+            },
+            sort: None,
+            limit: None,
+        };
+        let matched = queries::get_smart_playlist_tracks(&conn, &rules).unwrap();
+        assert_eq!(matched.iter().map(|t| t.id).collect::<Vec<_>>(), vec![1]);
+        drop(conn);
+
+        // Forcing decodes every track; the missing files can't be decoded, so
+        // the tag values stay
+        let result = run(true);
+        assert_eq!((result.from_tags, result.failed), (0, 3));
+        let conn = db_conn.lock().unwrap();
+        let features = queries::get_audio_features(&conn).unwrap();
+        assert_eq!(features[&1].bpm, Some(126.0));
+        assert_eq!(features[&1].key.as_deref(), Some("Am"));
+        assert_eq!(features[&2].bpm, Some(90.0));
+        assert!(!features.contains_key(&3));
+    }
+}
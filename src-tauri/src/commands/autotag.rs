@@ -0,0 +1,815 @@
+//! Album-level auto-tagging from MusicBrainz
+//!
+//! Tracks are grouped by folder and album, and each group is matched to a
+//! MusicBrainz release: candidates come from a release search by album, artist
+//! and track count (or, for files without an album tag, from a recording search
+//! by title and length), and each candidate's tracklist is scored against the files' titles,
+//! lengths and positions. The best release becomes a proposal listing every
+//! tag that would change. Accepted changes are written by the tag editor, so
+//! they reach the files, the library and the undo journal together.
+
+use super::cover_art::{quote, CoverArtConfig, RateLimiter, ReleaseSearchResponse};
+use super::musicbrainz::MB_USER_AGENT;
+use super::tags::{edit_tags, read_fields, TagEdit, TagEditResult};
+use crate::db::queries::{self, AutotagTrack};
+use crate::db::Database;
+use crate::utils::normalize_words;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tauri::State;
+
+/// Search results fetched in full and scored for each group
+const MAX_CANDIDATES: usize = 3;
+/// Search matches scoring lower than this (out of 100) are not considered
+const MIN_SEARCH_SCORE: u32 = 50;
+/// Releases scoring lower than this (out of 100) are listed but not proposed
+const MIN_MATCH_SCORE: u32 = 60;
+/// Pairs of a file and a release track less alike than this are not matched
+const MIN_TRACK_SIMILARITY: f64 = 0.3;
+/// Length differences beyond this many seconds count as a different track
+const LENGTH_TOLERANCE_SECS: f64 = 10.0;
+
+/// Fields the tagger fills in, in the order they are listed
+const FIELDS: &[&str] = &[
+    "title",
+    "artist",
+    "album",
+    "album_artist",
+    "year",
+    "track_number",
+    "track_total",
+    "disc_number",
+    "disc_total",
+    "musicbrainz_recording_id",
+    "musicbrainz_track_id",
+    "musicbrainz_release_id",
+    "musicbrainz_release_group_id",
+    "musicbrainz_artist_id",
+    "musicbrainz_release_artist_id",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+struct Release {
+    id: String,
+    title: String,
+    date: Option<String>,
+    country: Option<String>,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<ArtistCredit>,
+    #[serde(rename = "release-group")]
+    release_group: Option<ReleaseGroup>,
+    #[serde(default)]
+    media: Vec<Medium>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ArtistCredit {
+    name: String,
+    #[serde(default)]
+    joinphrase: String,
+    artist: CreditedArtist,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct CreditedArtist {
+    id: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ReleaseGroup {
+    id: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Medium {
+    position: u32,
+    #[serde(default)]
+    tracks: Vec<ReleaseTrack>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ReleaseTrack {
+    id: String,
+    position: u32,
+    title: String,
+    length: Option<u32>,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<ArtistCredit>,
+    recording: Recording,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Recording {
+    id: String,
+}
+
+impl Release {
+    fn track_count(&self) -> usize {
+        self.media.iter().map(|medium| medium.tracks.len()).sum()
+    }
+}
+
+/// "A feat. B" from a credit list
+fn credit_name(credits: &[ArtistCredit]) -> String {
+    credits
+        .iter()
+        .map(|credit| format!("{}{}", credit.name, credit.joinphrase))
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReleaseCandidate {
+    pub id: String,
+    pub title: String,
+    pub artist: String,
+    pub date: Option<String>,
+    pub country: Option<String>,
+    pub track_count: usize,
+    /// How well the tracklist fits the files, out of 100
+    pub score: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    /// The file's current values joined with "; ", None if the field is unset
+    pub current: Option<String>,
+    pub proposed: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrackProposal {
+    pub track_id: i64,
+    pub path: String,
+    /// Whether the file was matched to a track of the release
+    pub matched: bool,
+    /// Only the fields that differ from the file
+    pub changes: Vec<FieldChange>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlbumProposal {
+    pub folder: String,
+    pub album: Option<String>,
+    pub artist: Option<String>,
+    /// Best first; the proposal is for the first one when it scores high enough
+    pub candidates: Vec<ReleaseCandidate>,
+    pub release_id: Option<String>,
+    pub tracks: Vec<TrackProposal>,
+    pub error: Option<String>,
+}
+
+/// A library track with its file's current values of the tagger's fields
+#[derive(Debug, Clone)]
+pub struct TaggedFile {
+    pub track: AutotagTrack,
+    pub current: Result<HashMap<String, Vec<String>>, String>,
+}
+
+fn title_similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (normalize_words(a), normalize_words(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    if a == b {
+        return 1.0;
+    }
+    if a.contains(&b) || b.contains(&a) {
+        return 0.8;
+    }
+    let a: HashSet<&str> = a.split(' ').collect();
+    let b: HashSet<&str> = b.split(' ').collect();
+    a.intersection(&b).count() as f64 / a.union(&b).count() as f64
+}
+
+/// How alike a file and a release track are, from 0 to 1. Title, length
+/// and position count when known on both sides.
+fn track_similarity(track: &AutotagTrack, disc: u32, release_track: &ReleaseTrack) -> f64 {
+    let mut parts = Vec::new();
+    if let Some(title) = track.title.as_deref() {
+        parts.push((0.5, title_similarity(title, &release_track.title)));
+    }
+    if let (Some(duration), Some(length)) = (track.duration, release_track.length) {
+        let diff = (duration as f64 - length as f64 / 1000.0).abs();
+        parts.push((0.3, (1.0 - diff / LENGTH_TOLERANCE_SECS).max(0.0)));
+    }
+    if let Some(number) = track.track_number {
+        let same_disc = track.disc_number.unwrap_or(1) as u32 == disc;
+        let same = same_disc && number as u32 == release_track.position;
+        parts.push((0.2, if same { 1.0 } else { 0.0 }));
+    }
+    let weight: f64 = parts.iter().map(|(weight, _)| weight).sum();
+    if weight == 0.0 {
+        return 0.0;
+    }
+    parts
+        .iter()
+        .map(|(weight, score)| weight * score)
+        .sum::<f64>()
+        / weight
+}
+
+/// For each file, the (medium, track) index it matches, best pairs first;
+/// and the release's score out of 100. Missing and extra tracks on either
+/// side lower the score.
+fn match_release(tracks: &[AutotagTrack], release: &Release) -> (Vec<Option<(usize, usize)>>, u32) {
+    let mut pairs = Vec::new();
+    for (i, track) in tracks.iter().enumerate() {
+        for (m, medium) in release.media.iter().enumerate() {
+            for (t, release_track) in medium.tracks.iter().enumerate() {
+                let similarity = track_similarity(track, medium.position, release_track);
+                if similarity >= MIN_TRACK_SIMILARITY {
+                    pairs.push((similarity, i, (m, t)));
+                }
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let mut matches = vec![None; tracks.len()];
+    let mut taken = HashSet::new();
+    let mut total = 0.0;
+    for (similarity, i, position) in pairs {
+        if matches[i].is_none() && taken.insert(position) {
+            matches[i] = Some(position);
+            total += similarity;
+        }
+    }
+    let size = tracks.len().max(release.track_count()).max(1);
+    (matches, (100.0 * total / size as f64).round() as u32)
+}
+
+/// The tagger's values for `release_track` on medium `m` of `release`
+fn release_fields(
+    release: &Release,
+    m: usize,
+    release_track: &ReleaseTrack,
+) -> Vec<(&'static str, String)> {
+    let album_artist = credit_name(&release.artist_credit);
+    let artist_credit = if release_track.artist_credit.is_empty() {
+        &release.artist_credit
+    } else {
+        &release_track.artist_credit
+    };
+    let mut fields = vec![
+        ("title", release_track.title.clone()),
+        ("artist", credit_name(artist_credit)),
+        ("album", release.title.clone()),
+        ("album_artist", album_artist),
+        ("track_number", release_track.position.to_string()),
+        ("track_total", release.media[m].tracks.len().to_string()),
+        ("disc_number", release.media[m].position.to_string()),
+        ("disc_total", release.media.len().to_string()),
+        (
+            "musicbrainz_recording_id",
+            release_track.recording.id.clone(),
+        ),
+        ("musicbrainz_track_id", release_track.id.clone()),
+        ("musicbrainz_release_id", release.id.clone()),
+    ];
+    if let Some(year) = release.date.as_deref().and_then(|date| date.get(..4)) {
+        fields.push(("year", year.to_string()));
+    }
+    if let Some(ref group) = release.release_group {
+        fields.push(("musicbrainz_release_group_id", group.id.clone()));
+    }
+    if let Some(credit) = artist_credit.first() {
+        fields.push(("musicbrainz_artist_id", credit.artist.id.clone()));
+    }
+    if let Some(credit) = release.artist_credit.first() {
+        fields.push(("musicbrainz_release_artist_id", credit.artist.id.clone()));
+    }
+    fields.sort_by_key(|(field, _)| FIELDS.iter().position(|f| f == field));
+    fields
+}
+
+/// Whether a file's current values already say `proposed`
+fn is_unchanged(field: &str, current: &[String], proposed: &str) -> bool {
+    let Some(value) = current.first().map(|value| value.trim()) else {
+        return false;
+    };
+    match field {
+        // "03" and "3/12" are both track 3
+        "track_number" | "track_total" | "disc_number" | "disc_total" => {
+            let number = value.split('/').next().unwrap_or_default().trim();
+            number.parse::<u32>().ok() == proposed.parse::<u32>().ok()
+        }
+        "year" => value.get(..4) == Some(proposed),
+        _ => current.len() == 1 && value == proposed,
+    }
+}
+
+fn candidate(release: &Release, score: u32) -> ReleaseCandidate {
+    ReleaseCandidate {
+        id: release.id.clone(),
+        title: release.title.clone(),
+        artist: credit_name(&release.artist_credit),
+        date: release.date.clone(),
+        country: release.country.clone(),
+        track_count: release.track_count(),
+        score,
+    }
+}
+
+/// The proposal for one group of files given the scored `releases`, best first
+fn build_proposal(group: &[TaggedFile], releases: &[(Release, u32)]) -> AlbumProposal {
+    let first = &group[0].track;
+    let mut proposal = AlbumProposal {
+        folder: Path::new(&first.path)
+            .parent()
+            .map(|dir| dir.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        album: first.album.clone(),
+        artist: first.album_artist.clone().or_else(|| first.artist.clone()),
+        candidates: releases
+            .iter()
+            .map(|(release, score)| candidate(release, *score))
+            .collect(),
+        release_id: None,
+        tracks: Vec::new(),
+        error: None,
+    };
+
+    let best = releases
+        .first()
+        .filter(|(_, score)| *score >= MIN_MATCH_SCORE);
+    let tracks: Vec<AutotagTrack> = group.iter().map(|file| file.track.clone()).collect();
+    let matches = match best {
+        Some((release, _)) => {
+            proposal.release_id = Some(release.id.clone());
+            match_release(&tracks, release).0
+        }
+        None => {
+            proposal.error = Some("No release matched these files closely enough".to_string());
+            vec![None; tracks.len()]
+        }
+    };
+
+    for (file, matched) in group.iter().zip(matches) {
+        let mut track = TrackProposal {
+            track_id: file.track.id,
+            path: file.track.path.clone(),
+            matched: matched.is_some(),
+            changes: Vec::new(),
+            error: None,
+        };
+        let current = match file.current {
+            Ok(ref current) => current,
+            Err(ref e) => {
+                track.error = Some(e.clone());
+                proposal.tracks.push(track);
+                continue;
+            }
+        };
+        if let (Some((release, _)), Some((m, t))) = (best, matched) {
+            for (field, proposed) in release_fields(release, m, &release.media[m].tracks[t]) {
+                let values = current.get(field).map(Vec::as_slice).unwrap_or_default();
+                if !is_unchanged(field, values, &proposed) {
+                    track.changes.push(FieldChange {
+                        field: field.to_string(),
+                        current: (!values.is_empty()).then(|| values.join("; ")),
+                        proposed,
+                    });
+                }
+            }
+        }
+        proposal.tracks.push(track);
+    }
+    proposal
+}
+
+/// Split files into albums: same folder and same album tag
+fn group_files(files: Vec<TaggedFile>) -> Vec<Vec<TaggedFile>> {
+    let mut groups: Vec<Vec<TaggedFile>> = Vec::new();
+    let mut index: HashMap<(String, String), usize> = HashMap::new();
+    for file in files {
+        let key = (
+            Path::new(&file.track.path)
+                .parent()
+                .map(|dir| dir.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            normalize_words(file.track.album.as_deref().unwrap_or_default()),
+        );
+        match index.get(&key) {
+            Some(&i) => groups[i].push(file),
+            None => {
+                index.insert(key, groups.len());
+                groups.push(vec![file]);
+            }
+        }
+    }
+    groups
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    limiter: &mut RateLimiter,
+    url: String,
+    query: &[(&str, &str)],
+) -> Result<T, String> {
+    limiter.wait().await;
+    let resp = client
+        .get(url)
+        .query(query)
+        .send()
+        .await
+        .map_err(|e| format!("MusicBrainz request error: {}", e))?;
+    if !resp.status().is_success() {
+        return Err(format!("MusicBrainz returned {}", resp.status()));
+    }
+    resp.json().await.map_err(|e| format!("Parse error: {}", e))
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordingSearchResponse {
+    recordings: Option<Vec<RecordingMatch>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordingMatch {
+    score: Option<u32>,
+    releases: Option<Vec<ReleaseRef>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReleaseRef {
+    id: String,
+}
+
+/// Ids of the releases a search turns up for `group`, best first. The track
+/// count ranks releases of the group's size first without ruling out partial
+/// albums. Files without an album tag are looked up by one of their
+/// recordings instead, by title and length.
+async fn search_releases(
+    client: &reqwest::Client,
+    config: &CoverArtConfig,
+    limiter: &mut RateLimiter,
+    group: &[TaggedFile],
+) -> Result<Vec<String>, String> {
+    let first = &group[0].track;
+    let artist = first.album_artist.as_deref().or(first.artist.as_deref());
+    let track_count = group.len();
+    let limit = MAX_CANDIDATES.to_string();
+
+    if let Some(album) = first.album.as_deref().filter(|a| !a.trim().is_empty()) {
+        let mut query = format!("release:{}", quote(album));
+        if let Some(artist) = artist {
+            query.push_str(&format!(" AND artist:{}", quote(artist)));
+        }
+        query.push_str(&format!(" tracks:{0} tracksmedium:{0}", track_count));
+
+        let url = format!("{}/release", config.musicbrainz_base);
+        let params = [
+            ("query", query.as_str()),
+            ("limit", limit.as_str()),
+            ("fmt", "json"),
+        ];
+        let found: ReleaseSearchResponse = get_json(client, limiter, url, &params).await?;
+        return Ok(found
+            .releases
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|release| release.score.unwrap_or(0) >= MIN_SEARCH_SCORE)
+            .map(|release| release.id)
+            .collect());
+    }
+
+    let Some((title, duration)) = group.iter().find_map(|file| {
+        let title = file
+            .track
+            .title
+            .as_deref()
+            .filter(|t| !t.trim().is_empty())?;
+        Some((title, file.track.duration.filter(|d| *d > 0)?))
+    }) else {
+        return Err("These files have no album tag or timed title to search for".to_string());
+    };
+    let tolerance = (LENGTH_TOLERANCE_SECS * 1000.0) as i64;
+    let length = duration as i64 * 1000;
+    let mut query = format!(
+        "recording:{} AND dur:[{} TO {}]",
+        quote(title),
+        (length - tolerance).max(0),
+        length + tolerance
+    );
+    if let Some(artist) = artist {
+        query.push_str(&format!(" AND artist:{}", quote(artist)));
+    }
+    query.push_str(&format!(" tracksrelease:{}", track_count));
+
+    let url = format!("{}/recording", config.musicbrainz_base);
+    let params = [
+        ("query", query.as_str()),
+        ("limit", limit.as_str()),
+        ("fmt", "json"),
+    ];
+    let found: RecordingSearchResponse = get_json(client, limiter, url, &params).await?;
+    let mut ids: Vec<String> = Vec::new();
+    for release in found
+        .recordings
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|recording| recording.score.unwrap_or(0) >= MIN_SEARCH_SCORE)
+        .flat_map(|recording| recording.releases.unwrap_or_default())
+    {
+        if !ids.contains(&release.id) {
+            ids.push(release.id);
+        }
+    }
+    ids.truncate(MAX_CANDIDATES);
+    Ok(ids)
+}
+
+async fn fetch_release(
+    client: &reqwest::Client,
+    config: &CoverArtConfig,
+    limiter: &mut RateLimiter,
+    id: &str,
+) -> Result<Release, String> {
+    let url = format!("{}/release/{}", config.musicbrainz_base, id);
+    let query = [
+        ("inc", "recordings+artist-credits+release-groups"),
+        ("fmt", "json"),
+    ];
+    get_json(client, limiter, url, &query).await
+}
+
+/// Releases that may hold `group`, scored and best first. With `release_id`
+/// only that release is considered.
+async fn find_releases(
+    client: &reqwest::Client,
+    config: &CoverArtConfig,
+    limiter: &mut RateLimiter,
+    group: &[TaggedFile],
+    release_id: Option<&str>,
+) -> Result<Vec<(Release, u32)>, String> {
+    let ids = match release_id {
+        Some(id) => vec![id.to_string()],
+        None => search_releases(client, config, limiter, group).await?,
+    };
+
+    let tracks: Vec<AutotagTrack> = group.iter().map(|file| file.track.clone()).collect();
+    let mut releases = Vec::new();
+    for id in ids {
+        // One release failing to load leaves the others to choose from
+        let release = match fetch_release(client, config, limiter, &id).await {
+            Ok(release) => release,
+            Err(e) if release_id.is_none() => {
+                log::warn!("[Autotag] Skipping release {}: {}", id, e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let (_, score) = match_release(&tracks, &release);
+        releases.push((release, score));
+    }
+    releases.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+    Ok(releases)
+}
+
+/// Propose tags for each album among `files`
+pub async fn propose_tags(
+    config: &CoverArtConfig,
+    files: Vec<TaggedFile>,
+    release_id: Option<&str>,
+) -> Result<Vec<AlbumProposal>, String> {
+    let client = reqwest::Client::builder()
+        .user_agent(MB_USER_AGENT)
+        .build()
+        .map_err(|e| format!("HTTP client error: {}", e))?;
+    let mut limiter = RateLimiter::new(config.request_interval);
+
+    let mut proposals = Vec::new();
+    for group in group_files(files) {
+        let proposal = match find_releases(&client, config, &mut limiter, &group, release_id).await
+        {
+            Ok(releases) => build_proposal(&group, &releases),
+            Err(e) => {
+                log::warn!("[Autotag] Release lookup failed: {}", e);
+                AlbumProposal {
+                    error: Some(e),
+                    ..build_proposal(&group, &[])
+                }
+            }
+        };
+        proposals.push(proposal);
+    }
+    Ok(proposals)
+}
+
+/// Match the albums of `track_ids` to MusicBrainz releases and list the tag
+/// changes each would bring. Nothing is written. Pass `release_id` to use a
+/// specific release (e.g. another candidate) for the given tracks.
+#[tauri::command]
+pub async fn propose_album_tags(
+    track_ids: Vec<i64>,
+    release_id: Option<String>,
+    db: State<'_, Database>,
+) -> Result<Vec<AlbumProposal>, String> {
+    let conn = db.conn.clone();
+    let files = tauri::async_runtime::spawn_blocking(move || {
+        let tracks = {
+            let conn = conn.lock().map_err(|e| e.to_string())?;
+            queries::get_autotag_tracks(&conn, &track_ids).map_err(|e| e.to_string())?
+        };
+        Ok::<_, String>(
+            tracks
+                .into_iter()
+                .map(|track| TaggedFile {
+                    current: read_fields(Path::new(&track.path), FIELDS),
+                    track,
+                })
+                .collect(),
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    propose_tags(&CoverArtConfig::from_env(), files, release_id.as_deref()).await
+}
+
+/// Write accepted auto-tag changes to files and the library as one undoable
+/// operation, and remember each album's release
+#[tauri::command]
+pub async fn apply_album_tags(
+    edits: Vec<TagEdit>,
+    db: State<'_, Database>,
+) -> Result<Vec<TagEditResult>, String> {
+    let conn = db.conn.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let description = format!("Auto-tag {} files from MusicBrainz", edits.len());
+        let results = edit_tags(&conn, &description, &edits)?;
+
+        let conn = conn.lock().map_err(|e| e.to_string())?;
+        for (edit, result) in edits.iter().zip(&results) {
+            let release_id = edit
+                .fields
+                .get("musicbrainz_release_id")
+                .and_then(|values| values.first());
+            let (true, Some(release_id)) = (result.success, release_id) else {
+                continue;
+            };
+            if let Ok(Some(album_id)) =
+                queries::get_track_by_id(&conn, edit.track_id).map(|t| t.and_then(|t| t.album_id))
+            {
+                queries::set_album_release_mbid(&conn, album_id, release_id)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(results)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::cover_art::tests::mock_server;
+    use super::*;
+    use std::time::Duration;
+
+    const SEARCH: &str = r#"{"releases":[
+        {"id":"rel-other","score":100},
+        {"id":"rel-good","score":95},
+        {"id":"rel-gone","score":90},
+        {"id":"rel-weak","score":20}]}"#;
+
+    const GOOD: &str = r#"{"id":"rel-good","title":"Blue Train","date":"1958-01-01",
+        "country":"US","release-group":{"id":"rg-1"},
+        "artist-credit":[{"name":"John Coltrane","joinphrase":"","artist":{"id":"art-1"}}],
+        "media":[{"position":1,"tracks":[
+            {"id":"trk-1","position":1,"title":"Blue Train","length":643000,
+             "recording":{"id":"rec-1"}},
+            {"id":"trk-2","position":2,"title":"Moment's Notice","length":550000,
+             "recording":{"id":"rec-2"}}]}]}"#;
+
+    const OTHER: &str = r#"{"id":"rel-other","title":"Blue Train (Deluxe)",
+        "artist-credit":[{"name":"John Coltrane","joinphrase":"","artist":{"id":"art-1"}}],
+        "media":[{"position":1,"tracks":[
+            {"id":"x-1","position":1,"title":"Blue Train (alt take)","length":700000,
+             "recording":{"id":"x-rec-1"}},
+            {"id":"x-2","position":2,"title":"Lazy Bird","length":420000,
+             "recording":{"id":"x-rec-2"}},
+            {"id":"x-3","position":3,"title":"Locomotion","length":430000,
+             "recording":{"id":"x-rec-3"}}]}]}"#;
+
+    fn file(id: i64, title: &str, number: i32, duration: i32, current_title: &str) -> TaggedFile {
+        TaggedFile {
+            track: AutotagTrack {
+                id,
+                path: format!("/music/coltrane/{:02}.flac", number),
+                title: Some(title.to_string()),
+                artist: Some("John Coltrane".to_string()),
+                album: Some("Blue Train".to_string()),
+                album_artist: None,
+                track_number: Some(number),
+                disc_number: None,
+                duration: Some(duration),
+            },
+            current: Ok(HashMap::from([
+                ("title".to_string(), vec![current_title.to_string()]),
+                ("track_number".to_string(), vec![format!("{:02}", number)]),
+            ])),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_propose_tags_from_recorded_responses() {
+        let base = mock_server(|path| {
+            let body = if path.starts_with("/ws/2/release?") && path.contains("tracks%3A2") {
+                SEARCH
+            } else if path.starts_with("/ws/2/release/rel-good?") {
+                GOOD
+            } else if path.starts_with("/ws/2/release/rel-other?") {
+                OTHER
+            } else {
+                return (404, "text/plain", Vec::new());
+            };
+            (200, "application/json", body.as_bytes().to_vec())
+        });
+        let config = CoverArtConfig {
+            musicbrainz_base: format!("{}/ws/2", base),
+            cover_art_base: format!("{}/caa", base),
+            request_interval: Duration::ZERO,
+        };
+        let files = vec![
+            file(1, "Blue Train", 1, 642, "Blue Train"),
+            file(2, "Moments Notice", 2, 551, "Moments Notice"),
+        ];
+
+        let proposals = propose_tags(&config, files, None).await.unwrap();
+        assert_eq!(proposals.len(), 1);
+        let proposal = &proposals[0];
+        let ids: Vec<&str> = proposal.candidates.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["rel-good", "rel-other"]);
+        assert!(proposal.candidates[0].score >= 90);
+        assert_eq!(proposal.release_id.as_deref(), Some("rel-good"));
+
+        let change = |track: usize, field: &str| {
+            proposal.tracks[track]
+                .changes
+                .iter()
+                .find(|c| c.field == field)
+                .map(|c| c.proposed.clone())
+        };
+        assert_eq!(change(0, "title"), None);
+        assert_eq!(change(0, "track_number"), None);
+        assert_eq!(change(1, "title").as_deref(), Some("Moment's Notice"));
+        assert_eq!(
+            change(1, "musicbrainz_recording_id").as_deref(),
+            Some("rec-2")
+        );
+        assert_eq!(
+            change(1, "musicbrainz_release_group_id").as_deref(),
+            Some("rg-1")
+        );
+        assert_eq!(change(1, "year").as_deref(), Some("1958"));
+    }
+
+    #[tokio::test]
+    async fn test_propose_tags_without_album_tag() {
+        let base = mock_server(|path| {
+            let body = if path.starts_with("/ws/2/recording?")
+                && path.contains("dur%3A%5B632000+TO+652000%5D")
+            {
+                r#"{"recordings":[
+                    {"id":"rec-1","score":100,"releases":[{"id":"rel-good"},{"id":"rel-other"}]},
+                    {"id":"x-rec-1","score":80,"releases":[{"id":"rel-other"}]}]}"#
+            } else if path.starts_with("/ws/2/release/rel-good?") {
+                GOOD
+            } else if path.starts_with("/ws/2/release/rel-other?") {
+                OTHER
+            } else {
+                return (404, "text/plain", Vec::new());
+            };
+            (200, "application/json", body.as_bytes().to_vec())
+        });
+        let config = CoverArtConfig {
+            musicbrainz_base: format!("{}/ws/2", base),
+            cover_art_base: format!("{}/caa", base),
+            request_interval: Duration::ZERO,
+        };
+        let mut files = vec![
+            file(1, "Blue Train", 1, 642, "Blue Train"),
+            file(2, "Moments Notice", 2, 551, "Moments Notice"),
+        ];
+        for file in &mut files {
+            file.track.album = None;
+        }
+
+        let proposals = propose_tags(&config, files, None).await.unwrap();
+        assert_eq!(proposals.len(), 1);
+        assert_eq!(proposals[0].error, None);
+        let ids: Vec<&str> = proposals[0]
+            .candidates
+            .iter()
+            .map(|c| c.id.as_str())
+            .collect();
+        assert_eq!(ids, ["rel-good", "rel-other"]);
+        assert_eq!(proposals[0].release_id.as_deref(), Some("rel-good"));
+    }
+}
@@ -0,0 +1,517 @@
+//! Cover Art Archive lookup for albums missing art
+//!
+//! Albums without embedded or folder art are matched to a MusicBrainz release
+//! (the release MBID from their tags or an earlier lookup, otherwise a release
+//! search by album and artist) and get the release's front cover from the
+//! Cover Art Archive. Requests to both services share MusicBrainz's
+//! 1 req/sec limit.
+
+use super::musicbrainz::{MB_API_BASE, MB_USER_AGENT};
+use crate::db::queries::{self, ArtLookupAlbum};
+use crate::db::Database;
+use crate::scanner::cover_storage;
+use crate::scanner::walker::LibraryRules;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{Emitter, State};
+use tokio::time::{sleep_until, Instant};
+
+const CAA_API_BASE: &str = "https://coverartarchive.org";
+/// MusicBrainz allows one request per second
+pub(crate) const REQUEST_INTERVAL: Duration = Duration::from_millis(1100);
+/// Albums without art online are looked up again after this many days
+const RETRY_DAYS: i64 = 30;
+/// Release search matches scoring lower than this (out of 100) are ignored
+const MIN_RELEASE_SCORE: u32 = 90;
+/// The largest pre-scaled front image; originals can be tens of megabytes
+const FRONT_IMAGE: &str = "front-1200";
+
+/// Only one lookup runs at a time, whether on demand or after a scan
+static LOOKUP_RUNNING: AtomicBool = AtomicBool::new(false);
+
+struct RunningGuard;
+
+impl RunningGuard {
+    fn acquire() -> Option<Self> {
+        LOOKUP_RUNNING
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .ok()
+            .map(|_| RunningGuard)
+    }
+}
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        LOOKUP_RUNNING.store(false, Ordering::Release);
+    }
+}
+
+/// Service endpoints, shared with the auto-tagger
+#[derive(Debug, Clone)]
+pub struct CoverArtConfig {
+    pub musicbrainz_base: String,
+    pub cover_art_base: String,
+    pub request_interval: Duration,
+}
+
+impl Default for CoverArtConfig {
+    fn default() -> Self {
+        Self {
+            musicbrainz_base: MB_API_BASE.to_string(),
+            cover_art_base: CAA_API_BASE.to_string(),
+            request_interval: REQUEST_INTERVAL,
+        }
+    }
+}
+
+impl CoverArtConfig {
+    /// Defaults, with the base URLs overridable through `AUDION_MUSICBRAINZ_URL`
+    /// and `AUDION_COVER_ART_URL` (e.g. to point at a mock server)
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(url) = std::env::var("AUDION_MUSICBRAINZ_URL") {
+            config.musicbrainz_base = url.trim_end_matches('/').to_string();
+        }
+        if let Ok(url) = std::env::var("AUDION_COVER_ART_URL") {
+            config.cover_art_base = url.trim_end_matches('/').to_string();
+        }
+        config
+    }
+}
+
+/// Emitted as `album-art-fetched` for every album that got art
+#[derive(Debug, Serialize, Clone)]
+pub struct AlbumArtFetched {
+    pub album_id: i64,
+    pub art_path: String,
+}
+
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct CoverArtLookupResult {
+    pub fetched: usize,
+    /// No matching release, or the release has no front cover
+    pub not_found: usize,
+    /// Network or storage errors; these albums are retried on the next run
+    pub failed: usize,
+}
+
+/// A MusicBrainz release search, as used here and by the auto-tagger
+#[derive(Debug, Deserialize)]
+pub(crate) struct ReleaseSearchResponse {
+    pub releases: Option<Vec<ReleaseMatch>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ReleaseMatch {
+    pub id: String,
+    pub score: Option<u32>,
+}
+
+/// Spaces requests at least `interval` apart
+pub(crate) struct RateLimiter {
+    interval: Duration,
+    next: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next: None,
+        }
+    }
+
+    pub(crate) async fn wait(&mut self) {
+        if let Some(next) = self.next {
+            sleep_until(next).await;
+        }
+        self.next = Some(Instant::now() + self.interval);
+    }
+}
+
+/// Quote a value for a MusicBrainz (Lucene) search
+pub(crate) fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+async fn search_release(
+    client: &reqwest::Client,
+    config: &CoverArtConfig,
+    limiter: &mut RateLimiter,
+    album: &ArtLookupAlbum,
+) -> Result<Option<String>, String> {
+    let mut query = format!("release:{}", quote(&album.name));
+    if let Some(ref artist) = album.artist {
+        query.push_str(&format!(" AND artist:{}", quote(artist)));
+    }
+
+    limiter.wait().await;
+    let resp = client
+        .get(format!("{}/release", config.musicbrainz_base))
+        .query(&[("query", query.as_str()), ("limit", "1"), ("fmt", "json")])
+        .send()
+        .await
+        .map_err(|e| format!("MusicBrainz release search error: {}", e))?;
+    if !resp.status().is_success() {
+        return Err(format!("MusicBrainz returned {}", resp.status()));
+    }
+    let data: ReleaseSearchResponse = resp
+        .json()
+        .await
+        .map_err(|e| format!("Parse error: {}", e))?;
+
+    Ok(data
+        .releases
+        .and_then(|releases| releases.into_iter().next())
+        .filter(|release| release.score.unwrap_or(0) >= MIN_RELEASE_SCORE)
+        .map(|release| release.id))
+}
+
+/// The album's release MBID (if any) and the release's front cover (if any)
+async fn lookup_album(
+    client: &reqwest::Client,
+    config: &CoverArtConfig,
+    limiter: &mut RateLimiter,
+    album: &ArtLookupAlbum,
+) -> Result<(Option<String>, Option<Vec<u8>>), String> {
+    let mbid = match album.release_mbid {
+        Some(ref mbid) => mbid.clone(),
+        None => match search_release(client, config, limiter, album).await? {
+            Some(mbid) => mbid,
+            None => return Ok((None, None)),
+        },
+    };
+
+    limiter.wait().await;
+    let resp = client
+        .get(format!(
+            "{}/release/{}/{}",
+            config.cover_art_base, mbid, FRONT_IMAGE
+        ))
+        .send()
+        .await
+        .map_err(|e| format!("Cover Art Archive error: {}", e))?;
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok((Some(mbid), None));
+    }
+    if !resp.status().is_success() {
+        return Err(format!("Cover Art Archive returned {}", resp.status()));
+    }
+    let image = resp
+        .bytes()
+        .await
+        .map_err(|e| format!("Cover Art Archive download error: {}", e))?;
+    Ok((Some(mbid), Some(image.to_vec())))
+}
+
+/// Store a downloaded cover unless the album got art some other way meanwhile
+fn store_fetched_art(
+    conn: &Connection,
+    album_id: i64,
+    image: &[u8],
+) -> Result<Option<String>, String> {
+    let missing: bool = conn
+        .query_row(
+            "SELECT art_path IS NULL FROM albums WHERE id = ?1",
+            [album_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if !missing {
+        return Ok(None);
+    }
+    cover_storage::store_album_art(conn, album_id, image).map(Some)
+}
+
+/// Look up and store art for `albums`, calling `on_fetched` for each album
+/// that got art. Albums are marked as looked up unless a request or storing
+/// the art failed.
+pub async fn fetch_album_art(
+    db_conn: Arc<Mutex<Connection>>,
+    config: &CoverArtConfig,
+    albums: Vec<ArtLookupAlbum>,
+    on_fetched: impl Fn(&AlbumArtFetched),
+) -> Result<CoverArtLookupResult, String> {
+    let client = reqwest::Client::builder()
+        .user_agent(MB_USER_AGENT)
+        .build()
+        .map_err(|e| format!("HTTP client error: {}", e))?;
+    let mut limiter = RateLimiter::new(config.request_interval);
+    let mut result = CoverArtLookupResult::default();
+
+    for album in albums {
+        let (mbid, image) = match lookup_album(&client, config, &mut limiter, &album).await {
+            Ok(found) => found,
+            Err(e) => {
+                log::warn!("[CoverArt] Lookup for album {} failed: {}", album.id, e);
+                result.failed += 1;
+                continue;
+            }
+        };
+
+        let conn = db_conn.lock().map_err(|e| e.to_string())?;
+        match image.map(|image| store_fetched_art(&conn, album.id, &image)) {
+            Some(Ok(Some(art_path))) => {
+                result.fetched += 1;
+                on_fetched(&AlbumArtFetched {
+                    album_id: album.id,
+                    art_path,
+                });
+            }
+            Some(Ok(None)) => {}
+            Some(Err(e)) => {
+                log::warn!(
+                    "[CoverArt] Failed to store art for album {}: {}",
+                    album.id,
+                    e
+                );
+                result.failed += 1;
+                continue;
+            }
+            None => result.not_found += 1,
+        }
+        queries::mark_album_art_lookup(&conn, album.id, mbid.as_deref())
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(result)
+}
+
+/// Look up art on the Cover Art Archive for albums that have none. Albums
+/// already looked up recently are skipped unless `force` is set. Emits
+/// `album-art-fetched` as covers arrive.
+#[tauri::command]
+pub async fn fetch_missing_album_art(
+    app: tauri::AppHandle,
+    force: Option<bool>,
+    db: State<'_, Database>,
+) -> Result<CoverArtLookupResult, String> {
+    let Some(_running) = RunningGuard::acquire() else {
+        return Err("Cover art lookup is already running".to_string());
+    };
+    let albums = {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        queries::get_albums_for_art_lookup(&conn, force.unwrap_or(false), RETRY_DAYS)
+            .map_err(|e| e.to_string())?
+    };
+
+    fetch_album_art(
+        db.conn.clone(),
+        &CoverArtConfig::from_env(),
+        albums,
+        |fetched| {
+            let _ = app.emit("album-art-fetched", fetched);
+        },
+    )
+    .await
+}
+
+/// After a scan, look up art in the background for albums in folders that
+/// have `fetch_online_art` set
+pub fn spawn_lookup_after_scan(
+    window: &tauri::Window,
+    db_conn: Arc<Mutex<Connection>>,
+    rules: &LibraryRules,
+) {
+    let albums = match db_conn.lock() {
+        Ok(conn) => queries::get_albums_for_art_lookup(&conn, false, RETRY_DAYS),
+        Err(_) => return,
+    };
+    let albums: Vec<ArtLookupAlbum> = match albums {
+        Ok(albums) => albums
+            .into_iter()
+            .filter(|album| rules.fetch_online_art(std::path::Path::new(&album.track_path)))
+            .collect(),
+        Err(e) => {
+            log::warn!("[CoverArt] Failed to list albums without art: {}", e);
+            return;
+        }
+    };
+    if albums.is_empty() {
+        return;
+    }
+
+    let window = window.clone();
+    tauri::async_runtime::spawn(async move {
+        let Some(_running) = RunningGuard::acquire() else {
+            return;
+        };
+        let result = fetch_album_art(db_conn, &CoverArtConfig::from_env(), albums, |fetched| {
+            let _ = window.emit("album-art-fetched", fetched);
+        })
+        .await;
+        match result {
+            Ok(result) => log::info!("[CoverArt] Lookup after scan: {:?}", result),
+            Err(e) => log::warn!("[CoverArt] Lookup after scan failed: {}", e),
+        }
+    });
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Answers every request with `respond(path)` as (status, content type, body)
+    pub(crate) fn mock_server(respond: fn(&str) -> (u16, &'static str, Vec<u8>)) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request);
+                let path = request.split_whitespace().nth(1).unwrap_or("/");
+                let (status, content_type, body) = respond(path);
+                let head = format!(
+                    "HTTP/1.1 {} X\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    content_type,
+                    body.len()
+                );
+                let _ = stream.write_all(head.as_bytes());
+                let _ = stream.write_all(&body);
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    fn png() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image::RgbImage::new(4, 4)
+            .write_to(
+                &mut std::io::Cursor::new(&mut bytes),
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+        bytes
+    }
+
+    #[tokio::test]
+    async fn test_fetch_album_art_from_mock_server() {
+        let dir = std::env::temp_dir().join(format!("audion_caa_{}", std::process::id()));
+        cover_storage::init_app_data_dir(dir.clone());
+
+        let base = mock_server(|path| {
+            if path.starts_with("/ws/2/release?") {
+                let body = r#"{"releases":[{"id":"rel-b","score":100}]}"#;
+                (200, "application/json", body.as_bytes().to_vec())
+            } else if path == "/caa/release/rel-a/front-1200" {
+                (200, "image/png", png())
+            } else {
+                (404, "text/plain", Vec::new())
+            }
+        });
+        let config = CoverArtConfig {
+            musicbrainz_base: format!("{}/ws/2", base),
+            cover_art_base: format!("{}/caa", base),
+            request_interval: Duration::ZERO,
+        };
+
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&conn).unwrap();
+        conn.execute_batch(
+            r#"INSERT INTO albums (id, name, artist) VALUES (1, 'tagged', 'A'), (2, 'searched', 'B');
+               INSERT INTO tracks (path, title, album_id, metadata_json) VALUES
+                   ('/music/a.flac', 'a', 1, '{"MusicBrainzReleaseId":"rel-a"}'),
+                   ('/music/b.flac', 'b', 2, NULL);"#,
+        )
+        .unwrap();
+        let albums = queries::get_albums_for_art_lookup(&conn, false, RETRY_DAYS).unwrap();
+        assert_eq!(albums[0].release_mbid.as_deref(), Some("rel-a"));
+        assert_eq!(albums[1].release_mbid, None);
+
+        let db_conn = Arc::new(Mutex::new(conn));
+        let fetched = Mutex::new(Vec::new());
+        let result = fetch_album_art(db_conn.clone(), &config, albums, |f| {
+            fetched.lock().unwrap().push(f.album_id)
+        })
+        .await
+        .unwrap();
+
+        let conn = db_conn.lock().unwrap();
+        let art_path: Option<String> = conn
+            .query_row("SELECT art_path FROM albums WHERE id = 1", [], |r| r.get(0))
+            .unwrap();
+        let resolved: Option<String> = conn
+            .query_row(
+                "SELECT musicbrainz_release_id FROM albums WHERE id = 2",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        let remaining = queries::get_albums_for_art_lookup(&conn, false, RETRY_DAYS).unwrap();
+        let forced = queries::get_albums_for_art_lookup(&conn, true, RETRY_DAYS).unwrap();
+        let art_exists = art_path
+            .as_deref()
+            .is_some_and(|p| std::path::Path::new(p).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(
+            result,
+            CoverArtLookupResult {
+                fetched: 1,
+                not_found: 1,
+                failed: 0
+            }
+        );
+        assert_eq!(*fetched.lock().unwrap(), vec![1]);
+        assert!(art_exists);
+        assert_eq!(resolved.as_deref(), Some("rel-b"));
+        // Both were looked up, so only a forced run tries again
+        assert!(remaining.is_empty());
+        assert_eq!(forced.len(), 1);
+        assert_eq!(forced[0].release_mbid.as_deref(), Some("rel-b"));
+    }
+
+    #[tokio::test]
+    async fn test_album_is_retried_when_storing_art_fails() {
+        let dir = std::env::temp_dir().join(format!("audion_caa_{}", std::process::id()));
+        cover_storage::init_app_data_dir(dir.clone());
+
+        // The "cover" isn't an image, so storing it fails
+        let base = mock_server(|_| (200, "image/png", b"not an image".to_vec()));
+        let config = CoverArtConfig {
+            musicbrainz_base: format!("{}/ws/2", base),
+            cover_art_base: format!("{}/caa", base),
+            request_interval: Duration::ZERO,
+        };
+
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&conn).unwrap();
+        conn.execute_batch(
+            r#"INSERT INTO albums (id, name, artist) VALUES (1, 'tagged', 'A');
+               INSERT INTO tracks (path, title, album_id, metadata_json) VALUES
+                   ('/music/a.flac', 'a', 1, '{"MusicBrainzReleaseId":"rel-a"}');"#,
+        )
+        .unwrap();
+        let albums = queries::get_albums_for_art_lookup(&conn, false, RETRY_DAYS).unwrap();
+
+        let db_conn = Arc::new(Mutex::new(conn));
+        let result = fetch_album_art(db_conn.clone(), &config, albums, |_| {})
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            CoverArtLookupResult {
+                fetched: 0,
+                not_found: 0,
+                failed: 1
+            }
+        );
+        // Not marked as looked up, so the next run tries again
+        let conn = db_conn.lock().unwrap();
+        let remaining = queries::get_albums_for_art_lookup(&conn, false, RETRY_DAYS).unwrap();
+        assert_eq!(remaining.len(), 1);
+    }
+}
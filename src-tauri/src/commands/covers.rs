@@ -1,12 +1,16 @@
 // Cover management Tauri commands
+use super::journal::{Journal, Operation};
 use crate::db::{queries, Database};
+use crate::db::queries::AlbumPalette;
 use crate::scanner::cover_storage::{
-    cleanup_orphaned_covers, get_album_art_file_path, get_track_cover_file_path,
-    save_album_art_from_base64, save_track_cover_from_base64,
+    cleanup_orphaned_covers, cover_palette, get_album_art_file_path, get_track_cover_file_path,
+    save_album_art_from_base64, save_track_cover_from_base64, update_album_palettes,
 };
+use crate::scanner::thumbnails::{get_thumbnail, ThumbnailSize};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
+use std::path::Path;
 use tauri::State;
 use std::io::Read;
 use sha2::{Sha256, Digest};
@@ -546,6 +550,16 @@ pub async fn merge_duplicate_covers(
         let mut space_saved_bytes = 0u64;
         let mut errors = Vec::new();
 
+        // Merged-away covers are moved into the undo journal rather than deleted
+        let mut journal = match db_conn.lock() {
+            Ok(conn) => Journal::begin(&conn, "Merge duplicate covers"),
+            Err(e) => Err(e.to_string()),
+        }
+        .map_err(|e| errors.push(format!("Failed to start undo journal: {}", e)))
+        .ok();
+        let mut previous_covers: Vec<(i64, String)> = Vec::new();
+        let mut backups = Vec::new();
+
         for album_group in rx.iter() {
 
             for (hash, mut group) in album_group.cover_groups {
@@ -572,6 +586,7 @@ pub async fn merge_duplicate_covers(
                     if let Some(track_ids) = album_group.filepath_to_tracks.get(old_cover_path) {
                         for track_id in track_ids {
                             updates.push((*track_id, canonical_cover.clone()));
+                            previous_covers.push((*track_id, old_cover_path.clone()));
                         }
                         files_to_delete.push((old_cover_path.clone(), *file_size));
                     }
@@ -607,7 +622,14 @@ pub async fn merge_duplicate_covers(
 
                 // Delete duplicate files
                 for (old_cover_path, file_size) in files_to_delete {
-                    match fs::remove_file(&old_cover_path) {
+                    let removed = match journal.as_mut() {
+                        Some(journal) => journal
+                            .backup_file(Path::new(&old_cover_path))
+                            .map(|backup| backups.push(backup))
+                            .or_else(|_| fs::remove_file(&old_cover_path)),
+                        None => fs::remove_file(&old_cover_path),
+                    };
+                    match removed {
                         Ok(_) => {
                             space_saved_bytes += file_size;
                             covers_merged += 1;
@@ -644,6 +666,20 @@ pub async fn merge_duplicate_covers(
             });
         }
 
+        if let Some(journal) = journal {
+            let operation = Operation::CoverMerge {
+                covers: previous_covers,
+                backups,
+            };
+            if let Err(e) = db_conn
+                .lock()
+                .map_err(|e| e.to_string())
+                .and_then(|conn| journal.commit(&conn, &operation))
+            {
+                errors.push(format!("Failed to record merge for undo: {}", e));
+            }
+        }
+
         (covers_merged, space_saved_bytes, errors)
     })
     .await
@@ -924,22 +960,111 @@ pub async fn get_track_cover_path(
     get_track_cover_file_path(&conn, track_id).to_str_err()
 }
 
+/// The thumbnail of a stored cover, or the cover itself if no thumbnail
+/// can be made
+fn thumbnail_or_original(path: String, size: ThumbnailSize) -> String {
+    match get_thumbnail(std::path::Path::new(&path), size) {
+        Ok(thumbnail) => thumbnail.to_string_lossy().to_string(),
+        Err(e) => {
+            log::debug!("[Covers] No thumbnail for {}: {}", path, e);
+            path
+        }
+    }
+}
+
+/// Cover paths for `track_ids`; thumbnails at `size` when given
 #[tauri::command]
 pub async fn get_batch_cover_paths(
     track_ids: Vec<i64>,
+    size: Option<ThumbnailSize>,
     db: State<'_, Database>,
 ) -> Result<HashMap<i64, String>, String> {
-    let conn = db.conn.lock().to_str_err()?;
-    queries::get_batch_cover_paths(&conn, &track_ids).to_str_err()
+    let paths = {
+        let conn = db.conn.lock().to_str_err()?;
+        queries::get_batch_cover_paths(&conn, &track_ids).to_str_err()?
+    };
+    let Some(size) = size else {
+        return Ok(paths);
+    };
+
+    // Generating thumbnails decodes full-size images, so keep it off the
+    // async runtime and outside the database lock
+    tauri::async_runtime::spawn_blocking(move || {
+        paths
+            .into_par_iter()
+            .map(|(id, path)| (id, thumbnail_or_original(path, size)))
+            .collect()
+    })
+    .await
+    .to_str_err()
 }
 
 #[tauri::command]
 pub async fn get_album_art_path(
     album_id: i64,
+    size: Option<ThumbnailSize>,
     db: State<'_, Database>,
 ) -> Result<Option<String>, String> {
+    let path = {
+        let conn = db.conn.lock().to_str_err()?;
+        get_album_art_file_path(&conn, album_id).to_str_err()?
+    };
+    match (path, size) {
+        (Some(path), Some(size)) => tauri::async_runtime::spawn_blocking(move || {
+            Some(thumbnail_or_original(path, size))
+        })
+        .await
+        .to_str_err(),
+        (path, _) => Ok(path),
+    }
+}
+
+/// The colour palette of an album's art, computed now if the art is new.
+/// None when the album has no art or it can't be decoded.
+#[tauri::command]
+pub async fn get_album_palette(
+    album_id: i64,
+    db: State<'_, Database>,
+) -> Result<Option<AlbumPalette>, String> {
+    let art_path = {
+        let conn = db.conn.lock().to_str_err()?;
+        match queries::get_album_palette(&conn, album_id).to_str_err()? {
+            Some((_, Some(palette))) => return Ok(palette),
+            Some((Some(art_path), None)) => art_path,
+            _ => return Ok(None),
+        }
+    };
+
+    let palette = tauri::async_runtime::spawn_blocking(move || {
+        cover_palette(std::path::Path::new(&art_path))
+    })
+    .await
+    .to_str_err()?;
     let conn = db.conn.lock().to_str_err()?;
-    get_album_art_file_path(&conn, album_id).to_str_err()
+    queries::set_album_palette(&conn, album_id, palette.as_ref()).to_str_err()?;
+    Ok(palette)
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PaletteResult {
+    pub computed: usize,
+    pub failed: usize,
+}
+
+/// Compute palettes for album art that doesn't have one yet (all of it when
+/// `force` is set). Scans do this for new art automatically.
+#[tauri::command]
+pub async fn compute_album_palettes(
+    force: Option<bool>,
+    db: State<'_, Database>,
+) -> Result<PaletteResult, String> {
+    let db_conn = db.conn.clone();
+    let (computed, failed) = tauri::async_runtime::spawn_blocking(move || {
+        update_album_palettes(&db_conn, force.unwrap_or(false))
+    })
+    .await
+    .to_str_err()??;
+    Ok(PaletteResult { computed, failed })
 }
 
 #[tauri::command]
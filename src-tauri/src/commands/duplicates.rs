@@ -0,0 +1,93 @@
+// Duplicate detection commands (acoustic fingerprints and the duplicates report)
+use crate::db::{queries, Database};
+use crate::scanner::duplicates::{find_duplicates, DuplicateGroup};
+use crate::scanner::fingerprint::{acoustic_fingerprint, fingerprint_to_blob};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::path::Path;
+use tauri::State;
+
+/// Tracks fingerprinted per database write
+const FINGERPRINT_CHUNK: usize = 64;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FingerprintResult {
+    pub fingerprinted: usize,
+    pub failed: usize,
+}
+
+/// Compute acoustic fingerprints for local tracks that don't have one yet (all of
+/// them when `force` is set). Files that can't be decoded get an empty
+/// fingerprint so they aren't retried on every run.
+#[tauri::command]
+pub async fn compute_acoustic_fingerprints(
+    force: Option<bool>,
+    db: State<'_, Database>,
+) -> Result<FingerprintResult, String> {
+    let tracks = {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        queries::get_tracks_to_fingerprint(&conn, force.unwrap_or(false))
+            .map_err(|e| e.to_string())?
+    };
+
+    let db_conn = db.conn.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut result = FingerprintResult {
+            fingerprinted: 0,
+            failed: 0,
+        };
+
+        for chunk in tracks.chunks(FINGERPRINT_CHUNK) {
+            // Decode without holding the database lock
+            let computed: Vec<(i64, Vec<u8>)> = chunk
+                .par_iter()
+                .map(|(id, path)| {
+                    let blob = acoustic_fingerprint(Path::new(path))
+                        .map(|fp| fingerprint_to_blob(&fp))
+                        .unwrap_or_default();
+                    (*id, blob)
+                })
+                .collect();
+
+            let conn = db_conn.lock().map_err(|e| e.to_string())?;
+            let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+            for (id, blob) in &computed {
+                queries::set_acoustic_fingerprint(&tx, *id, blob).map_err(|e| e.to_string())?;
+                if blob.is_empty() {
+                    result.failed += 1;
+                } else {
+                    result.fingerprinted += 1;
+                }
+            }
+            tx.commit().map_err(|e| e.to_string())?;
+        }
+
+        Ok(result)
+    })
+    .await
+    .map_err(|e| format!("Fingerprinting task failed: {}", e))?
+}
+
+/// Copies of the same recording, grouped, best quality first. Skipped copies
+/// whose files have since disappeared are forgotten.
+#[tauri::command]
+pub async fn get_duplicates_report(db: State<'_, Database>) -> Result<Vec<DuplicateGroup>, String> {
+    let (tracks, skipped) = {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        (
+            queries::get_local_audio_identities(&conn).map_err(|e| e.to_string())?,
+            queries::get_duplicate_files(&conn).map_err(|e| e.to_string())?,
+        )
+    };
+
+    let (skipped, gone): (Vec<_>, Vec<_>) = skipped
+        .into_iter()
+        .partition(|file| Path::new(&file.path).exists());
+    if !gone.is_empty() {
+        let paths: Vec<String> = gone.into_iter().map(|file| file.path).collect();
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        queries::delete_duplicate_files(&conn, &paths).map_err(|e| e.to_string())?;
+    }
+
+    Ok(find_duplicates(&tracks, &skipped))
+}
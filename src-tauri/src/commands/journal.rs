@@ -0,0 +1,422 @@
+//! Undo journal for destructive operations
+//!
+//! Tag edits, track and album deletion and cover merges record what they are
+//! about to change in `operation_journal`: previous tag values, the deleted
+//! database rows and where files were. Files the operation would delete
+//! (covers, replaced embedded pictures) are moved into `journal/{id}` in the
+//! app data directory instead; audio files go to the trash and are restored
+//! from there on platforms whose trash can be read.
+
+use super::tags::{restore_tags, FrontCover};
+use crate::db::queries::{self, JournalEntry, RowSnapshot};
+use crate::db::Database;
+use crate::scanner::cover_storage;
+use lofty::picture::{MimeType, Picture, PictureType};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::State;
+
+/// Entries kept; older ones and their backed-up files are deleted
+const JOURNAL_LIMIT: usize = 50;
+
+/// A file moved into an entry's directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupFile {
+    pub original: String,
+    /// File name in the entry's directory
+    pub backup: String,
+}
+
+/// The front cover of a file before a tag edit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum PreviousCover {
+    /// The edit didn't touch the cover
+    Unchanged,
+    /// There was no front cover
+    Absent,
+    Saved {
+        backup: String,
+        mime_type: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagSnapshot {
+    pub track_id: i64,
+    pub path: String,
+    /// Previous values of the edited fields, empty if a field was unset
+    pub fields: HashMap<String, Vec<String>>,
+    pub cover: PreviousCover,
+}
+
+/// An audio file removed along with its track
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeletedFile {
+    pub path: String,
+    /// In the trash rather than deleted permanently
+    pub trashed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Operation {
+    TagEdit {
+        files: Vec<TagSnapshot>,
+    },
+    Delete {
+        rows: Vec<RowSnapshot>,
+        files: Vec<DeletedFile>,
+        backups: Vec<BackupFile>,
+    },
+    CoverMerge {
+        /// (track id, cover path before the merge)
+        covers: Vec<(i64, String)>,
+        backups: Vec<BackupFile>,
+    },
+}
+
+impl Operation {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::TagEdit { .. } => "tag_edit",
+            Self::Delete { .. } => "delete",
+            Self::CoverMerge { .. } => "cover_merge",
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Self::TagEdit { files } => files.is_empty(),
+            Self::Delete { rows, .. } => rows.is_empty(),
+            Self::CoverMerge { covers, backups } => covers.is_empty() && backups.is_empty(),
+        }
+    }
+}
+
+fn journal_directory() -> Result<PathBuf, String> {
+    Ok(cover_storage::get_app_data_directory()?.join("journal"))
+}
+
+/// Remove every backed-up file, once the journal entries are gone
+pub fn clear_backups() {
+    if let Ok(dir) = journal_directory() {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+/// Move a file, copying it when it's on another device
+fn move_file(from: &Path, to: &Path) -> Result<(), String> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)
+        .and_then(|_| fs::remove_file(from))
+        .map_err(|e| format!("Failed to move {:?} to {:?}: {}", from, to, e))
+}
+
+/// An operation being recorded. The entry is inserted up front so backups have
+/// a directory; it is filled in by [`Journal::commit`].
+pub struct Journal {
+    id: i64,
+    dir: PathBuf,
+    backups: usize,
+}
+
+impl Journal {
+    pub fn begin(conn: &Connection, description: &str) -> Result<Self, String> {
+        let id = queries::insert_journal_entry(conn, "pending", description, "{}")
+            .map_err(|e| e.to_string())?;
+        Ok(Journal {
+            id,
+            dir: journal_directory()?.join(id.to_string()),
+            backups: 0,
+        })
+    }
+
+    fn backup_path(&mut self, name: &str) -> Result<(String, PathBuf), String> {
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create journal directory: {}", e))?;
+        self.backups += 1;
+        let backup = format!("{}-{}", self.backups, name);
+        let path = self.dir.join(&backup);
+        Ok((backup, path))
+    }
+
+    /// Move `path` into the journal instead of deleting it
+    pub fn backup_file(&mut self, path: &Path) -> Result<BackupFile, String> {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let (backup, target) = self.backup_path(&name)?;
+        move_file(path, &target)?;
+        Ok(BackupFile {
+            original: path.to_string_lossy().to_string(),
+            backup,
+        })
+    }
+
+    /// Keep a file's current front cover before it is replaced or removed
+    pub fn backup_cover(&mut self, cover: Option<&Picture>) -> Result<PreviousCover, String> {
+        let Some(picture) = cover else {
+            return Ok(PreviousCover::Absent);
+        };
+        let (backup, path) = self.backup_path("cover")?;
+        fs::write(&path, picture.data()).map_err(|e| format!("Failed to save cover: {}", e))?;
+        Ok(PreviousCover::Saved {
+            backup,
+            mime_type: picture.mime_type().map(|m| m.as_str().to_string()),
+        })
+    }
+
+    /// Store the operation, or drop the entry if it changed nothing, then
+    /// apply the retention limit
+    pub fn commit(self, conn: &Connection, operation: &Operation) -> Result<(), String> {
+        if operation.is_empty() {
+            return self.discard(conn);
+        }
+        let data = serde_json::to_string(operation).map_err(|e| e.to_string())?;
+        queries::update_journal_entry(conn, self.id, operation.kind(), &data)
+            .map_err(|e| e.to_string())?;
+
+        let pruned = queries::prune_journal(conn, JOURNAL_LIMIT).map_err(|e| e.to_string())?;
+        let dir = journal_directory()?;
+        for id in pruned {
+            let _ = fs::remove_dir_all(dir.join(id.to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn discard(self, conn: &Connection) -> Result<(), String> {
+        let _ = fs::remove_dir_all(&self.dir);
+        queries::delete_journal_entry(conn, self.id).map_err(|e| e.to_string())
+    }
+
+    /// Put files back after a deletion failed part way, then drop the entry
+    pub fn roll_back(self, conn: &Connection, files: &[DeletedFile], backups: &[BackupFile]) {
+        let mut errors = Vec::new();
+        for file in files.iter().filter(|file| file.trashed) {
+            let path = Path::new(&file.path);
+            if !path.exists() {
+                if let Err(e) = crate::security::restore_from_trash(path) {
+                    errors.push(e);
+                }
+            }
+        }
+        restore_backups(&self.dir, backups, &mut errors);
+        if let Err(e) = self.discard(conn) {
+            errors.push(e);
+        }
+        for error in errors {
+            log::warn!("[JOURNAL] {}", error);
+        }
+    }
+}
+
+/// Put backed-up files back where they were. Files whose place is taken are
+/// left in the journal.
+fn restore_backups(dir: &Path, backups: &[BackupFile], errors: &mut Vec<String>) -> usize {
+    let mut restored = 0;
+    for file in backups {
+        let original = Path::new(&file.original);
+        if original.exists() {
+            continue;
+        }
+        if let Some(parent) = original.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        match move_file(&dir.join(&file.backup), original) {
+            Ok(()) => restored += 1,
+            Err(e) => errors.push(e),
+        }
+    }
+    restored
+}
+
+fn undo_tag_edit(
+    conn: &std::sync::Mutex<Connection>,
+    dir: &Path,
+    files: &[TagSnapshot],
+    errors: &mut Vec<String>,
+) -> usize {
+    let mut restored = 0;
+    for file in files {
+        let cover = match &file.cover {
+            PreviousCover::Unchanged => FrontCover::Keep,
+            PreviousCover::Absent => FrontCover::Remove,
+            PreviousCover::Saved { backup, mime_type } => match fs::read(dir.join(backup)) {
+                Ok(data) => FrontCover::Set(Picture::new_unchecked(
+                    PictureType::CoverFront,
+                    mime_type.as_deref().map(MimeType::from_str),
+                    None,
+                    data,
+                )),
+                Err(e) => {
+                    errors.push(format!("{}: previous cover is missing: {}", file.path, e));
+                    continue;
+                }
+            },
+        };
+        match restore_tags(conn, file.track_id, &file.fields, cover) {
+            Ok(()) => restored += 1,
+            Err(e) => errors.push(format!("{}: {}", file.path, e)),
+        }
+    }
+    restored
+}
+
+fn undo_delete(
+    conn: &Connection,
+    dir: &Path,
+    rows: &[RowSnapshot],
+    files: &[DeletedFile],
+    backups: &[BackupFile],
+) -> Result<usize, String> {
+    // Audio files come back first: without them the rows would be restored as
+    // missing tracks
+    for file in files {
+        let path = Path::new(&file.path);
+        if path.exists() {
+            continue;
+        }
+        if !file.trashed {
+            return Err(format!("{} was deleted permanently", file.path));
+        }
+        crate::security::restore_from_trash(path)?;
+    }
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    queries::restore_rows(&tx, rows)
+        .map_err(|e| format!("Failed to restore library entries: {}", e))?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    let mut errors = Vec::new();
+    restore_backups(dir, backups, &mut errors);
+    for error in errors {
+        log::warn!("[JOURNAL] {}", error);
+    }
+
+    let track_ids = rows
+        .iter()
+        .filter(|row| row.table == "tracks")
+        .filter_map(|row| match row.values.first() {
+            Some(queries::StoredValue::Integer(id)) => Some(*id),
+            _ => None,
+        });
+    let mut restored = 0;
+    for track_id in track_ids {
+        if let Ok(Some(track)) = queries::get_track_by_id(conn, track_id) {
+            let _ = queries::enqueue_track_sync_change(conn, &track, "create");
+            restored += 1;
+        }
+    }
+    Ok(restored)
+}
+
+fn undo_cover_merge(
+    conn: &Connection,
+    dir: &Path,
+    covers: &[(i64, String)],
+    backups: &[BackupFile],
+    errors: &mut Vec<String>,
+) -> Result<usize, String> {
+    restore_backups(dir, backups, errors);
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let mut restored = 0;
+    for (track_id, path) in covers {
+        if Path::new(path).exists() {
+            queries::update_track_cover_path(&tx, *track_id, Some(path))
+                .map_err(|e| e.to_string())?;
+            restored += 1;
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(restored)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UndoResult {
+    pub entry: JournalEntry,
+    /// Files or tracks put back
+    pub restored: usize,
+    /// Parts that couldn't be undone; the entry stays undoable if there are any
+    pub errors: Vec<String>,
+}
+
+/// Undo an operation from the journal (the most recent one when `entry_id` is
+/// not given)
+fn undo(conn: &std::sync::Mutex<Connection>, entry_id: Option<i64>) -> Result<UndoResult, String> {
+    let (entry, data) = {
+        let conn = conn.lock().map_err(|e| e.to_string())?;
+        queries::get_journal_entry(&conn, entry_id)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| "Nothing to undo".to_string())?
+    };
+    if entry.undone_at.is_some() {
+        return Err("This operation was already undone".to_string());
+    }
+    let operation: Operation =
+        serde_json::from_str(&data).map_err(|_| "This operation can't be undone".to_string())?;
+    let dir = journal_directory()?.join(entry.id.to_string());
+
+    let mut errors = Vec::new();
+    let restored = match &operation {
+        // Tag edits write files, so they take the lock per file
+        Operation::TagEdit { files } => undo_tag_edit(conn, &dir, files, &mut errors),
+        Operation::Delete {
+            rows,
+            files,
+            backups,
+        } => {
+            let conn = conn.lock().map_err(|e| e.to_string())?;
+            undo_delete(&conn, &dir, rows, files, backups)?
+        }
+        Operation::CoverMerge { covers, backups } => {
+            let conn = conn.lock().map_err(|e| e.to_string())?;
+            undo_cover_merge(&conn, &dir, covers, backups, &mut errors)?
+        }
+    };
+
+    let conn = conn.lock().map_err(|e| e.to_string())?;
+    if errors.is_empty() {
+        queries::mark_journal_entry_undone(&conn, entry.id).map_err(|e| e.to_string())?;
+        let _ = fs::remove_dir_all(&dir);
+    }
+    let entry = queries::get_journal_entry(&conn, Some(entry.id))
+        .map_err(|e| e.to_string())?
+        .map_or(entry, |(entry, _)| entry);
+    log::info!(
+        "[AUDIT] Undid journal entry {} ({})",
+        entry.id,
+        entry.description
+    );
+    Ok(UndoResult {
+        entry,
+        restored,
+        errors,
+    })
+}
+
+#[tauri::command]
+pub async fn get_operation_journal(
+    limit: Option<usize>,
+    db: State<'_, Database>,
+) -> Result<Vec<JournalEntry>, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    queries::get_journal_entries(&conn, limit.unwrap_or(JOURNAL_LIMIT)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn undo_operation(
+    entry_id: Option<i64>,
+    db: State<'_, Database>,
+) -> Result<UndoResult, String> {
+    let conn = db.conn.clone();
+    tauri::async_runtime::spawn_blocking(move || undo(&conn, entry_id))
+        .await
+        .map_err(|e| e.to_string())?
+}
@@ -1,13 +1,20 @@
 // Library-related Tauri commands
-use crate::db::{queries, Database};
-use crate::scanner::{cover_storage, extract_metadata, scan_directory};
+use super::journal::{BackupFile, DeletedFile, Journal, Operation};
+use crate::db::queries::{self, ScanIssue};
+use crate::db::Database;
+use crate::scanner::relink::{self, ExtractedFiles, RelinkedTrack};
+use crate::scanner::walker::{self, file_fingerprint, LibraryRules};
+use crate::scanner::cover_storage;
+use crate::scanner::metadata::extract_metadata_with_issues;
 use crate::security;
 use base64::{engine::general_purpose::STANDARD, Engine};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tauri::Emitter;
+use tauri::Manager;
 use tauri::State;
 
 /// Emitted per-batch during progressive rescan so the frontend can render
@@ -38,10 +45,89 @@ pub enum ScanSource {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ScanResult {
+    /// New files imported
     pub tracks_added: usize,
+    /// Known files whose tags were re-read because they changed
     pub tracks_updated: usize,
+    /// Tracks removed because their file is gone or now excluded by folder settings
     pub tracks_deleted: usize,
-    pub errors: Vec<String>,
+    /// Known files left alone because their size and mtime are unchanged
+    #[serde(default)]
+    pub tracks_skipped: usize,
+    /// Missing tracks that were matched to their moved file and kept
+    #[serde(default)]
+    pub relinked: Vec<RelinkedTrack>,
+    /// Files that failed some step; also kept in the `scan_issues` table
+    pub errors: Vec<ScanIssue>,
+}
+
+impl ScanResult {
+    /// Counts gathered before the import phase (cleanup, skipped files, walk errors)
+    fn before_import(
+        tracks_deleted: usize,
+        tracks_skipped: usize,
+        relinked: Vec<RelinkedTrack>,
+        errors: Vec<ScanIssue>,
+    ) -> Self {
+        Self {
+            tracks_added: 0,
+            tracks_updated: 0,
+            tracks_deleted,
+            tracks_skipped,
+            relinked,
+            errors,
+        }
+    }
+}
+
+/// Scan rules for the registered music folders
+pub(crate) fn load_scan_rules(conn: &rusqlite::Connection) -> Result<LibraryRules, String> {
+    queries::get_music_folders_with_settings(conn)
+        .map(LibraryRules::new)
+        .map_err(|e| e.to_string())
+}
+
+/// Relink missing tracks whose files moved to one of `discovered`, then delete
+/// the tracks that are still missing (or excluded by `rules`) and the issues
+/// of files that are gone.
+/// Returns (deleted count, relinked tracks, files read while matching).
+fn relink_and_cleanup(
+    db: &Database,
+    folders: &[String],
+    discovered: &[String],
+    rules: &LibraryRules,
+) -> Result<(usize, Vec<RelinkedTrack>, ExtractedFiles), String> {
+    let (mut missing, new_files) = {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        queries::dismiss_missing_scan_issues(&conn, folders).map_err(|e| e.to_string())?;
+        let missing = queries::get_missing_tracks(&conn, folders, |track| {
+            let path = std::path::Path::new(&track.path);
+            rules.excludes_file(path) || rules.too_short(path, track.duration)
+        })
+        .map_err(|e| e.to_string())?;
+        if missing.is_empty() {
+            return Ok((0, Vec::new(), ExtractedFiles::new()));
+        }
+        let known = queries::get_track_paths(&conn).map_err(|e| e.to_string())?;
+        let new_files: Vec<String> = discovered
+            .iter()
+            .filter(|p| !known.contains(*p))
+            .cloned()
+            .collect();
+        (missing, new_files)
+    };
+
+    // Stats and tag reads happen without the DB lock
+    let (relinked, extracted) = relink::plan_relinks(&mut missing, &new_files);
+
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let deleted = relink::apply_relinks(&tx, &relinked, &missing)
+        .map_err(|e| format!("Failed to cleanup deleted tracks: {}", e))?;
+    tx.commit().map_err(|e| e.to_string())?;
+    let _ = queries::cleanup_empty_albums(&conn);
+
+    Ok((deleted, relinked, extracted))
 }
 
 #[tauri::command]
@@ -91,17 +177,21 @@ pub async fn begin_folder_import(
         .map(|n| n.to_string_lossy().to_string())
         .unwrap_or_else(|| folder_str.clone());
 
-    let playlist_id = {
+    let (playlist_id, rules) = {
         let conn = db.conn.lock().map_err(|e| e.to_string())?;
         let id = queries::create_playlist(&conn, &playlist_name, None)
             .map_err(|e| e.to_string())?;
         queries::set_playlist_folder_path(&conn, id, &folder_str)
             .map_err(|e| e.to_string())?;
         let _ = queries::register_music_folder(&conn, &folder_str);
-        id
+        (id, Arc::new(load_scan_rules(&conn)?))
     };
 
-    let scan_result = scan_directory(&folder_str);
+    if let Err(e) = super::watcher::restart_library_watcher(window.app_handle()) {
+        log::warn!("[Watcher] Failed to restart: {}", e);
+    }
+
+    let scan_result = rules.scan(&folder_str);
     let all_files = scan_result.audio_files;
     let scan_errors = scan_result.errors;
 
@@ -120,9 +210,10 @@ pub async fn begin_folder_import(
             db_conn,
             all_files,
             file_playlist_map,
-            0,
-            scan_errors,
+            ScanResult::before_import(0, 0, Vec::new(), scan_errors),
+            ExtractedFiles::new(),
             vec![folder_str],
+            rules,
             ScanSource::FolderImport(playlist_id),
         )
         .await;
@@ -231,9 +322,7 @@ async fn handle_track_import(
                 .unwrap_or(false);
 
             if !has_art {
-                if let Ok(p) = cover_storage::save_album_art(id, art_bytes) {
-                    let _ = queries::update_album_art_path(&conn, id, Some(&p));
-                }
+                let _ = cover_storage::store_album_art(&conn, id, art_bytes);
             }
         }
     }
@@ -317,81 +406,70 @@ pub async fn scan_music(paths: Vec<String>, db: State<'_, Database>) -> Result<S
     // Use spawn_blocking for the file system scanning and metadata extraction
     // This prevents blocking the Tauri async executor's threads
     let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+    // Every audio file found, for relinking moved tracks during cleanup
+    let discovered = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let rules = {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        Arc::new(load_scan_rules(&conn)?)
+    };
 
     for path in paths.clone() {
         let db_clone = db.inner().clone();
         let path_clone = path.clone();
         let tx_clone = tx.clone();
+        let discovered_clone = Arc::clone(&discovered);
+        let rules_clone = Arc::clone(&rules);
 
         tokio::task::spawn_blocking(move || {
-            let scan_result = scan_directory(&path_clone);
+            let scan_result = rules_clone.scan(&path_clone);
+            if let Ok(mut found) = discovered_clone.lock() {
+                found.extend(scan_result.audio_files.iter().cloned());
+            }
             let conn = db_clone.conn.lock().unwrap();
 
             // Add folder to database
             let _ = queries::add_music_folder(&conn, &path_clone);
 
+            let mut issues = scan_result.errors;
+            let mut processed = HashSet::new();
             for file_path in scan_result.audio_files {
-                if let Some(track_data) = extract_metadata(&file_path) {
-                    match queries::insert_or_update_track(&conn, &track_data) {
-                        Ok((track_id, was_new)) => {
-                            if track_id > 0 {
-                                // Track the operation type
-                                let result = if was_new { 1 } else { 0 };
-
-                                // Save track cover if present
-                                if let Some(ref cover_bytes) = track_data.track_cover {
-                                    let _ = cover_storage::save_track_cover(track_id, cover_bytes)
-                                        .map(|p| {
-                                            let _ = queries::update_track_cover_path(
-                                                &conn,
-                                                track_id,
-                                                Some(&p),
-                                            );
-                                        });
-                                }
-
-                                // Save album art if present and album doesn't have one
-                                if let Some(album_id) = track_data.album.as_ref().and_then(|_| {
-                                    conn.query_row(
-                                        "SELECT album_id FROM tracks WHERE id = ?1",
-                                        [track_id],
-                                        |row| row.get::<_, Option<i64>>(0),
-                                    )
-                                    .ok()
-                                    .flatten()
-                                }) {
-                                    if let Some(ref art_bytes) = track_data.album_art {
-                                        let has_art: bool = conn
-                                            .query_row(
-                                                "SELECT art_path IS NOT NULL FROM albums WHERE id = ?1",
-                                                [album_id],
-                                                |row| row.get(0),
-                                            )
-                                            .unwrap_or(false);
-
-                                        if !has_art {
-                                            let _ =
-                                                cover_storage::save_album_art(album_id, art_bytes)
-                                                    .map(|p| {
-                                                        let _ = queries::update_album_art_path(
-                                                            &conn,
-                                                            album_id,
-                                                            Some(&p),
-                                                        );
-                                                    });
-                                        }
-                                    }
-                                }
-
-                                let _ = tx_clone.blocking_send(Ok((result, 0)));
-                            }
-                        }
-                        Err(e) => {
-                            let _ = tx_clone.blocking_send(Err(e.to_string()));
+                let (track_data, file_issues) = extract_metadata_with_issues(&file_path);
+                processed.insert(file_path.clone());
+                let Some(track_data) = track_data else {
+                    issues.extend(file_issues);
+                    continue;
+                };
+                if rules_clone.too_short(std::path::Path::new(&file_path), track_data.duration) {
+                    continue;
+                }
+                issues.extend(file_issues);
+                match queries::insert_or_update_track(&conn, &track_data) {
+                    Ok((track_id, was_new)) => {
+                        if track_id > 0 {
+                            // Track the operation type
+                            let result = if was_new { 1 } else { 0 };
+
+                            let (_, cover_issues) = cover_storage::save_scanned_artwork(
+                                &conn,
+                                track_id,
+                                &track_data,
+                                &rules_clone,
+                            );
+                            issues.extend(cover_issues);
+
+                            let _ = tx_clone.blocking_send(Ok((result, 0)));
                         }
                     }
+                    Err(e) => issues.push(ScanIssue::database(&file_path, e)),
                 }
             }
+            let walked = std::slice::from_ref(&path_clone);
+            if let Err(e) = queries::record_scan_results(&conn, walked, &processed, &issues) {
+                log::warn!("[Scanner] Failed to record scan issues: {}", e);
+            }
+            for issue in issues {
+                let _ = tx_clone.blocking_send(Err(issue));
+            }
             let _ = queries::update_folder_last_scanned(&conn, &path_clone);
         });
     }
@@ -408,25 +486,32 @@ pub async fn scan_music(paths: Vec<String>, db: State<'_, Database>) -> Result<S
         }
     }
 
-    // Cleanup after scan
-    let conn = db.conn.lock().map_err(|e| e.to_string())?;
-    let tracks_deleted = queries::cleanup_deleted_tracks(&conn, &paths).unwrap_or_else(|e| {
-        errors.push(format!("Failed to cleanup deleted tracks: {}", e));
-        0
-    });
-    let _ = queries::cleanup_empty_albums(&conn);
+    // Cleanup after scan. Moved files were skipped above as duplicates of their
+    // old track, so relinking picks them up here instead of losing the track.
+    let discovered = discovered.lock().map(|d| d.clone()).unwrap_or_default();
+    let (tracks_deleted, relinked, _) = relink_and_cleanup(db.inner(), &paths, &discovered, &rules)
+        .unwrap_or_else(|e| {
+            errors.extend(paths.iter().map(|path| ScanIssue::database(path, &e)));
+            (0, Vec::new(), ExtractedFiles::new())
+        });
 
     Ok(ScanResult {
         tracks_added,
         tracks_updated,
         tracks_deleted,
+        tracks_skipped: 0,
+        relinked,
         errors,
     })
 }
 
 /// Add a music folder with path validation
 #[tauri::command]
-pub async fn add_folder(path: String, db: State<'_, Database>) -> Result<(), String> {
+pub async fn add_folder(
+    app: tauri::AppHandle,
+    path: String,
+    db: State<'_, Database>,
+) -> Result<(), String> {
     let path_buf = std::path::PathBuf::from(&path);
 
     // Validate path exists and is a directory
@@ -445,9 +530,15 @@ pub async fn add_folder(path: String, db: State<'_, Database>) -> Result<(), Str
 
     let path_str = canonical_path.to_string_lossy().to_string();
 
-    let conn = db.conn.lock().map_err(|e| e.to_string())?;
-    queries::register_music_folder(&conn, &path_str)
-        .map_err(|e| format!("Failed to add folder: {}", e))?;
+    {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        queries::register_music_folder(&conn, &path_str)
+            .map_err(|e| format!("Failed to add folder: {}", e))?;
+    }
+
+    if let Err(e) = super::watcher::restart_library_watcher(&app) {
+        log::warn!("[Watcher] Failed to restart: {}", e);
+    }
 
     Ok(())
 }
@@ -455,7 +546,11 @@ pub async fn add_folder(path: String, db: State<'_, Database>) -> Result<(), Str
 /// Replace existing music folders with a single validated folder.
 /// Useful on Android where users want strict one-folder scanning.
 #[tauri::command]
-pub async fn set_single_music_folder(path: String, db: State<'_, Database>) -> Result<(), String> {
+pub async fn set_single_music_folder(
+    app: tauri::AppHandle,
+    path: String,
+    db: State<'_, Database>,
+) -> Result<(), String> {
     if path.starts_with("content://") {
         return Err(
             "Invalid folder path: content URIs are not supported for scanning yet".to_string(),
@@ -478,12 +573,69 @@ pub async fn set_single_music_folder(path: String, db: State<'_, Database>) -> R
 
     let path_str = canonical_path.to_string_lossy().to_string();
 
+    {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        conn.execute("DELETE FROM music_folders", [])
+            .map_err(|e| format!("Failed to clear existing folders: {}", e))?;
+
+        queries::register_music_folder(&conn, &path_str)
+            .map_err(|e| format!("Failed to set music folder: {}", e))?;
+    }
+
+    if let Err(e) = super::watcher::restart_library_watcher(&app) {
+        log::warn!("[Watcher] Failed to restart: {}", e);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct MusicFolderSettings {
+    pub path: String,
+    pub settings: queries::FolderScanSettings,
+}
+
+/// Registered music folders with their scan settings
+#[tauri::command]
+pub async fn get_music_folder_settings(
+    db: State<'_, Database>,
+) -> Result<Vec<MusicFolderSettings>, String> {
     let conn = db.conn.lock().map_err(|e| e.to_string())?;
-    conn.execute("DELETE FROM music_folders", [])
-        .map_err(|e| format!("Failed to clear existing folders: {}", e))?;
+    let folders = queries::get_music_folders_with_settings(&conn).map_err(|e| e.to_string())?;
+    Ok(folders
+        .into_iter()
+        .map(|(path, settings)| MusicFolderSettings { path, settings })
+        .collect())
+}
+
+/// Change how a music folder is scanned. Files already in the library that
+/// the new settings exclude are removed on the next rescan.
+#[tauri::command]
+pub async fn set_music_folder_settings(
+    app: tauri::AppHandle,
+    path: String,
+    settings: queries::FolderScanSettings,
+    db: State<'_, Database>,
+) -> Result<(), String> {
+    walker::validate_exclude_patterns(&settings.exclude_patterns)?;
+    walker::validate_cover_art_names(&settings.cover_art_names)?;
+    if settings.min_duration.is_some_and(|d| d < 0) {
+        return Err("Minimum duration cannot be negative".to_string());
+    }
 
-    queries::register_music_folder(&conn, &path_str)
-        .map_err(|e| format!("Failed to set music folder: {}", e))?;
+    {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        let updated = queries::set_music_folder_settings(&conn, &path, &settings)
+            .map_err(|e| format!("Failed to save folder settings: {}", e))?;
+        if !updated {
+            return Err(format!("Not a music folder: {}", path));
+        }
+    }
+
+    // The watcher applies the exclusions to live changes
+    if let Err(e) = super::watcher::restart_library_watcher(&app) {
+        log::warn!("[Watcher] Failed to restart: {}", e);
+    }
 
     Ok(())
 }
@@ -493,25 +645,38 @@ async fn run_scan_and_import(
     db_conn: Arc<std::sync::Mutex<rusqlite::Connection>>,
     all_files: Vec<String>,
     file_playlist_map: std::collections::HashMap<String, Vec<i64>>,
-    tracks_deleted: usize,
-    scan_errors: Vec<String>,
+    summary: ScanResult, // deleted/skipped/relinked counts and walk errors
+    extracted: ExtractedFiles, // files relinking already read, not read again
     folders: Vec<String>, // used for timestamp update after batch
+    rules: Arc<LibraryRules>, // minimum durations and folder art names
     source: ScanSource,
 ) -> Result<ScanResult, String> {
     let total_files = all_files.len();
     let total_start = std::time::Instant::now();
  
     if total_files == 0 {
-        let result = ScanResult {
-            tracks_added: 0,
-            tracks_updated: 0,
-            tracks_deleted,
-            errors: scan_errors,
-        };
+        // Still record the walk, so issues it no longer runs into are cleared,
+        // and pick up folder art added since the last scan
+        if let Ok(conn) = db_conn.lock() {
+            if let Err(e) = cover_storage::fill_missing_album_art(&conn, &rules) {
+                log::warn!("[Scanner] Failed to add folder art: {}", e);
+            }
+            if let Err(e) =
+                queries::record_scan_results(&conn, &folders, &HashSet::new(), &summary.errors)
+            {
+                log::warn!("[Scanner] Failed to record scan issues: {}", e);
+            }
+        }
+        super::cover_art::spawn_lookup_after_scan(window, db_conn, &rules);
         let (complete_event, _) = event_names(&source);
-        let _ = window.emit(complete_event, &result);
-        return Ok(result);
+        let _ = window.emit(complete_event, &summary);
+        return Ok(summary);
     }
+
+    // Every file is read again, so its earlier issues are replaced
+    let processed: HashSet<String> = all_files.iter().cloned().collect();
+    let extraction_issues = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let extraction_issues_clone = Arc::clone(&extraction_issues);
  
     // Parallel metadata extraction
     let (tx, rx): (
@@ -520,10 +685,24 @@ async fn run_scan_and_import(
     ) = crossbeam::channel::bounded(500);
     let extracted_count = Arc::new(std::sync::atomic::AtomicUsize::new(0));
     let extracted_count_clone = extracted_count.clone();
+    let db_rules = Arc::clone(&rules);
+    let lookup_rules = Arc::clone(&rules);
 
     std::thread::spawn(move || {
         all_files.par_iter().for_each(|file_path| {
-            if let Some(track_data) = extract_metadata(file_path) {
+            let (track_data, issues) = match extracted.get(file_path) {
+                Some((track_data, issues)) => (Some(track_data.clone()), issues.clone()),
+                None => extract_metadata_with_issues(file_path),
+            };
+            let too_short = track_data.as_ref().is_some_and(|track_data| {
+                rules.too_short(std::path::Path::new(file_path), track_data.duration)
+            });
+            if !issues.is_empty() && !too_short {
+                if let Ok(mut found) = extraction_issues_clone.lock() {
+                    found.extend(issues);
+                }
+            }
+            if let Some(track_data) = track_data.filter(|_| !too_short) {
                 let _ = tx.send(track_data);
             }
             // increment regardless of success so the receiver loop exits cleanly
@@ -533,6 +712,9 @@ async fn run_scan_and_import(
  
     let window_clone = window.clone();
     let source_clone = source.clone();
+    let summary_errors = summary.errors.clone();
+    let palette_conn = Arc::clone(&db_conn);
+    let lookup_conn = Arc::clone(&db_conn);
  
     let batch_result = tauri::async_runtime::spawn_blocking(move || {
         let mut tracks_added = 0usize;
@@ -548,7 +730,12 @@ async fn run_scan_and_import(
         let mut conn = match db_conn.lock() {
             Ok(c) => c,
             Err(e) => {
-                return (0, 0, 0, vec![format!("Failed to acquire DB lock: {}", e)]);
+                let message = format!("Failed to acquire DB lock: {}", e);
+                let errors = folders
+                    .iter()
+                    .map(|folder| ScanIssue::database(folder, &message))
+                    .collect();
+                return (0, 0, 0, errors);
             }
         };
  
@@ -576,7 +763,12 @@ async fn run_scan_and_import(
             let tx_db = match conn.transaction() {
                 Ok(t) => t,
                 Err(e) => {
-                    errors.push(format!("Failed to begin transaction: {}", e));
+                    let message = format!("Failed to begin transaction: {}", e);
+                    errors.extend(
+                        pending
+                            .iter()
+                            .map(|t| ScanIssue::database(&t.path, &message)),
+                    );
                     break;
                 }
             };
@@ -592,74 +784,23 @@ async fn run_scan_and_import(
                             batch_updated += 1;
                         }
 
-                        // Save track cover
-                        let cover_path = track_data.track_cover.as_ref().and_then(|bytes| {
-                            cover_storage::save_track_cover(track_id, bytes).ok()
-                        });
-
-                        if let Some(ref path) = cover_path {
-                            if let Err(e) =
-                                queries::update_track_cover_path(&tx_db, track_id, Some(path))
-                            {
-                                errors.push(format!(
-                                    "Cover path update failed for track {}: {}",
-                                    track_id, e
-                                ));
-                            }
-                        }
-
-                        // Save album art (only if the album doesn't have one yet)
-                        if let Some(album_id) = track_data.album.as_ref().and_then(|_| {
-                            tx_db
-                                .query_row(
-                                    "SELECT album_id FROM tracks WHERE id = ?1",
-                                    [track_id],
-                                    |row| row.get::<_, Option<i64>>(0),
-                                )
-                                .ok()
-                                .flatten()
-                        }) {
-                            if let Some(ref art_bytes) = track_data.album_art {
-                                let has_art: bool = tx_db
-                                    .query_row(
-                                        "SELECT art_path IS NOT NULL FROM albums WHERE id = ?1",
-                                        [album_id],
-                                        |row| row.get(0),
-                                    )
-                                    .unwrap_or(false);
-
-                                if !has_art {
-                                    match cover_storage::save_album_art(album_id, art_bytes) {
-                                        Ok(art_path) => {
-                                            if let Err(e) = queries::update_album_art_path(
-                                                &tx_db,
-                                                album_id,
-                                                Some(&art_path),
-                                            ) {
-                                                errors.push(format!(
-                                                    "Art path update failed for album {}: {}",
-                                                    album_id, e
-                                                ));
-                                            }
-                                        }
-                                        Err(e) => errors.push(format!(
-                                            "Album art save failed for album {}: {}",
-                                            album_id, e
-                                        )),
-                                    }
-                                }
-                            }
-                        }
+                        let (cover_path, cover_issues) = cover_storage::save_scanned_artwork(
+                            &tx_db,
+                            track_id,
+                            track_data,
+                            &db_rules,
+                        );
+                        errors.extend(cover_issues);
  
                         // Update playlist membership
                         if let Some(playlist_ids) = file_playlist_map.get(&track_data.path) {
                             for playlist_id in playlist_ids {
                                 if let Err(e) =
-                                    queries::add_track_to_playlist(&tx_db, *playlist_id, track_id)
+                                    queries::ensure_track_in_playlist(&tx_db, *playlist_id, track_id)
                                 {
-                                    errors.push(format!(
-                                        "Failed to add track {} to playlist {}: {}",
-                                        track_id, playlist_id, e
+                                    errors.push(ScanIssue::database(
+                                        &track_data.path,
+                                        format!("Failed to add to playlist {}: {}", playlist_id, e),
                                     ));
                                 }
                             }
@@ -702,7 +843,7 @@ async fn run_scan_and_import(
                         });
                     }
                     Ok(_) => {}
-                    Err(e) => errors.push(format!("Insert failed for {}: {}", track_data.path, e)),
+                    Err(e) => errors.push(ScanIssue::database(&track_data.path, e)),
                 }
             }
  
@@ -710,7 +851,12 @@ async fn run_scan_and_import(
             tracks_inserted = 0; // reset for next batch
 
             if let Err(e) = tx_db.commit() {
-                errors.push(format!("Failed to commit batch transaction: {}", e));
+                let message = format!("Failed to commit batch transaction: {}", e);
+                errors.extend(
+                    pending
+                        .iter()
+                        .map(|t| ScanIssue::database(&t.path, &message)),
+                );
                 // Don't advance tracks_sent .these weren't persisted
             } else {
                 tracks_sent += tracks_processed;
@@ -767,26 +913,51 @@ async fn run_scan_and_import(
             }
         }
  
+        // Albums whose art is only in their folder, including unchanged ones
+        if let Err(e) = cover_storage::fill_missing_album_art(&conn, &db_rules) {
+            log::warn!("[Scanner] Failed to add folder art: {}", e);
+        }
+
         // Update folder timestamps for all scanned folders
         for folder in &folders {
             if let Err(e) = queries::update_folder_last_scanned(&conn, folder) {
-                errors.push(format!("Scan time update failed for {}: {}", folder, e));
+                errors.push(ScanIssue::database(
+                    folder,
+                    format!("Scan time update failed: {}", e),
+                ));
             }
         }
 
+        if let Ok(mut found) = extraction_issues.lock() {
+            errors.append(&mut found);
+        }
+        let mut issues = summary_errors;
+        issues.extend(errors.iter().cloned());
+        if let Err(e) = queries::record_scan_results(&conn, &folders, &processed, &issues) {
+            log::warn!("[Scanner] Failed to record scan issues: {}", e);
+        }
+
         (tracks_added, tracks_updated, batches_sent, errors)
     })
     .await
     .map_err(|e| e.to_string())?;
 
     let (tracks_added, tracks_updated, _batches_sent, mut errors) = batch_result;
-    errors.extend(scan_errors);
+
+    // Palettes for art the scan added, without holding up the results
+    tauri::async_runtime::spawn_blocking(move || {
+        if let Err(e) = cover_storage::update_album_palettes(&palette_conn, false) {
+            log::warn!("[Scanner] Failed to compute album palettes: {}", e);
+        }
+    });
+    super::cover_art::spawn_lookup_after_scan(window, lookup_conn, &lookup_rules);
+    errors.extend(summary.errors.iter().cloned());
  
     let result = ScanResult {
         tracks_added,
         tracks_updated,
-        tracks_deleted,
-        errors: errors.clone(),
+        errors,
+        ..summary
     };
  
     let (complete_event, _) = event_names(&source);
@@ -806,22 +977,19 @@ fn event_names(source: &ScanSource) -> (&'static str, &'static str) {
 #[tauri::command]
 pub async fn rescan_music(
     window: tauri::Window,
+    force_full: Option<bool>,
     db: State<'_, Database>,
 ) -> Result<ScanResult, String> {
-    // 1: Cleanup
-    let (folders, folder_playlists, tracks_deleted) = {
+    // 1: Load folders and their scan settings
+    let (folders, rules, folder_playlists) = {
         let conn = db.conn.lock().map_err(|e| e.to_string())?;
 
-        let folders = queries::get_music_folders(&conn).map_err(|e| e.to_string())?;
-
-        let tracks_deleted = queries::cleanup_deleted_tracks(&conn, &folders)
-            .map_err(|e| format!("Failed to cleanup deleted tracks: {}", e))?;
-
-        let _ = queries::cleanup_empty_albums(&conn);
+        let rules = load_scan_rules(&conn)?;
+        let folders = rules.folders();
 
         let folder_playlists = queries::get_folder_playlists(&conn).unwrap_or_default();
 
-        (folders, folder_playlists, tracks_deleted)
+        (folders, Arc::new(rules), folder_playlists)
     }; // conn dropped here
 
     // 2: Directory walk
@@ -830,7 +998,7 @@ pub async fn rescan_music(
     let mut scan_errors = Vec::new();
 
     for folder in &folders {
-        let result = scan_directory(folder);
+        let result = rules.scan(folder);
         all_files.extend(result.audio_files);
         scan_errors.extend(result.errors);
     }
@@ -855,7 +1023,7 @@ pub async fn rescan_music(
                     .push(*playlist_id);
             }
         } else {
-            let result = scan_directory(folder_path);
+            let result = rules.scan(folder_path);
             for file_path in &result.audio_files {
                 file_playlist_map
                     .entry(file_path.clone())
@@ -867,7 +1035,46 @@ pub async fn rescan_music(
         }
     }
 
-    // 3: Parallel metadata extraction + DB import (handled inside run_scan_and_import)
+    // 3: Cleanup
+    // Tracks whose file moved to a newly discovered path are relinked (keeping
+    // their id, history and playlist entries); the rest are deleted.
+    let (tracks_deleted, relinked, extracted) =
+        relink_and_cleanup(db.inner(), &folders, &all_files, &rules)?;
+
+    // 4: Incremental check
+    // Files whose size and mtime match the last scan can't have new tags, so
+    // only their folder-playlist membership is refreshed. `force_full` re-reads all.
+    let mut tracks_skipped = 0;
+    if !force_full.unwrap_or(false) {
+        let known = {
+            let conn = db.conn.lock().map_err(|e| e.to_string())?;
+            queries::get_track_file_fingerprints(&conn).map_err(|e| e.to_string())?
+        };
+
+        // Stat without holding the DB lock; this is the slow part on network shares
+        let (unchanged, changed): (Vec<String>, Vec<String>) =
+            all_files.into_par_iter().partition(|file_path| {
+                known.get(file_path).map_or(false, |stored| {
+                    file_fingerprint(std::path::Path::new(file_path)).as_ref() == Some(stored)
+                })
+            });
+        all_files = changed;
+        tracks_skipped = unchanged.len();
+
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        for file_path in &unchanged {
+            let Some(playlist_ids) = file_playlist_map.get(file_path) else {
+                continue;
+            };
+            if let Ok(Some(track_id)) = queries::get_track_id_by_path(&conn, file_path) {
+                for playlist_id in playlist_ids {
+                    let _ = queries::ensure_track_in_playlist(&conn, *playlist_id, track_id);
+                }
+            }
+        }
+    }
+
+    // 5: Parallel metadata extraction + DB import (handled inside run_scan_and_import)
     // run_scan_and_import handles the zero-files case and emits scan-complete there.
     let db_conn = Arc::clone(&db.conn);
     let result = run_scan_and_import(
@@ -875,12 +1082,25 @@ pub async fn rescan_music(
         db_conn,
         all_files,
         file_playlist_map,
-        tracks_deleted,
-        scan_errors,
+        ScanResult::before_import(tracks_deleted, tracks_skipped, relinked, scan_errors),
+        extracted,
         folders, // all registered folders, for timestamp update
+        rules,
         ScanSource::Rescan,
     )
     .await?;
+
+    // Playlist files other players edited since the last sync
+    let synced = {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        super::playlist_link::sync_linked_playlists(&conn)
+    };
+    if synced
+        .iter()
+        .any(|report| report.outcome != super::playlist_link::PlaylistSyncOutcome::Unchanged)
+    {
+        let _ = window.emit("playlist-files-synced", &synced);
+    }
  
     // Background orphan cleanup (non-blocking)
     let db_conn_cleanup = Arc::clone(&db.conn);
@@ -893,6 +1113,141 @@ pub async fn rescan_music(
     Ok(result)
 }
 
+/// Files that failed some step of a scan, most recent first. Issues of files
+/// imported with filename-only metadata carry the track's id.
+#[tauri::command]
+pub async fn get_scan_issues(
+    db: State<'_, Database>,
+) -> Result<Vec<queries::ScanIssueRecord>, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    queries::get_scan_issues(&conn).map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ScanRetryResult {
+    /// Selected issues that no longer occur
+    pub resolved: usize,
+    /// Issues the retry still ran into
+    pub remaining: Vec<ScanIssue>,
+    /// Tracks imported or re-read by the retry
+    pub tracks: Vec<queries::Track>,
+}
+
+/// Process the files of the given scan issues (all of them when `ids` is None)
+/// again: directories are walked, files re-read and re-imported. Issues of
+/// files that have since disappeared are dropped.
+#[tauri::command]
+pub async fn retry_scan_issues(
+    ids: Option<Vec<i64>>,
+    db: State<'_, Database>,
+) -> Result<ScanRetryResult, String> {
+    let (selected, rules) = {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        let issues = queries::get_scan_issues(&conn).map_err(|e| e.to_string())?;
+        let selected: Vec<ScanIssue> = issues
+            .into_iter()
+            .filter(|record| ids.as_ref().map_or(true, |ids| ids.contains(&record.id)))
+            .map(|record| record.issue)
+            .collect();
+        (selected, load_scan_rules(&conn)?)
+    };
+
+    let db_conn = Arc::clone(&db.conn);
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut paths: Vec<String> = selected.iter().map(|i| i.path.clone()).collect();
+        paths.sort();
+        paths.dedup();
+
+        let mut walked = Vec::new();
+        let mut processed = HashSet::new();
+        let mut issues = Vec::new();
+        let mut files = Vec::new();
+        for path in paths {
+            let fs_path = std::path::Path::new(&path);
+            if fs_path.is_dir() {
+                issues.extend(rules.scan(&path).errors);
+                walked.push(path);
+            } else {
+                if fs_path.is_file() {
+                    files.push(path.clone());
+                }
+                processed.insert(path);
+            }
+        }
+
+        // Read tags without holding the DB lock
+        let extracted: Vec<_> = files
+            .par_iter()
+            .map(|path| extract_metadata_with_issues(path))
+            .collect();
+
+        let conn = db_conn.lock().map_err(|e| e.to_string())?;
+        let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+        let folder_playlists = queries::get_folder_playlists(&tx).unwrap_or_default();
+        let mut track_ids = Vec::new();
+        for (track_data, file_issues) in extracted {
+            issues.extend(file_issues);
+            let Some(track_data) = track_data else {
+                continue;
+            };
+            if rules.too_short(std::path::Path::new(&track_data.path), track_data.duration) {
+                continue;
+            }
+            match queries::insert_or_update_track(&tx, &track_data) {
+                Ok((track_id, was_new)) if track_id > 0 => {
+                    let (_, cover_issues) =
+                        cover_storage::save_scanned_artwork(&tx, track_id, &track_data, &rules);
+                    issues.extend(cover_issues);
+                    if was_new {
+                        for (playlist_id, folder) in &folder_playlists {
+                            if std::path::Path::new(&track_data.path).starts_with(folder) {
+                                let _ =
+                                    queries::ensure_track_in_playlist(&tx, *playlist_id, track_id);
+                            }
+                        }
+                    }
+                    track_ids.push(track_id);
+                }
+                // Duplicate of another track
+                Ok(_) => {}
+                Err(e) => issues.push(ScanIssue::database(&track_data.path, e)),
+            }
+        }
+        queries::record_scan_results(&tx, &walked, &processed, &issues)
+            .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+
+        let resolved = selected
+            .iter()
+            .filter(|old| {
+                !issues
+                    .iter()
+                    .any(|new| new.path == old.path && new.stage == old.stage)
+            })
+            .count();
+        let tracks = track_ids
+            .into_iter()
+            .filter_map(|id| queries::get_track_by_id(&conn, id).ok().flatten())
+            .collect();
+
+        Ok(ScanRetryResult {
+            resolved,
+            remaining: issues,
+            tracks,
+        })
+    })
+    .await
+    .map_err(|e| format!("Retry task failed: {}", e))?
+}
+
+/// Forget scan issues the user doesn't want to fix. They come back if a later
+/// scan runs into them again.
+#[tauri::command]
+pub async fn dismiss_scan_issues(ids: Vec<i64>, db: State<'_, Database>) -> Result<usize, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    queries::dismiss_scan_issues(&conn, &ids).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub async fn get_library(db: State<'_, Database>) -> Result<Library, String> {
     let conn = db.conn.lock().map_err(|e| e.to_string())?;
@@ -1016,51 +1371,93 @@ pub async fn get_albums_by_artist(
     Ok(albums)
 }
 
-/// Delete a track from the library (moves file to trash for safety)
-#[tauri::command]
-pub async fn delete_track(track_id: i64, db: State<'_, Database>) -> Result<bool, String> {
-    let conn = db.conn.lock().map_err(|e| e.to_string())?;
-
-    // Get track info before deletion
-    let track_info: Option<(String, Option<String>, Option<String>)> = conn
-        .query_row(
-            "SELECT path, source_type, track_cover_path FROM tracks WHERE id = ?1",
-            [track_id],
-            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
-        )
-        .ok();
-
-    if let Some((path, source_type, cover_path)) = track_info {
+/// Move tracks' local files to the trash and their cover files into the undo
+/// journal, returning what to record for undo
+fn remove_track_files(
+    journal: &mut Journal,
+    tracks: &[queries::Track],
+) -> (Vec<DeletedFile>, Vec<BackupFile>) {
+    let mut files = Vec::new();
+    let mut backups = Vec::new();
+    for track in tracks {
         // Only delete file if it's a local track
-        let is_local = source_type.is_none() || source_type.as_deref() == Some("local");
+        let is_local = track.source_type.is_none() || track.source_type.as_deref() == Some("local");
 
         if is_local {
-            let path_obj = std::path::Path::new(&path);
+            let path_obj = std::path::Path::new(&track.path);
             // Use secure deletion (moves to trash with path validation)
-            if let Err(e) = security::safe_delete_file(path_obj) {
-                log::error!("[AUDIT] Failed to delete track file {}: {}", path, e);
+            match security::delete_to_trash(path_obj) {
+                Ok(security::DeleteOutcome::Missing) => {}
+                Ok(outcome) => files.push(DeletedFile {
+                    path: track.path.clone(),
+                    trashed: outcome == security::DeleteOutcome::Trashed,
+                }),
                 // Continue to delete from DB even if file deletion fails
+                Err(e) => log::error!("[AUDIT] Failed to delete track file {}: {}", track.path, e),
             }
         }
 
-        // Delete cover file
-        let _ = cover_storage::delete_track_cover_file(cover_path.as_deref());
+        // Keep the cover file for undo, or delete it if that fails
+        if let Some(cover_path) = track.track_cover_path.as_deref() {
+            match journal.backup_file(std::path::Path::new(cover_path)) {
+                Ok(backup) => backups.push(backup),
+                Err(_) => {
+                    let _ = cover_storage::delete_track_cover_file(Some(cover_path));
+                }
+            }
+        }
     }
+    (files, backups)
+}
 
-    // Get track info before deletion for sync
-    let track_full_info = queries::get_track_by_id(&conn, track_id).ok().flatten();
+/// Delete a track from the library (moves file to trash for safety)
+#[tauri::command]
+pub async fn delete_track(track_id: i64, db: State<'_, Database>) -> Result<bool, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+
+    // Get track info before deletion
+    let Some(track) = queries::get_track_by_id(&conn, track_id).ok().flatten() else {
+        return queries::delete_track(&conn, track_id)
+            .map_err(|e| format!("Failed to delete track: {}", e));
+    };
 
-    let result = queries::delete_track(&conn, track_id)
-        .map_err(|e| format!("Failed to delete track: {}", e))?;
+    let rows = queries::snapshot_track_rows(&conn, &[track_id]).map_err(|e| e.to_string())?;
+    let mut journal = Journal::begin(
+        &conn,
+        &format!(
+            "Delete track \"{}\"",
+            track.title.as_deref().unwrap_or(&track.path)
+        ),
+    )?;
+    let (files, backups) = remove_track_files(&mut journal, std::slice::from_ref(&track));
+
+    let result = match queries::delete_track(&conn, track_id) {
+        Ok(result) => result,
+        Err(e) => {
+            journal.roll_back(&conn, &files, &backups);
+            return Err(format!("Failed to delete track: {}", e));
+        }
+    };
 
     // Enqueue sync change
-    if let Some(track) = track_full_info {
-        let _ = queries::enqueue_track_sync_change(&conn, &track, "delete");
-    }
+    let _ = queries::enqueue_track_sync_change(&conn, &track, "delete");
 
     // Clean up empty albums after track deletion
     let _ = queries::cleanup_empty_albums(&conn);
 
+    let operation = Operation::Delete {
+        rows,
+        files,
+        backups,
+    };
+    if let Err(e) = journal.commit(&conn, &operation) {
+        log::error!(
+            "[JOURNAL] Failed to record deletion of track {}: {}",
+            track_id,
+            e
+        );
+    }
+
     log::info!("[AUDIT] Track {} deleted from library", track_id);
     Ok(result)
 }
@@ -1071,14 +1468,14 @@ pub async fn delete_album(album_id: i64, db: State<'_, Database>) -> Result<bool
     let conn = db.conn.lock().map_err(|e| e.to_string())?;
 
     // Get album art path before deletion
-    let art_path: Option<String> = conn
+    let album: Option<(String, Option<String>)> = conn
         .query_row(
-            "SELECT art_path FROM albums WHERE id = ?1",
+            "SELECT name, art_path FROM albums WHERE id = ?1",
             [album_id],
-            |row| row.get(0),
+            |row| Ok((row.get(0)?, row.get(1)?)),
         )
-        .ok()
-        .flatten();
+        .ok();
+    let (name, art_path) = album.unwrap_or_default();
 
     // Get all tracks for this album to delete files
     let tracks = queries::get_tracks_by_album(&conn, album_id).map_err(|e| e.to_string())?;
@@ -1089,34 +1486,50 @@ pub async fn delete_album(album_id: i64, db: State<'_, Database>) -> Result<bool
         tracks.len()
     );
 
-    for track in &tracks {
-        // Only delete file if it's a local track
-        let is_local = track.source_type.is_none() || track.source_type.as_deref() == Some("local");
-
-        if is_local {
-            let path_obj = std::path::Path::new(&track.path);
-            // Use secure deletion (moves to trash with path validation)
-            if let Err(e) = security::safe_delete_file(path_obj) {
-                log::error!("[AUDIT] Failed to delete track file {}: {}", track.path, e);
-                // Continue with other tracks
+    let track_ids: Vec<i64> = tracks.iter().map(|track| track.id).collect();
+    let rows = queries::snapshot_track_rows(&conn, &track_ids).map_err(|e| e.to_string())?;
+    let mut journal = Journal::begin(&conn, &format!("Delete album \"{}\"", name))?;
+    let (files, mut backups) = remove_track_files(&mut journal, &tracks);
+
+    // Keep the album art file for undo, unless another album shares it
+    let shared = art_path
+        .as_deref()
+        .is_some_and(|path| queries::is_album_art_shared(&conn, path, album_id).unwrap_or(true));
+    if let (Some(path), false) = (art_path.as_deref(), shared) {
+        match journal.backup_file(std::path::Path::new(path)) {
+            Ok(backup) => backups.push(backup),
+            Err(_) => {
+                let _ = cover_storage::delete_album_art_file(Some(path));
             }
         }
-
-        // Delete track cover file
-        let _ = cover_storage::delete_track_cover_file(track.track_cover_path.as_deref());
     }
 
-    // Delete album art file
-    let _ = cover_storage::delete_album_art_file(art_path.as_deref());
-
-    let result = queries::delete_album(&conn, album_id)
-        .map_err(|e| format!("Failed to delete album: {}", e))?;
+    let result = match queries::delete_album(&conn, album_id) {
+        Ok(result) => result,
+        Err(e) => {
+            journal.roll_back(&conn, &files, &backups);
+            return Err(format!("Failed to delete album: {}", e));
+        }
+    };
 
     // Enqueue sync changes for deleted tracks
     for track in &tracks {
         let _ = queries::enqueue_track_sync_change(&conn, track, "delete");
     }
 
+    let operation = Operation::Delete {
+        rows,
+        files,
+        backups,
+    };
+    if let Err(e) = journal.commit(&conn, &operation) {
+        log::error!(
+            "[JOURNAL] Failed to record deletion of album {}: {}",
+            album_id,
+            e
+        );
+    }
+
     log::info!("[AUDIT] Album {} deleted from library", album_id);
     Ok(result)
 }
@@ -1148,9 +1561,6 @@ pub async fn add_external_track(
     track: ExternalTrackInput,
     db: State<'_, Database>,
 ) -> Result<i64, String> {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-
     let conn = db.conn.lock().map_err(|e| e.to_string())?;
 
     // Use stream_url as path if provided, otherwise construct from source_type://external_id
@@ -1159,17 +1569,13 @@ pub async fn add_external_track(
         .clone()
         .unwrap_or_else(|| format!("{}://{}", track.source_type, track.external_id));
 
-    // Generate content hash for external tracks
-    let mut hasher = DefaultHasher::new();
-    let combined = format!(
-        "{}|{}|{}|{}",
-        track.title.trim().to_lowercase(),
-        track.artist.trim().to_lowercase(),
-        track.album.as_deref().unwrap_or("").trim().to_lowercase(),
-        track.duration.map(|d| d.to_string()).unwrap_or_default()
-    );
-    combined.hash(&mut hasher);
-    let content_hash = Some(format!("{:016x}", hasher.finish()));
+    // External tracks have no audio to hash, so they are identified by metadata
+    let content_hash = Some(crate::scanner::metadata::generate_content_hash(
+        Some(&track.title),
+        Some(&track.artist),
+        track.album.as_deref(),
+        track.duration,
+    ));
 
     let track_insert = queries::TrackInsert {
         path,
@@ -1190,6 +1596,9 @@ pub async fn add_external_track(
         local_src: None,
         musicbrainz_recording_id: track.musicbrainz_recording_id,
         metadata_json: track.metadata_json,
+        file_size: None,
+        file_mtime: None,
+        metadata_fallback: false,
     };
 
     queries::insert_or_update_track(&conn, &track_insert)
@@ -1205,19 +1614,31 @@ pub async fn add_external_track(
 
 /// Reset the database by clearing all data
 #[tauri::command]
-pub async fn reset_database(db: State<'_, Database>) -> Result<(), String> {
-    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+pub async fn reset_database(app: tauri::AppHandle, db: State<'_, Database>) -> Result<(), String> {
+    {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
 
-    conn.execute_batch(
-        "
-        DELETE FROM playlist_tracks;
-        DELETE FROM playlists;
-        DELETE FROM tracks;
-        DELETE FROM albums;
-        DELETE FROM music_folders;
-        ",
-    )
-    .map_err(|e| format!("Failed to reset database: {}", e))?;
+        conn.execute_batch(
+            "
+            DELETE FROM playlist_tracks;
+            DELETE FROM playlists;
+            DELETE FROM playlist_folders;
+            DELETE FROM tags;
+            DELETE FROM tracks;
+            DELETE FROM albums;
+            DELETE FROM music_folders;
+            DELETE FROM scan_issues;
+            DELETE FROM operation_journal;
+            ",
+        )
+        .map_err(|e| format!("Failed to reset database: {}", e))?;
+    }
+    super::journal::clear_backups();
+
+    // No folders are left to watch
+    if let Err(e) = super::watcher::restart_library_watcher(&app) {
+        log::warn!("[Watcher] Failed to restart: {}", e);
+    }
 
     Ok(())
 }
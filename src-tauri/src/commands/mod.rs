@@ -1,29 +1,53 @@
 // Tauri IPC commands
 pub mod activity;
+pub mod cover_art;
 pub mod covers;
+pub mod duplicates;
 pub mod library;
 pub mod listenbrainz;
 pub mod lyrics;
 pub mod applelyrics;
+pub mod audio_features;
+pub mod autotag;
 pub mod geniuslyrics;
+pub mod journal;
 pub mod metadata;
+pub mod mix;
 pub mod musicbrainz;
 pub mod network;
+pub mod organiser;
 pub mod playlist;
+pub mod playlist_folders;
+pub mod playlist_io;
+pub mod playlist_link;
 pub mod plugin;
 pub mod sync;
+pub mod tags;
+pub mod watcher;
 
 pub use activity::*;
+pub use cover_art::*;
+pub use duplicates::*;
 pub use library::*;
 pub use listenbrainz::*;
 pub use lyrics::*;
 pub use applelyrics::*;
+pub use audio_features::*;
+pub use autotag::*;
 pub use geniuslyrics::*;
+pub use journal::*;
 pub use metadata::*;
+pub use mix::*;
 pub use musicbrainz::*;
 pub use network::*;
+pub use organiser::*;
 pub use playlist::*;
+pub use playlist_folders::*;
+pub use playlist_io::*;
+pub use playlist_link::*;
 pub use plugin::*;
 pub mod window;
 pub use covers::*;
 pub use sync::*;
+pub use tags::*;
+pub use watcher::*;
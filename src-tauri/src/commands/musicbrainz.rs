@@ -10,8 +10,8 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use tokio::time::{sleep, Duration};
 
-const MB_API_BASE: &str = "https://musicbrainz.org/ws/2";
-const MB_USER_AGENT: &str = "Audion/1.3.1 (https://audionplayer.com)";
+pub(crate) const MB_API_BASE: &str = "https://musicbrainz.org/ws/2";
+pub(crate) const MB_USER_AGENT: &str = "Audion/1.3.1 (https://audionplayer.com)";
 const WIKI_SUMMARY_BASE: &str = "https://en.wikipedia.org/api/rest_v1/page/summary";
 
 // ── Raw MusicBrainz JSON shapes ───────────────────────────────────────────────
@@ -645,6 +645,7 @@ pub async fn get_release_mb_info(
 
 /// Find artists related to the given artist on MusicBrainz (band members,
 /// collaborators, etc.) and mark which ones exist in the local library.
+/// The relations are cached for `generate_mix`.
 ///
 /// Makes 2 rate-limited HTTP requests:
 /// 1. Artist search to resolve the MBID.
@@ -705,6 +706,12 @@ pub async fn get_similar_artists_mb(
         .take(30)
         .collect();
 
+    // Keep the relations for mixes built offline
+    {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        crate::db::queries::cache_artist_relations(&conn, &artist_name, &artist_rels).ok();
+    }
+
     if artist_rels.is_empty() {
         return Ok(vec![]);
     }
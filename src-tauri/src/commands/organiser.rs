@@ -0,0 +1,148 @@
+//! Renaming and moving library files by a tag template
+//!
+//! A dry run returns the planned moves without touching anything. A real run
+//! moves the files, then points the tracks at their new paths in one
+//! transaction; if that fails the files are moved back.
+
+use crate::db::queries;
+use crate::db::Database;
+use crate::scanner::organiser::{
+    self, CollisionPolicy, Filesystem, MoveStatus, PlannedMove, Template,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::State;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OrganiseRequest {
+    /// e.g. `{albumartist}/[{year} - ]{album}/{disc}-{track:02} {title}.{ext}`
+    pub template: String,
+    /// Tracks to organise; the whole library when absent
+    #[serde(default)]
+    pub track_ids: Option<Vec<i64>>,
+    /// Naming rules to follow; the current OS's when absent
+    #[serde(default)]
+    pub filesystem: Option<Filesystem>,
+    #[serde(default)]
+    pub collisions: CollisionPolicy,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OrganiseResult {
+    pub dry_run: bool,
+    /// Every track that moves, is skipped or failed; unchanged ones are only counted
+    pub moves: Vec<PlannedMove>,
+    pub unchanged: usize,
+    pub moved: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl OrganiseResult {
+    fn new(dry_run: bool, moves: Vec<PlannedMove>) -> Self {
+        let count = |status| moves.iter().filter(|m| m.status == status).count();
+        OrganiseResult {
+            dry_run,
+            unchanged: count(MoveStatus::Unchanged),
+            moved: count(MoveStatus::Move),
+            skipped: count(MoveStatus::Skipped),
+            failed: count(MoveStatus::Failed),
+            moves: moves
+                .into_iter()
+                .filter(|m| m.status != MoveStatus::Unchanged)
+                .collect(),
+        }
+    }
+}
+
+/// Fail moves whose source or destination is outside the allowed directories
+fn check_security(moves: &mut [PlannedMove], roots: &[PathBuf]) {
+    for planned in moves.iter_mut() {
+        if planned.status != MoveStatus::Move {
+            continue;
+        }
+        let from = Path::new(&planned.from);
+        let to = Path::new(&planned.to);
+        let safe = crate::security::is_safe_path(from).unwrap_or(false)
+            && roots.iter().any(|root| to.starts_with(root))
+            && planned.sidecars.iter().all(|sidecar| {
+                crate::security::is_safe_path(Path::new(&sidecar.from)).unwrap_or(false)
+            });
+        if !safe {
+            planned.status = MoveStatus::Failed;
+            planned.error = Some("Security: File is outside the music folders".to_string());
+        }
+    }
+}
+
+fn organise(conn: &Mutex<Connection>, request: &OrganiseRequest) -> Result<OrganiseResult, String> {
+    let template = Template::parse(&request.template)?;
+    let filesystem = request.filesystem.unwrap_or_else(Filesystem::current);
+
+    let (tracks, folders) = {
+        let conn = conn.lock().map_err(|e| e.to_string())?;
+        let tracks = queries::get_tracks_to_organise(&conn, request.track_ids.as_deref())
+            .map_err(|e| e.to_string())?;
+        let folders = queries::get_music_folders(&conn).map_err(|e| e.to_string())?;
+        (tracks, folders)
+    };
+    // Files in a folder that isn't allowed count as outside every music folder
+    let roots: Vec<PathBuf> = folders
+        .into_iter()
+        .map(PathBuf::from)
+        .filter(|root| crate::security::is_safe_path(root).unwrap_or(false))
+        .collect();
+
+    let mut moves = organiser::plan(&tracks, &roots, &template, filesystem, request.collisions);
+    check_security(&mut moves, &roots);
+    if request.dry_run {
+        return Ok(OrganiseResult::new(true, moves));
+    }
+
+    log::info!(
+        "[AUDIT] Organising {} files with template {:?}",
+        moves
+            .iter()
+            .filter(|m| m.status == MoveStatus::Move)
+            .count(),
+        request.template
+    );
+    organiser::execute(&mut moves);
+
+    let updated = (|| {
+        let conn = conn.lock().map_err(|e| e.to_string())?;
+        let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+        for planned in moves.iter().filter(|m| m.status == MoveStatus::Move) {
+            queries::relocate_track(&tx, planned.track_id, &planned.to)
+                .map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| e.to_string())
+    })();
+    if let Err(e) = updated {
+        log::error!(
+            "[Organiser] Library update failed, moving files back: {}",
+            e
+        );
+        organiser::revert(&mut moves, &format!("Failed to update the library: {}", e));
+        return Ok(OrganiseResult::new(false, moves));
+    }
+
+    organiser::remove_empty_dirs(&moves, &roots);
+    Ok(OrganiseResult::new(false, moves))
+}
+
+/// Rename and move library files according to a template
+#[tauri::command]
+pub async fn organise_files(
+    request: OrganiseRequest,
+    db: State<'_, Database>,
+) -> Result<OrganiseResult, String> {
+    let conn = db.conn.clone();
+    tauri::async_runtime::spawn_blocking(move || organise(&conn, &request))
+        .await
+        .map_err(|e| e.to_string())?
+}
@@ -13,64 +13,157 @@ pub async fn create_playlist(
     let id = queries::create_playlist(&conn, &name, cover_url.as_deref())
         .map_err(|e| e.to_string())?;
 
+    enqueue_playlist_create(&conn, id, &name, cover_url.as_deref(), None);
+
+    Ok(id)
+}
+
+/// Enqueue the sync change for a newly created playlist
+pub(crate) fn enqueue_playlist_create(
+    conn: &rusqlite::Connection,
+    playlist_id: i64,
+    name: &str,
+    cover_url: Option<&str>,
+    rules: Option<&queries::SmartPlaylistRules>,
+) {
+    if !queries::is_logged_in(conn) {
+        return;
+    }
+
+    let mut payload = serde_json::json!({
+        "name": name,
+        "coverUrl": cover_url
+    });
+    if let Some(rules) = rules {
+        payload["smartRules"] = serde_json::json!(rules);
+    }
+    let _ = queries::enqueue_sync_change(
+        conn,
+        "playlist",
+        &format!("local_{}", playlist_id),
+        "create",
+        Some(&payload.to_string()),
+    );
+}
+
+/// Create a smart playlist whose tracks are computed from `rules` on every read
+#[tauri::command]
+pub async fn create_smart_playlist(
+    name: String,
+    rules: queries::SmartPlaylistRules,
+    cover_url: Option<String>,
+    db: State<'_, Database>,
+) -> Result<i64, String> {
+    rules.validate()?;
+
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    let id = queries::create_playlist(&conn, &name, cover_url.as_deref())
+        .map_err(|e| e.to_string())?;
+    queries::set_playlist_smart_rules(&conn, id, Some(&rules)).map_err(|e| e.to_string())?;
+
+    enqueue_playlist_create(&conn, id, &name, cover_url.as_deref(), Some(&rules));
+
+    Ok(id)
+}
+
+/// Replace the rules of an existing smart playlist
+#[tauri::command]
+pub async fn update_smart_playlist_rules(
+    playlist_id: i64,
+    rules: queries::SmartPlaylistRules,
+    db: State<'_, Database>,
+) -> Result<(), String> {
+    rules.validate()?;
+
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    if queries::get_playlist_smart_rules(&conn, playlist_id)
+        .map_err(|e| e.to_string())?
+        .is_none()
+    {
+        return Err("Playlist is not a smart playlist".to_string());
+    }
+    queries::set_playlist_smart_rules(&conn, playlist_id, Some(&rules))
+        .map_err(|e| e.to_string())?;
+
     // Enqueue sync change
     if queries::is_logged_in(&conn) {
-        let payload = serde_json::json!({
-            "name": name,
-            "coverUrl": cover_url
-        })
-        .to_string();
+        let payload = serde_json::json!({ "smartRules": rules }).to_string();
         let _ = queries::enqueue_sync_change(
             &conn,
             "playlist",
-            &format!("local_{}", id),
-            "create",
+            &format!("local_{}", playlist_id),
+            "update",
             Some(&payload),
         );
     }
 
-    Ok(id)
-}
-
-#[tauri::command]
-pub async fn get_playlists(db: State<'_, Database>) -> Result<Vec<queries::Playlist>, String> {
-    let conn = db.conn.lock().map_err(|e| e.to_string())?;
-    queries::get_all_playlists(&conn).map_err(|e| e.to_string())
+    Ok(())
 }
 
+/// Evaluate rules without saving them (used by the smart playlist editor)
 #[tauri::command]
-pub async fn get_playlist_tracks(
-    playlist_id: i64,
+pub async fn preview_smart_playlist(
+    rules: queries::SmartPlaylistRules,
     db: State<'_, Database>,
 ) -> Result<Vec<queries::Track>, String> {
+    rules.validate()?;
+
     let conn = db.conn.lock().map_err(|e| e.to_string())?;
-    queries::get_playlist_tracks(&conn, playlist_id).map_err(|e| e.to_string())
+    queries::get_smart_playlist_tracks(&conn, &rules).map_err(|e| e.to_string())
 }
 
-#[tauri::command]
-pub async fn add_track_to_playlist(
+/// Smart playlist membership is derived from rules, so manual edits are rejected
+pub(crate) fn ensure_not_smart(
+    conn: &rusqlite::Connection,
     playlist_id: i64,
-    track_id: i64,
-    db: State<'_, Database>,
 ) -> Result<(), String> {
+    match queries::get_playlist_smart_rules(conn, playlist_id) {
+        Ok(Some(_)) => Err("Cannot edit the tracks of a smart playlist".to_string()),
+        Ok(None) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub async fn get_playlists(db: State<'_, Database>) -> Result<Vec<queries::Playlist>, String> {
     let conn = db.conn.lock().map_err(|e| e.to_string())?;
-    queries::add_track_to_playlist(&conn, playlist_id, track_id).map_err(|e| e.to_string())?;
+    queries::get_all_playlists(&conn).map_err(|e| e.to_string())
+}
+
+/// Enqueue a sync change for one playlist entry. Entries are keyed by their own
+/// ID so duplicates of the same track sync independently. Call after the DB change
+/// for create/update so the payload reflects the stored position.
+pub(crate) fn enqueue_playlist_entry_change(
+    conn: &rusqlite::Connection,
+    playlist_id: i64,
+    entry_id: i64,
+    operation: &str,
+) {
+    if !queries::is_logged_in(conn) {
+        return;
+    }
+
+    let mut payload = serde_json::json!({
+        "playlistId": format!("local_{}", playlist_id),
+    });
+
+    if operation != "delete" {
+        let Ok(Some((_, track_id, sort_key))) = queries::get_playlist_entry(conn, entry_id) else {
+            return;
+        };
+        // Index for older clients, sortKey for gap-based ordering
+        let position: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM playlist_tracks WHERE playlist_id = ?1 AND (position < ?2 OR (position = ?2 AND id < ?3))",
+                params![playlist_id, sort_key, entry_id],
+                |row| row.get(0),
+            )
+            .unwrap_or(0);
+        payload["position"] = serde_json::json!(position);
+        payload["sortKey"] = serde_json::json!(sort_key);
 
-    // Enqueue sync change
-    if queries::is_logged_in(&conn) {
-        // Get the position that was just assigned
-        let position: i32 = conn.query_row(
-            "SELECT COALESCE(position, 0) FROM playlist_tracks WHERE playlist_id = ?1 AND track_id = ?2",
-            rusqlite::params![playlist_id, track_id],
-            |row| row.get(0),
-        ).unwrap_or(0);
-
-        let mut payload = serde_json::json!({
-            "playlistId": format!("local_{}", playlist_id),
-            "position": position,
-        });
         // Attach track metadata for cross-device matching
-        if let Ok(Some(track)) = queries::get_track_by_id(&conn, track_id) {
+        if let Ok(Some(track)) = queries::get_track_by_id(conn, track_id) {
             let track_hash = queries::build_track_hash_str(
                 track.title.as_deref(),
                 track.artist.as_deref(),
@@ -85,18 +178,123 @@ pub async fn add_track_to_playlist(
             payload["sourceType"] = serde_json::json!(track.source_type);
             payload["coverUrl"] = serde_json::json!(track.cover_url);
         }
-        let _ = queries::enqueue_sync_change(
-            &conn,
-            "playlist_track",
-            &format!("local_{}_{}", playlist_id, track_id),
-            "create",
-            Some(&payload.to_string()),
-        );
     }
 
+    let _ = queries::enqueue_sync_change(
+        conn,
+        "playlist_track",
+        &format!("local_entry_{}", entry_id),
+        operation,
+        Some(&payload.to_string()),
+    );
+}
+
+#[tauri::command]
+pub async fn get_playlist_tracks(
+    playlist_id: i64,
+    db: State<'_, Database>,
+) -> Result<Vec<queries::Track>, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    queries::get_playlist_tracks(&conn, playlist_id).map_err(|e| e.to_string())
+}
+
+/// Playlist tracks with their entry IDs, for editing playlists that contain duplicates
+#[tauri::command]
+pub async fn get_playlist_entries(
+    playlist_id: i64,
+    db: State<'_, Database>,
+) -> Result<Vec<queries::PlaylistEntry>, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    queries::get_playlist_entries(&conn, playlist_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn add_track_to_playlist(
+    playlist_id: i64,
+    track_id: i64,
+    db: State<'_, Database>,
+) -> Result<i64, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    ensure_not_smart(&conn, playlist_id)?;
+    let entry_id =
+        queries::add_track_to_playlist(&conn, playlist_id, track_id).map_err(|e| e.to_string())?;
+
+    enqueue_playlist_entry_change(&conn, playlist_id, entry_id, "create");
+    super::playlist_link::write_back(&conn, playlist_id);
+
+    Ok(entry_id)
+}
+
+/// Insert tracks at `index` (append when omitted). Returns the new entry IDs.
+#[tauri::command]
+pub async fn insert_tracks_into_playlist(
+    playlist_id: i64,
+    track_ids: Vec<i64>,
+    index: Option<usize>,
+    db: State<'_, Database>,
+) -> Result<Vec<i64>, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    ensure_not_smart(&conn, playlist_id)?;
+    let entry_ids = queries::insert_playlist_entries(&conn, playlist_id, &track_ids, index)
+        .map_err(|e| e.to_string())?;
+
+    for entry_id in &entry_ids {
+        enqueue_playlist_entry_change(&conn, playlist_id, *entry_id, "create");
+    }
+    super::playlist_link::write_back(&conn, playlist_id);
+
+    Ok(entry_ids)
+}
+
+/// Move entries together to `to_index` (counted without the moved entries)
+#[tauri::command]
+pub async fn move_playlist_entries(
+    playlist_id: i64,
+    entry_ids: Vec<i64>,
+    to_index: usize,
+    db: State<'_, Database>,
+) -> Result<(), String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    ensure_not_smart(&conn, playlist_id)?;
+    let moved = queries::move_playlist_entries(&conn, playlist_id, &entry_ids, to_index)
+        .map_err(|e| e.to_string())?;
+
+    for (entry_id, _) in moved {
+        enqueue_playlist_entry_change(&conn, playlist_id, entry_id, "update");
+    }
+    super::playlist_link::write_back(&conn, playlist_id);
+
     Ok(())
 }
 
+/// Remove specific entries, leaving other occurrences of the same track in place
+#[tauri::command]
+pub async fn remove_playlist_entries(
+    playlist_id: i64,
+    entry_ids: Vec<i64>,
+    db: State<'_, Database>,
+) -> Result<usize, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    ensure_not_smart(&conn, playlist_id)?;
+    // Only sync deletes for entries that actually belong to this playlist
+    let entry_ids: Vec<i64> = entry_ids
+        .into_iter()
+        .filter(|id| {
+            matches!(queries::get_playlist_entry(&conn, *id), Ok(Some((pl, _, _))) if pl == playlist_id)
+        })
+        .collect();
+    let removed = queries::remove_playlist_entries(&conn, playlist_id, &entry_ids)
+        .map_err(|e| e.to_string())?;
+
+    for entry_id in &entry_ids {
+        enqueue_playlist_entry_change(&conn, playlist_id, *entry_id, "delete");
+    }
+    super::playlist_link::write_back(&conn, playlist_id);
+
+    Ok(removed)
+}
+
+/// Remove every occurrence of a track from a playlist
 #[tauri::command]
 pub async fn remove_track_from_playlist(
     playlist_id: i64,
@@ -104,22 +302,19 @@ pub async fn remove_track_from_playlist(
     db: State<'_, Database>,
 ) -> Result<(), String> {
     let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    ensure_not_smart(&conn, playlist_id)?;
+    let entry_ids: Vec<i64> = queries::get_playlist_entries(&conn, playlist_id)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|entry| entry.track.id == track_id)
+        .map(|entry| entry.entry_id)
+        .collect();
     queries::remove_track_from_playlist(&conn, playlist_id, track_id).map_err(|e| e.to_string())?;
 
-    // Enqueue sync change
-    if queries::is_logged_in(&conn) {
-        let payload = serde_json::json!({
-            "playlistId": format!("local_{}", playlist_id),
-        })
-        .to_string();
-        let _ = queries::enqueue_sync_change(
-            &conn,
-            "playlist_track",
-            &format!("local_{}_{}", playlist_id, track_id),
-            "delete",
-            Some(&payload),
-        );
+    for entry_id in entry_ids {
+        enqueue_playlist_entry_change(&conn, playlist_id, entry_id, "delete");
     }
+    super::playlist_link::write_back(&conn, playlist_id);
 
     Ok(())
 }
@@ -192,8 +387,79 @@ pub async fn update_playlist_cover(
     Ok(())
 }
 
-/// Reorder tracks in a playlist by moving a track from one position to another
+/// Enqueue a sync update with the changed playlist fields
+pub(crate) fn enqueue_playlist_update(
+    conn: &rusqlite::Connection,
+    playlist_id: i64,
+    payload: serde_json::Value,
+) {
+    if !queries::is_logged_in(conn) {
+        return;
+    }
+    let _ = queries::enqueue_sync_change(
+        conn,
+        "playlist",
+        &format!("local_{}", playlist_id),
+        "update",
+        Some(&payload.to_string()),
+    );
+}
+
+/// Set or clear (None or blank) a playlist's description
+#[tauri::command]
+pub async fn update_playlist_description(
+    playlist_id: i64,
+    description: Option<String>,
+    db: State<'_, Database>,
+) -> Result<(), String> {
+    let description = description
+        .map(|d| d.trim().to_string())
+        .filter(|d| !d.is_empty());
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    queries::set_playlist_description(&conn, playlist_id, description.as_deref())
+        .map_err(|e| e.to_string())?;
+    enqueue_playlist_update(
+        &conn,
+        playlist_id,
+        serde_json::json!({ "description": description }),
+    );
+    Ok(())
+}
+
+/// Pinned playlists are listed before the others
+#[tauri::command]
+pub async fn set_playlist_pinned(
+    playlist_id: i64,
+    pinned: bool,
+    db: State<'_, Database>,
+) -> Result<(), String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    queries::set_playlist_pinned(&conn, playlist_id, pinned).map_err(|e| e.to_string())?;
+    enqueue_playlist_update(&conn, playlist_id, serde_json::json!({ "pinned": pinned }));
+    Ok(())
+}
+
+/// Choose how `get_playlist_tracks` lists a playlist. Entries keep their
+/// manual order, which comes back when switching to `manual`.
+#[tauri::command]
+pub async fn set_playlist_sort_order(
+    playlist_id: i64,
+    sort_order: queries::PlaylistSortOrder,
+    db: State<'_, Database>,
+) -> Result<(), String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    queries::set_playlist_sort_order(&conn, playlist_id, sort_order).map_err(|e| e.to_string())?;
+    enqueue_playlist_update(
+        &conn,
+        playlist_id,
+        serde_json::json!({ "sortOrder": sort_order }),
+    );
+    Ok(())
+}
 
+/// Reorder tracks in a playlist by moving a track from one position to another.
+/// Only the moved entry's position changes (see `queries::move_playlist_entries`).
+///
 /// from_index - The current index of the track to move (0-based)
 /// to_index - The target index where the track should be moved (0-based)
 #[tauri::command]
@@ -204,28 +470,21 @@ pub async fn reorder_playlist_tracks(
     db: State<'_, Database>,
 ) -> Result<(), String> {
     let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    ensure_not_smart(&conn, playlist_id)?;
+    // Indices refer to the listed order, which only matches the entries when manual
+    if queries::get_playlist_sort_order(&conn, playlist_id).map_err(|e| e.to_string())?
+        != queries::PlaylistSortOrder::Manual
+    {
+        return Err("Switch the playlist to manual order to move tracks".to_string());
+    }
 
-    // Get all tracks in the playlist ordered by position
-    let mut stmt = conn
-        .prepare(
-            "SELECT track_id, position FROM playlist_tracks 
-         WHERE playlist_id = ?1 
-         ORDER BY position",
-        )
-        .map_err(|e| e.to_string())?;
-
-    let tracks: Vec<(i64, i64)> = stmt
-        .query_map(params![playlist_id], |row| Ok((row.get(0)?, row.get(1)?)))
-        .map_err(|e| e.to_string())?
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| e.to_string())?;
-
-    if tracks.is_empty() {
+    let entries = queries::get_playlist_entries(&conn, playlist_id).map_err(|e| e.to_string())?;
+    if entries.is_empty() {
         return Err("Playlist is empty".to_string());
     }
 
     // Validate indices
-    let max_index = tracks.len() as i64 - 1;
+    let max_index = entries.len() as i64 - 1;
     if from_index < 0 || from_index > max_index {
         return Err(format!("Invalid from_index: {}", from_index));
     }
@@ -238,52 +497,12 @@ pub async fn reorder_playlist_tracks(
         return Ok(());
     }
 
-    // Create a new ordered list of track IDs
-    let mut track_ids: Vec<i64> = tracks.iter().map(|(id, _)| *id).collect();
-
-    // Remove the track from its current position
-    let moved_track_id = track_ids.remove(from_index as usize);
-
-    // Insert it at the new position
-    track_ids.insert(to_index as usize, moved_track_id);
-
-    // Update all positions in the database
-    // Use a transaction to ensure atomicity
-    conn.execute("BEGIN TRANSACTION", [])
+    let entry_id = entries[from_index as usize].entry_id;
+    queries::move_playlist_entries(&conn, playlist_id, &[entry_id], to_index as usize)
         .map_err(|e| e.to_string())?;
 
-    for (new_position, track_id) in track_ids.iter().enumerate() {
-        conn.execute(
-            "UPDATE playlist_tracks 
-             SET position = ?1 
-             WHERE playlist_id = ?2 AND track_id = ?3",
-            params![new_position as i64, playlist_id, track_id],
-        )
-        .map_err(|e| {
-            // Rollback on error
-            let _ = conn.execute("ROLLBACK", []);
-            e.to_string()
-        })?;
-    }
-
-    conn.execute("COMMIT", []).map_err(|e| e.to_string())?;
-
-    // Enqueue sync change for the reorder
-    if queries::is_logged_in(&conn) {
-        let payload = serde_json::json!({
-            "playlistId": format!("local_{}", playlist_id),
-            "fromIndex": from_index,
-            "toIndex": to_index,
-        })
-        .to_string();
-        let _ = queries::enqueue_sync_change(
-            &conn,
-            "playlist_track",
-            &format!("local_{}_reorder", playlist_id),
-            "update",
-            Some(&payload),
-        );
-    }
+    enqueue_playlist_entry_change(&conn, playlist_id, entry_id, "update");
+    super::playlist_link::write_back(&conn, playlist_id);
 
     Ok(())
 }
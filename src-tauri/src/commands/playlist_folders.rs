@@ -0,0 +1,185 @@
+//! Playlist folders
+//!
+//! Folders form a tree holding playlists and subfolders. Folders and
+//! playlists with the same parent share one order, so a move places an item
+//! among both. Deleting a folder keeps its contents by moving them up.
+
+use crate::db::queries::{self, PlaylistFolder, PlaylistTreeItem, PlaylistTreeMove};
+use crate::db::Database;
+use rusqlite::Connection;
+use tauri::State;
+
+fn local_folder_id(folder_id: Option<i64>) -> Option<String> {
+    folder_id.map(|id| format!("local_{}", id))
+}
+
+/// Enqueue the sync change for a folder. Parents are sent as local IDs and
+/// translated to server IDs on push.
+fn enqueue_folder_change(conn: &Connection, folder_id: i64, operation: &str) {
+    if !queries::is_logged_in(conn) {
+        return;
+    }
+    let payload = match operation {
+        "delete" => None,
+        _ => match queries::get_playlist_folder(conn, folder_id) {
+            Ok(Some(folder)) => Some(
+                serde_json::json!({
+                    "name": folder.name,
+                    "parentId": local_folder_id(folder.parent_id),
+                    "position": folder.position,
+                })
+                .to_string(),
+            ),
+            _ => return,
+        },
+    };
+    let _ = queries::enqueue_sync_change(
+        conn,
+        "playlist_folder",
+        &format!("local_{}", folder_id),
+        operation,
+        payload.as_deref(),
+    );
+}
+
+/// Enqueue updates for folders and playlists that moved in the tree
+fn enqueue_tree_moves(conn: &Connection, moves: &[PlaylistTreeMove]) {
+    if !queries::is_logged_in(conn) {
+        return;
+    }
+    for moved in moves {
+        match moved.item {
+            PlaylistTreeItem::Folder(id) => enqueue_folder_change(conn, id, "update"),
+            PlaylistTreeItem::Playlist(id) => {
+                let payload = serde_json::json!({
+                    "folderId": local_folder_id(moved.parent_id),
+                    "position": moved.position,
+                });
+                let _ = queries::enqueue_sync_change(
+                    conn,
+                    "playlist",
+                    &format!("local_{}", id),
+                    "update",
+                    Some(&payload.to_string()),
+                );
+            }
+        }
+    }
+}
+
+fn ensure_folder_exists(conn: &Connection, folder_id: Option<i64>) -> Result<(), String> {
+    let Some(folder_id) = folder_id else {
+        return Ok(());
+    };
+    match queries::get_playlist_folder(conn, folder_id) {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err("Folder not found".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn folder_name(name: &str) -> Result<&str, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Folder name cannot be empty".to_string());
+    }
+    Ok(name)
+}
+
+fn move_item(
+    conn: &Connection,
+    item: PlaylistTreeItem,
+    parent_id: Option<i64>,
+    index: usize,
+) -> Result<(), String> {
+    ensure_folder_exists(conn, parent_id)?;
+    if let (PlaylistTreeItem::Folder(folder_id), Some(parent_id)) = (item, parent_id) {
+        let ancestors =
+            queries::get_playlist_folder_ancestors(conn, parent_id).map_err(|e| e.to_string())?;
+        if parent_id == folder_id || ancestors.contains(&folder_id) {
+            return Err("Cannot move a folder into itself".to_string());
+        }
+    }
+    let moves = queries::move_playlist_tree_item(conn, item, parent_id, index)
+        .map_err(|e| e.to_string())?;
+    enqueue_tree_moves(conn, &moves);
+    Ok(())
+}
+
+/// Every playlist folder; playlists carry their `folder_id` and `folder_position`
+#[tauri::command]
+pub async fn get_playlist_folders(db: State<'_, Database>) -> Result<Vec<PlaylistFolder>, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    queries::get_playlist_folders(&conn).map_err(|e| e.to_string())
+}
+
+/// Create a folder at the end of `parent_id` (the top level when omitted)
+#[tauri::command]
+pub async fn create_playlist_folder(
+    name: String,
+    parent_id: Option<i64>,
+    db: State<'_, Database>,
+) -> Result<i64, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    ensure_folder_exists(&conn, parent_id)?;
+    let id = queries::create_playlist_folder(&conn, folder_name(&name)?, parent_id)
+        .map_err(|e| e.to_string())?;
+    enqueue_folder_change(&conn, id, "create");
+    Ok(id)
+}
+
+#[tauri::command]
+pub async fn rename_playlist_folder(
+    folder_id: i64,
+    new_name: String,
+    db: State<'_, Database>,
+) -> Result<(), String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    ensure_folder_exists(&conn, Some(folder_id))?;
+    queries::rename_playlist_folder(&conn, folder_id, folder_name(&new_name)?)
+        .map_err(|e| e.to_string())?;
+    enqueue_folder_change(&conn, folder_id, "update");
+    Ok(())
+}
+
+/// Delete a folder. Its playlists and subfolders take its place in the parent.
+#[tauri::command]
+pub async fn delete_playlist_folder(folder_id: i64, db: State<'_, Database>) -> Result<(), String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    let moves = queries::delete_playlist_folder(&conn, folder_id).map_err(|e| e.to_string())?;
+    enqueue_tree_moves(&conn, &moves);
+    enqueue_folder_change(&conn, folder_id, "delete");
+    Ok(())
+}
+
+/// Move a folder into `parent_id` (the top level when None) at `index` among
+/// the folders and playlists there
+#[tauri::command]
+pub async fn move_playlist_folder(
+    folder_id: i64,
+    parent_id: Option<i64>,
+    index: usize,
+    db: State<'_, Database>,
+) -> Result<(), String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    ensure_folder_exists(&conn, Some(folder_id))?;
+    move_item(&conn, PlaylistTreeItem::Folder(folder_id), parent_id, index)
+}
+
+/// Move a playlist into `folder_id` (the top level when None) at `index`
+/// among the folders and playlists there
+#[tauri::command]
+pub async fn move_playlist(
+    playlist_id: i64,
+    folder_id: Option<i64>,
+    index: usize,
+    db: State<'_, Database>,
+) -> Result<(), String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    move_item(
+        &conn,
+        PlaylistTreeItem::Playlist(playlist_id),
+        folder_id,
+        index,
+    )
+}
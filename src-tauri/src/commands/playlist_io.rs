@@ -0,0 +1,440 @@
+//! Playlist import and export
+//!
+//! Exports write a playlist as M3U/M3U8, PLS, XSPF or JSON. Imports create a
+//! new playlist from such a file: each entry is resolved to a library track by
+//! its path, then by artist, title and length, and entries that match nothing
+//! are listed in the report.
+
+use super::playlist::{
+    enqueue_playlist_create, enqueue_playlist_entry_change, enqueue_playlist_update,
+};
+use crate::db::queries::{self, PlaylistSortOrder, TrackMatchCandidate};
+use crate::db::search::levenshtein;
+use crate::db::Database;
+use crate::scanner::playlist_formats::{
+    read_playlist_file, write_playlist_file, PathMode, PlaylistFile, PlaylistFileEntry,
+    PlaylistFormat,
+};
+use crate::utils::normalize_words;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use tauri::State;
+
+/// Names at least this alike (0 to 1) count as the same
+const MIN_NAME_SIMILARITY: f64 = 0.85;
+/// Tracks whose lengths differ by more than this many seconds are different
+const MAX_DURATION_DIFF: i32 = 10;
+
+/// How an entry was resolved to a library track
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryMatch {
+    Path,
+    /// By artist, title and length
+    Metadata,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UnmatchedEntry {
+    /// Position in the playlist file
+    pub index: usize,
+    pub location: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub duration: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaylistImportReport {
+    pub playlist_id: i64,
+    pub name: String,
+    pub total: usize,
+    pub matched_by_path: usize,
+    pub matched_by_metadata: usize,
+    pub unmatched: Vec<UnmatchedEntry>,
+}
+
+/// 1 for equal names, less the more edits apart they are
+fn name_similarity(a: &str, b: &str) -> f64 {
+    if a == b {
+        return 1.0;
+    }
+    let longest = a.chars().count().max(b.chars().count());
+    1.0 - levenshtein(a, b) as f64 / longest as f64
+}
+
+/// (artist, title) from the entry, or guessed from a file name like
+/// "01 - Artist - Title"
+fn entry_names(entry: &PlaylistFileEntry) -> (Option<String>, Option<String>) {
+    if entry.title.is_some() {
+        return (entry.artist.clone(), entry.title.clone());
+    }
+    let Some(stem) = Path::new(&entry.location).file_stem() else {
+        return (None, None);
+    };
+    let stem = stem.to_string_lossy();
+    let stem = stem
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .trim_start_matches([' ', '.', '-', '_']);
+    match stem.split_once(" - ") {
+        Some((artist, title)) => (Some(artist.to_string()), Some(title.to_string())),
+        None => (None, Some(stem.to_string())),
+    }
+}
+
+/// Library tracks indexed by normalised title and artist
+struct TrackIndex {
+    tracks: Vec<TrackMatchCandidate>,
+    by_title: HashMap<String, Vec<usize>>,
+    by_artist: HashMap<String, Vec<usize>>,
+}
+
+impl TrackIndex {
+    fn new(tracks: Vec<TrackMatchCandidate>) -> Self {
+        let mut by_title: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut by_artist: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, track) in tracks.iter().enumerate() {
+            if let Some(ref title) = track.title {
+                by_title.entry(normalize_words(title)).or_default().push(i);
+            }
+            if let Some(ref artist) = track.artist {
+                by_artist
+                    .entry(normalize_words(artist))
+                    .or_default()
+                    .push(i);
+            }
+        }
+        TrackIndex {
+            tracks,
+            by_title,
+            by_artist,
+        }
+    }
+
+    /// The best track for an entry, or None if no track is close enough.
+    /// Candidates share the title, or the artist with a similar title.
+    fn find(&self, entry: &PlaylistFileEntry) -> Option<i64> {
+        let (artist, title) = entry_names(entry);
+        let title = normalize_words(&title?);
+        let artist = artist.map(|artist| normalize_words(&artist));
+        if title.is_empty() {
+            return None;
+        }
+
+        let mut candidates: Vec<usize> = self.by_title.get(&title).cloned().unwrap_or_default();
+        if let Some(same_artist) = artist.as_ref().and_then(|a| self.by_artist.get(a)) {
+            candidates.extend(same_artist.iter().filter(|&&i| {
+                let other = normalize_words(self.tracks[i].title.as_deref().unwrap_or_default());
+                other != title && name_similarity(&other, &title) >= MIN_NAME_SIMILARITY
+            }));
+        }
+        let unique_title = candidates.len() == 1;
+
+        // (score, prefers local files, smaller length difference)
+        let mut best: Option<((f64, bool, i32), i64)> = None;
+        for i in candidates {
+            let track = &self.tracks[i];
+            let track_title = normalize_words(track.title.as_deref().unwrap_or_default());
+            let mut score = name_similarity(&track_title, &title);
+            let duration_diff = match (entry.duration, track.duration) {
+                (Some(a), Some(b)) => Some((a - b).abs()),
+                _ => None,
+            };
+            if duration_diff.is_some_and(|diff| diff > MAX_DURATION_DIFF) {
+                continue;
+            }
+            match (&artist, &track.artist) {
+                (Some(artist), Some(track_artist)) => {
+                    let similarity = name_similarity(&normalize_words(track_artist), artist);
+                    if similarity < MIN_NAME_SIMILARITY {
+                        continue;
+                    }
+                    score *= similarity;
+                }
+                // Without an artist, only a close length or the only such title will do
+                _ if duration_diff.is_some_and(|diff| diff <= 3) || unique_title => {}
+                _ => continue,
+            }
+            let key = (
+                score,
+                !track.path.contains("://"),
+                -duration_diff.unwrap_or(MAX_DURATION_DIFF),
+            );
+            if best.as_ref().is_none_or(|(best_key, _)| key > *best_key) {
+                best = Some((key, track.id));
+            }
+        }
+        best.map(|(_, id)| id)
+    }
+}
+
+fn find_by_path(conn: &Connection, location: &str) -> Result<Option<i64>, String> {
+    if let Some(id) = queries::get_track_id_by_path(conn, location).map_err(|e| e.to_string())? {
+        return Ok(Some(id));
+    }
+    // The playlist may reach the file through a symlink or differently cased path
+    match Path::new(location).canonicalize() {
+        Ok(canonical) if canonical.to_string_lossy() != location => {
+            queries::get_track_id_by_path(conn, &canonical.to_string_lossy())
+                .map_err(|e| e.to_string())
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Library track for each entry: by path first, then by artist, title and length
+pub(crate) fn match_entries(
+    conn: &Connection,
+    entries: &[PlaylistFileEntry],
+) -> Result<Vec<Option<(i64, EntryMatch)>>, String> {
+    let mut index = None;
+    let mut matches = Vec::with_capacity(entries.len());
+    for entry in entries {
+        if let Some(id) = find_by_path(conn, &entry.location)? {
+            matches.push(Some((id, EntryMatch::Path)));
+            continue;
+        }
+        if index.is_none() {
+            let tracks = queries::get_track_match_candidates(conn).map_err(|e| e.to_string())?;
+            index = Some(TrackIndex::new(tracks));
+        }
+        let found = index.as_ref().and_then(|index| index.find(entry));
+        matches.push(found.map(|id| (id, EntryMatch::Metadata)));
+    }
+    Ok(matches)
+}
+
+/// A playlist with its tracks, ready to be written to a file
+pub(crate) fn playlist_file(conn: &Connection, playlist_id: i64) -> Result<PlaylistFile, String> {
+    let playlist = queries::get_all_playlists(conn)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|playlist| playlist.id == playlist_id)
+        .ok_or_else(|| "Playlist not found".to_string())?;
+    // Files keep the manual order whatever order the playlist is shown in
+    let tracks = match playlist.smart_rules {
+        Some(_) => queries::get_playlist_tracks(conn, playlist_id),
+        None => queries::get_playlist_entries(conn, playlist_id)
+            .map(|entries| entries.into_iter().map(|entry| entry.track).collect()),
+    }
+    .map_err(|e| e.to_string())?;
+    Ok(PlaylistFile {
+        name: Some(playlist.name),
+        cover_url: playlist.cover_url,
+        smart_rules: playlist.smart_rules,
+        description: playlist.description,
+        pinned: playlist.pinned,
+        sort_order: playlist.sort_order,
+        entries: tracks
+            .into_iter()
+            .map(|track| PlaylistFileEntry {
+                location: track.path,
+                title: track.title,
+                artist: track.artist,
+                album: track.album,
+                duration: track.duration,
+                track_number: track.track_number,
+                disc_number: track.disc_number,
+                external_id: track.external_id,
+                source_type: track.source_type,
+            })
+            .collect(),
+    })
+}
+
+/// Write a playlist to a file. The format follows the extension unless given.
+/// Returns the number of tracks written.
+#[tauri::command]
+pub async fn export_playlist(
+    playlist_id: i64,
+    path: String,
+    format: Option<PlaylistFormat>,
+    path_mode: Option<PathMode>,
+    db: State<'_, Database>,
+) -> Result<usize, String> {
+    let path = Path::new(&path);
+    // Only ever replace playlist files
+    let Some(extension_format) = PlaylistFormat::from_path(path) else {
+        return Err("Playlists can only be saved as .m3u, .m3u8, .pls, .xspf or .json".to_string());
+    };
+    let playlist = {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        playlist_file(&conn, playlist_id)?
+    };
+
+    log::info!("[AUDIT] Exporting playlist {} to {:?}", playlist_id, path);
+    write_playlist_file(
+        &playlist,
+        path,
+        format.unwrap_or(extension_format),
+        path_mode.unwrap_or_default(),
+    )?;
+    Ok(playlist.entries.len())
+}
+
+fn import(
+    conn: &Connection,
+    path: &Path,
+    name: Option<String>,
+) -> Result<PlaylistImportReport, String> {
+    let file = read_playlist_file(path)?;
+    let name = name
+        .or(file.name.clone())
+        .or_else(|| path.file_stem().map(|s| s.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "Imported playlist".to_string());
+    if let Some(ref rules) = file.smart_rules {
+        rules.validate()?;
+    }
+
+    let matches = if file.smart_rules.is_some() {
+        Vec::new()
+    } else {
+        match_entries(conn, &file.entries)?
+    };
+
+    let playlist_id = queries::create_playlist(conn, &name, file.cover_url.as_deref())
+        .map_err(|e| e.to_string())?;
+    if let Some(ref rules) = file.smart_rules {
+        queries::set_playlist_smart_rules(conn, playlist_id, Some(rules))
+            .map_err(|e| e.to_string())?;
+    }
+    enqueue_playlist_create(
+        conn,
+        playlist_id,
+        &name,
+        file.cover_url.as_deref(),
+        file.smart_rules.as_ref(),
+    );
+    if file.description.is_some() || file.pinned || file.sort_order != PlaylistSortOrder::Manual {
+        queries::set_playlist_description(conn, playlist_id, file.description.as_deref())
+            .and_then(|_| queries::set_playlist_pinned(conn, playlist_id, file.pinned))
+            .and_then(|_| queries::set_playlist_sort_order(conn, playlist_id, file.sort_order))
+            .map_err(|e| e.to_string())?;
+        enqueue_playlist_update(
+            conn,
+            playlist_id,
+            serde_json::json!({
+                "description": file.description,
+                "pinned": file.pinned,
+                "sortOrder": file.sort_order,
+            }),
+        );
+    }
+
+    let track_ids: Vec<i64> = matches.iter().flatten().map(|(id, _)| *id).collect();
+    let entry_ids = queries::insert_playlist_entries(conn, playlist_id, &track_ids, None)
+        .map_err(|e| e.to_string())?;
+    for entry_id in entry_ids {
+        enqueue_playlist_entry_change(conn, playlist_id, entry_id, "create");
+    }
+
+    let count = |kind| matches.iter().flatten().filter(|(_, k)| *k == kind).count();
+    Ok(PlaylistImportReport {
+        playlist_id,
+        name,
+        total: file.entries.len(),
+        matched_by_path: count(EntryMatch::Path),
+        matched_by_metadata: count(EntryMatch::Metadata),
+        // Smart playlists keep their rules and have no entries to match
+        unmatched: file
+            .entries
+            .iter()
+            .zip(&matches)
+            .enumerate()
+            .filter(|(_, (_, matched))| matched.is_none())
+            .map(|(index, (entry, _))| UnmatchedEntry {
+                index,
+                location: entry.location.clone(),
+                title: entry.title.clone(),
+                artist: entry.artist.clone(),
+                duration: entry.duration,
+            })
+            .collect(),
+    })
+}
+
+/// Create a playlist from an M3U/M3U8, PLS, XSPF or JSON file. Named after
+/// the file unless `name` is given.
+#[tauri::command]
+pub async fn import_playlist(
+    path: String,
+    name: Option<String>,
+    db: State<'_, Database>,
+) -> Result<PlaylistImportReport, String> {
+    let conn = db.conn.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let conn = conn.lock().map_err(|e| e.to_string())?;
+        import(&conn, Path::new(&path), name)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_matches_by_path_then_metadata() {
+        let dir = std::env::temp_dir().join(format!("audion_import_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let list = dir.join("list.m3u8");
+        std::fs::write(
+            &list,
+            "#EXTM3U\n\
+             #EXTINF:200,Daft Punk - One More Time\n\
+             /music/one.flac\n\
+             #EXTINF:321,Daft Punk - Digital Love\n\
+             /old/place/digital love.mp3\n\
+             /old/place/03 - Daft Punk - Aerodynamic.mp3\n\
+             #EXTINF:240,Someone Else - Digital Love\n\
+             /old/place/cover.mp3\n",
+        )
+        .unwrap();
+
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO tracks (id, path, title, artist, duration) VALUES
+                 (1, '/music/one.flac', 'One More Time', 'Daft Punk', 320),
+                 (2, '/music/digital.flac', 'Digital Love!', 'Daft Punk', 301),
+                 (3, '/music/aero.flac', 'Aerodynamic', 'Daft Punk', 212),
+                 (4, '/music/other.flac', 'Digital Love', 'Other Band', 240);",
+        )
+        .unwrap();
+
+        let report = import(&conn, &list, None).unwrap();
+        let tracks = queries::get_playlist_tracks(&conn, report.playlist_id).unwrap();
+
+        // Audion's JSON playlists bring their details along
+        let json = dir.join("details.json");
+        std::fs::write(
+            &json,
+            r#"{"format":"audion-playlist","version":1,"name":"Details",
+                "description":"Late","pinned":true,"sort_order":"title","entries":[]}"#,
+        )
+        .unwrap();
+        let details_id = import(&conn, &json, None).unwrap().playlist_id;
+        let details = queries::get_all_playlists(&conn)
+            .unwrap()
+            .into_iter()
+            .find(|p| p.id == details_id)
+            .unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(details.description.as_deref(), Some("Late"));
+        assert!(details.pinned);
+        assert_eq!(details.sort_order, PlaylistSortOrder::Title);
+
+        assert_eq!(report.name, "list");
+        assert_eq!(report.total, 4);
+        assert_eq!(report.matched_by_path, 1);
+        assert_eq!(report.matched_by_metadata, 1);
+        let ids: Vec<i64> = tracks.iter().map(|t| t.id).collect();
+        assert_eq!(ids, [1, 3]);
+        // Digital Love is 20 seconds off, the cover is by an unknown band
+        let unmatched: Vec<usize> = report.unmatched.iter().map(|u| u.index).collect();
+        assert_eq!(unmatched, [1, 3]);
+    }
+}
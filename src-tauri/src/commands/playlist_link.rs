@@ -0,0 +1,488 @@
+//! Playlists linked to playlist files
+//!
+//! A linked playlist is written to its file after every change made in Audion,
+//! and re-read from it on rescan when another player edited the file. Hashes of
+//! both sides at the last sync tell which one changed; when both did, the sync
+//! stops with a conflict until one side is chosen. File entries that match no
+//! track are kept with their positions and written back in place.
+
+use super::playlist::{enqueue_playlist_entry_change, ensure_not_smart};
+use super::playlist_io::{match_entries, playlist_file};
+use crate::db::queries::{self, PlaylistLink};
+use crate::db::Database;
+use crate::scanner::playlist_formats::{
+    parse_playlist_file, write_playlist_file, PathMode, PlaylistFileEntry, PlaylistFormat,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tauri::State;
+
+/// Which side wins when both changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncResolution {
+    KeepPlaylist,
+    KeepFile,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistSyncOutcome {
+    Unchanged,
+    /// The playlist was written to the file
+    Written,
+    /// The playlist's tracks were replaced by the file's
+    Read,
+    /// Both changed since the last sync; nothing was touched
+    Conflict,
+    /// The file is gone; the next change in Audion writes it again
+    Missing,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaylistSyncReport {
+    pub playlist_id: i64,
+    pub path: String,
+    pub outcome: PlaylistSyncOutcome,
+    /// Entries of a read file that match no library track
+    pub unmatched: usize,
+    pub error: Option<String>,
+}
+
+fn hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn playlist_track_ids(conn: &Connection, playlist_id: i64) -> Result<Vec<i64>, String> {
+    Ok(queries::get_playlist_entries(conn, playlist_id)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|entry| entry.track.id)
+        .collect())
+}
+
+fn entries_hash(track_ids: &[i64]) -> String {
+    let ids: Vec<String> = track_ids.iter().map(|id| id.to_string()).collect();
+    hash(ids.join(",").as_bytes())
+}
+
+/// A file entry that matched no track, with its index in the file. These are
+/// written back in place so another player's entries survive edits in Audion.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UnmatchedFileEntry {
+    position: usize,
+    entry: PlaylistFileEntry,
+}
+
+fn stored_unmatched(link: &PlaylistLink) -> Vec<UnmatchedFileEntry> {
+    link.unmatched
+        .as_deref()
+        .and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_default()
+}
+
+fn write_file(conn: &Connection, link: &PlaylistLink) -> Result<(), String> {
+    let path = Path::new(&link.path);
+    let format = PlaylistFormat::from_path(path)
+        .ok_or_else(|| format!("Unsupported playlist file: {}", link.path))?;
+    let mut playlist = playlist_file(conn, link.playlist_id)?;
+    for unmatched in stored_unmatched(link) {
+        let at = unmatched.position.min(playlist.entries.len());
+        playlist.entries.insert(at, unmatched.entry);
+    }
+    write_playlist_file(&playlist, path, format, PathMode::Relative)?;
+
+    let written =
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", link.path, e))?;
+    let track_ids = playlist_track_ids(conn, link.playlist_id)?;
+    queries::set_playlist_link_hashes(
+        conn,
+        link.playlist_id,
+        &hash(&written),
+        &entries_hash(&track_ids),
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Largest middle section (entries x tracks) diffed entry by entry; past this
+/// it is replaced as a whole
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Turn the playlist's entries into `track_ids` with as few removals and
+/// insertions as possible, so entries still in place keep their IDs and
+/// added dates
+fn apply_track_ids(conn: &Connection, playlist_id: i64, track_ids: &[i64]) -> Result<(), String> {
+    let entries = queries::get_playlist_entries(conn, playlist_id).map_err(|e| e.to_string())?;
+    let current: Vec<i64> = entries.iter().map(|entry| entry.track.id).collect();
+    if current == track_ids {
+        return Ok(());
+    }
+
+    // Only the part between the common prefix and suffix needs diffing
+    let prefix = current
+        .iter()
+        .zip(track_ids)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = current[prefix..]
+        .iter()
+        .rev()
+        .zip(track_ids[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old = &current[prefix..current.len() - suffix];
+    let new = &track_ids[prefix..track_ids.len() - suffix];
+
+    // Longest common subsequence lengths of old[i..] and new[j..]
+    let (n, m) = (old.len(), new.len());
+    let lcs = if n * m <= MAX_DIFF_CELLS {
+        let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i][j] = if old[i] == new[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+        Some(lcs)
+    } else {
+        None
+    };
+
+    let mut removed = Vec::new();
+    let mut inserted: Vec<(usize, i64)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        let keep = lcs.is_some() && i < n && j < m && old[i] == new[j];
+        let insert = match &lcs {
+            _ if i == n => true,
+            _ if j == m => false,
+            Some(lcs) => lcs[i][j + 1] >= lcs[i + 1][j],
+            None => false,
+        };
+        if keep {
+            i += 1;
+            j += 1;
+        } else if insert && j < m {
+            inserted.push((prefix + j, new[j]));
+            j += 1;
+        } else {
+            removed.push(entries[prefix + i].entry_id);
+            i += 1;
+        }
+    }
+
+    queries::remove_playlist_entries(conn, playlist_id, &removed).map_err(|e| e.to_string())?;
+    for entry_id in removed {
+        enqueue_playlist_entry_change(conn, playlist_id, entry_id, "delete");
+    }
+    // Insert runs of consecutive tracks in order; earlier positions are
+    // already filled when each run goes in
+    let mut runs: Vec<(usize, Vec<i64>)> = Vec::new();
+    for (index, track_id) in inserted {
+        match runs.last_mut() {
+            Some((start, run)) if *start + run.len() == index => run.push(track_id),
+            _ => runs.push((index, vec![track_id])),
+        }
+    }
+    for (index, run) in runs {
+        let entry_ids = queries::insert_playlist_entries(conn, playlist_id, &run, Some(index))
+            .map_err(|e| e.to_string())?;
+        for entry_id in entry_ids {
+            enqueue_playlist_entry_change(conn, playlist_id, entry_id, "create");
+        }
+    }
+    Ok(())
+}
+
+/// Make the playlist's tracks the file's. Returns the number of entries that
+/// match no track; they are kept aside and written back with the playlist.
+fn read_file(conn: &Connection, link: &PlaylistLink, content: &[u8]) -> Result<usize, String> {
+    let file = parse_playlist_file(Path::new(&link.path), content)?;
+    let matches = match_entries(conn, &file.entries)?;
+    let track_ids: Vec<i64> = matches.iter().flatten().map(|(id, _)| *id).collect();
+    let unmatched: Vec<UnmatchedFileEntry> = file
+        .entries
+        .iter()
+        .zip(&matches)
+        .enumerate()
+        .filter(|(_, (_, matched))| matched.is_none())
+        .map(|(position, (entry, _))| UnmatchedFileEntry {
+            position,
+            entry: entry.clone(),
+        })
+        .collect();
+
+    apply_track_ids(conn, link.playlist_id, &track_ids)?;
+    let unmatched_json = match unmatched.is_empty() {
+        true => None,
+        false => Some(serde_json::to_string(&unmatched).map_err(|e| e.to_string())?),
+    };
+    queries::set_playlist_link_unmatched(conn, link.playlist_id, unmatched_json.as_deref())
+        .map_err(|e| e.to_string())?;
+    queries::set_playlist_link_hashes(
+        conn,
+        link.playlist_id,
+        &hash(content),
+        &entries_hash(&track_ids),
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(unmatched.len())
+}
+
+fn sync(
+    conn: &Connection,
+    link: &PlaylistLink,
+    resolution: Option<SyncResolution>,
+) -> Result<(PlaylistSyncOutcome, usize), String> {
+    let content = match std::fs::read(&link.path) {
+        Ok(content) => Some(content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(format!("Failed to read {}: {}", link.path, e)),
+    };
+    let track_ids = playlist_track_ids(conn, link.playlist_id)?;
+    // Before the first sync only a playlist with tracks has anything to keep
+    let playlist_changed = match link.entries_hash {
+        Some(ref stored) => *stored != entries_hash(&track_ids),
+        None => !track_ids.is_empty(),
+    };
+    let never_synced = link.file_hash.is_none();
+
+    let write = || write_file(conn, link).map(|_| (PlaylistSyncOutcome::Written, 0));
+    let read =
+        |content: &[u8]| read_file(conn, link, content).map(|n| (PlaylistSyncOutcome::Read, n));
+    match (content, resolution) {
+        (_, Some(SyncResolution::KeepPlaylist)) => write(),
+        (Some(content), Some(SyncResolution::KeepFile)) => read(&content),
+        (None, Some(SyncResolution::KeepFile)) => Err("The playlist file is missing".to_string()),
+        (None, None) if playlist_changed || never_synced => write(),
+        (None, None) => Ok((PlaylistSyncOutcome::Missing, 0)),
+        (Some(content), None) => {
+            let file_changed = link.file_hash.as_deref() != Some(hash(&content).as_str());
+            match (file_changed, playlist_changed) {
+                (true, true) => Ok((PlaylistSyncOutcome::Conflict, 0)),
+                (true, false) => read(&content),
+                (false, true) => write(),
+                (false, false) => Ok((PlaylistSyncOutcome::Unchanged, 0)),
+            }
+        }
+    }
+}
+
+/// Bring a linked playlist and its file in line. Without a resolution, a
+/// playlist and file that both changed are left as they are.
+pub(crate) fn sync_link(
+    conn: &Connection,
+    link: &PlaylistLink,
+    resolution: Option<SyncResolution>,
+) -> PlaylistSyncReport {
+    let (outcome, unmatched, error) = match sync(conn, link, resolution) {
+        Ok((outcome, unmatched)) => (outcome, unmatched, None),
+        Err(e) => (PlaylistSyncOutcome::Failed, 0, Some(e)),
+    };
+    match outcome {
+        PlaylistSyncOutcome::Conflict => log::warn!(
+            "[Playlists] Playlist {} and {} both changed",
+            link.playlist_id,
+            link.path
+        ),
+        PlaylistSyncOutcome::Failed => log::warn!(
+            "[Playlists] Failed to sync playlist {} with {}: {}",
+            link.playlist_id,
+            link.path,
+            error.as_deref().unwrap_or_default()
+        ),
+        _ => {}
+    }
+    PlaylistSyncReport {
+        playlist_id: link.playlist_id,
+        path: link.path.clone(),
+        outcome,
+        unmatched,
+        error,
+    }
+}
+
+/// Sync every linked playlist, e.g. after a rescan
+pub(crate) fn sync_linked_playlists(conn: &Connection) -> Vec<PlaylistSyncReport> {
+    match queries::get_playlist_links(conn) {
+        Ok(links) => links
+            .iter()
+            .map(|link| sync_link(conn, link, None))
+            .collect(),
+        Err(e) => {
+            log::warn!("[Playlists] Failed to load linked playlists: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Write a playlist's file after it was edited, if it has one
+pub(crate) fn write_back(conn: &Connection, playlist_id: i64) {
+    match queries::get_playlist_link(conn, playlist_id) {
+        Ok(Some(link)) => {
+            sync_link(conn, &link, None);
+        }
+        Ok(None) => {}
+        Err(e) => log::warn!(
+            "[Playlists] Failed to load the link of {}: {}",
+            playlist_id,
+            e
+        ),
+    }
+}
+
+/// Link a playlist to a playlist file and sync them. A new file is written
+/// from the playlist and an empty playlist is filled from an existing file;
+/// otherwise `resolution` picks the side to keep.
+#[tauri::command]
+pub async fn link_playlist_file(
+    playlist_id: i64,
+    path: String,
+    resolution: Option<SyncResolution>,
+    db: State<'_, Database>,
+) -> Result<PlaylistSyncReport, String> {
+    if !Path::new(&path).is_absolute() {
+        return Err("The playlist file needs an absolute path".to_string());
+    }
+    if PlaylistFormat::from_path(Path::new(&path)).is_none() {
+        return Err(
+            "Playlists can only be linked to .m3u, .m3u8, .pls, .xspf or .json files".to_string(),
+        );
+    }
+
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    ensure_not_smart(&conn, playlist_id)?;
+    queries::set_playlist_link(&conn, playlist_id, Some(&path)).map_err(|e| e.to_string())?;
+    let link = queries::get_playlist_link(&conn, playlist_id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Playlist not found".to_string())?;
+
+    log::info!("[AUDIT] Linking playlist {} to {:?}", playlist_id, path);
+    Ok(sync_link(&conn, &link, resolution))
+}
+
+/// Stop syncing a playlist with its file. The file is left in place.
+#[tauri::command]
+pub async fn unlink_playlist_file(playlist_id: i64, db: State<'_, Database>) -> Result<(), String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    queries::set_playlist_link(&conn, playlist_id, None).map_err(|e| e.to_string())
+}
+
+/// Sync a linked playlist now, e.g. to settle a conflict with `resolution`
+#[tauri::command]
+pub async fn sync_playlist_file(
+    playlist_id: i64,
+    resolution: Option<SyncResolution>,
+    db: State<'_, Database>,
+) -> Result<PlaylistSyncReport, String> {
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    let link = queries::get_playlist_link(&conn, playlist_id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Playlist is not linked to a file".to_string())?;
+    Ok(sync_link(&conn, &link, resolution))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sync_now(conn: &Connection, resolution: Option<SyncResolution>) -> PlaylistSyncOutcome {
+        let link = queries::get_playlist_link(conn, 1).unwrap().unwrap();
+        let report = sync_link(conn, &link, resolution);
+        assert_eq!(report.error, None);
+        report.outcome
+    }
+
+    #[test]
+    fn test_linked_playlist_syncs_both_ways() {
+        let dir = std::env::temp_dir().join(format!("audion_link_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("mix.m3u8");
+        let _ = std::fs::remove_file(&file);
+
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&conn).unwrap();
+        conn.execute_batch(&format!(
+            "INSERT INTO tracks (id, path, title, duration) VALUES
+                 (1, '{0}/a.mp3', 'A', 100),
+                 (2, '{0}/b.mp3', 'B', 200),
+                 (3, '{0}/c.mp3', 'C', 300);
+             INSERT INTO playlists (id, name) VALUES (1, 'Mix');",
+            dir.display()
+        ))
+        .unwrap();
+        queries::insert_playlist_entries(&conn, 1, &[1, 2], None).unwrap();
+        queries::set_playlist_link(&conn, 1, Some(&file.to_string_lossy())).unwrap();
+
+        // A new file is written, and nothing changes after that
+        assert_eq!(sync_now(&conn, None), PlaylistSyncOutcome::Written);
+        let written = std::fs::read_to_string(&file).unwrap();
+        assert!(written.contains("a.mp3") && written.contains("b.mp3"));
+        assert_eq!(sync_now(&conn, None), PlaylistSyncOutcome::Unchanged);
+
+        // Another player adds tracks around the existing ones
+        let entry_ids = |conn: &Connection| -> Vec<i64> {
+            queries::get_playlist_entries(conn, 1)
+                .unwrap()
+                .iter()
+                .map(|e| e.entry_id)
+                .collect()
+        };
+        let before = entry_ids(&conn);
+        std::fs::write(&file, "#EXTM3U\nc.mp3\na.mp3\nmissing.mp3\nb.mp3\n").unwrap();
+        let link = queries::get_playlist_link(&conn, 1).unwrap().unwrap();
+        let report = sync_link(&conn, &link, None);
+        assert_eq!(report.outcome, PlaylistSyncOutcome::Read);
+        assert_eq!(report.unmatched, 1);
+        assert_eq!(playlist_track_ids(&conn, 1).unwrap(), [3, 1, 2]);
+        // Entries still in the file keep their IDs
+        assert_eq!(entry_ids(&conn)[1..], before[..]);
+
+        // Sorting the view doesn't change the order in the file
+        queries::set_playlist_sort_order(&conn, 1, queries::PlaylistSortOrder::Title).unwrap();
+        write_file(
+            &conn,
+            &queries::get_playlist_link(&conn, 1).unwrap().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(sync_now(&conn, None), PlaylistSyncOutcome::Unchanged);
+        let written = std::fs::read_to_string(&file).unwrap();
+        let order: Vec<usize> = ["c.mp3", "a.mp3", "missing.mp3", "b.mp3"]
+            .iter()
+            .map(|name| written.find(name).unwrap())
+            .collect();
+        assert!(order.windows(2).all(|w| w[0] < w[1]));
+
+        // An edit in Audion keeps the unmatched entry in its place
+        let last = *entry_ids(&conn).last().unwrap();
+        queries::remove_playlist_entries(&conn, 1, &[last]).unwrap();
+        write_back(&conn, 1);
+        let written = std::fs::read_to_string(&file).unwrap();
+        let order: Vec<usize> = ["c.mp3", "a.mp3", "missing.mp3"]
+            .iter()
+            .map(|name| written.find(name).unwrap())
+            .collect();
+        assert!(order[0] < order[1] && order[1] < order[2]);
+        assert!(!written.contains("b.mp3"));
+        assert_eq!(sync_now(&conn, None), PlaylistSyncOutcome::Unchanged);
+
+        // Both sides change
+        queries::insert_playlist_entries(&conn, 1, &[1], None).unwrap();
+        std::fs::write(&file, "#EXTM3U\na.mp3\n").unwrap();
+        assert_eq!(sync_now(&conn, None), PlaylistSyncOutcome::Conflict);
+        assert_eq!(playlist_track_ids(&conn, 1).unwrap(), [3, 1, 1]);
+        assert_eq!(
+            sync_now(&conn, Some(SyncResolution::KeepPlaylist)),
+            PlaylistSyncOutcome::Written
+        );
+        let written = std::fs::read_to_string(&file).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(written.matches("a.mp3").count(), 2);
+    }
+}
@@ -0,0 +1,500 @@
+//! Batch tag editing for library files
+//!
+//! Edits are written with lofty's generic tag to the file's primary tag type
+//! (ID3v2 for MP3, Vorbis comments for FLAC/OGG/Opus, iTunes atoms for M4A),
+//! then the file is re-read so the library row matches what is on disk. The
+//! previous values are kept in the undo journal.
+
+use super::journal::{Journal, Operation, PreviousCover, TagSnapshot};
+use super::library::load_scan_rules;
+use crate::db::queries;
+use crate::db::Database;
+use crate::scanner::cover_storage::{self, ImageFormat};
+use crate::scanner::metadata::extract_metadata_with_issues;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use lofty::config::{ParseOptions, WriteOptions};
+use lofty::file::FileType;
+use lofty::picture::{MimeType, Picture, PictureType};
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::{ItemValue, Tag, TagItem, TagType};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use tauri::State;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum CoverEdit {
+    /// Replace the front cover with a base64-encoded JPEG or PNG
+    Set { data: String },
+    /// Remove the front cover
+    Remove,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TagEdit {
+    pub track_id: i64,
+    /// Field name to values. Several values write a multi-value field, no
+    /// values remove the field. Names are the friendly names below or the
+    /// format's own keys (a Vorbis comment name, an ID3v2 frame ID).
+    #[serde(default)]
+    pub fields: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub cover: Option<CoverEdit>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagEditResult {
+    pub track_id: i64,
+    pub path: Option<String>,
+    pub success: bool,
+    pub error: Option<String>,
+    /// Fields the file's tag format has no place for
+    pub skipped_fields: Vec<String>,
+}
+
+/// Friendly field names accepted for every format
+fn friendly_key(name: &str) -> Option<ItemKey> {
+    let key = match name.to_ascii_lowercase().replace([' ', '-'], "_").as_str() {
+        "title" => ItemKey::TrackTitle,
+        "subtitle" => ItemKey::TrackSubtitle,
+        "artist" => ItemKey::TrackArtist,
+        "artists" => ItemKey::TrackArtists,
+        "album" => ItemKey::AlbumTitle,
+        "album_artist" => ItemKey::AlbumArtist,
+        "genre" => ItemKey::Genre,
+        "composer" => ItemKey::Composer,
+        "lyricist" => ItemKey::Lyricist,
+        "conductor" => ItemKey::Conductor,
+        "producer" => ItemKey::Producer,
+        "remixer" => ItemKey::Remixer,
+        "year" => ItemKey::Year,
+        "date" => ItemKey::RecordingDate,
+        "release_date" => ItemKey::ReleaseDate,
+        "original_date" => ItemKey::OriginalReleaseDate,
+        "track_number" => ItemKey::TrackNumber,
+        "track_total" => ItemKey::TrackTotal,
+        "disc_number" => ItemKey::DiscNumber,
+        "disc_total" => ItemKey::DiscTotal,
+        "comment" => ItemKey::Comment,
+        "lyrics" => ItemKey::Lyrics,
+        "bpm" => ItemKey::Bpm,
+        "key" => ItemKey::InitialKey,
+        "mood" => ItemKey::Mood,
+        "grouping" => ItemKey::ContentGroup,
+        "compilation" => ItemKey::FlagCompilation,
+        "label" => ItemKey::Label,
+        "publisher" => ItemKey::Publisher,
+        "copyright" => ItemKey::CopyrightMessage,
+        "isrc" => ItemKey::Isrc,
+        "barcode" => ItemKey::Barcode,
+        "catalog_number" => ItemKey::CatalogNumber,
+        "language" => ItemKey::Language,
+        "title_sort" => ItemKey::TrackTitleSortOrder,
+        "artist_sort" => ItemKey::TrackArtistSortOrder,
+        "album_sort" => ItemKey::AlbumTitleSortOrder,
+        "album_artist_sort" => ItemKey::AlbumArtistSortOrder,
+        "musicbrainz_recording_id" => ItemKey::MusicBrainzRecordingId,
+        "musicbrainz_track_id" => ItemKey::MusicBrainzTrackId,
+        "musicbrainz_release_id" => ItemKey::MusicBrainzReleaseId,
+        "musicbrainz_release_group_id" => ItemKey::MusicBrainzReleaseGroupId,
+        "musicbrainz_artist_id" => ItemKey::MusicBrainzArtistId,
+        "musicbrainz_release_artist_id" => ItemKey::MusicBrainzReleaseArtistId,
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Replace `key`'s values in `tag`. Returns false if the tag format can't hold it.
+fn set_field(tag: &mut Tag, key: ItemKey, values: &[String]) -> bool {
+    tag.remove_key(&key);
+    // Free-form keys only exist in formats whose keys are arbitrary text
+    let free_form = matches!(key, ItemKey::Unknown(_))
+        && matches!(tag.tag_type(), TagType::VorbisComments | TagType::Ape);
+    if !free_form && key.map_key(tag.tag_type(), false).is_none() {
+        return false;
+    }
+    for value in values {
+        let item = TagItem::new(key.clone(), ItemValue::Text(value.clone()));
+        if free_form {
+            tag.push_unchecked(item);
+        } else {
+            tag.push(item);
+        }
+    }
+    true
+}
+
+/// What to do with a file's front cover
+pub enum FrontCover {
+    Keep,
+    Remove,
+    Set(Picture),
+}
+
+impl FrontCover {
+    fn from_edit(edit: Option<&CoverEdit>) -> Result<Self, String> {
+        let data = match edit {
+            None => return Ok(Self::Keep),
+            Some(CoverEdit::Remove) => return Ok(Self::Remove),
+            Some(CoverEdit::Set { data }) => data,
+        };
+        let bytes = STANDARD
+            .decode(data)
+            .map_err(|e| format!("Failed to decode cover: {}", e))?;
+        // Every supported format can embed JPEG and PNG, M4A nothing else
+        let mime_type = match ImageFormat::from_bytes(&bytes) {
+            Some(ImageFormat::Jpeg) => MimeType::Jpeg,
+            Some(ImageFormat::Png) => MimeType::Png,
+            _ => return Err("Cover must be a JPEG or PNG image".to_string()),
+        };
+        Ok(Self::Set(Picture::new_unchecked(
+            PictureType::CoverFront,
+            Some(mime_type),
+            None,
+            bytes,
+        )))
+    }
+}
+
+/// The primary tag of the file at `path` (empty if it has none yet)
+fn read_primary_tag(path: &Path) -> Result<Tag, String> {
+    let tagged_file = Probe::open(path)
+        .and_then(|probe| {
+            probe
+                .options(ParseOptions::new().read_properties(false))
+                .read()
+        })
+        .map_err(|e| format!("Failed to read tags: {}", e))?;
+    match tagged_file.file_type() {
+        FileType::Mpeg | FileType::Flac | FileType::Mp4 | FileType::Vorbis | FileType::Opus => {}
+        other => {
+            return Err(format!(
+                "Tag editing is not supported for {:?} files",
+                other
+            ))
+        }
+    }
+
+    let tag_type = tagged_file.primary_tag_type();
+    Ok(tagged_file
+        .primary_tag()
+        .cloned()
+        .unwrap_or_else(|| Tag::new(tag_type)))
+}
+
+/// Current values of the fields named in `names` in the file at `path`
+pub(crate) fn read_fields(
+    path: &Path,
+    names: &[&str],
+) -> Result<HashMap<String, Vec<String>>, String> {
+    let tag = read_primary_tag(path)?;
+    Ok(names
+        .iter()
+        .map(|name| (name.to_string(), field_values(&tag, name)))
+        .collect())
+}
+
+fn field_key(tag: &Tag, name: &str) -> ItemKey {
+    friendly_key(name).unwrap_or_else(|| ItemKey::from_key(tag.tag_type(), name))
+}
+
+fn field_values(tag: &Tag, name: &str) -> Vec<String> {
+    tag.get_strings(&field_key(tag, name))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Current values of the fields named in `fields`
+fn current_values(
+    tag: &Tag,
+    fields: &HashMap<String, Vec<String>>,
+) -> HashMap<String, Vec<String>> {
+    fields
+        .keys()
+        .map(|name| (name.clone(), field_values(tag, name)))
+        .collect()
+}
+
+fn front_cover(tag: &Tag) -> Option<&Picture> {
+    tag.pictures()
+        .iter()
+        .find(|picture| picture.pic_type() == PictureType::CoverFront)
+}
+
+/// Apply `fields` and `cover` to `tag` and write it to `path`. Returns the
+/// field names that were skipped.
+fn save_tags(
+    mut tag: Tag,
+    path: &Path,
+    fields: &HashMap<String, Vec<String>>,
+    cover: FrontCover,
+) -> Result<Vec<String>, String> {
+    let mut names: Vec<&String> = fields.keys().collect();
+    names.sort();
+    let mut skipped = Vec::new();
+    for name in names {
+        let key = field_key(&tag, name);
+        if !set_field(&mut tag, key, &fields[name]) {
+            skipped.push(name.clone());
+        }
+    }
+
+    match cover {
+        FrontCover::Keep => {}
+        FrontCover::Remove => tag.remove_picture_type(PictureType::CoverFront),
+        FrontCover::Set(picture) => {
+            tag.remove_picture_type(PictureType::CoverFront);
+            tag.push_picture(picture);
+        }
+    }
+
+    tag.save_to_path(path, WriteOptions::default())
+        .map_err(|e| format!("Failed to write tags: {}", e))?;
+    Ok(skipped)
+}
+
+/// Write `fields` and `cover` to the primary tag of the file at `path`.
+/// Returns the field names that were skipped.
+pub fn write_tags(
+    path: &Path,
+    fields: &HashMap<String, Vec<String>>,
+    cover: Option<&CoverEdit>,
+) -> Result<Vec<String>, String> {
+    let cover = FrontCover::from_edit(cover)?;
+    save_tags(read_primary_tag(path)?, path, fields, cover)
+}
+
+/// Re-read an edited file into its library row, including its covers
+fn refresh_track(conn: &Connection, track: &queries::Track) -> Result<(), String> {
+    let (Some(updated), _) = extract_metadata_with_issues(&track.path) else {
+        return Err("Tags written, but the file could not be re-read".to_string());
+    };
+    let (track_id, _) =
+        queries::insert_or_update_track(conn, &updated).map_err(|e| e.to_string())?;
+    if track_id != track.id {
+        return Err("Tags written, but the library entry could not be updated".to_string());
+    }
+
+    let rules = load_scan_rules(conn)?;
+    let (cover_path, _) = cover_storage::save_scanned_artwork(conn, track_id, &updated, &rules);
+    if cover_path.is_none() && track.track_cover_path.is_some() {
+        cover_storage::delete_track_cover_file(track.track_cover_path.as_deref())?;
+        queries::update_track_cover_path(conn, track_id, None).map_err(|e| e.to_string())?;
+    }
+
+    if let Ok(Some(track)) = queries::get_track_by_id(conn, track_id) {
+        let _ = queries::enqueue_track_sync_change(conn, &track, "update");
+    }
+    Ok(())
+}
+
+/// A track whose file may be tagged: local, present and inside the music folders
+fn editable_track(conn: &Mutex<Connection>, track_id: i64) -> Result<queries::Track, String> {
+    let conn = conn.lock().map_err(|e| e.to_string())?;
+    let track = queries::get_track_by_id(&conn, track_id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Track not found".to_string())?;
+    drop(conn);
+
+    let is_local =
+        track.source_type.as_deref().is_none_or(|s| s == "local") && !track.path.contains("://");
+    if !is_local {
+        return Err("Only local files can be tagged".to_string());
+    }
+    let file = Path::new(&track.path);
+    if !file.is_file() {
+        return Err("File not found".to_string());
+    }
+    if !crate::security::is_safe_path(file)? {
+        return Err("Security: File is outside the music folders".to_string());
+    }
+    Ok(track)
+}
+
+/// Apply one edit, recording the previous values in `journal`. Returns the
+/// skipped fields.
+fn apply_edit(
+    conn: &Mutex<Connection>,
+    track: &queries::Track,
+    edit: &TagEdit,
+    journal: &mut Journal,
+    snapshots: &mut Vec<TagSnapshot>,
+) -> Result<Vec<String>, String> {
+    let file = Path::new(&track.path);
+    let cover = FrontCover::from_edit(edit.cover.as_ref())?;
+    let tag = read_primary_tag(file)?;
+    let previous_cover = match cover {
+        FrontCover::Keep => PreviousCover::Unchanged,
+        _ => journal.backup_cover(front_cover(&tag))?,
+    };
+    let snapshot = TagSnapshot {
+        track_id: track.id,
+        path: track.path.clone(),
+        fields: current_values(&tag, &edit.fields),
+        cover: previous_cover,
+    };
+
+    log::info!("[AUDIT] Writing tags to {:?}", file);
+    let skipped = save_tags(tag, file, &edit.fields, cover)?;
+    snapshots.push(snapshot);
+    let conn = conn.lock().map_err(|e| e.to_string())?;
+    refresh_track(&conn, track)?;
+    Ok(skipped)
+}
+
+/// Write a file's previous tags back (used by undo)
+pub fn restore_tags(
+    conn: &Mutex<Connection>,
+    track_id: i64,
+    fields: &HashMap<String, Vec<String>>,
+    cover: FrontCover,
+) -> Result<(), String> {
+    let track = editable_track(conn, track_id)?;
+    let file = Path::new(&track.path);
+    log::info!("[AUDIT] Restoring tags of {:?}", file);
+    save_tags(read_primary_tag(file)?, file, fields, cover)?;
+    let conn = conn.lock().map_err(|e| e.to_string())?;
+    refresh_track(&conn, &track)
+}
+
+/// Apply `edits`, journalled as one operation under `description`
+pub(crate) fn edit_tags(
+    conn: &Mutex<Connection>,
+    description: &str,
+    edits: &[TagEdit],
+) -> Result<Vec<TagEditResult>, String> {
+    let mut journal = {
+        let conn = conn.lock().map_err(|e| e.to_string())?;
+        Journal::begin(&conn, description)?
+    };
+    let mut snapshots = Vec::new();
+
+    let results = edits
+        .iter()
+        .map(|edit| {
+            let track = editable_track(conn, edit.track_id);
+            let path = track.as_ref().ok().map(|track| track.path.clone());
+            let result = track
+                .and_then(|track| apply_edit(conn, &track, edit, &mut journal, &mut snapshots));
+            match result {
+                Ok(skipped_fields) => TagEditResult {
+                    track_id: edit.track_id,
+                    path,
+                    success: true,
+                    error: None,
+                    skipped_fields,
+                },
+                Err(e) => TagEditResult {
+                    track_id: edit.track_id,
+                    path,
+                    success: false,
+                    error: Some(e),
+                    skipped_fields: Vec::new(),
+                },
+            }
+        })
+        .collect();
+
+    let conn = conn.lock().map_err(|e| e.to_string())?;
+    journal.commit(&conn, &Operation::TagEdit { files: snapshots })?;
+    Ok(results)
+}
+
+/// Write tags to library files. Each file is edited independently, so one
+/// failure doesn't stop the rest of the batch. The previous values go to the
+/// undo journal.
+#[tauri::command]
+pub async fn edit_track_tags(
+    edits: Vec<TagEdit>,
+    db: State<'_, Database>,
+) -> Result<Vec<TagEditResult>, String> {
+    let conn = db.conn.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let description = format!("Edit tags of {} files", edits.len());
+        edit_tags(&conn, &description, &edits)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A FLAC file with STREAMINFO and padding blocks but no audio frames
+    fn write_empty_flac(path: &Path) {
+        let mut data = b"fLaC".to_vec();
+        data.extend([0x00, 0x00, 0x00, 0x22]);
+        data.extend([0x10, 0x00, 0x10, 0x00, 0, 0, 0, 0, 0, 0]);
+        // 44.1 kHz, 2 channels, 16 bits, 0 samples
+        data.extend([0x0A, 0xC4, 0x42, 0xF0, 0, 0, 0, 0]);
+        data.extend([0; 16]);
+        data.extend([0x81, 0x00, 0x00, 0x08]);
+        data.extend([0; 8]);
+        std::fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn test_write_tags_round_trip() {
+        let dir = std::env::temp_dir().join(format!("audion_tags_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("track.flac");
+        write_empty_flac(&path);
+
+        let mut png = Vec::new();
+        image::RgbImage::new(4, 4)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let fields = HashMap::from([
+            ("Title".to_string(), vec!["Song".to_string()]),
+            ("artist".to_string(), vec!["A".to_string(), "B".to_string()]),
+            ("MOOD_CUSTOM".to_string(), vec!["calm".to_string()]),
+        ]);
+        let cover = CoverEdit::Set {
+            data: STANDARD.encode(&png),
+        };
+        let skipped = write_tags(&path, &fields, Some(&cover)).unwrap();
+
+        let tag = lofty::read_from_path(&path)
+            .unwrap()
+            .primary_tag()
+            .cloned()
+            .unwrap();
+        let artists: Vec<_> = tag.get_strings(&ItemKey::TrackArtist).collect();
+        let custom = tag
+            .get_string(&ItemKey::Unknown("MOOD_CUSTOM".to_string()))
+            .map(str::to_string);
+        let pictures = tag.pictures().len();
+
+        // Removing a field and the cover
+        let removal = HashMap::from([("artist".to_string(), Vec::new())]);
+        write_tags(&path, &removal, Some(&CoverEdit::Remove)).unwrap();
+        let cleared = lofty::read_from_path(&path)
+            .unwrap()
+            .primary_tag()
+            .cloned()
+            .unwrap();
+        let webp = write_tags(
+            &path,
+            &HashMap::new(),
+            Some(&CoverEdit::Set {
+                data: STANDARD.encode(b"RIFF\0\0\0\0WEBPVP8 "),
+            }),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(skipped.is_empty());
+        assert_eq!(tag.title().as_deref(), Some("Song"));
+        assert_eq!(artists, ["A", "B"]);
+        assert_eq!(custom.as_deref(), Some("calm"));
+        assert_eq!(pictures, 1);
+        assert_eq!(cleared.artist(), None);
+        assert_eq!(cleared.title().as_deref(), Some("Song"));
+        assert!(cleared.pictures().is_empty());
+        assert!(webp.is_err());
+    }
+}
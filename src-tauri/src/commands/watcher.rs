@@ -0,0 +1,78 @@
+// Library watcher commands (live updates from the music folders)
+use crate::db::{queries, Database};
+use crate::scanner::walker::LibraryRules;
+use crate::scanner::watcher::LibraryWatcher;
+use serde::Serialize;
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+pub struct WatcherState(pub Mutex<Option<LibraryWatcher>>);
+
+impl WatcherState {
+    pub fn new() -> Self {
+        Self(Mutex::new(None))
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct WatcherStatus {
+    pub running: bool,
+    pub folders: Vec<String>,
+}
+
+impl WatcherStatus {
+    fn of(watcher: Option<&LibraryWatcher>) -> Self {
+        Self {
+            running: watcher.is_some(),
+            folders: watcher.map(|w| w.folders().to_vec()).unwrap_or_default(),
+        }
+    }
+}
+
+/// (Re)start watching every registered music folder. Applied batches are
+/// emitted to the frontend as `library-changed`.
+pub fn restart_library_watcher(app: &AppHandle) -> Result<WatcherStatus, String> {
+    let db = app.state::<Database>();
+    let folders = {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        queries::get_music_folders_with_settings(&conn).map_err(|e| e.to_string())?
+    };
+
+    let state = app.state::<WatcherState>();
+    let mut guard = state.0.lock().map_err(|e| e.to_string())?;
+    // Drop the old watcher first so its pending batch is flushed
+    *guard = None;
+
+    if folders.is_empty() {
+        return Ok(WatcherStatus::of(None));
+    }
+
+    let handle = app.clone();
+    let rules = LibraryRules::new(folders);
+    let watcher = LibraryWatcher::start(db.conn.clone(), rules, move |event| {
+        let _ = handle.emit("library-changed", &event);
+    })?;
+    *guard = Some(watcher);
+
+    Ok(WatcherStatus::of(guard.as_ref()))
+}
+
+#[tauri::command]
+pub async fn start_library_watcher(app: AppHandle) -> Result<WatcherStatus, String> {
+    restart_library_watcher(&app)
+}
+
+#[tauri::command]
+pub async fn stop_library_watcher(state: State<'_, WatcherState>) -> Result<(), String> {
+    let mut guard = state.0.lock().map_err(|e| e.to_string())?;
+    *guard = None;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_library_watcher_status(
+    state: State<'_, WatcherState>,
+) -> Result<WatcherStatus, String> {
+    let guard = state.0.lock().map_err(|e| e.to_string())?;
+    Ok(WatcherStatus::of(guard.as_ref()))
+}
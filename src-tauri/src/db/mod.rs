@@ -1,6 +1,8 @@
 // Database module for SQLite operations
 pub mod queries;
 pub mod schema;
+pub mod search;
+pub mod stats;
 
 use rusqlite::Connection;
 use std::path::PathBuf;
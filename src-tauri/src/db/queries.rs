@@ -37,6 +37,19 @@ pub struct Album {
     pub art_path: Option<String>,
 }
 
+/// Colours picked from an album's cover, as `#rrggbb`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlbumPalette {
+    /// The most common colour
+    pub dominant: String,
+    /// A prominent saturated colour, for accents
+    pub vibrant: String,
+    /// A prominent desaturated colour, for backgrounds
+    pub muted: String,
+    /// Black or white, whichever reads better on `dominant`
+    pub text: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Artist {
     pub name: String,
@@ -51,6 +64,68 @@ pub struct Playlist {
     pub cover_url: Option<String>,
     pub created_at: Option<String>,
     pub folder_path: Option<String>,
+    pub smart_rules: Option<SmartPlaylistRules>,
+    /// Playlist file kept in sync with this playlist
+    pub linked_file: Option<String>,
+    /// Playlist folder holding this playlist; None at the top level
+    pub folder_id: Option<i64>,
+    /// Order among the folders and playlists of the same parent
+    pub folder_position: f64,
+    pub description: Option<String>,
+    /// Last change to the details or tracks; the creation time until then
+    pub modified_at: Option<String>,
+    /// Pinned playlists are listed first
+    pub pinned: bool,
+    pub sort_order: PlaylistSortOrder,
+    /// Entries and their total length in seconds; None for smart playlists,
+    /// whose tracks depend on the library
+    pub track_count: Option<i64>,
+    pub duration: Option<i64>,
+}
+
+/// How a playlist's tracks are listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistSortOrder {
+    /// The order the entries were arranged in
+    #[default]
+    Manual,
+    /// When the entries were added to the playlist
+    DateAdded,
+    Title,
+    Artist,
+}
+
+impl PlaylistSortOrder {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PlaylistSortOrder::Manual => "manual",
+            PlaylistSortOrder::DateAdded => "date_added",
+            PlaylistSortOrder::Title => "title",
+            PlaylistSortOrder::Artist => "artist",
+        }
+    }
+
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "date_added" => PlaylistSortOrder::DateAdded,
+            "title" => PlaylistSortOrder::Title,
+            "artist" => PlaylistSortOrder::Artist,
+            _ => PlaylistSortOrder::Manual,
+        }
+    }
+
+    /// ORDER BY clause over `tracks t` joined with `playlist_tracks pt`
+    fn order_by(self) -> &'static str {
+        match self {
+            PlaylistSortOrder::Manual => "pt.position, pt.id",
+            PlaylistSortOrder::DateAdded => "pt.added_at, pt.position, pt.id",
+            PlaylistSortOrder::Title => "t.title COLLATE NOCASE, t.artist COLLATE NOCASE, pt.position, pt.id",
+            PlaylistSortOrder::Artist => {
+                "t.artist COLLATE NOCASE, t.album COLLATE NOCASE, t.disc_number, t.track_number, pt.position, pt.id"
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -73,6 +148,16 @@ pub struct TrackInsert {
     pub local_src: Option<String>,
     pub musicbrainz_recording_id: Option<String>,
     pub metadata_json: Option<String>,
+    /// File size in bytes, used to skip unchanged files on rescan
+    #[serde(default)]
+    pub file_size: Option<i64>,
+    /// File modification time in milliseconds since the Unix epoch
+    #[serde(default)]
+    pub file_mtime: Option<i64>,
+    /// Tags couldn't be read, so the title is the filename and other fields may
+    /// be missing
+    #[serde(default)]
+    pub metadata_fallback: bool,
 }
 
 // Track operations
@@ -87,8 +172,12 @@ pub fn insert_or_update_track(conn: &Connection, track: &TrackInsert) -> Result<
             )
             .ok();
 
-        if existing.is_some() {
-            // Duplicate detected - skip this track
+        if let Some(original_id) = existing {
+            // Duplicate detected - skip this track. Copies read from disk are
+            // remembered for the duplicates report.
+            if track.file_size.is_some() {
+                record_duplicate_file(conn, original_id, track)?;
+            }
             return Ok((0, false)); // Return tuple
         }
     }
@@ -130,11 +219,20 @@ pub fn insert_or_update_track(conn: &Connection, track: &TrackInsert) -> Result<
                 source_type = ?9,
                 cover_url = ?10,
                 external_id = ?11,
+                acoustic_fingerprint = CASE WHEN content_hash IS ?12 THEN acoustic_fingerprint END,
+                bpm = CASE WHEN content_hash IS ?12 THEN bpm END,
+                musical_key = CASE WHEN content_hash IS ?12 THEN musical_key END,
+                energy = CASE WHEN content_hash IS ?12 THEN energy END,
+                danceability = CASE WHEN content_hash IS ?12 THEN danceability END,
+                features_analysed_at = CASE WHEN content_hash IS ?12 THEN features_analysed_at END,
                 content_hash = ?12,
                 local_src = ?13,
                 disc_number = ?15,
                 musicbrainz_recording_id = ?16,
                 metadata_json = ?17,
+                file_size = ?18,
+                file_mtime = ?19,
+                metadata_fallback = ?20,
                 date_added = COALESCE(date_added, CURRENT_TIMESTAMP)
              WHERE id = ?14",
             params![
@@ -155,15 +253,19 @@ pub fn insert_or_update_track(conn: &Connection, track: &TrackInsert) -> Result<
                 track.disc_number,
                 track.musicbrainz_recording_id,
                 track.metadata_json,
+                track.file_size,
+                track.file_mtime,
+                track.metadata_fallback,
             ],
         )?;
+        forget_duplicate_file(conn, &track.path)?;
 
         Ok((track_id, false)) // Return (existing_id, was_new = false)
     } else {
         // insert new track
         conn.execute(
-            "INSERT INTO tracks (path, title, artist, album, track_number, duration, album_id, format, bitrate, source_type, cover_url, external_id, content_hash, local_src, disc_number, musicbrainz_recording_id, metadata_json, date_added)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, CURRENT_TIMESTAMP)",
+            "INSERT INTO tracks (path, title, artist, album, track_number, duration, album_id, format, bitrate, source_type, cover_url, external_id, content_hash, local_src, disc_number, musicbrainz_recording_id, metadata_json, file_size, file_mtime, metadata_fallback, date_added)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, CURRENT_TIMESTAMP)",
             params![
                 track.path,
                 track.title,
@@ -182,13 +284,41 @@ pub fn insert_or_update_track(conn: &Connection, track: &TrackInsert) -> Result<
                 track.disc_number,
                 track.musicbrainz_recording_id,
                 track.metadata_json,
+                track.file_size,
+                track.file_mtime,
+                track.metadata_fallback,
             ],
         )?;
+        let track_id = conn.last_insert_rowid();
+        forget_duplicate_file(conn, &track.path)?;
 
-        Ok((conn.last_insert_rowid(), true)) // Return (new_id, was_new = true)
+        Ok((track_id, true)) // Return (new_id, was_new = true)
     }
 }
 
+/// Remember a file that was skipped because `original_id` has identical audio
+fn record_duplicate_file(conn: &Connection, original_id: i64, track: &TrackInsert) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO duplicate_files (path, track_id, format, bitrate, file_size, duration)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            track.path,
+            original_id,
+            track.format,
+            track.bitrate,
+            track.file_size,
+            track.duration,
+        ],
+    )?;
+    Ok(())
+}
+
+/// A path that is now a track in its own right is no longer a skipped copy
+fn forget_duplicate_file(conn: &Connection, path: &str) -> Result<()> {
+    conn.execute("DELETE FROM duplicate_files WHERE path = ?1", params![path])?;
+    Ok(())
+}
+
 /// Update MusicBrainz Recording ID and/or genre for a track.
 /// Uses COALESCE so that passing `None` preserves the existing DB value.
 pub fn update_track_mb_data(
@@ -315,28 +445,134 @@ pub fn init_fts(conn: &Connection) -> Result<()> {
         CREATE TRIGGER IF NOT EXISTS tracks_au AFTER UPDATE ON tracks BEGIN
             INSERT INTO tracks_fts(tracks_fts, rowid, title, artist, album) VALUES('delete', old.id, old.title, old.artist, old.album);
             INSERT INTO tracks_fts(rowid, title, artist, album) VALUES (new.id, new.title, new.artist, new.album);
-        END;"
+        END;
+
+        -- Vocabulary view over the index, used for typo-tolerant search
+        CREATE VIRTUAL TABLE IF NOT EXISTS tracks_fts_vocab USING fts5vocab(tracks_fts, 'row');"
     )?;
     Ok(())
 }
 
-/// Search tracks using FTS5
+/// Search tracks using the query language parsed in `search.rs`.
+/// Text terms go through FTS5 and are ranked with bm25; field filters reuse the
+/// smart playlist compiler. When nothing matches, the search is retried with close
+/// spellings from the index, and if FTS itself fails a LIKE search is used instead.
 pub fn search_tracks(
     conn: &Connection,
     query: &str,
     limit: i32,
     offset: i32,
 ) -> Result<Vec<Track>> {
-    let mut stmt = conn.prepare(
-        "SELECT id, path, title, artist, album, track_number, duration, album_id, format, bitrate, source_type, cover_url, external_id, local_src, track_cover_path, disc_number, metadata_json, date_added 
-         FROM tracks 
-         WHERE id IN (SELECT rowid FROM tracks_fts WHERE tracks_fts MATCH ?1)
-         ORDER BY artist, album, disc_number, track_number, title
-         LIMIT ?2 OFFSET ?3",
-    )?;
+    let parsed = super::search::parse_query(query);
+    if parsed.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let tracks = match run_track_search(conn, &parsed, true, limit, offset) {
+        Ok(tracks) => tracks,
+        Err(e) => {
+            eprintln!("[Search] FTS query failed, falling back to LIKE: {}", e);
+            return run_track_search(conn, &parsed, false, limit, offset);
+        }
+    };
+
+    // Only retry with typo candidates when the query matches nothing at all,
+    // not when a later page is simply past the end of the results
+    if tracks.is_empty()
+        && (offset == 0 || run_track_search(conn, &parsed, true, 1, 0)?.is_empty())
+    {
+        if let Some(expanded) = super::search::expand_typos(conn, &parsed) {
+            return run_track_search(conn, &expanded, true, limit, offset);
+        }
+    }
+
+    Ok(tracks)
+}
+
+fn run_track_search(
+    conn: &Connection,
+    parsed: &super::search::ParsedQuery,
+    use_fts: bool,
+    limit: i32,
+    offset: i32,
+) -> Result<Vec<Track>> {
+    use rusqlite::types::Value;
+
+    let mut values: Vec<Value> = Vec::new();
+    let mut conditions: Vec<String> = Vec::new();
+    let mut from = SMART_PLAYLIST_FROM.to_string();
+    let mut order = "t.artist, t.album, t.disc_number, t.track_number, t.title".to_string();
+
+    if use_fts {
+        if let Some(expr) = parsed.fts_match() {
+            from.push_str(" JOIN tracks_fts ON tracks_fts.rowid = t.id");
+            conditions.push("tracks_fts MATCH ?".to_string());
+            values.push(Value::Text(expr));
+            // Column weights: title, artist, album
+            order = format!("bm25(tracks_fts, 3.0, 2.0, 1.0), {}", order);
+        }
+    }
+
+    for term in &parsed.terms {
+        if use_fts {
+            if term.negated {
+                conditions.push(
+                    "t.id NOT IN (SELECT rowid FROM tracks_fts WHERE tracks_fts MATCH ?)"
+                        .to_string(),
+                );
+                values.push(Value::Text(term.fts_expr()));
+            }
+            continue;
+        }
 
+        let columns: Vec<&str> = match term.column {
+            Some(col) => vec![col],
+            None => vec!["title", "artist", "album"],
+        };
+        let pattern = format!("%{}%", escape_like(&term.text));
+        let matches: Vec<String> = columns
+            .iter()
+            .map(|col| {
+                values.push(Value::Text(pattern.clone()));
+                format!("t.{} LIKE ? ESCAPE '\\'", col)
+            })
+            .collect();
+        let any = format!("({})", matches.join(" OR "));
+        conditions.push(if term.negated {
+            format!("NOT COALESCE({}, 0)", any)
+        } else {
+            any
+        });
+    }
+
+    for filter in &parsed.filters {
+        let cond = compile_smart_node(&filter.rule, &mut values);
+        conditions.push(if filter.negated {
+            format!("NOT COALESCE({}, 0)", cond)
+        } else {
+            cond
+        });
+    }
+
+    let where_clause = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    };
+    values.push(Value::Integer(limit as i64));
+    values.push(Value::Integer(offset as i64));
+
+    let sql = format!(
+        "SELECT t.id, t.path, t.title, t.artist, t.album, t.track_number, t.duration, t.album_id, t.format, t.bitrate, t.source_type, t.cover_url, t.external_id, t.local_src, t.track_cover_path, t.disc_number, t.metadata_json, t.date_added
+         {} {}
+         ORDER BY {}
+         LIMIT ? OFFSET ?",
+        from, where_clause, order
+    );
+
+    let mut stmt = conn.prepare(&sql)?;
     let tracks = stmt
-        .query_map(params![query, limit, offset], |row| {
+        .query_map(rusqlite::params_from_iter(values.iter()), |row| {
             Ok(Track {
                 id: row.get(0)?,
                 path: row.get(1)?,
@@ -604,12 +840,167 @@ pub fn update_track_cover_path(conn: &Connection, track_id: i64, path: Option<&s
 /// Update album art path
 pub fn update_album_art_path(conn: &Connection, album_id: i64, path: Option<&str>) -> Result<()> {
     conn.execute(
-        "UPDATE albums SET art_path = ?1 WHERE id = ?2",
+        "UPDATE albums SET art_path = ?1, art_hash = NULL, palette = NULL WHERE id = ?2",
+        params![path, album_id],
+    )?;
+    Ok(())
+}
+
+/// Set album art along with the hash of its contents, for sharing the file
+/// (and its palette) with other albums that have the same art
+pub fn set_album_art(conn: &Connection, album_id: i64, path: &str, hash: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE albums SET art_path = ?1, art_hash = ?2,
+             palette = (SELECT palette FROM albums
+                        WHERE art_hash = ?2 AND palette IS NOT NULL AND id != ?3 LIMIT 1)
+         WHERE id = ?3",
+        params![path, hash, album_id],
+    )?;
+    Ok(())
+}
+
+/// An album's art path and stored palette. A palette of `Some(None)` means it
+/// was computed but the art could not be decoded.
+pub fn get_album_palette(
+    conn: &Connection,
+    album_id: i64,
+) -> Result<Option<(Option<String>, Option<Option<AlbumPalette>>)>> {
+    conn.query_row(
+        "SELECT art_path, palette FROM albums WHERE id = ?1",
+        [album_id],
+        |row| {
+            let palette: Option<String> = row.get(1)?;
+            Ok((
+                row.get(0)?,
+                palette.map(|json| serde_json::from_str(&json).ok()),
+            ))
+        },
+    )
+    .optional()
+}
+
+/// Store an album's palette; None records that the art could not be decoded
+pub fn set_album_palette(
+    conn: &Connection,
+    album_id: i64,
+    palette: Option<&AlbumPalette>,
+) -> Result<()> {
+    let json = match palette {
+        Some(palette) => serde_json::to_string(palette)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?,
+        None => String::new(),
+    };
+    conn.execute(
+        "UPDATE albums SET palette = ?1 WHERE id = ?2",
+        params![json, album_id],
+    )?;
+    Ok(())
+}
+
+/// Albums with art but no palette yet (every album with art when `force` is set)
+pub fn get_albums_needing_palette(conn: &Connection, force: bool) -> Result<Vec<(i64, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, art_path FROM albums
+         WHERE art_path IS NOT NULL AND (?1 OR palette IS NULL)",
+    )?;
+    let rows = stmt.query_map([force], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+/// Art files of other albums whose art has this content hash
+pub fn find_album_art_by_hash(conn: &Connection, hash: &str, album_id: i64) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT art_path FROM albums
+         WHERE art_hash = ?1 AND art_path IS NOT NULL AND id != ?2",
+    )?;
+    let rows = stmt.query_map(params![hash, album_id], |row| row.get(0))?;
+    rows.collect()
+}
+
+/// Whether an album other than `album_id` uses this art file
+pub fn is_album_art_shared(conn: &Connection, path: &str, album_id: i64) -> Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM albums WHERE art_path = ?1 AND id != ?2)",
         params![path, album_id],
+        |row| row.get(0),
+    )
+}
+
+/// A local album without art, for looking it up online
+#[derive(Debug, Clone)]
+pub struct ArtLookupAlbum {
+    pub id: i64,
+    pub name: String,
+    pub artist: Option<String>,
+    /// From an earlier lookup or the tracks' tags
+    pub release_mbid: Option<String>,
+    /// One of the album's tracks, for matching it to its music folder
+    pub track_path: String,
+}
+
+/// Local albums without art that weren't looked up online in the last
+/// `retry_days` days (all of them when `force` is set)
+pub fn get_albums_for_art_lookup(
+    conn: &Connection,
+    force: bool,
+    retry_days: i64,
+) -> Result<Vec<ArtLookupAlbum>> {
+    let sql = format!(
+        "SELECT a.id, a.name, a.artist,
+                COALESCE(a.musicbrainz_release_id,
+                         MAX(CASE WHEN json_valid(t.metadata_json)
+                             THEN json_extract(t.metadata_json, '$.MusicBrainzReleaseId') END)),
+                MIN(t.path)
+         FROM albums a JOIN tracks t ON t.album_id = a.id
+         WHERE a.art_path IS NULL AND {}
+           AND (?1 OR a.art_lookup_at IS NULL
+                OR a.art_lookup_at < datetime('now', '-' || ?2 || ' days'))
+         GROUP BY a.id
+         ORDER BY a.id",
+        LOCAL_TRACKS_FILTER
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(params![force, retry_days], |row| {
+        Ok(ArtLookupAlbum {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            artist: row.get(2)?,
+            release_mbid: row.get(3)?,
+            track_path: row.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Record an online art lookup, with the release it resolved to if any
+pub fn mark_album_art_lookup(
+    conn: &Connection,
+    album_id: i64,
+    release_mbid: Option<&str>,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE albums
+         SET art_lookup_at = CURRENT_TIMESTAMP,
+             musicbrainz_release_id = COALESCE(?1, musicbrainz_release_id)
+         WHERE id = ?2",
+        params![release_mbid, album_id],
     )?;
     Ok(())
 }
 
+/// Albums without art, each with the path of one of its local tracks
+pub fn get_local_albums_without_art(conn: &Connection) -> Result<Vec<(i64, String)>> {
+    let sql = format!(
+        "SELECT a.id, MIN(t.path) FROM albums a JOIN tracks t ON t.album_id = a.id
+         WHERE a.art_path IS NULL AND {}
+         GROUP BY a.id",
+        LOCAL_TRACKS_FILTER
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
 /// Get album art path
 pub fn get_album_art_path(conn: &Connection, album_id: i64) -> Result<Option<String>> {
     conn.query_row(
@@ -860,25 +1251,55 @@ pub fn get_album_by_id(conn: &Connection, album_id: i64) -> Result<Option<Album>
 
 // Playlist operations
 pub fn create_playlist(conn: &Connection, name: &str, cover_url: Option<&str>) -> Result<i64> {
+    // New playlists go last among the top-level folders and playlists
+    let position = next_playlist_tree_position(conn, None)?;
     conn.execute(
-        "INSERT INTO playlists (name, cover_url) VALUES (?1, ?2)",
-        params![name, cover_url],
+        "INSERT INTO playlists (name, cover_url, folder_position) VALUES (?1, ?2, ?3)",
+        params![name, cover_url, position],
     )?;
     Ok(conn.last_insert_rowid())
 }
 
+/// Every playlist with its entry count and length, pinned ones first
 pub fn get_all_playlists(conn: &Connection) -> Result<Vec<Playlist>> {
-    let mut stmt =
-        conn.prepare("SELECT id, name, cover_url, created_at, folder_path FROM playlists ORDER BY name")?;
+    let mut stmt = conn.prepare(
+        "SELECT p.id, p.name, p.cover_url, p.created_at, p.folder_path, p.smart_rules,
+                p.linked_file, p.folder_id, p.folder_position, p.description,
+                COALESCE(p.modified_at, p.created_at), p.pinned, p.sort_order,
+                COALESCE(totals.track_count, 0), COALESCE(totals.duration, 0)
+         FROM playlists p
+         LEFT JOIN (
+             SELECT pt.playlist_id, COUNT(*) AS track_count,
+                    SUM(COALESCE(t.duration, 0)) AS duration
+             FROM playlist_tracks pt
+             INNER JOIN tracks t ON t.id = pt.track_id
+             GROUP BY pt.playlist_id
+         ) totals ON totals.playlist_id = p.id
+         ORDER BY p.pinned DESC, p.name",
+    )?;
 
     let playlists = stmt
         .query_map([], |row| {
+            let smart_rules: Option<SmartPlaylistRules> = row
+                .get::<_, Option<String>>(5)?
+                .and_then(|json| serde_json::from_str(&json).ok());
+            let is_smart = smart_rules.is_some();
             Ok(Playlist {
                 id: row.get(0)?,
                 name: row.get(1)?,
                 cover_url: row.get(2)?,
                 created_at: row.get(3)?,
                 folder_path: row.get(4)?,
+                smart_rules,
+                linked_file: row.get(6)?,
+                folder_id: row.get(7)?,
+                folder_position: row.get(8)?,
+                description: row.get(9)?,
+                modified_at: row.get(10)?,
+                pinned: row.get(11)?,
+                sort_order: PlaylistSortOrder::parse(&row.get::<_, String>(12)?),
+                track_count: if is_smart { None } else { Some(row.get(13)?) },
+                duration: if is_smart { None } else { Some(row.get(14)?) },
             })
         })?
         .collect::<Result<Vec<_>>>()?;
@@ -887,13 +1308,20 @@ pub fn get_all_playlists(conn: &Connection) -> Result<Vec<Playlist>> {
 }
 
 pub fn get_playlist_tracks(conn: &Connection, playlist_id: i64) -> Result<Vec<Track>> {
-    let mut stmt = conn.prepare(
+    // Smart playlists have no playlist_tracks rows; evaluate their rules instead
+    if let Some(rules) = get_playlist_smart_rules(conn, playlist_id)? {
+        return get_smart_playlist_tracks(conn, &rules);
+    }
+
+    let sort_order = get_playlist_sort_order(conn, playlist_id)?;
+    let mut stmt = conn.prepare(&format!(
         "SELECT t.id, t.path, t.title, t.artist, t.album, t.track_number, t.duration, t.album_id, t.format, t.bitrate, t.source_type, t.cover_url, t.external_id, t.local_src, t.track_cover, t.track_cover_path, t.disc_number, t.metadata_json, t.date_added 
          FROM tracks t
          INNER JOIN playlist_tracks pt ON t.id = pt.track_id
          WHERE pt.playlist_id = ?1
-         ORDER BY pt.position",
-    )?;
+         ORDER BY {}",
+        sort_order.order_by()
+    ))?;
 
     let tracks = stmt
         .query_map([playlist_id], |row| {
@@ -924,21 +1352,45 @@ pub fn get_playlist_tracks(conn: &Connection, playlist_id: i64) -> Result<Vec<Tr
     Ok(tracks)
 }
 
-pub fn add_track_to_playlist(conn: &Connection, playlist_id: i64, track_id: i64) -> Result<()> {
-    let position: i32 = conn.query_row(
-        "SELECT COALESCE(MAX(position), 0) + 1 FROM playlist_tracks WHERE playlist_id = ?1",
-        [playlist_id],
+/// Append a track to a playlist. Tracks may appear more than once; returns the new entry ID.
+/// Runs without its own transaction so it can be used inside batch imports.
+pub fn add_track_to_playlist(conn: &Connection, playlist_id: i64, track_id: i64) -> Result<i64> {
+    let position: f64 = conn.query_row(
+        "SELECT COALESCE(MAX(position), 0) + ?2 FROM playlist_tracks WHERE playlist_id = ?1",
+        params![playlist_id, PLAYLIST_POSITION_GAP],
         |row| row.get(0),
     )?;
 
     conn.execute(
-        "INSERT OR IGNORE INTO playlist_tracks (playlist_id, track_id, position) VALUES (?1, ?2, ?3)",
+        "INSERT INTO playlist_tracks (playlist_id, track_id, position) VALUES (?1, ?2, ?3)",
         params![playlist_id, track_id, position],
     )?;
 
-    Ok(())
+    Ok(conn.last_insert_rowid())
+}
+
+/// Append a track only if the playlist does not contain it yet (used by folder
+/// imports, which must stay idempotent across rescans). Returns the new entry ID.
+pub fn ensure_track_in_playlist(
+    conn: &Connection,
+    playlist_id: i64,
+    track_id: i64,
+) -> Result<Option<i64>> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM playlist_tracks WHERE playlist_id = ?1 AND track_id = ?2)",
+        params![playlist_id, track_id],
+        |row| row.get(0),
+    )?;
+
+    if exists {
+        Ok(None)
+    } else {
+        add_track_to_playlist(conn, playlist_id, track_id).map(Some)
+    }
 }
 
+/// Remove every entry of a track from a playlist (use `remove_playlist_entries`
+/// to remove a single occurrence).
 pub fn remove_track_from_playlist(
     conn: &Connection,
     playlist_id: i64,
@@ -951,6 +1403,240 @@ pub fn remove_track_from_playlist(
     Ok(())
 }
 
+// ============================================================================
+// Playlist entries (stable per-slot IDs with gap-based ordering)
+// ============================================================================
+
+/// Spacing between consecutive entries after an append or renumber. Inserts and
+/// moves take the midpoint of their neighbours, so only the moved rows change.
+pub const PLAYLIST_POSITION_GAP: f64 = 1024.0;
+
+/// Below this spacing the playlist is renumbered before inserting.
+const PLAYLIST_MIN_GAP: f64 = 1e-6;
+
+/// One slot in a playlist. Serialized flat, so the frontend sees a `Track` plus
+/// `entry_id` and `position`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistEntry {
+    pub entry_id: i64,
+    pub position: f64,
+    pub added_at: Option<String>,
+    #[serde(flatten)]
+    pub track: Track,
+}
+
+pub fn get_playlist_entries(conn: &Connection, playlist_id: i64) -> Result<Vec<PlaylistEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT pt.id, pt.position, pt.added_at, t.id, t.path, t.title, t.artist, t.album, t.track_number, t.duration, t.album_id, t.format, t.bitrate, t.source_type, t.cover_url, t.external_id, t.local_src, t.track_cover, t.track_cover_path, t.disc_number, t.metadata_json, t.date_added
+         FROM playlist_tracks pt
+         INNER JOIN tracks t ON t.id = pt.track_id
+         WHERE pt.playlist_id = ?1
+         ORDER BY pt.position, pt.id",
+    )?;
+
+    let entries = stmt
+        .query_map([playlist_id], |row| {
+            Ok(PlaylistEntry {
+                entry_id: row.get(0)?,
+                position: row.get(1)?,
+                added_at: row.get(2)?,
+                track: Track {
+                    id: row.get(3)?,
+                    path: row.get(4)?,
+                    title: row.get(5)?,
+                    artist: row.get(6)?,
+                    album: row.get(7)?,
+                    track_number: row.get(8)?,
+                    duration: row.get(9)?,
+                    album_id: row.get(10)?,
+                    format: row.get(11)?,
+                    bitrate: row.get(12)?,
+                    source_type: row.get(13)?,
+                    cover_url: row.get(14)?,
+                    external_id: row.get(15)?,
+                    local_src: row.get(16)?,
+                    track_cover: row.get(17)?,
+                    track_cover_path: row.get(18)?,
+                    disc_number: row.get(19)?,
+                    metadata_json: row.get(20)?,
+                    date_added: row.get(21)?,
+                },
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(entries)
+}
+
+/// Get a single entry's (playlist_id, track_id, position).
+pub fn get_playlist_entry(conn: &Connection, entry_id: i64) -> Result<Option<(i64, i64, f64)>> {
+    conn.query_row(
+        "SELECT playlist_id, track_id, position FROM playlist_tracks WHERE id = ?1",
+        [entry_id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )
+    .optional()
+}
+
+/// Ordered (entry_id, position) pairs of a playlist, skipping `exclude`.
+fn playlist_entry_positions(
+    conn: &Connection,
+    playlist_id: i64,
+    exclude: &[i64],
+) -> Result<Vec<(i64, f64)>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, position FROM playlist_tracks WHERE playlist_id = ?1 ORDER BY position, id",
+    )?;
+    let rows = stmt
+        .query_map([playlist_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(i64, f64)>>>()?;
+
+    Ok(rows
+        .into_iter()
+        .filter(|(id, _)| !exclude.contains(id))
+        .collect())
+}
+
+/// Re-space every entry of a playlist `gap` apart, keeping the current order.
+pub fn renumber_playlist_positions(conn: &Connection, playlist_id: i64, gap: f64) -> Result<()> {
+    let order = playlist_entry_positions(conn, playlist_id, &[])?;
+    let mut stmt = conn.prepare("UPDATE playlist_tracks SET position = ?1 WHERE id = ?2")?;
+    for (i, (entry_id, _)) in order.iter().enumerate() {
+        stmt.execute(params![(i as f64 + 1.0) * gap, entry_id])?;
+    }
+    Ok(())
+}
+
+/// Allocate `count` increasing positions for a slot at `index` (None = end) in
+/// the playlist as it looks without the `exclude` entries.
+fn allocate_playlist_positions(
+    conn: &Connection,
+    playlist_id: i64,
+    exclude: &[i64],
+    index: Option<usize>,
+    count: usize,
+) -> Result<Vec<f64>> {
+    let slots = count as f64 + 1.0;
+    let mut order = playlist_entry_positions(conn, playlist_id, exclude)?;
+    let index = index.unwrap_or(order.len()).min(order.len());
+    let neighbours = |order: &[(i64, f64)]| {
+        (
+            index.checked_sub(1).map(|i| order[i].1),
+            order.get(index).map(|(_, p)| *p),
+        )
+    };
+
+    if let (Some(lo), Some(hi)) = neighbours(&order) {
+        if (hi - lo) / slots < PLAYLIST_MIN_GAP {
+            // Midpoints exhausted: spread the playlist out wide enough for this insert
+            renumber_playlist_positions(conn, playlist_id, PLAYLIST_POSITION_GAP * slots)?;
+            order = playlist_entry_positions(conn, playlist_id, exclude)?;
+        }
+    }
+
+    let (lo, hi) = match neighbours(&order) {
+        (Some(lo), Some(hi)) => (lo, hi),
+        (Some(lo), None) => (lo, lo + PLAYLIST_POSITION_GAP * slots),
+        (None, Some(hi)) => (hi - PLAYLIST_POSITION_GAP * slots, hi),
+        (None, None) => (0.0, PLAYLIST_POSITION_GAP * slots),
+    };
+    let step = (hi - lo) / slots;
+
+    Ok((1..=count).map(|i| lo + step * i as f64).collect())
+}
+
+/// Insert tracks (duplicates allowed) at `index` (None = append), keeping their
+/// order. Returns the new entry IDs.
+pub fn insert_playlist_entries(
+    conn: &Connection,
+    playlist_id: i64,
+    track_ids: &[i64],
+    index: Option<usize>,
+) -> Result<Vec<i64>> {
+    if track_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    let positions = allocate_playlist_positions(&tx, playlist_id, &[], index, track_ids.len())?;
+
+    let mut entry_ids = Vec::with_capacity(track_ids.len());
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO playlist_tracks (playlist_id, track_id, position) VALUES (?1, ?2, ?3)",
+        )?;
+        for (track_id, position) in track_ids.iter().zip(positions) {
+            stmt.execute(params![playlist_id, track_id, position])?;
+            entry_ids.push(tx.last_insert_rowid());
+        }
+    }
+    tx.commit()?;
+
+    Ok(entry_ids)
+}
+
+/// Move entries so they sit together at `to_index` of the playlist as it looks
+/// without them, keeping their current relative order. Only the moved rows are
+/// updated unless the gaps need renumbering. Returns (entry_id, new_position).
+pub fn move_playlist_entries(
+    conn: &Connection,
+    playlist_id: i64,
+    entry_ids: &[i64],
+    to_index: usize,
+) -> Result<Vec<(i64, f64)>> {
+    let tx = conn.unchecked_transaction()?;
+
+    // Keep the moved entries in playlist order, ignoring IDs from other playlists
+    let moving: Vec<i64> = playlist_entry_positions(&tx, playlist_id, &[])?
+        .into_iter()
+        .map(|(id, _)| id)
+        .filter(|id| entry_ids.contains(id))
+        .collect();
+    if moving.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let positions = allocate_playlist_positions(&tx, playlist_id, &moving, Some(to_index), moving.len())?;
+    {
+        let mut stmt = tx.prepare("UPDATE playlist_tracks SET position = ?1 WHERE id = ?2")?;
+        for (entry_id, position) in moving.iter().zip(&positions) {
+            stmt.execute(params![position, entry_id])?;
+        }
+    }
+    tx.commit()?;
+
+    Ok(moving.into_iter().zip(positions).collect())
+}
+
+/// Remove specific entries from a playlist. Returns the number removed.
+pub fn remove_playlist_entries(
+    conn: &Connection,
+    playlist_id: i64,
+    entry_ids: &[i64],
+) -> Result<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let mut removed = 0;
+    {
+        let mut stmt =
+            tx.prepare("DELETE FROM playlist_tracks WHERE playlist_id = ?1 AND id = ?2")?;
+        for entry_id in entry_ids {
+            removed += stmt.execute(params![playlist_id, entry_id])?;
+        }
+    }
+    tx.commit()?;
+
+    Ok(removed)
+}
+
+/// Set an entry's position directly (used when applying synced moves).
+pub fn set_playlist_entry_position(conn: &Connection, entry_id: i64, position: f64) -> Result<()> {
+    conn.execute(
+        "UPDATE playlist_tracks SET position = ?1 WHERE id = ?2",
+        params![position, entry_id],
+    )?;
+    Ok(())
+}
+
 pub fn delete_playlist(conn: &Connection, playlist_id: i64) -> Result<()> {
     conn.execute("DELETE FROM playlists WHERE id = ?1", [playlist_id])?;
     Ok(())
@@ -976,6 +1662,50 @@ pub fn update_playlist_cover(
     Ok(())
 }
 
+pub fn set_playlist_description(
+    conn: &Connection,
+    playlist_id: i64,
+    description: Option<&str>,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE playlists SET description = ?1 WHERE id = ?2",
+        params![description, playlist_id],
+    )?;
+    Ok(())
+}
+
+pub fn set_playlist_pinned(conn: &Connection, playlist_id: i64, pinned: bool) -> Result<()> {
+    conn.execute(
+        "UPDATE playlists SET pinned = ?1 WHERE id = ?2",
+        params![pinned, playlist_id],
+    )?;
+    Ok(())
+}
+
+/// The order `get_playlist_tracks` lists a playlist in (manual for unknown playlists)
+pub fn get_playlist_sort_order(conn: &Connection, playlist_id: i64) -> Result<PlaylistSortOrder> {
+    let value: Option<String> = conn
+        .query_row(
+            "SELECT sort_order FROM playlists WHERE id = ?1",
+            params![playlist_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(value.map_or(PlaylistSortOrder::Manual, |v| PlaylistSortOrder::parse(&v)))
+}
+
+pub fn set_playlist_sort_order(
+    conn: &Connection,
+    playlist_id: i64,
+    sort_order: PlaylistSortOrder,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE playlists SET sort_order = ?1 WHERE id = ?2",
+        params![sort_order.as_str(), playlist_id],
+    )?;
+    Ok(())
+}
+
 pub fn get_folder_playlists(conn: &Connection) -> Result<Vec<(i64, String)>> {
     let mut stmt = conn.prepare(
         "SELECT id, folder_path FROM playlists WHERE folder_path IS NOT NULL"
@@ -996,104 +1726,1654 @@ pub fn set_playlist_folder_path(conn: &Connection, playlist_id: i64, folder_path
     Ok(())
 }
 
+/// A playlist file linked to a playlist, with hashes of both sides as they
+/// were at the last sync (None before the first one)
+#[derive(Debug, Clone)]
+pub struct PlaylistLink {
+    pub playlist_id: i64,
+    pub path: String,
+    pub file_hash: Option<String>,
+    pub entries_hash: Option<String>,
+    /// File entries that matched no track at the last read, as JSON
+    pub unmatched: Option<String>,
+}
+
+fn playlist_link_from_row(row: &rusqlite::Row) -> Result<PlaylistLink> {
+    Ok(PlaylistLink {
+        playlist_id: row.get(0)?,
+        path: row.get(1)?,
+        file_hash: row.get(2)?,
+        entries_hash: row.get(3)?,
+        unmatched: row.get(4)?,
+    })
+}
+
+pub fn get_playlist_link(conn: &Connection, playlist_id: i64) -> Result<Option<PlaylistLink>> {
+    conn.query_row(
+        "SELECT id, linked_file, linked_file_hash, linked_entries_hash, linked_unmatched
+         FROM playlists WHERE id = ?1 AND linked_file IS NOT NULL",
+        params![playlist_id],
+        playlist_link_from_row,
+    )
+    .optional()
+}
+
+pub fn get_playlist_links(conn: &Connection) -> Result<Vec<PlaylistLink>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, linked_file, linked_file_hash, linked_entries_hash, linked_unmatched
+         FROM playlists WHERE linked_file IS NOT NULL ORDER BY id",
+    )?;
+    let links = stmt
+        .query_map([], playlist_link_from_row)?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(links)
+}
+
+/// Link a playlist to a file (None to unlink), forgetting the last sync
+pub fn set_playlist_link(conn: &Connection, playlist_id: i64, path: Option<&str>) -> Result<()> {
+    conn.execute(
+        "UPDATE playlists SET linked_file = ?1, linked_file_hash = NULL, linked_entries_hash = NULL,
+                linked_unmatched = NULL
+         WHERE id = ?2",
+        params![path, playlist_id],
+    )?;
+    Ok(())
+}
+
+pub fn set_playlist_link_hashes(
+    conn: &Connection,
+    playlist_id: i64,
+    file_hash: &str,
+    entries_hash: &str,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE playlists SET linked_file_hash = ?1, linked_entries_hash = ?2 WHERE id = ?3",
+        params![file_hash, entries_hash, playlist_id],
+    )?;
+    Ok(())
+}
+
+pub fn set_playlist_link_unmatched(
+    conn: &Connection,
+    playlist_id: i64,
+    unmatched: Option<&str>,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE playlists SET linked_unmatched = ?1 WHERE id = ?2",
+        params![unmatched, playlist_id],
+    )?;
+    Ok(())
+}
+
+// ============================================================================
+// Playlist folders (a tree of folders holding playlists and subfolders)
+// ============================================================================
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistFolder {
+    pub id: i64,
+    pub name: String,
+    /// None at the top level
+    pub parent_id: Option<i64>,
+    /// Order among the folders and playlists of the same parent
+    pub position: f64,
+    pub created_at: Option<String>,
+}
+
+/// A folder or playlist placed in the folder tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "id", rename_all = "snake_case")]
+pub enum PlaylistTreeItem {
+    Folder(i64),
+    Playlist(i64),
+}
+
+/// An item whose place in the tree changed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaylistTreeMove {
+    pub item: PlaylistTreeItem,
+    pub parent_id: Option<i64>,
+    pub position: f64,
+}
+
+fn playlist_folder_from_row(row: &rusqlite::Row) -> Result<PlaylistFolder> {
+    Ok(PlaylistFolder {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        parent_id: row.get(2)?,
+        position: row.get(3)?,
+        created_at: row.get(4)?,
+    })
+}
+
+pub fn get_playlist_folders(conn: &Connection) -> Result<Vec<PlaylistFolder>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, name, parent_id, position, created_at FROM playlist_folders
+         ORDER BY parent_id, position, name",
+    )?;
+    let folders = stmt
+        .query_map([], playlist_folder_from_row)?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(folders)
+}
+
+pub fn get_playlist_folder(conn: &Connection, folder_id: i64) -> Result<Option<PlaylistFolder>> {
+    conn.query_row(
+        "SELECT id, name, parent_id, position, created_at FROM playlist_folders WHERE id = ?1",
+        params![folder_id],
+        playlist_folder_from_row,
+    )
+    .optional()
+}
+
+/// Position after the last item in `parent_id` (the top level when None)
+fn next_playlist_tree_position(conn: &Connection, parent_id: Option<i64>) -> Result<f64> {
+    conn.query_row(
+        "SELECT COALESCE(MAX(position), -1) + 1 FROM (
+             SELECT position FROM playlist_folders WHERE parent_id IS ?1
+             UNION ALL
+             SELECT folder_position FROM playlists WHERE folder_id IS ?1
+         )",
+        params![parent_id],
+        |row| row.get(0),
+    )
+}
+
+pub fn create_playlist_folder(
+    conn: &Connection,
+    name: &str,
+    parent_id: Option<i64>,
+) -> Result<i64> {
+    let position = next_playlist_tree_position(conn, parent_id)?;
+    conn.execute(
+        "INSERT INTO playlist_folders (name, parent_id, position) VALUES (?1, ?2, ?3)",
+        params![name, parent_id, position],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn rename_playlist_folder(conn: &Connection, folder_id: i64, name: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE playlist_folders SET name = ?1 WHERE id = ?2",
+        params![name, folder_id],
+    )?;
+    Ok(())
+}
+
+/// Folders and playlists directly in `parent_id`, in order
+pub fn get_playlist_tree_children(
+    conn: &Connection,
+    parent_id: Option<i64>,
+) -> Result<Vec<PlaylistTreeItem>> {
+    let mut stmt = conn.prepare(
+        "SELECT 0, id, position, name FROM playlist_folders WHERE parent_id IS ?1
+         UNION ALL
+         SELECT 1, id, folder_position, name FROM playlists WHERE folder_id IS ?1
+         ORDER BY 3, 1, 4",
+    )?;
+    let items = stmt
+        .query_map(params![parent_id], |row| {
+            let id = row.get(1)?;
+            Ok(match row.get::<_, i64>(0)? {
+                0 => PlaylistTreeItem::Folder(id),
+                _ => PlaylistTreeItem::Playlist(id),
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(items)
+}
+
+/// Place an item directly (used when applying synced changes)
+pub fn set_playlist_tree_position(
+    conn: &Connection,
+    item: PlaylistTreeItem,
+    parent_id: Option<i64>,
+    position: f64,
+) -> Result<()> {
+    match item {
+        PlaylistTreeItem::Folder(id) => conn.execute(
+            "UPDATE playlist_folders SET parent_id = ?1, position = ?2 WHERE id = ?3",
+            params![parent_id, position, id],
+        )?,
+        PlaylistTreeItem::Playlist(id) => conn.execute(
+            "UPDATE playlists SET folder_id = ?1, folder_position = ?2 WHERE id = ?3",
+            params![parent_id, position, id],
+        )?,
+    };
+    Ok(())
+}
+
+/// Parent folder and position of a folder or playlist
+pub fn get_playlist_tree_place(
+    conn: &Connection,
+    item: PlaylistTreeItem,
+) -> Result<(Option<i64>, f64)> {
+    let (sql, id) = match item {
+        PlaylistTreeItem::Folder(id) => (
+            "SELECT parent_id, position FROM playlist_folders WHERE id = ?1",
+            id,
+        ),
+        PlaylistTreeItem::Playlist(id) => (
+            "SELECT folder_id, folder_position FROM playlists WHERE id = ?1",
+            id,
+        ),
+    };
+    conn.query_row(sql, params![id], |row| Ok((row.get(0)?, row.get(1)?)))
+}
+
+/// Number the items of `parent_id` in the given order, returning the ones that changed
+fn renumber_playlist_tree(
+    conn: &Connection,
+    parent_id: Option<i64>,
+    items: &[PlaylistTreeItem],
+) -> Result<Vec<PlaylistTreeMove>> {
+    let mut moves = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        let position = index as f64;
+        if get_playlist_tree_place(conn, *item)? == (parent_id, position) {
+            continue;
+        }
+        set_playlist_tree_position(conn, *item, parent_id, position)?;
+        moves.push(PlaylistTreeMove {
+            item: *item,
+            parent_id,
+            position,
+        });
+    }
+    Ok(moves)
+}
+
+/// Folders containing `folder_id`, nearest first
+pub fn get_playlist_folder_ancestors(conn: &Connection, folder_id: i64) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare(
+        "WITH RECURSIVE ancestors(id, depth) AS (
+             SELECT parent_id, 1 FROM playlist_folders WHERE id = ?1
+             UNION ALL
+             SELECT f.parent_id, a.depth + 1 FROM playlist_folders f
+             INNER JOIN ancestors a ON f.id = a.id
+             WHERE a.depth < 100
+         )
+         SELECT id FROM ancestors WHERE id IS NOT NULL ORDER BY depth",
+    )?;
+    let ids = stmt
+        .query_map(params![folder_id], |row| row.get(0))?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(ids)
+}
+
+/// Move an item into `parent_id` at `index` among the items already there.
+/// Returns every item whose parent or position changed. Callers make sure a
+/// folder doesn't end up inside itself.
+pub fn move_playlist_tree_item(
+    conn: &Connection,
+    item: PlaylistTreeItem,
+    parent_id: Option<i64>,
+    index: usize,
+) -> Result<Vec<PlaylistTreeMove>> {
+    let tx = conn.unchecked_transaction()?;
+    let mut items: Vec<PlaylistTreeItem> = get_playlist_tree_children(&tx, parent_id)?
+        .into_iter()
+        .filter(|other| *other != item)
+        .collect();
+    items.insert(index.min(items.len()), item);
+    let moves = renumber_playlist_tree(&tx, parent_id, &items)?;
+    tx.commit()?;
+    Ok(moves)
+}
+
+/// Delete a folder, moving its folders and playlists up into its place.
+/// Returns every item whose parent or position changed.
+pub fn delete_playlist_folder(conn: &Connection, folder_id: i64) -> Result<Vec<PlaylistTreeMove>> {
+    let Some(folder) = get_playlist_folder(conn, folder_id)? else {
+        return Ok(Vec::new());
+    };
+    let tx = conn.unchecked_transaction()?;
+    let children = get_playlist_tree_children(&tx, Some(folder_id))?;
+    let items: Vec<PlaylistTreeItem> = get_playlist_tree_children(&tx, folder.parent_id)?
+        .into_iter()
+        .flat_map(|item| match item {
+            PlaylistTreeItem::Folder(id) if id == folder_id => children.clone(),
+            _ => vec![item],
+        })
+        .collect();
+    let moves = renumber_playlist_tree(&tx, folder.parent_id, &items)?;
+    tx.execute(
+        "DELETE FROM playlist_folders WHERE id = ?1",
+        params![folder_id],
+    )?;
+    tx.commit()?;
+    Ok(moves)
+}
+
+// ============================================================================
+// Smart Playlists (rule tree compiled to parameterised SQL)
+// ============================================================================
+
+/// Track attributes a smart playlist rule can filter or sort on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmartField {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Format,
+    Path,
+    Year,
+    Duration,
+    Bitrate,
+    TrackNumber,
+    DiscNumber,
+    PlayCount,
+    LastPlayed,
+    Liked,
+    DateAdded,
+    /// Star rating, 0 when unrated
+    Rating,
+    /// Matches if any of the track's user tags satisfies the operator
+    Tag,
+    /// Imported with filename-only metadata because its tags couldn't be read
+    Untagged,
+    Bpm,
+    /// Musical key such as "Am" (see `AudioFeatures`)
+    Key,
+    Energy,
+    Danceability,
+}
+
+impl SmartField {
+    /// SQL expression for this field. Relies on the aliases set up in
+    /// `SMART_PLAYLIST_FROM` (`t`, `ph`, `lt`).
+    fn sql(&self) -> &'static str {
+        match self {
+            SmartField::Title => "t.title",
+            SmartField::Artist => "t.artist",
+            SmartField::Album => "t.album",
+            // Scanned files keep their genre in the metadata JSON only
+            SmartField::Genre => {
+                "COALESCE(NULLIF(t.genre, ''), json_extract(t.metadata_json, '$.Genre'))"
+            }
+            SmartField::Format => "t.format",
+            SmartField::Path => "t.path",
+            SmartField::Year => "CAST(json_extract(t.metadata_json, '$.Year') AS INTEGER)",
+            SmartField::Duration => "t.duration",
+            SmartField::Bitrate => "t.bitrate",
+            SmartField::TrackNumber => "t.track_number",
+            SmartField::DiscNumber => "t.disc_number",
+            SmartField::PlayCount => "COALESCE(ph.play_count, 0)",
+            SmartField::LastPlayed => "ph.last_played",
+            SmartField::Liked => "(lt.track_id IS NOT NULL)",
+            SmartField::DateAdded => "t.date_added",
+            SmartField::Rating => "COALESCE(tr.rating, 0)",
+            // Used for sorting; filters on tags compile to EXISTS (see compile_smart_node)
+            SmartField::Tag => {
+                "(SELECT MIN(tg.name) FROM track_tags tt JOIN tags tg ON tg.id = tt.tag_id WHERE tt.track_id = t.id)"
+            }
+            SmartField::Untagged => "t.metadata_fallback",
+            SmartField::Bpm => "t.bpm",
+            SmartField::Key => "t.musical_key",
+            SmartField::Energy => "t.energy",
+            SmartField::Danceability => "t.danceability",
+        }
+    }
+
+    fn is_text(&self) -> bool {
+        matches!(
+            self,
+            SmartField::Title
+                | SmartField::Artist
+                | SmartField::Album
+                | SmartField::Genre
+                | SmartField::Format
+                | SmartField::Path
+                | SmartField::Tag
+                | SmartField::Key
+        )
+    }
+
+    fn is_date(&self) -> bool {
+        matches!(self, SmartField::LastPlayed | SmartField::DateAdded)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmartOperator {
+    Is,
+    IsNot,
+    Contains,
+    NotContains,
+    StartsWith,
+    EndsWith,
+    GreaterThan,
+    LessThan,
+    /// `value` is a two-element array `[low, high]` (inclusive)
+    Between,
+    /// `value` is a number of days, for date fields
+    InLast,
+    NotInLast,
+    IsEmpty,
+    IsNotEmpty,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmartCombinator {
+    And,
+    Or,
+}
+
+/// A node in the smart playlist rule tree: either a single condition or a
+/// group of nested nodes joined with AND/OR.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SmartRuleNode {
+    Rule {
+        field: SmartField,
+        operator: SmartOperator,
+        #[serde(default)]
+        value: serde_json::Value,
+    },
+    Group {
+        combinator: SmartCombinator,
+        rules: Vec<SmartRuleNode>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmartSort {
+    pub field: SmartField,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+/// Serialisable definition of a smart playlist, stored as JSON in `playlists.smart_rules`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmartPlaylistRules {
+    pub root: SmartRuleNode,
+    #[serde(default)]
+    pub sort: Option<SmartSort>,
+    #[serde(default)]
+    pub limit: Option<i64>,
+}
+
+const SMART_PLAYLIST_FROM: &str = "FROM tracks t
+         LEFT JOIN (
+             SELECT track_id, COUNT(*) AS play_count, MAX(played_at) AS last_played
+             FROM play_history GROUP BY track_id
+         ) ph ON ph.track_id = t.id
+         LEFT JOIN liked_tracks lt ON lt.track_id = t.id
+         LEFT JOIN track_ratings tr ON tr.track_id = t.id";
+
+impl SmartPlaylistRules {
+    /// Check operator/value combinations before the rules are stored or run.
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if let Some(limit) = self.limit {
+            if limit <= 0 {
+                return Err(format!("Invalid limit: {}", limit));
+            }
+        }
+        validate_smart_node(&self.root)
+    }
+
+    /// Compile the rule tree to a WHERE/ORDER BY/LIMIT tail and its bound parameters.
+    /// Values are never interpolated into the SQL string.
+    pub fn to_sql(&self) -> (String, Vec<rusqlite::types::Value>) {
+        let mut params = Vec::new();
+        let where_clause = compile_smart_node(&self.root, &mut params);
+
+        let order_by = match &self.sort {
+            Some(sort) => format!(
+                "{} {}, t.artist, t.album, t.disc_number, t.track_number, t.title",
+                sort.field.sql(),
+                if sort.descending { "DESC" } else { "ASC" }
+            ),
+            None => "t.artist, t.album, t.disc_number, t.track_number, t.title".to_string(),
+        };
+
+        let mut sql = format!("WHERE {} ORDER BY {}", where_clause, order_by);
+        if let Some(limit) = self.limit {
+            sql.push_str(" LIMIT ?");
+            params.push(rusqlite::types::Value::Integer(limit));
+        }
+
+        (sql, params)
+    }
+}
+
+fn validate_smart_node(node: &SmartRuleNode) -> std::result::Result<(), String> {
+    match node {
+        SmartRuleNode::Group { rules, .. } => rules.iter().try_for_each(validate_smart_node),
+        SmartRuleNode::Rule {
+            field,
+            operator,
+            value,
+        } => {
+            let text_only = matches!(
+                operator,
+                SmartOperator::Contains
+                    | SmartOperator::NotContains
+                    | SmartOperator::StartsWith
+                    | SmartOperator::EndsWith
+            );
+            if text_only && !field.is_text() {
+                return Err(format!("{:?} only applies to text fields, not {:?}", operator, field));
+            }
+
+            match operator {
+                SmartOperator::InLast | SmartOperator::NotInLast => {
+                    if !field.is_date() {
+                        return Err(format!("{:?} only applies to date fields, not {:?}", operator, field));
+                    }
+                    if value.as_i64().map_or(true, |days| days < 0) {
+                        return Err(format!("{:?} expects a number of days", operator));
+                    }
+                }
+                SmartOperator::Between => {
+                    let ok = value.as_array().map_or(false, |a| {
+                        a.len() == 2 && a.iter().all(|v| v.is_number() || v.is_string())
+                    });
+                    if !ok {
+                        return Err("Between expects a [low, high] pair".to_string());
+                    }
+                }
+                SmartOperator::IsEmpty | SmartOperator::IsNotEmpty => {}
+                _ => {
+                    if !(value.is_string() || value.is_number() || value.is_boolean()) {
+                        return Err(format!("{:?} on {:?} expects a value", operator, field));
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+fn smart_value(value: &serde_json::Value) -> rusqlite::types::Value {
+    use rusqlite::types::Value;
+    match value {
+        serde_json::Value::Bool(b) => Value::Integer(*b as i64),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Real(n.as_f64().unwrap_or(0.0)),
+        },
+        serde_json::Value::String(s) => Value::Text(s.clone()),
+        _ => Value::Null,
+    }
+}
+
+/// Escape LIKE wildcards so user input is matched literally.
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn compile_smart_node(node: &SmartRuleNode, params: &mut Vec<rusqlite::types::Value>) -> String {
+    match node {
+        SmartRuleNode::Group { combinator, rules } => {
+            if rules.is_empty() {
+                return "1".to_string();
+            }
+            let joiner = match combinator {
+                SmartCombinator::And => " AND ",
+                SmartCombinator::Or => " OR ",
+            };
+            let parts: Vec<String> = rules
+                .iter()
+                .map(|rule| compile_smart_node(rule, params))
+                .collect();
+            format!("({})", parts.join(joiner))
+        }
+        SmartRuleNode::Rule {
+            field: SmartField::Tag,
+            operator,
+            value,
+        } => {
+            // A track has many tags: test each one, negative operators become NOT EXISTS
+            let (negate, inner) = match operator {
+                SmartOperator::IsEmpty => (true, "1".to_string()),
+                SmartOperator::IsNotEmpty => (false, "1".to_string()),
+                SmartOperator::IsNot => (true, compile_smart_condition("tg.name", true, SmartOperator::Is, value, params)),
+                SmartOperator::NotContains => (true, compile_smart_condition("tg.name", true, SmartOperator::Contains, value, params)),
+                op => (false, compile_smart_condition("tg.name", true, *op, value, params)),
+            };
+            format!(
+                "{}EXISTS (SELECT 1 FROM track_tags tt JOIN tags tg ON tg.id = tt.tag_id WHERE tt.track_id = t.id AND {})",
+                if negate { "NOT " } else { "" },
+                inner
+            )
+        }
+        SmartRuleNode::Rule {
+            field,
+            operator,
+            value,
+        } => compile_smart_condition(field.sql(), field.is_text(), *operator, value, params),
+    }
+}
+
+/// Compile one comparison of `f` (an SQL expression) against `value`.
+fn compile_smart_condition(
+    f: &str,
+    is_text: bool,
+    operator: SmartOperator,
+    value: &serde_json::Value,
+    params: &mut Vec<rusqlite::types::Value>,
+) -> String {
+    use rusqlite::types::Value;
+
+    let collate = if is_text { " COLLATE NOCASE" } else { "" };
+    let text = value
+        .as_str()
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| value.to_string());
+
+    match operator {
+        SmartOperator::Is => {
+            params.push(smart_value(value));
+            format!("{} = ?{}", f, collate)
+        }
+        SmartOperator::IsNot => {
+            params.push(smart_value(value));
+            format!("({f} IS NULL OR {f} != ?{c})", f = f, c = collate)
+        }
+        SmartOperator::Contains => {
+            params.push(Value::Text(format!("%{}%", escape_like(&text))));
+            format!("{} LIKE ? ESCAPE '\\'", f)
+        }
+        SmartOperator::NotContains => {
+            params.push(Value::Text(format!("%{}%", escape_like(&text))));
+            format!("({f} IS NULL OR {f} NOT LIKE ? ESCAPE '\\')", f = f)
+        }
+        SmartOperator::StartsWith => {
+            params.push(Value::Text(format!("{}%", escape_like(&text))));
+            format!("{} LIKE ? ESCAPE '\\'", f)
+        }
+        SmartOperator::EndsWith => {
+            params.push(Value::Text(format!("%{}", escape_like(&text))));
+            format!("{} LIKE ? ESCAPE '\\'", f)
+        }
+        SmartOperator::GreaterThan => {
+            params.push(smart_value(value));
+            format!("{} > ?", f)
+        }
+        SmartOperator::LessThan => {
+            params.push(smart_value(value));
+            format!("{} < ?", f)
+        }
+        SmartOperator::Between => {
+            let bounds = value.as_array().cloned().unwrap_or_default();
+            params.push(bounds.first().map(smart_value).unwrap_or(Value::Null));
+            params.push(bounds.get(1).map(smart_value).unwrap_or(Value::Null));
+            format!("{} BETWEEN ? AND ?", f)
+        }
+        SmartOperator::InLast => {
+            params.push(Value::Text(format!("-{} days", value.as_i64().unwrap_or(0))));
+            format!("{} >= datetime('now', ?)", f)
+        }
+        SmartOperator::NotInLast => {
+            params.push(Value::Text(format!("-{} days", value.as_i64().unwrap_or(0))));
+            format!("({f} IS NULL OR {f} < datetime('now', ?))", f = f)
+        }
+        SmartOperator::IsEmpty => format!("({f} IS NULL OR {f} = '')", f = f),
+        SmartOperator::IsNotEmpty => format!("({f} IS NOT NULL AND {f} != '')", f = f),
+    }
+}
+
+/// Evaluate smart playlist rules against the current library.
+/// Runs on every read, so results always reflect the latest tracks, plays and likes.
+pub fn get_smart_playlist_tracks(conn: &Connection, rules: &SmartPlaylistRules) -> Result<Vec<Track>> {
+    let (tail, values) = rules.to_sql();
+    let sql = format!(
+        "SELECT t.id, t.path, t.title, t.artist, t.album, t.track_number, t.duration, t.album_id, t.format, t.bitrate, t.source_type, t.cover_url, t.external_id, t.local_src, t.track_cover_path, t.disc_number, t.metadata_json, t.date_added
+         {} {}",
+        SMART_PLAYLIST_FROM, tail
+    );
+
+    let mut stmt = conn.prepare(&sql)?;
+    let tracks = stmt
+        .query_map(rusqlite::params_from_iter(values.iter()), |row| {
+            Ok(Track {
+                id: row.get(0)?,
+                path: row.get(1)?,
+                title: row.get(2)?,
+                artist: row.get(3)?,
+                album: row.get(4)?,
+                track_number: row.get(5)?,
+                duration: row.get(6)?,
+                album_id: row.get(7)?,
+                format: row.get(8)?,
+                bitrate: row.get(9)?,
+                source_type: row.get(10)?,
+                cover_url: row.get(11)?,
+                external_id: row.get(12)?,
+                local_src: row.get(13)?,
+                track_cover: None,
+                track_cover_path: row.get(14)?,
+                disc_number: row.get(15)?,
+                metadata_json: row.get(16)?,
+                date_added: row.get(17)?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(tracks)
+}
+
+/// Get the parsed rules of a smart playlist (None for regular playlists).
+pub fn get_playlist_smart_rules(
+    conn: &Connection,
+    playlist_id: i64,
+) -> Result<Option<SmartPlaylistRules>> {
+    let raw: Option<String> = conn
+        .query_row(
+            "SELECT smart_rules FROM playlists WHERE id = ?1",
+            [playlist_id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+
+    Ok(raw.and_then(|json| serde_json::from_str(&json).ok()))
+}
+
+/// Store (or clear, with `None`) the rule tree of a playlist.
+pub fn set_playlist_smart_rules(
+    conn: &Connection,
+    playlist_id: i64,
+    rules: Option<&SmartPlaylistRules>,
+) -> Result<()> {
+    let json = rules.and_then(|r| serde_json::to_string(r).ok());
+    conn.execute(
+        "UPDATE playlists SET smart_rules = ?1 WHERE id = ?2",
+        params![json, playlist_id],
+    )?;
+    Ok(())
+}
+
 // Music folder operations
 pub fn add_music_folder(conn: &Connection, path: &str) -> Result<i64> {
     conn.execute(
         "INSERT OR IGNORE INTO music_folders (path, last_scanned) VALUES (?1, CURRENT_TIMESTAMP)",
         [path],
     )?;
-    Ok(conn.last_insert_rowid())
+    Ok(conn.last_insert_rowid())
+}
+
+/// Returns true if the folder was registered, false if it was already covered.
+pub fn register_music_folder(conn: &Connection, path: &str) -> Result<bool> {
+    let existing = {
+        let mut stmt = conn.prepare("SELECT path FROM music_folders")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut v = Vec::new();
+        for r in rows { v.push(r?); }
+        v
+    };
+
+    // If any existing folder is a parent
+    if existing.iter().any(|f| Path::new(path).starts_with(Path::new(f))) {
+        return Ok(false);
+    }
+
+    // Remove any existing folders that are subfolders of the new path
+    for f in existing.iter().filter(|f| Path::new(f).starts_with(Path::new(path))) {
+        conn.execute("DELETE FROM music_folders WHERE path = ?1", [f])?;
+    }
+
+    conn.execute(
+        "INSERT OR IGNORE INTO music_folders (path, last_scanned) VALUES (?1, CURRENT_TIMESTAMP)",
+        [path],
+    )?;
+
+    Ok(true)
+}
+
+pub fn get_music_folders(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT path FROM music_folders ORDER BY path")?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    let mut folders = Vec::new();
+    for folder in rows {
+        folders.push(folder?);
+    }
+    Ok(folders)
+}
+
+/// Per-folder scanner configuration, stored on `music_folders`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FolderScanSettings {
+    /// Glob patterns, relative to the folder, of files and directories to skip
+    #[serde(default)]
+    pub exclude_patterns: Vec<String>,
+    /// Files shorter than this many seconds are skipped (ringtones, samples)
+    #[serde(default)]
+    pub min_duration: Option<i32>,
+    /// Subfolder levels walked below the folder (0 = only the folder's own files)
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+    #[serde(default = "default_follow_symlinks")]
+    pub follow_symlinks: bool,
+    /// Folder art file names (case-insensitive globs matched against the name
+    /// without extension), highest priority first
+    #[serde(default = "default_cover_art_names")]
+    pub cover_art_names: Vec<String>,
+    /// Look up art for albums without any on the Cover Art Archive after scans
+    #[serde(default)]
+    pub fetch_online_art: bool,
+}
+
+fn default_follow_symlinks() -> bool {
+    true
+}
+
+pub fn default_cover_art_names() -> Vec<String> {
+    ["cover", "folder", "front", "albumart*"]
+        .iter()
+        .map(|name| name.to_string())
+        .collect()
+}
+
+impl Default for FolderScanSettings {
+    fn default() -> Self {
+        Self {
+            exclude_patterns: Vec::new(),
+            min_duration: None,
+            max_depth: None,
+            follow_symlinks: default_follow_symlinks(),
+            cover_art_names: default_cover_art_names(),
+            fetch_online_art: false,
+        }
+    }
+}
+
+pub fn get_music_folders_with_settings(
+    conn: &Connection,
+) -> Result<Vec<(String, FolderScanSettings)>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, exclude_patterns, min_duration, max_depth, follow_symlinks, cover_art_names,
+                fetch_online_art
+         FROM music_folders ORDER BY path",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        let patterns: Option<String> = row.get(1)?;
+        let cover_art_names: Option<String> = row.get(5)?;
+        Ok((
+            row.get(0)?,
+            FolderScanSettings {
+                exclude_patterns: patterns
+                    .and_then(|json| serde_json::from_str(&json).ok())
+                    .unwrap_or_default(),
+                min_duration: row.get(2)?,
+                max_depth: row.get::<_, Option<i64>>(3)?.map(|d| d.max(0) as usize),
+                follow_symlinks: row.get(4)?,
+                cover_art_names: cover_art_names
+                    .and_then(|json| serde_json::from_str(&json).ok())
+                    .unwrap_or_else(default_cover_art_names),
+                fetch_online_art: row.get(6)?,
+            },
+        ))
+    })?;
+    rows.collect()
+}
+
+/// Returns false if `path` is not a registered music folder
+pub fn set_music_folder_settings(
+    conn: &Connection,
+    path: &str,
+    settings: &FolderScanSettings,
+) -> Result<bool> {
+    let patterns = serde_json::to_string(&settings.exclude_patterns)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    let cover_art_names = serde_json::to_string(&settings.cover_art_names)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    let updated = conn.execute(
+        "UPDATE music_folders
+         SET exclude_patterns = ?1, min_duration = ?2, max_depth = ?3, follow_symlinks = ?4,
+             cover_art_names = ?5, fetch_online_art = ?6
+         WHERE path = ?7",
+        params![
+            patterns,
+            settings.min_duration,
+            settings.max_depth.map(|d| d as i64),
+            settings.follow_symlinks,
+            cover_art_names,
+            settings.fetch_online_art,
+            path
+        ],
+    )?;
+    Ok(updated > 0)
+}
+
+pub fn remove_music_folder(conn: &Connection, path: &str) -> Result<()> {
+    conn.execute("DELETE FROM music_folders WHERE path = ?1", [path])?;
+    Ok(())
+}
+
+pub fn update_folder_last_scanned(conn: &Connection, path: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE music_folders SET last_scanned = CURRENT_TIMESTAMP WHERE path = ?1",
+        [path],
+    )?;
+    Ok(())
+}
+
+/// Stored (size, mtime) of every local track that has them, keyed by path.
+/// Used by incremental rescans to skip files that have not changed.
+pub fn get_track_file_fingerprints(conn: &Connection) -> Result<HashMap<String, (i64, i64)>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, file_size, file_mtime FROM tracks
+         WHERE file_size IS NOT NULL AND file_mtime IS NOT NULL
+           -- Hashes from before they were computed from the audio are redone once
+           AND (content_hash IS NULL OR content_hash LIKE 'a1:%' OR content_hash LIKE 'm1:%')",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, (row.get(1)?, row.get(2)?)))
+    })?;
+    rows.collect()
+}
+
+/// Look up a track id by its file path
+pub fn get_track_id_by_path(conn: &Connection, path: &str) -> Result<Option<i64>> {
+    conn.query_row(
+        "SELECT id FROM tracks WHERE path = ?1",
+        params![path],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// What a playlist entry without a usable path is matched on
+#[derive(Debug, Clone)]
+pub struct TrackMatchCandidate {
+    pub id: i64,
+    pub path: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub duration: Option<i32>,
+}
+
+pub fn get_track_match_candidates(conn: &Connection) -> Result<Vec<TrackMatchCandidate>> {
+    let mut stmt =
+        conn.prepare("SELECT id, path, title, artist, duration FROM tracks WHERE title IS NOT NULL")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(TrackMatchCandidate {
+            id: row.get(0)?,
+            path: row.get(1)?,
+            title: row.get(2)?,
+            artist: row.get(3)?,
+            duration: row.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Tracks whose path lies inside `dir`, as (id, path, content_hash)
+pub fn get_tracks_under_path(
+    conn: &Connection,
+    dir: &str,
+) -> Result<Vec<(i64, String, Option<String>)>> {
+    let dir = dir.trim_end_matches(std::path::MAIN_SEPARATOR);
+    let prefix = format!("{}{}", dir, std::path::MAIN_SEPARATOR);
+    // substr instead of LIKE so '_' and '%' in folder names are matched literally
+    let mut stmt = conn.prepare(
+        "SELECT id, path, content_hash FROM tracks
+         WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+    )?;
+    let rows = stmt
+        .query_map(params![dir, prefix], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// A library track whose file no longer exists, with what is known about the
+/// file so it can be matched to a moved copy.
+#[derive(Debug, Clone)]
+pub struct MissingTrack {
+    pub id: i64,
+    pub path: String,
+    pub content_hash: Option<String>,
+    pub duration: Option<i32>,
+    pub file_size: Option<i64>,
+    pub file_mtime: Option<i64>,
+}
+
+/// Tracks under any of `folder_paths` whose file is gone, or which the
+/// folder's scan settings now exclude
+pub fn get_missing_tracks(
+    conn: &Connection,
+    folder_paths: &[String],
+    is_excluded: impl Fn(&MissingTrack) -> bool,
+) -> Result<Vec<MissingTrack>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, path, content_hash, duration, file_size, file_mtime FROM tracks
+         WHERE substr(path, 1, length(?1)) = ?1",
+    )?;
+
+    let mut seen = std::collections::HashSet::new();
+    let mut missing = Vec::new();
+    for folder in folder_paths {
+        let prefix = format!(
+            "{}{}",
+            folder.trim_end_matches(std::path::MAIN_SEPARATOR),
+            std::path::MAIN_SEPARATOR
+        );
+        let rows = stmt.query_map(params![prefix], |row| {
+            Ok(MissingTrack {
+                id: row.get(0)?,
+                path: row.get(1)?,
+                content_hash: row.get(2)?,
+                duration: row.get(3)?,
+                file_size: row.get(4)?,
+                file_mtime: row.get(5)?,
+            })
+        })?;
+        for track in rows {
+            let track = track?;
+            if seen.insert(track.id) && (!Path::new(&track.path).exists() || is_excluded(&track)) {
+                missing.push(track);
+            }
+        }
+    }
+    Ok(missing)
+}
+
+/// Paths of every track in the library
+pub fn get_track_paths(conn: &Connection) -> Result<std::collections::HashSet<String>> {
+    let mut stmt = conn.prepare("SELECT path FROM tracks")?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    rows.collect()
+}
+
+/// Point a track at a new file path. The id is kept, so play history, likes,
+/// ratings and playlist entries follow the file.
+pub fn relocate_track(conn: &Connection, track_id: i64, new_path: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE tracks
+         SET local_src = CASE WHEN local_src = path THEN ?1 ELSE local_src END,
+             path = ?1
+         WHERE id = ?2",
+        params![new_path, track_id],
+    )?;
+    forget_duplicate_file(conn, new_path)
+}
+
+/// Move every track under `old_dir` to the same relative path under `new_dir`.
+/// Returns (track_id, old_path, new_path) for each relocated track.
+pub fn relocate_tracks_under(
+    conn: &Connection,
+    old_dir: &str,
+    new_dir: &str,
+) -> Result<Vec<(i64, String, String)>> {
+    let old_dir = old_dir.trim_end_matches(std::path::MAIN_SEPARATOR);
+    let new_dir = new_dir.trim_end_matches(std::path::MAIN_SEPARATOR);
+
+    let mut moved = Vec::new();
+    for (id, path, _) in get_tracks_under_path(conn, old_dir)? {
+        let new_path = format!("{}{}", new_dir, &path[old_dir.len()..]);
+        relocate_track(conn, id, &new_path)?;
+        moved.push((id, path, new_path));
+    }
+    Ok(moved)
+}
+
+/// Local library tracks that can hold decoded-audio hashes and fingerprints
+const LOCAL_TRACKS_FILTER: &str =
+    "(source_type IS NULL OR source_type = 'local') AND path NOT LIKE '%://%'";
+
+/// A local track with what the duplicates report needs to compare and rank it
+#[derive(Debug, Clone)]
+pub struct AudioIdentity {
+    pub id: i64,
+    pub path: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub format: Option<String>,
+    pub bitrate: Option<i32>,
+    pub file_size: Option<i64>,
+    pub duration: Option<i32>,
+    pub content_hash: Option<String>,
+    pub acoustic_fingerprint: Option<Vec<u8>>,
+}
+
+pub fn get_local_audio_identities(conn: &Connection) -> Result<Vec<AudioIdentity>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, path, title, artist, format, bitrate, file_size, duration, content_hash, acoustic_fingerprint
+         FROM tracks WHERE {}",
+        LOCAL_TRACKS_FILTER
+    ))?;
+    let rows = stmt.query_map([], |row| {
+        Ok(AudioIdentity {
+            id: row.get(0)?,
+            path: row.get(1)?,
+            title: row.get(2)?,
+            artist: row.get(3)?,
+            format: row.get(4)?,
+            bitrate: row.get(5)?,
+            file_size: row.get(6)?,
+            duration: row.get(7)?,
+            content_hash: row.get(8)?,
+            acoustic_fingerprint: row.get(9)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// A file that was not imported because a track with identical audio exists
+#[derive(Debug, Clone)]
+pub struct DuplicateFile {
+    pub path: String,
+    pub track_id: i64,
+    pub format: Option<String>,
+    pub bitrate: Option<i32>,
+    pub file_size: Option<i64>,
+    pub duration: Option<i32>,
+}
+
+pub fn get_duplicate_files(conn: &Connection) -> Result<Vec<DuplicateFile>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, track_id, format, bitrate, file_size, duration FROM duplicate_files",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(DuplicateFile {
+            path: row.get(0)?,
+            track_id: row.get(1)?,
+            format: row.get(2)?,
+            bitrate: row.get(3)?,
+            file_size: row.get(4)?,
+            duration: row.get(5)?,
+        })
+    })?;
+    rows.collect()
 }
 
-/// Returns true if the folder was registered, false if it was already covered.
-pub fn register_music_folder(conn: &Connection, path: &str) -> Result<bool> {
-    let existing = {
-        let mut stmt = conn.prepare("SELECT path FROM music_folders")?;
-        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
-        let mut v = Vec::new();
-        for r in rows { v.push(r?); }
-        v
-    };
+pub fn delete_duplicate_files(conn: &Connection, paths: &[String]) -> Result<usize> {
+    let mut stmt = conn.prepare("DELETE FROM duplicate_files WHERE path = ?1")?;
+    let mut deleted = 0;
+    for path in paths {
+        deleted += stmt.execute(params![path])?;
+    }
+    Ok(deleted)
+}
 
-    // If any existing folder is a parent
-    if existing.iter().any(|f| Path::new(path).starts_with(Path::new(f))) {
-        return Ok(false);
+/// Step of a scan at which a file failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanStage {
+    /// Listing the folder
+    Walk,
+    /// Opening the file and reading its audio properties
+    Probe,
+    /// Reading tags
+    Tags,
+    /// Saving embedded artwork
+    Cover,
+    /// Writing the track to the library
+    Db,
+}
+
+impl ScanStage {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ScanStage::Walk => "walk",
+            ScanStage::Probe => "probe",
+            ScanStage::Tags => "tags",
+            ScanStage::Cover => "cover",
+            ScanStage::Db => "db",
+        }
     }
 
-    // Remove any existing folders that are subfolders of the new path
-    for f in existing.iter().filter(|f| Path::new(f).starts_with(Path::new(path))) {
-        conn.execute("DELETE FROM music_folders WHERE path = ?1", [f])?;
+    fn parse(value: &str) -> Self {
+        match value {
+            "walk" => ScanStage::Walk,
+            "probe" => ScanStage::Probe,
+            "tags" => ScanStage::Tags,
+            "cover" => ScanStage::Cover,
+            _ => ScanStage::Db,
+        }
     }
+}
 
-    conn.execute(
-        "INSERT OR IGNORE INTO music_folders (path, last_scanned) VALUES (?1, CURRENT_TIMESTAMP)",
-        [path],
-    )?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanIssueKind {
+    NotFound,
+    PermissionDenied,
+    /// Any other filesystem error
+    Io,
+    /// Path that isn't valid UTF-8
+    InvalidPath,
+    SymlinkCycle,
+    /// Not a format the tag reader understands
+    Unsupported,
+    /// Recognised but unreadable, e.g. truncated or damaged
+    Corrupt,
+    /// Readable audio without any tags
+    MissingTags,
+    Database,
+    Other,
+}
 
-    Ok(true)
+impl ScanIssueKind {
+    pub fn from_io(error: &std::io::Error) -> Self {
+        match error.kind() {
+            std::io::ErrorKind::NotFound => ScanIssueKind::NotFound,
+            std::io::ErrorKind::PermissionDenied => ScanIssueKind::PermissionDenied,
+            _ => ScanIssueKind::Io,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            ScanIssueKind::NotFound => "not_found",
+            ScanIssueKind::PermissionDenied => "permission_denied",
+            ScanIssueKind::Io => "io",
+            ScanIssueKind::InvalidPath => "invalid_path",
+            ScanIssueKind::SymlinkCycle => "symlink_cycle",
+            ScanIssueKind::Unsupported => "unsupported",
+            ScanIssueKind::Corrupt => "corrupt",
+            ScanIssueKind::MissingTags => "missing_tags",
+            ScanIssueKind::Database => "database",
+            ScanIssueKind::Other => "other",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value {
+            "not_found" => ScanIssueKind::NotFound,
+            "permission_denied" => ScanIssueKind::PermissionDenied,
+            "io" => ScanIssueKind::Io,
+            "invalid_path" => ScanIssueKind::InvalidPath,
+            "symlink_cycle" => ScanIssueKind::SymlinkCycle,
+            "unsupported" => ScanIssueKind::Unsupported,
+            "corrupt" => ScanIssueKind::Corrupt,
+            "missing_tags" => ScanIssueKind::MissingTags,
+            "database" => ScanIssueKind::Database,
+            _ => ScanIssueKind::Other,
+        }
+    }
 }
 
-pub fn get_music_folders(conn: &Connection) -> Result<Vec<String>> {
-    let mut stmt = conn.prepare("SELECT path FROM music_folders ORDER BY path")?;
-    let rows = stmt.query_map([], |row| row.get(0))?;
-    let mut folders = Vec::new();
-    for folder in rows {
-        folders.push(folder?);
+/// A file (or directory, for walk failures) that a scan couldn't fully process
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanIssue {
+    pub path: String,
+    pub stage: ScanStage,
+    pub kind: ScanIssueKind,
+    pub message: String,
+}
+
+impl ScanIssue {
+    pub fn new(
+        path: impl Into<String>,
+        stage: ScanStage,
+        kind: ScanIssueKind,
+        message: impl Into<String>,
+    ) -> Self {
+        Self {
+            path: path.into(),
+            stage,
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// Failure to write a file's track to the library
+    pub fn database(path: impl Into<String>, error: impl std::fmt::Display) -> Self {
+        Self::new(
+            path,
+            ScanStage::Db,
+            ScanIssueKind::Database,
+            error.to_string(),
+        )
     }
-    Ok(folders)
 }
 
-pub fn remove_music_folder(conn: &Connection, path: &str) -> Result<()> {
-    conn.execute("DELETE FROM music_folders WHERE path = ?1", [path])?;
-    Ok(())
+/// A stored scan issue, with the track imported from the file if there is one
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanIssueRecord {
+    pub id: i64,
+    #[serde(flatten)]
+    pub issue: ScanIssue,
+    /// Number of scans that ran into it
+    pub occurrences: i64,
+    pub first_seen: Option<String>,
+    pub last_seen: Option<String>,
+    pub track_id: Option<i64>,
+    /// The track was imported with filename-only metadata
+    pub metadata_fallback: bool,
 }
 
-pub fn update_folder_last_scanned(conn: &Connection, path: &str) -> Result<()> {
-    conn.execute(
-        "UPDATE music_folders SET last_scanned = CURRENT_TIMESTAMP WHERE path = ?1",
-        [path],
+/// Store the issues found by a scan. Earlier issues that didn't recur are
+/// forgotten if the scan covered them: walk issues under `walked` roots, and
+/// any issue of a file in `processed`.
+pub fn record_scan_results(
+    conn: &Connection,
+    walked: &[String],
+    processed: &std::collections::HashSet<String>,
+    issues: &[ScanIssue],
+) -> Result<()> {
+    let mut stmt = conn.prepare("SELECT id, path, stage FROM scan_issues")?;
+    let existing = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                ScanStage::parse(&row.get::<_, String>(2)?),
+            ))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    let current: std::collections::HashSet<(&str, ScanStage)> =
+        issues.iter().map(|i| (i.path.as_str(), i.stage)).collect();
+    let stale: Vec<i64> = existing
+        .into_iter()
+        .filter(|(_, path, stage)| {
+            let covered = processed.contains(path)
+                || (*stage == ScanStage::Walk
+                    && walked.iter().any(|root| Path::new(path).starts_with(root)));
+            covered && !current.contains(&(path.as_str(), *stage))
+        })
+        .map(|(id, _, _)| id)
+        .collect();
+    dismiss_scan_issues(conn, &stale)?;
+
+    let mut upsert = conn.prepare(
+        "INSERT INTO scan_issues (path, stage, kind, message) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(path, stage) DO UPDATE SET
+            kind = excluded.kind,
+            message = excluded.message,
+            occurrences = occurrences + 1,
+            last_seen = CURRENT_TIMESTAMP",
     )?;
+    for issue in issues {
+        upsert.execute(params![
+            issue.path,
+            issue.stage.as_str(),
+            issue.kind.as_str(),
+            issue.message
+        ])?;
+    }
     Ok(())
 }
 
-// Cleanup tracks that no longer exist on filesystem
-pub fn cleanup_deleted_tracks(conn: &Connection, folder_paths: &[String]) -> Result<usize> {
-    if folder_paths.is_empty() {
-        return Ok(0);
+/// Stored scan issues, most recent first
+pub fn get_scan_issues(conn: &Connection) -> Result<Vec<ScanIssueRecord>> {
+    let mut stmt = conn.prepare(
+        "SELECT si.id, si.path, si.stage, si.kind, si.message, si.occurrences, si.first_seen, si.last_seen, t.id, COALESCE(t.metadata_fallback, 0)
+         FROM scan_issues si
+         LEFT JOIN tracks t ON t.path = si.path
+         ORDER BY si.last_seen DESC, si.path, si.stage",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(ScanIssueRecord {
+            id: row.get(0)?,
+            issue: ScanIssue {
+                path: row.get(1)?,
+                stage: ScanStage::parse(&row.get::<_, String>(2)?),
+                kind: ScanIssueKind::parse(&row.get::<_, String>(3)?),
+                message: row.get(4)?,
+            },
+            occurrences: row.get(5)?,
+            first_seen: row.get(6)?,
+            last_seen: row.get(7)?,
+            track_id: row.get(8)?,
+            metadata_fallback: row.get(9)?,
+        })
+    })?;
+    rows.collect()
+}
+
+pub fn dismiss_scan_issues(conn: &Connection, ids: &[i64]) -> Result<usize> {
+    let mut stmt = conn.prepare("DELETE FROM scan_issues WHERE id = ?1")?;
+    let mut deleted = 0;
+    for id in ids {
+        deleted += stmt.execute(params![id])?;
     }
+    Ok(deleted)
+}
 
-    // Build query with OR conditions for each folder
-    let conditions: Vec<String> = folder_paths
-        .iter()
-        .enumerate()
-        .map(|(i, _)| format!("path LIKE ?{}", i + 1))
+/// Dismiss the issues of paths under `folder_paths` that no longer exist, from
+/// whichever stage. Returns the number dismissed.
+pub fn dismiss_missing_scan_issues(conn: &Connection, folder_paths: &[String]) -> Result<usize> {
+    let mut stmt = conn.prepare("SELECT id, path FROM scan_issues")?;
+    let gone: Vec<i64> = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|(_, path)| {
+            let path = Path::new(path);
+            folder_paths.iter().any(|folder| path.starts_with(folder)) && !path.exists()
+        })
+        .map(|(id, _)| id)
         .collect();
-    let query = format!(
-        "SELECT id, path FROM tracks WHERE {}",
-        conditions.join(" OR ")
-    );
+    dismiss_scan_issues(conn, &gone)
+}
 
-    let mut params = Vec::new();
-    for folder in folder_paths {
-        params.push(format!("{}%", folder));
-    }
+/// Tempo, key and feel of a track, estimated from its audio or read from its tags
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioFeatures {
+    pub bpm: Option<f64>,
+    /// Key name such as "C", "F#m" or "Bbm"
+    pub key: Option<String>,
+    /// 0 (calm) to 1 (intense)
+    pub energy: Option<f64>,
+    /// 0 to 1: how steady and clear the beat is
+    pub danceability: Option<f64>,
+}
 
-    let mut stmt = conn.prepare(&query)?;
-    let track_rows = stmt.query_map(rusqlite::params_from_iter(params.iter()), |row| {
-        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+/// A local track to analyse, with the BPM and key its tags give
+#[derive(Debug, Clone)]
+pub struct FeatureAnalysisTrack {
+    pub id: i64,
+    pub path: String,
+    pub tag_bpm: Option<String>,
+    pub tag_key: Option<String>,
+}
+
+/// Local tracks not analysed yet (or all of them when `force` is set)
+pub fn get_tracks_to_analyse(conn: &Connection, force: bool) -> Result<Vec<FeatureAnalysisTrack>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, path, json_extract(metadata_json, '$.Bpm'), json_extract(metadata_json, '$.InitialKey')
+         FROM tracks WHERE {} AND (?1 OR features_analysed_at IS NULL)",
+        LOCAL_TRACKS_FILTER
+    ))?;
+    let rows = stmt.query_map(params![force], |row| {
+        Ok(FeatureAnalysisTrack {
+            id: row.get(0)?,
+            path: row.get(1)?,
+            tag_bpm: row.get(2)?,
+            tag_key: row.get(3)?,
+        })
     })?;
+    rows.collect()
+}
+
+/// Store a track's features and mark it analysed; None records a failed
+/// analysis so the track isn't retried on every run
+pub fn set_audio_features(
+    conn: &Connection,
+    track_id: i64,
+    features: Option<&AudioFeatures>,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE tracks SET bpm = ?1, musical_key = ?2, energy = ?3, danceability = ?4,
+                features_analysed_at = CURRENT_TIMESTAMP
+         WHERE id = ?5",
+        params![
+            features.and_then(|f| f.bpm),
+            features.and_then(|f| f.key.as_deref()),
+            features.and_then(|f| f.energy),
+            features.and_then(|f| f.danceability),
+            track_id
+        ],
+    )?;
+    Ok(())
+}
+
+/// Features of every analysed track as track_id -> features, for the frontend
+/// to cache like ratings
+pub fn get_audio_features(conn: &Connection) -> Result<HashMap<i64, AudioFeatures>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, bpm, musical_key, energy, danceability FROM tracks
+         WHERE bpm IS NOT NULL OR musical_key IS NOT NULL OR energy IS NOT NULL",
+    )?;
+    let features = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                AudioFeatures {
+                    bpm: row.get(1)?,
+                    key: row.get(2)?,
+                    energy: row.get(3)?,
+                    danceability: row.get(4)?,
+                },
+            ))
+        })?
+        .collect::<Result<HashMap<_, _>>>()?;
+    Ok(features)
+}
+
+/// Local tracks still lacking an acoustic fingerprint (or all of them when
+/// `force` is set), as (id, path)
+pub fn get_tracks_to_fingerprint(conn: &Connection, force: bool) -> Result<Vec<(i64, String)>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, path FROM tracks WHERE {} AND (?1 OR acoustic_fingerprint IS NULL)",
+        LOCAL_TRACKS_FILTER
+    ))?;
+    let rows = stmt.query_map(params![force], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
 
-    let mut deleted_count = 0;
-    for track_result in track_rows {
-        let (id, path) = track_result?;
-        if !std::path::Path::new(&path).exists() {
-            // Track file doesn't exist, remove it
-            conn.execute("DELETE FROM tracks WHERE id = ?1", [id])?;
-            deleted_count += 1;
+/// A local track with the tags the file organiser can put in a path, keyed
+/// by template field name (title, albumartist, year, track...)
+#[derive(Debug, Clone)]
+pub struct OrganiseTrack {
+    pub id: i64,
+    pub path: String,
+    pub tags: HashMap<String, String>,
+}
+
+/// Local tracks to organise: those in `track_ids`, or the whole library
+pub fn get_tracks_to_organise(
+    conn: &Connection,
+    track_ids: Option<&[i64]>,
+) -> Result<Vec<OrganiseTrack>> {
+    const FIELDS: [&str; 11] = [
+        "title",
+        "artist",
+        "album",
+        "albumartist",
+        "year",
+        "genre",
+        "composer",
+        "track",
+        "tracktotal",
+        "disc",
+        "disctotal",
+    ];
+    let mut stmt = conn.prepare(&format!(
+        "SELECT t.id, t.path, t.title, t.artist, t.album,
+                COALESCE(NULLIF(json_extract(t.metadata_json, '$.AlbumArtist'), ''), a.artist),
+                substr(json_extract(t.metadata_json, '$.Year'), 1, 4),
+                COALESCE(NULLIF(t.genre, ''), json_extract(t.metadata_json, '$.Genre')),
+                json_extract(t.metadata_json, '$.Composer'),
+                t.track_number, json_extract(t.metadata_json, '$.TrackTotal'),
+                t.disc_number, json_extract(t.metadata_json, '$.DiscTotal')
+         FROM tracks t LEFT JOIN albums a ON a.id = t.album_id
+         WHERE {}",
+        LOCAL_TRACKS_FILTER
+    ))?;
+    let wanted: Option<std::collections::HashSet<i64>> =
+        track_ids.map(|ids| ids.iter().copied().collect());
+    let rows = stmt.query_map([], |row| {
+        let mut tags = HashMap::new();
+        for (i, field) in FIELDS.iter().enumerate() {
+            let value = match row.get_ref(i + 2)? {
+                rusqlite::types::ValueRef::Integer(n) => n.to_string(),
+                rusqlite::types::ValueRef::Text(text) => String::from_utf8_lossy(text).to_string(),
+                _ => continue,
+            };
+            tags.insert(field.to_string(), value);
+        }
+        Ok(OrganiseTrack {
+            id: row.get(0)?,
+            path: row.get(1)?,
+            tags,
+        })
+    })?;
+    let mut tracks = Vec::new();
+    for track in rows {
+        let track = track?;
+        if wanted.as_ref().is_none_or(|ids| ids.contains(&track.id)) {
+            tracks.push(track);
         }
     }
+    Ok(tracks)
+}
+
+/// A local track as the MusicBrainz auto-tagger groups and matches it
+#[derive(Debug, Clone)]
+pub struct AutotagTrack {
+    pub id: i64,
+    pub path: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<i32>,
+    pub disc_number: Option<i32>,
+    pub duration: Option<i32>,
+}
+
+pub fn get_autotag_tracks(conn: &Connection, track_ids: &[i64]) -> Result<Vec<AutotagTrack>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT t.id, t.path, t.title, t.artist, t.album,
+                COALESCE(NULLIF(json_extract(t.metadata_json, '$.AlbumArtist'), ''), a.artist),
+                t.track_number, t.disc_number, t.duration
+         FROM tracks t LEFT JOIN albums a ON a.id = t.album_id
+         WHERE t.id = ?1 AND {}",
+        LOCAL_TRACKS_FILTER
+    ))?;
+    let mut tracks = Vec::new();
+    for id in track_ids {
+        let track = stmt
+            .query_row(params![id], |row| {
+                Ok(AutotagTrack {
+                    id: row.get(0)?,
+                    path: row.get(1)?,
+                    title: row.get(2)?,
+                    artist: row.get(3)?,
+                    album: row.get(4)?,
+                    album_artist: row.get(5)?,
+                    track_number: row.get(6)?,
+                    disc_number: row.get(7)?,
+                    duration: row.get(8)?,
+                })
+            })
+            .optional()?;
+        tracks.extend(track);
+    }
+    Ok(tracks)
+}
+
+/// Remember the MusicBrainz release an album was tagged from
+pub fn set_album_release_mbid(conn: &Connection, album_id: i64, release_mbid: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE albums SET musicbrainz_release_id = ?1 WHERE id = ?2",
+        params![release_mbid, album_id],
+    )?;
+    Ok(())
+}
 
-    Ok(deleted_count)
+pub fn set_acoustic_fingerprint(conn: &Connection, track_id: i64, fingerprint: &[u8]) -> Result<()> {
+    conn.execute(
+        "UPDATE tracks SET acoustic_fingerprint = ?1 WHERE id = ?2",
+        params![fingerprint, track_id],
+    )?;
+    Ok(())
 }
 
 /// Cleanup albums that have no tracks associated with them
@@ -1175,66 +3455,217 @@ pub fn like_track(conn: &Connection, track_id: i64) -> Result<()> {
     Ok(())
 }
 
-pub fn unlike_track(conn: &Connection, track_id: i64) -> Result<()> {
+pub fn unlike_track(conn: &Connection, track_id: i64) -> Result<()> {
+    conn.execute(
+        "DELETE FROM liked_tracks WHERE track_id = ?1",
+        params![track_id],
+    )?;
+    Ok(())
+}
+
+pub fn is_track_liked(conn: &Connection, track_id: i64) -> Result<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM liked_tracks WHERE track_id = ?1",
+        params![track_id],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+pub fn get_liked_track_ids(conn: &Connection) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare("SELECT track_id FROM liked_tracks ORDER BY liked_at DESC")?;
+    let ids = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(ids)
+}
+
+pub fn get_liked_tracks(conn: &Connection) -> Result<Vec<Track>> {
+    let mut stmt = conn.prepare(
+        "SELECT t.id, t.path, t.title, t.artist, t.album, t.track_number, t.duration, t.album_id, t.format, t.bitrate, t.source_type, t.cover_url, t.external_id, t.local_src, t.track_cover_path, t.disc_number, t.metadata_json, t.date_added
+         FROM tracks t
+         INNER JOIN liked_tracks lt ON t.id = lt.track_id
+         ORDER BY lt.liked_at DESC",
+    )?;
+
+    let tracks = stmt
+        .query_map([], |row| {
+            Ok(Track {
+                id: row.get(0)?,
+                path: row.get(1)?,
+                title: row.get(2)?,
+                artist: row.get(3)?,
+                album: row.get(4)?,
+                track_number: row.get(5)?,
+                duration: row.get(6)?,
+                album_id: row.get(7)?,
+                format: row.get(8)?,
+                bitrate: row.get(9)?,
+                source_type: row.get(10)?,
+                cover_url: row.get(11)?,
+                external_id: row.get(12)?,
+                local_src: row.get(13)?,
+                track_cover: None,
+                track_cover_path: row.get(14)?,
+                disc_number: row.get(15)?,
+                metadata_json: row.get(16)?,
+                date_added: row.get(17)?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(tracks)
+}
+
+// ============================================================================
+// Ratings & User Tags
+// ============================================================================
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagInfo {
+    pub id: i64,
+    pub name: String,
+    pub track_count: i64,
+}
+
+/// Set a 1-5 star rating, or clear it with 0.
+pub fn set_track_rating(conn: &Connection, track_id: i64, rating: u8) -> Result<()> {
+    if rating == 0 {
+        conn.execute("DELETE FROM track_ratings WHERE track_id = ?1", [track_id])?;
+    } else {
+        conn.execute(
+            "INSERT INTO track_ratings (track_id, rating) VALUES (?1, ?2)
+             ON CONFLICT(track_id) DO UPDATE SET rating = excluded.rating, rated_at = CURRENT_TIMESTAMP",
+            params![track_id, rating.min(5)],
+        )?;
+    }
+    Ok(())
+}
+
+/// Get a track's rating (0 = unrated).
+pub fn get_track_rating(conn: &Connection, track_id: i64) -> Result<u8> {
+    let rating: Option<u8> = conn
+        .query_row(
+            "SELECT rating FROM track_ratings WHERE track_id = ?1",
+            [track_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(rating.unwrap_or(0))
+}
+
+/// All rated tracks as track_id -> rating, for the frontend to cache like liked IDs.
+pub fn get_track_ratings(conn: &Connection) -> Result<HashMap<i64, u8>> {
+    let mut stmt = conn.prepare("SELECT track_id, rating FROM track_ratings")?;
+    let ratings = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<HashMap<_, _>>>()?;
+    Ok(ratings)
+}
+
+/// Get or create a tag by name (case-insensitive). Returns the tag ID.
+pub fn get_or_create_tag(conn: &Connection, name: &str) -> Result<i64> {
+    conn.execute("INSERT OR IGNORE INTO tags (name) VALUES (?1)", [name])?;
+    conn.query_row("SELECT id FROM tags WHERE name = ?1", [name], |row| row.get(0))
+}
+
+/// Tag tracks, creating the tag if needed. Returns the track IDs that were newly tagged.
+pub fn add_tag_to_tracks(conn: &Connection, name: &str, track_ids: &[i64]) -> Result<Vec<i64>> {
+    let tag_id = get_or_create_tag(conn, name)?;
+    let mut stmt =
+        conn.prepare("INSERT OR IGNORE INTO track_tags (track_id, tag_id) VALUES (?1, ?2)")?;
+
+    let mut added = Vec::new();
+    for track_id in track_ids {
+        if stmt.execute(params![track_id, tag_id])? > 0 {
+            added.push(*track_id);
+        }
+    }
+    Ok(added)
+}
+
+/// Untag tracks. Returns the track IDs that had the tag. Unused tags are kept so
+/// they stay available for autocomplete until deleted explicitly.
+pub fn remove_tag_from_tracks(conn: &Connection, name: &str, track_ids: &[i64]) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare(
+        "DELETE FROM track_tags
+         WHERE track_id = ?1 AND tag_id = (SELECT id FROM tags WHERE name = ?2)",
+    )?;
+
+    let mut removed = Vec::new();
+    for track_id in track_ids {
+        if stmt.execute(params![track_id, name])? > 0 {
+            removed.push(*track_id);
+        }
+    }
+    Ok(removed)
+}
+
+/// Rename a tag. Fails if another tag already has the new name.
+pub fn rename_tag(conn: &Connection, tag_id: i64, new_name: &str) -> Result<()> {
     conn.execute(
-        "DELETE FROM liked_tracks WHERE track_id = ?1",
-        params![track_id],
+        "UPDATE tags SET name = ?1 WHERE id = ?2",
+        params![new_name, tag_id],
     )?;
     Ok(())
 }
 
-pub fn is_track_liked(conn: &Connection, track_id: i64) -> Result<bool> {
-    let count: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM liked_tracks WHERE track_id = ?1",
-        params![track_id],
-        |row| row.get(0),
-    )?;
-    Ok(count > 0)
+pub fn get_tag_name(conn: &Connection, tag_id: i64) -> Result<Option<String>> {
+    conn.query_row("SELECT name FROM tags WHERE id = ?1", [tag_id], |row| row.get(0))
+        .optional()
 }
 
-pub fn get_liked_track_ids(conn: &Connection) -> Result<Vec<i64>> {
-    let mut stmt = conn.prepare("SELECT track_id FROM liked_tracks ORDER BY liked_at DESC")?;
-    let ids = stmt
-        .query_map([], |row| row.get(0))?
-        .collect::<Result<Vec<_>>>()?;
-    Ok(ids)
+pub fn delete_tag(conn: &Connection, tag_id: i64) -> Result<()> {
+    conn.execute("DELETE FROM tags WHERE id = ?1", [tag_id])?;
+    Ok(())
 }
 
-pub fn get_liked_tracks(conn: &Connection) -> Result<Vec<Track>> {
+/// All tags with how many tracks use them, most used first.
+pub fn get_all_tags(conn: &Connection) -> Result<Vec<TagInfo>> {
     let mut stmt = conn.prepare(
-        "SELECT t.id, t.path, t.title, t.artist, t.album, t.track_number, t.duration, t.album_id, t.format, t.bitrate, t.source_type, t.cover_url, t.external_id, t.local_src, t.track_cover_path, t.disc_number, t.metadata_json, t.date_added
-         FROM tracks t
-         INNER JOIN liked_tracks lt ON t.id = lt.track_id
-         ORDER BY lt.liked_at DESC",
+        "SELECT tg.id, tg.name, COUNT(tt.track_id)
+         FROM tags tg
+         LEFT JOIN track_tags tt ON tt.tag_id = tg.id
+         GROUP BY tg.id
+         ORDER BY COUNT(tt.track_id) DESC, tg.name COLLATE NOCASE",
     )?;
-
-    let tracks = stmt
+    let tags = stmt
         .query_map([], |row| {
-            Ok(Track {
+            Ok(TagInfo {
                 id: row.get(0)?,
-                path: row.get(1)?,
-                title: row.get(2)?,
-                artist: row.get(3)?,
-                album: row.get(4)?,
-                track_number: row.get(5)?,
-                duration: row.get(6)?,
-                album_id: row.get(7)?,
-                format: row.get(8)?,
-                bitrate: row.get(9)?,
-                source_type: row.get(10)?,
-                cover_url: row.get(11)?,
-                external_id: row.get(12)?,
-                local_src: row.get(13)?,
-                track_cover: None,
-                track_cover_path: row.get(14)?,
-                disc_number: row.get(15)?,
-                metadata_json: row.get(16)?,
-                date_added: row.get(17)?,
+                name: row.get(1)?,
+                track_count: row.get(2)?,
             })
         })?
         .collect::<Result<Vec<_>>>()?;
+    Ok(tags)
+}
 
-    Ok(tracks)
+pub fn get_track_tags(conn: &Connection, track_id: i64) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT tg.name FROM track_tags tt
+         INNER JOIN tags tg ON tg.id = tt.tag_id
+         WHERE tt.track_id = ?1
+         ORDER BY tg.name COLLATE NOCASE",
+    )?;
+    let names = stmt
+        .query_map([track_id], |row| row.get(0))?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(names)
+}
+
+/// Track IDs carrying a tag, used to expand "tag:" lookups on the frontend.
+pub fn get_track_ids_by_tag(conn: &Connection, name: &str) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare(
+        "SELECT tt.track_id FROM track_tags tt
+         INNER JOIN tags tg ON tg.id = tt.tag_id
+         WHERE tg.name = ?1
+         ORDER BY tt.added_at DESC",
+    )?;
+    let ids = stmt
+        .query_map([name], |row| row.get(0))?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(ids)
 }
 
 // ============================================================================
@@ -1259,7 +3690,7 @@ pub fn get_top_tracks(conn: &Connection, limit: i32) -> Result<Vec<TrackWithCoun
         "SELECT t.id, t.path, t.title, t.artist, t.album, t.track_number, t.duration, t.album_id, t.format, t.bitrate, t.source_type, t.cover_url, t.external_id, t.local_src, t.track_cover_path, t.disc_number, t.metadata_json, t.date_added, COUNT(ph.id) as play_count
          FROM tracks t
          INNER JOIN play_history ph ON t.id = ph.track_id
-         WHERE strftime('%Y-%m', ph.played_at) = strftime('%Y-%m', 'now')
+         WHERE ph.played_at >= strftime('%Y-%m-01', 'now')
          GROUP BY t.id
          ORDER BY play_count DESC
          LIMIT ?1",
@@ -1303,7 +3734,7 @@ pub fn get_top_albums(conn: &Connection, limit: i32) -> Result<Vec<AlbumWithCoun
          FROM albums a
          INNER JOIN play_history ph ON a.id = ph.album_id
          WHERE ph.album_id IS NOT NULL 
-         AND strftime('%Y-%m', ph.played_at) = strftime('%Y-%m', 'now')
+         AND ph.played_at >= strftime('%Y-%m-01', 'now')
          GROUP BY a.id
          ORDER BY play_count DESC
          LIMIT ?1",
@@ -1372,7 +3803,7 @@ pub fn get_top_artists(conn: &Connection, limit: i32) -> Result<Vec<ArtistWithCo
          FROM tracks t
          INNER JOIN play_history ph ON t.id = ph.track_id
          WHERE t.artist IS NOT NULL
-         AND strftime('%Y-%m', ph.played_at) = strftime('%Y-%m', 'now')
+         AND ph.played_at >= strftime('%Y-%m-01', 'now')
          GROUP BY t.artist
          ORDER BY play_count DESC
          LIMIT ?1",
@@ -1392,10 +3823,10 @@ pub fn get_top_artists(conn: &Connection, limit: i32) -> Result<Vec<ArtistWithCo
 
 pub fn get_stats_summary(conn: &Connection) -> Result<StatsSummary> {
     let total_plays: i64 =
-        conn.query_row("SELECT COUNT(*) FROM play_history WHERE strftime('%Y-%m', played_at) = strftime('%Y-%m', 'now')", [], |row| row.get(0))?;
+        conn.query_row("SELECT COUNT(*) FROM play_history WHERE played_at >= strftime('%Y-%m-01', 'now')", [], |row| row.get(0))?;
 
     let total_duration: i64 = conn.query_row(
-        "SELECT COALESCE(SUM(duration_played), 0) FROM play_history WHERE strftime('%Y-%m', played_at) = strftime('%Y-%m', 'now')",
+        "SELECT COALESCE(SUM(duration_played), 0) FROM play_history WHERE played_at >= strftime('%Y-%m-01', 'now')",
         [],
         |row| row.get(0),
     )?;
@@ -1406,7 +3837,7 @@ pub fn get_stats_summary(conn: &Connection) -> Result<StatsSummary> {
          FROM tracks t
          INNER JOIN play_history ph ON t.id = ph.track_id
          WHERE t.artist IS NOT NULL
-         AND strftime('%Y-%m', ph.played_at) = strftime('%Y-%m', 'now')
+         AND ph.played_at >= strftime('%Y-%m-01', 'now')
          GROUP BY t.artist
          ORDER BY COUNT(ph.id) DESC
          LIMIT 1",
@@ -1423,6 +3854,333 @@ pub fn get_stats_summary(conn: &Connection) -> Result<StatsSummary> {
     })
 }
 
+// ============================================================================
+// Mix operations
+// ============================================================================
+
+/// A library track with what the mix generator scores it on
+#[derive(Debug, Clone)]
+pub struct MixCandidate {
+    pub id: i64,
+    pub artist: Option<String>,
+    pub genre: Option<String>,
+    /// Days since the track was last played; None when it never was
+    pub days_since_played: Option<f64>,
+}
+
+/// Tracks that play without a connection: local files and downloaded streams
+pub fn get_mix_candidates(conn: &Connection) -> Result<Vec<MixCandidate>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT t.id, t.artist,
+                COALESCE(NULLIF(t.genre, ''), json_extract(t.metadata_json, '$.Genre')),
+                julianday('now') - julianday(ph.last_played)
+         FROM tracks t
+         LEFT JOIN (
+             SELECT track_id, MAX(played_at) AS last_played FROM play_history GROUP BY track_id
+         ) ph ON ph.track_id = t.id
+         WHERE ({}) OR t.local_src IS NOT NULL
+         ORDER BY t.id",
+        LOCAL_TRACKS_FILTER
+    ))?;
+    let candidates = stmt
+        .query_map([], |row| {
+            Ok(MixCandidate {
+                id: row.get(0)?,
+                artist: row.get(1)?,
+                genre: row.get(2)?,
+                days_since_played: row.get(3)?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(candidates)
+}
+
+/// Replace the cached relations of `artist` with `related` (name, relation type)
+pub fn cache_artist_relations(
+    conn: &Connection,
+    artist: &str,
+    related: &[(String, String)],
+) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "DELETE FROM artist_relations WHERE artist = ?1",
+        params![artist],
+    )?;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT OR IGNORE INTO artist_relations (artist, related_artist, relation_type)
+             VALUES (?1, ?2, ?3)",
+        )?;
+        for (name, relation_type) in related {
+            stmt.execute(params![artist, name, relation_type])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Lowercased names of artists related to `artist` in either direction
+pub fn get_related_artists(conn: &Connection, artist: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT lower(related_artist) FROM artist_relations WHERE artist = ?1
+         UNION
+         SELECT lower(artist) FROM artist_relations WHERE related_artist = ?1",
+    )?;
+    let names = stmt
+        .query_map(params![artist], |row| row.get(0))?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(names)
+}
+
+// ============================================================================
+// Operation journal (undo for destructive operations)
+// ============================================================================
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JournalEntry {
+    pub id: i64,
+    pub kind: String,
+    pub description: String,
+    pub created_at: Option<String>,
+    pub undone_at: Option<String>,
+}
+
+/// A column value of a snapshotted row; blobs are base64
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum StoredValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(String),
+}
+
+impl From<rusqlite::types::Value> for StoredValue {
+    fn from(value: rusqlite::types::Value) -> Self {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+        use rusqlite::types::Value;
+        match value {
+            Value::Null => Self::Null,
+            Value::Integer(i) => Self::Integer(i),
+            Value::Real(r) => Self::Real(r),
+            Value::Text(t) => Self::Text(t),
+            Value::Blob(b) => Self::Blob(STANDARD.encode(b)),
+        }
+    }
+}
+
+impl rusqlite::ToSql for StoredValue {
+    fn to_sql(&self) -> Result<rusqlite::types::ToSqlOutput<'_>> {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+        use rusqlite::types::{ToSqlOutput, Value, ValueRef};
+        Ok(match self {
+            Self::Null => ToSqlOutput::Borrowed(ValueRef::Null),
+            Self::Integer(i) => ToSqlOutput::Owned(Value::Integer(*i)),
+            Self::Real(r) => ToSqlOutput::Owned(Value::Real(*r)),
+            Self::Text(t) => ToSqlOutput::Borrowed(ValueRef::Text(t.as_bytes())),
+            Self::Blob(b) => ToSqlOutput::Owned(Value::Blob(
+                STANDARD
+                    .decode(b)
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?,
+            )),
+        })
+    }
+}
+
+/// A whole table row, as it was before an operation removed it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RowSnapshot {
+    pub table: String,
+    pub columns: Vec<String>,
+    pub values: Vec<StoredValue>,
+}
+
+fn snapshot_rows_where(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    ids: &[i64],
+) -> Result<Vec<RowSnapshot>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT * FROM \"{}\" WHERE \"{}\" = ?1",
+        table, column
+    ))?;
+    let columns: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
+    let mut snapshots = Vec::new();
+    for id in ids {
+        let rows = stmt.query_map(params![id], |row| {
+            (0..columns.len())
+                .map(|i| {
+                    row.get::<_, rusqlite::types::Value>(i)
+                        .map(StoredValue::from)
+                })
+                .collect::<Result<Vec<_>>>()
+        })?;
+        for values in rows {
+            snapshots.push(RowSnapshot {
+                table: table.to_string(),
+                columns: columns.clone(),
+                values: values?,
+            });
+        }
+    }
+    Ok(snapshots)
+}
+
+/// Snapshot of tracks together with their albums and every row that
+/// references them (playlist entries, likes, ratings, play history...), in the
+/// order they have to be restored
+pub fn snapshot_track_rows(conn: &Connection, track_ids: &[i64]) -> Result<Vec<RowSnapshot>> {
+    let mut album_ids = Vec::new();
+    for track_id in track_ids {
+        let album_id: Option<i64> = conn
+            .query_row(
+                "SELECT album_id FROM tracks WHERE id = ?1",
+                params![track_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        if let Some(id) = album_id.filter(|id| !album_ids.contains(id)) {
+            album_ids.push(id);
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT m.name, f.\"from\" FROM sqlite_master m, pragma_foreign_key_list(m.name) f
+         WHERE m.type = 'table' AND f.\"table\" = 'tracks'
+         ORDER BY m.name",
+    )?;
+    let dependents: Vec<(String, String)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_>>()?;
+
+    let mut snapshots = snapshot_rows_where(conn, "albums", "id", &album_ids)?;
+    snapshots.extend(snapshot_rows_where(conn, "tracks", "id", track_ids)?);
+    for (table, column) in dependents {
+        snapshots.extend(snapshot_rows_where(conn, &table, &column, track_ids)?);
+    }
+    Ok(snapshots)
+}
+
+/// Insert snapshotted rows again. Tracks must not exist; albums and
+/// dependent rows that are back already are left as they are.
+pub fn restore_rows(conn: &Connection, rows: &[RowSnapshot]) -> Result<()> {
+    for row in rows {
+        let verb = if row.table == "tracks" {
+            "INSERT"
+        } else {
+            "INSERT OR IGNORE"
+        };
+        let columns: Vec<String> = row.columns.iter().map(|c| format!("\"{}\"", c)).collect();
+        let placeholders: Vec<String> = (1..=row.values.len()).map(|i| format!("?{}", i)).collect();
+        conn.execute(
+            &format!(
+                "{} INTO \"{}\" ({}) VALUES ({})",
+                verb,
+                row.table,
+                columns.join(", "),
+                placeholders.join(", ")
+            ),
+            rusqlite::params_from_iter(&row.values),
+        )?;
+    }
+    Ok(())
+}
+
+pub fn insert_journal_entry(
+    conn: &Connection,
+    kind: &str,
+    description: &str,
+    data: &str,
+) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO operation_journal (kind, description, data) VALUES (?1, ?2, ?3)",
+        params![kind, description, data],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn update_journal_entry(conn: &Connection, id: i64, kind: &str, data: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE operation_journal SET kind = ?1, data = ?2 WHERE id = ?3",
+        params![kind, data, id],
+    )?;
+    Ok(())
+}
+
+pub fn delete_journal_entry(conn: &Connection, id: i64) -> Result<()> {
+    conn.execute("DELETE FROM operation_journal WHERE id = ?1", params![id])?;
+    Ok(())
+}
+
+/// Recorded journal entries, most recent first
+pub fn get_journal_entries(conn: &Connection, limit: usize) -> Result<Vec<JournalEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, kind, description, created_at, undone_at FROM operation_journal
+         WHERE kind != 'pending'
+         ORDER BY id DESC LIMIT ?1",
+    )?;
+    let rows = stmt.query_map(params![limit as i64], |row| {
+        Ok(JournalEntry {
+            id: row.get(0)?,
+            kind: row.get(1)?,
+            description: row.get(2)?,
+            created_at: row.get(3)?,
+            undone_at: row.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// An entry with its data, or the latest entry not undone yet when `id` is None
+pub fn get_journal_entry(
+    conn: &Connection,
+    id: Option<i64>,
+) -> Result<Option<(JournalEntry, String)>> {
+    conn.query_row(
+        "SELECT id, kind, description, created_at, undone_at, data FROM operation_journal
+         WHERE kind != 'pending'
+           AND CASE WHEN ?1 IS NULL THEN undone_at IS NULL ELSE id = ?1 END
+         ORDER BY id DESC LIMIT 1",
+        params![id],
+        |row| {
+            Ok((
+                JournalEntry {
+                    id: row.get(0)?,
+                    kind: row.get(1)?,
+                    description: row.get(2)?,
+                    created_at: row.get(3)?,
+                    undone_at: row.get(4)?,
+                },
+                row.get(5)?,
+            ))
+        },
+    )
+    .optional()
+}
+
+pub fn mark_journal_entry_undone(conn: &Connection, id: i64) -> Result<()> {
+    conn.execute(
+        "UPDATE operation_journal SET undone_at = CURRENT_TIMESTAMP WHERE id = ?1",
+        params![id],
+    )?;
+    Ok(())
+}
+
+/// Delete all but the newest `keep` entries, returning the deleted ids
+pub fn prune_journal(conn: &Connection, keep: usize) -> Result<Vec<i64>> {
+    let mut stmt =
+        conn.prepare("SELECT id FROM operation_journal ORDER BY id DESC LIMIT -1 OFFSET ?1")?;
+    let ids: Vec<i64> = stmt
+        .query_map(params![keep as i64], |row| row.get(0))?
+        .collect::<Result<_>>()?;
+    for id in &ids {
+        delete_journal_entry(conn, *id)?;
+    }
+    Ok(ids)
+}
+
 // =============================================================================
 // SYNC QUEUE & METADATA OPERATIONS
 // =============================================================================
@@ -1782,3 +4540,598 @@ pub fn enqueue_track_sync_change(conn: &Connection, track: &Track, operation: &s
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&conn).unwrap();
+        conn
+    }
+
+    fn insert_track(conn: &Connection, path: &str, title: &str, artist: &str, duration: i32) -> i64 {
+        conn.execute(
+            "INSERT INTO tracks (path, title, artist, duration) VALUES (?1, ?2, ?3, ?4)",
+            params![path, title, artist, duration],
+        )
+        .unwrap();
+        conn.last_insert_rowid()
+    }
+
+    fn rules(json: serde_json::Value) -> SmartPlaylistRules {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_smart_rules_compile_to_parameters() {
+        let r = rules(serde_json::json!({
+            "root": {
+                "type": "group",
+                "combinator": "or",
+                "rules": [
+                    { "type": "rule", "field": "artist", "operator": "contains", "value": "50%'; DROP" },
+                    { "type": "rule", "field": "duration", "operator": "greater_than", "value": 300 }
+                ]
+            },
+            "limit": 10
+        }));
+        let (sql, values) = r.to_sql();
+        assert!(!sql.contains("DROP"));
+        assert_eq!(values.len(), 3);
+        assert_eq!(
+            values[0],
+            rusqlite::types::Value::Text("%50\\%'; DROP%".to_string())
+        );
+    }
+
+    #[test]
+    fn test_smart_rules_validation() {
+        let bad = rules(serde_json::json!({
+            "root": { "type": "rule", "field": "duration", "operator": "contains", "value": "3" }
+        }));
+        assert!(bad.validate().is_err());
+
+        let bad_between = rules(serde_json::json!({
+            "root": { "type": "rule", "field": "year", "operator": "between", "value": [1990] }
+        }));
+        assert!(bad_between.validate().is_err());
+    }
+
+    #[test]
+    fn test_smart_playlist_tracks_follow_library() {
+        let conn = test_conn();
+        let short = insert_track(&conn, "/m/a.mp3", "Short", "Alpha", 120);
+        let long = insert_track(&conn, "/m/b.mp3", "Long", "Beta", 400);
+        record_play(&conn, long, None, 400).unwrap();
+        record_play(&conn, long, None, 400).unwrap();
+
+        let playlist_id = create_playlist(&conn, "Heavy rotation", None).unwrap();
+        let r = rules(serde_json::json!({
+            "root": {
+                "type": "group",
+                "combinator": "or",
+                "rules": [
+                    { "type": "rule", "field": "play_count", "operator": "greater_than", "value": 1 },
+                    { "type": "rule", "field": "liked", "operator": "is", "value": true }
+                ]
+            }
+        }));
+        set_playlist_smart_rules(&conn, playlist_id, Some(&r)).unwrap();
+
+        let ids: Vec<i64> = get_playlist_tracks(&conn, playlist_id)
+            .unwrap()
+            .iter()
+            .map(|t| t.id)
+            .collect();
+        assert_eq!(ids, vec![long]);
+
+        // Liking a track changes the result without touching the playlist
+        like_track(&conn, short).unwrap();
+        let ids: Vec<i64> = get_playlist_tracks(&conn, playlist_id)
+            .unwrap()
+            .iter()
+            .map(|t| t.id)
+            .collect();
+        assert_eq!(ids, vec![short, long]);
+    }
+
+    #[test]
+    fn test_search_query_language() {
+        let conn = test_conn();
+        init_fts(&conn).unwrap();
+        let around = insert_track(&conn, "/m/1.flac", "Around the World", "Daft Punk", 429);
+        let one_more = insert_track(&conn, "/m/2.mp3", "One More Time", "Daft Punk", 320);
+        let live = insert_track(&conn, "/m/3.mp3", "One More Time (Live)", "Daft Punk", 250);
+        insert_track(&conn, "/m/4.mp3", "Enter Sandman", "Metallica", 331);
+        conn.execute("UPDATE tracks SET format = 'Flac' WHERE id = ?1", [around])
+            .unwrap();
+        conn.execute("INSERT INTO liked_tracks (track_id) VALUES (?1)", [one_more])
+            .unwrap();
+
+        let ids = |q: &str| -> Vec<i64> {
+            let mut ids: Vec<i64> = search_tracks(&conn, q, 50, 0)
+                .unwrap()
+                .iter()
+                .map(|t| t.id)
+                .collect();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(ids("artist:daft -live duration:>300"), vec![around, one_more]);
+        assert_eq!(ids("format:flac"), vec![around]);
+        assert_eq!(ids("liked:yes"), vec![one_more]);
+        assert_eq!(ids("\"one more time\" -liked:yes"), vec![live]);
+        assert_eq!(ids("arou"), vec![around]);
+        // Typo falls back to close spellings from the index
+        assert_eq!(ids("metalica").len(), 1);
+        // Malformed input never errors
+        assert!(ids("\"unterminated AND ( OR").is_empty());
+        assert!(ids("").is_empty());
+    }
+
+    #[test]
+    fn test_genre_rules_read_scanned_metadata() {
+        let conn = test_conn();
+        init_fts(&conn).unwrap();
+        let scanned = insert_track(&conn, "/m/1.flac", "Scanned", "A", 200);
+        let edited = insert_track(&conn, "/m/2.flac", "Edited", "B", 200);
+        insert_track(&conn, "/m/3.flac", "Other", "C", 200);
+        conn.execute(
+            "UPDATE tracks SET metadata_json = '{\"Genre\":\"Deep House\"}' WHERE id = ?1",
+            [scanned],
+        )
+        .unwrap();
+        conn.execute("UPDATE tracks SET genre = 'House' WHERE id = ?1", [edited])
+            .unwrap();
+
+        let r = rules(serde_json::json!({
+            "root": { "type": "rule", "field": "genre", "operator": "contains", "value": "house" }
+        }));
+        let mut ids: Vec<i64> = get_smart_playlist_tracks(&conn, &r)
+            .unwrap()
+            .iter()
+            .map(|t| t.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec![scanned, edited]);
+
+        let found = search_tracks(&conn, "genre:deep", 50, 0).unwrap();
+        assert_eq!(
+            found.iter().map(|t| t.id).collect::<Vec<_>>(),
+            vec![scanned]
+        );
+    }
+
+    fn entry_track_ids(conn: &Connection, playlist_id: i64) -> Vec<i64> {
+        get_playlist_entries(conn, playlist_id)
+            .unwrap()
+            .iter()
+            .map(|e| e.track.id)
+            .collect()
+    }
+
+    #[test]
+    fn test_playlist_entries_allow_duplicates_and_moves() {
+        let conn = test_conn();
+        let a = insert_track(&conn, "/m/a.mp3", "A", "X", 100);
+        let b = insert_track(&conn, "/m/b.mp3", "B", "X", 100);
+        let c = insert_track(&conn, "/m/c.mp3", "C", "X", 100);
+        let playlist_id = create_playlist(&conn, "Mix", None).unwrap();
+
+        add_track_to_playlist(&conn, playlist_id, a).unwrap();
+        add_track_to_playlist(&conn, playlist_id, b).unwrap();
+        add_track_to_playlist(&conn, playlist_id, a).unwrap();
+        insert_playlist_entries(&conn, playlist_id, &[c, c], Some(1)).unwrap();
+        assert_eq!(entry_track_ids(&conn, playlist_id), vec![a, c, c, b, a]);
+
+        // Move the last two entries to the front, keeping their order
+        let entries = get_playlist_entries(&conn, playlist_id).unwrap();
+        let moved = move_playlist_entries(
+            &conn,
+            playlist_id,
+            &[entries[4].entry_id, entries[3].entry_id],
+            0,
+        )
+        .unwrap();
+        assert_eq!(moved.len(), 2);
+        assert_eq!(entry_track_ids(&conn, playlist_id), vec![b, a, a, c, c]);
+
+        // Removing one entry keeps the other occurrence
+        let first_a = get_playlist_entries(&conn, playlist_id).unwrap()[1].entry_id;
+        assert_eq!(remove_playlist_entries(&conn, playlist_id, &[first_a]).unwrap(), 1);
+        assert_eq!(entry_track_ids(&conn, playlist_id), vec![b, a, c, c]);
+
+        // Folder imports stay idempotent
+        assert!(ensure_track_in_playlist(&conn, playlist_id, b).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_playlist_positions_renumber_when_gap_exhausted() {
+        let conn = test_conn();
+        let a = insert_track(&conn, "/m/a.mp3", "A", "X", 100);
+        let b = insert_track(&conn, "/m/b.mp3", "B", "X", 100);
+        let playlist_id = create_playlist(&conn, "Mix", None).unwrap();
+        add_track_to_playlist(&conn, playlist_id, a).unwrap();
+        add_track_to_playlist(&conn, playlist_id, b).unwrap();
+
+        // Repeatedly inserting at the same index halves the gap each time
+        for _ in 0..60 {
+            insert_playlist_entries(&conn, playlist_id, &[b], Some(1)).unwrap();
+        }
+        let ids = entry_track_ids(&conn, playlist_id);
+        assert_eq!(ids.len(), 62);
+        assert_eq!(ids[0], a);
+        assert!(ids[1..].iter().all(|id| *id == b));
+
+        let positions: Vec<f64> = get_playlist_entries(&conn, playlist_id)
+            .unwrap()
+            .iter()
+            .map(|e| e.position)
+            .collect();
+        assert!(positions.windows(2).all(|w| w[1] - w[0] >= PLAYLIST_MIN_GAP));
+    }
+
+    #[test]
+    fn test_playlist_tracks_migrates_to_entry_ids() {
+        // Recreate the pre-entry-id layout on top of the current schema
+        let conn = test_conn();
+        for path in ["/a", "/b", "/c"] {
+            insert_track(&conn, path, path, "X", 100);
+        }
+        create_playlist(&conn, "Old", None).unwrap();
+        conn.execute_batch(
+            "DROP TABLE playlist_tracks;
+             CREATE TABLE playlist_tracks (
+                 playlist_id INTEGER NOT NULL,
+                 track_id INTEGER NOT NULL,
+                 position INTEGER,
+                 PRIMARY KEY (playlist_id, track_id)
+             );
+             INSERT INTO playlist_tracks VALUES (1, 1, 2), (1, 2, 0), (1, 3, 1);",
+        )
+        .unwrap();
+
+        crate::db::schema::init_schema(&conn).unwrap();
+
+        assert_eq!(entry_track_ids(&conn, 1), vec![2, 3, 1]);
+        add_track_to_playlist(&conn, 1, 2).unwrap();
+        assert_eq!(entry_track_ids(&conn, 1), vec![2, 3, 1, 2]);
+    }
+
+    #[test]
+    fn test_ratings_and_tags_in_smart_rules_and_search() {
+        let conn = test_conn();
+        init_fts(&conn).unwrap();
+        let a = insert_track(&conn, "/m/a.mp3", "Run", "Alpha", 200);
+        let b = insert_track(&conn, "/m/b.mp3", "Walk", "Beta", 200);
+        let c = insert_track(&conn, "/m/c.mp3", "Rest", "Gamma", 200);
+
+        set_track_rating(&conn, a, 5).unwrap();
+        set_track_rating(&conn, b, 2).unwrap();
+        set_track_rating(&conn, b, 0).unwrap();
+        assert_eq!(get_track_rating(&conn, b).unwrap(), 0);
+
+        add_tag_to_tracks(&conn, "Workout", &[a, b]).unwrap();
+        // Tag names are case-insensitive
+        assert!(add_tag_to_tracks(&conn, "workout", &[a]).unwrap().is_empty());
+
+        let ids = |json: serde_json::Value| -> Vec<i64> {
+            get_smart_playlist_tracks(&conn, &rules(json))
+                .unwrap()
+                .iter()
+                .map(|t| t.id)
+                .collect()
+        };
+        assert_eq!(
+            ids(serde_json::json!({ "root": { "type": "rule", "field": "rating", "operator": "greater_than", "value": 3 } })),
+            vec![a]
+        );
+        assert_eq!(
+            ids(serde_json::json!({ "root": { "type": "rule", "field": "tag", "operator": "is_not", "value": "WORKOUT" } })),
+            vec![c]
+        );
+
+        let found: Vec<i64> = search_tracks(&conn, "tag:workout -rating:5", 10, 0)
+            .unwrap()
+            .iter()
+            .map(|t| t.id)
+            .collect();
+        assert_eq!(found, vec![b]);
+    }
+
+    #[test]
+    fn test_file_fingerprints_follow_track_updates() {
+        let conn = test_conn();
+        let mut track: TrackInsert = serde_json::from_value(serde_json::json!({
+            "path": "/music/a.flac",
+            "title": "A",
+            "artist": null, "album": null, "track_number": null, "disc_number": null,
+            "duration": 200, "album_art": null, "track_cover": null, "format": null,
+            "bitrate": null, "source_type": null, "cover_url": null, "external_id": null,
+            "content_hash": null, "local_src": null, "musicbrainz_recording_id": null,
+            "metadata_json": null,
+            "file_size": 1000,
+            "file_mtime": 1700000000000i64
+        }))
+        .unwrap();
+        insert_or_update_track(&conn, &track).unwrap();
+        // Tracks without a fingerprint (e.g. streams) are never skipped
+        insert_track(&conn, "/music/b.flac", "B", "X", 100);
+
+        let known = get_track_file_fingerprints(&conn).unwrap();
+        assert_eq!(known.len(), 1);
+        assert_eq!(known["/music/a.flac"], (1000, 1700000000000));
+
+        track.file_size = Some(2000);
+        insert_or_update_track(&conn, &track).unwrap();
+        let known = get_track_file_fingerprints(&conn).unwrap();
+        assert_eq!(known["/music/a.flac"], (2000, 1700000000000));
+
+        // Audio features last until the audio itself changes
+        let id = get_track_id_by_path(&conn, "/music/a.flac")
+            .unwrap()
+            .unwrap();
+        let features = AudioFeatures {
+            bpm: Some(120.0),
+            key: Some("Am".to_string()),
+            energy: Some(0.5),
+            danceability: Some(0.5),
+        };
+        let to_analyse = |conn: &Connection| -> Vec<i64> {
+            get_tracks_to_analyse(conn, false)
+                .unwrap()
+                .iter()
+                .map(|t| t.id)
+                .collect()
+        };
+        set_audio_features(&conn, id, Some(&features)).unwrap();
+        insert_or_update_track(&conn, &track).unwrap();
+        assert!(!to_analyse(&conn).contains(&id));
+        assert!(get_audio_features(&conn).unwrap().contains_key(&id));
+        track.content_hash = Some("changed".to_string());
+        insert_or_update_track(&conn, &track).unwrap();
+        assert!(to_analyse(&conn).contains(&id));
+        assert!(!get_audio_features(&conn).unwrap().contains_key(&id));
+    }
+
+    #[test]
+    fn test_scan_issues_are_replaced_by_later_scans() {
+        let conn = test_conn();
+        let track_id = insert_track(&conn, "/music/a.mp3", "a", "", 100);
+        conn.execute(
+            "UPDATE tracks SET metadata_fallback = 1 WHERE id = ?1",
+            [track_id],
+        )
+        .unwrap();
+
+        let tags = ScanIssue::new(
+            "/music/a.mp3",
+            ScanStage::Tags,
+            ScanIssueKind::MissingTags,
+            "No tags",
+        );
+        let walk = ScanIssue::new(
+            "/music/locked",
+            ScanStage::Walk,
+            ScanIssueKind::PermissionDenied,
+            "denied",
+        );
+        let elsewhere = ScanIssue::new("/other/x", ScanStage::Walk, ScanIssueKind::Io, "io");
+        let walked = vec!["/music".to_string()];
+        let processed: std::collections::HashSet<String> = ["/music/a.mp3".to_string()].into();
+
+        record_scan_results(&conn, &walked, &processed, &[tags.clone(), walk, elsewhere]).unwrap();
+        record_scan_results(&conn, &walked, &processed, &[tags]).unwrap();
+
+        let issues = get_scan_issues(&conn).unwrap();
+        let mut found: Vec<_> = issues
+            .iter()
+            .map(|r| (r.issue.path.as_str(), r.issue.stage, r.occurrences))
+            .collect();
+        found.sort_by_key(|f| f.0);
+        // The locked folder was readable on the second walk; /other wasn't walked
+        assert_eq!(
+            found,
+            vec![
+                ("/music/a.mp3", ScanStage::Tags, 2),
+                ("/other/x", ScanStage::Walk, 1)
+            ]
+        );
+        let tagged = issues
+            .iter()
+            .find(|r| r.issue.path == "/music/a.mp3")
+            .unwrap();
+        assert_eq!(tagged.track_id, Some(track_id));
+        assert!(tagged.metadata_fallback);
+
+        let untagged = rules(serde_json::json!({
+            "root": { "type": "rule", "field": "untagged", "operator": "is", "value": true }
+        }));
+        let results = get_smart_playlist_tracks(&conn, &untagged).unwrap();
+        assert_eq!(results.len(), 1);
+
+        // Read again with tags this time
+        record_scan_results(&conn, &[], &processed, &[]).unwrap();
+        assert_eq!(get_scan_issues(&conn).unwrap().len(), 1);
+
+        // Gone from disk, so dismissed whatever their stage
+        assert_eq!(dismiss_missing_scan_issues(&conn, &walked).unwrap(), 0);
+        assert_eq!(
+            dismiss_missing_scan_issues(&conn, &["/other".to_string()]).unwrap(),
+            1
+        );
+        assert!(get_scan_issues(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_deleted_tracks_restore_from_snapshot() {
+        let conn = test_conn();
+        init_fts(&conn).unwrap();
+        let a = insert_track(&conn, "/m/a.mp3", "A", "X", 100);
+        let b = insert_track(&conn, "/m/b.mp3", "B", "X", 100);
+        conn.execute("INSERT INTO albums (name, artist) VALUES ('LP', 'X')", [])
+            .unwrap();
+        let album_id = conn.last_insert_rowid();
+        conn.execute(
+            "UPDATE tracks SET album_id = ?1, acoustic_fingerprint = x'0102'",
+            params![album_id],
+        )
+        .unwrap();
+        let playlist_id = create_playlist(&conn, "Mix", None).unwrap();
+        add_track_to_playlist(&conn, playlist_id, b).unwrap();
+        add_track_to_playlist(&conn, playlist_id, a).unwrap();
+        set_track_rating(&conn, a, 4).unwrap();
+        like_track(&conn, a).unwrap();
+
+        let rows = snapshot_track_rows(&conn, &[a]).unwrap();
+        delete_track(&conn, a).unwrap();
+        delete_track(&conn, b).unwrap();
+        cleanup_empty_albums(&conn).unwrap();
+        restore_rows(&conn, &rows).unwrap();
+
+        let restored = get_track_by_id(&conn, a).unwrap().unwrap();
+        assert_eq!(restored.album_id, Some(album_id));
+        let fingerprint: Vec<u8> = conn
+            .query_row(
+                "SELECT acoustic_fingerprint FROM tracks WHERE id = ?1",
+                [a],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(fingerprint, vec![1, 2]);
+        assert_eq!(entry_track_ids(&conn, playlist_id), vec![a]);
+        let rating: u8 = conn
+            .query_row(
+                "SELECT rating FROM track_ratings WHERE track_id = ?1",
+                [a],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(rating, 4);
+        assert!(is_track_liked(&conn, a).unwrap());
+        // Searchable again through the FTS triggers
+        let found: i64 = conn
+            .query_row(
+                "SELECT rowid FROM tracks_fts WHERE tracks_fts MATCH 'A'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(found, a);
+        // The track itself can't be restored twice
+        assert!(restore_rows(&conn, &rows).is_err());
+
+        for i in 0..5 {
+            insert_journal_entry(&conn, "delete", &format!("Delete {}", i), "{}").unwrap();
+        }
+        let pruned = prune_journal(&conn, 3).unwrap();
+        assert_eq!(pruned.len(), 2);
+        let latest = get_journal_entry(&conn, None).unwrap().unwrap().0;
+        assert_eq!(latest.description, "Delete 4");
+        mark_journal_entry_undone(&conn, latest.id).unwrap();
+        let latest = get_journal_entry(&conn, None).unwrap().unwrap().0;
+        assert_eq!(latest.description, "Delete 3");
+        // An operation still being recorded can't be listed or undone
+        let pending = insert_journal_entry(&conn, "pending", "Delete 5", "{}").unwrap();
+        assert!(get_journal_entry(&conn, Some(pending)).unwrap().is_none());
+        let latest = get_journal_entry(&conn, None).unwrap().unwrap().0;
+        assert_eq!(latest.description, "Delete 3");
+        assert_eq!(get_journal_entries(&conn, 10).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_playlist_folder_tree_moves() {
+        use PlaylistTreeItem::{Folder, Playlist};
+        let conn = test_conn();
+        let rock = create_playlist_folder(&conn, "Rock", None).unwrap();
+        let mix = create_playlist(&conn, "Mix", None).unwrap();
+        let live = create_playlist_folder(&conn, "Live", Some(rock)).unwrap();
+        let gigs = create_playlist(&conn, "Gigs", None).unwrap();
+        assert_eq!(
+            get_playlist_tree_children(&conn, None).unwrap(),
+            [Folder(rock), Playlist(mix), Playlist(gigs)]
+        );
+
+        // Into a folder, before its subfolder
+        let moves = move_playlist_tree_item(&conn, Playlist(gigs), Some(rock), 0).unwrap();
+        assert_eq!(moves.len(), 2);
+        assert_eq!(
+            get_playlist_tree_children(&conn, Some(rock)).unwrap(),
+            [Playlist(gigs), Folder(live)]
+        );
+        assert_eq!(get_playlist_folder_ancestors(&conn, live).unwrap(), [rock]);
+
+        // Within the top level; only the moved items change
+        let moves = move_playlist_tree_item(&conn, Playlist(mix), None, 0).unwrap();
+        assert_eq!(moves.len(), 2);
+        assert_eq!(
+            get_playlist_tree_children(&conn, None).unwrap(),
+            [Playlist(mix), Folder(rock)]
+        );
+
+        // Deleting a folder moves its contents up into its place
+        delete_playlist_folder(&conn, rock).unwrap();
+        assert_eq!(
+            get_playlist_tree_children(&conn, None).unwrap(),
+            [Playlist(mix), Playlist(gigs), Folder(live)]
+        );
+        assert_eq!(get_playlist_folders(&conn).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_playlist_details_and_sort_orders() {
+        let conn = test_conn();
+        let b = insert_track(&conn, "/m/b.mp3", "Blue", "Zed", 100);
+        let a = insert_track(&conn, "/m/a.mp3", "azure", "Yam", 50);
+        let mix = create_playlist(&conn, "Mix", None).unwrap();
+        let pinned = create_playlist(&conn, "Pinned", None).unwrap();
+        insert_playlist_entries(&conn, mix, &[b, a, b], None).unwrap();
+        set_playlist_pinned(&conn, pinned, true).unwrap();
+        set_playlist_description(&conn, mix, Some("Colours")).unwrap();
+
+        let playlists = get_all_playlists(&conn).unwrap();
+        assert_eq!(playlists[0].id, pinned);
+        assert_eq!(playlists[0].track_count, Some(0));
+        let listed = &playlists[1];
+        assert_eq!(listed.track_count, Some(3));
+        assert_eq!(listed.duration, Some(250));
+        assert_eq!(listed.description.as_deref(), Some("Colours"));
+        assert!(listed.modified_at.is_some());
+
+        let ids = |conn: &Connection| -> Vec<i64> {
+            get_playlist_tracks(conn, mix)
+                .unwrap()
+                .iter()
+                .map(|t| t.id)
+                .collect()
+        };
+        assert_eq!(ids(&conn), [b, a, b]);
+        set_playlist_sort_order(&conn, mix, PlaylistSortOrder::Title).unwrap();
+        assert_eq!(ids(&conn), [a, b, b]);
+        set_playlist_sort_order(&conn, mix, PlaylistSortOrder::Artist).unwrap();
+        assert_eq!(ids(&conn), [a, b, b]);
+        // The manual order is kept underneath
+        set_playlist_sort_order(&conn, mix, PlaylistSortOrder::Manual).unwrap();
+        assert_eq!(ids(&conn), [b, a, b]);
+
+        // Entry changes touch the modified time
+        conn.execute(
+            "UPDATE playlists SET modified_at = NULL, created_at = '2000-01-01 00:00:00'",
+            [],
+        )
+        .unwrap();
+        remove_track_from_playlist(&conn, mix, a).unwrap();
+        let playlists = get_all_playlists(&conn).unwrap();
+        assert_ne!(
+            playlists[1].modified_at.as_deref(),
+            Some("2000-01-01 00:00:00")
+        );
+        assert_eq!(
+            playlists[0].modified_at.as_deref(),
+            Some("2000-01-01 00:00:00")
+        );
+    }
+}
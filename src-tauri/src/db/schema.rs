@@ -49,12 +49,14 @@ pub fn init_schema(conn: &Connection) -> Result<()> {
             created_at TEXT DEFAULT CURRENT_TIMESTAMP
         );
 
-        -- Playlist tracks junction table
+        -- Playlist entries: one row per slot, so a track may appear more than once.
+        -- position is sparse (gap-based) so moves only touch the moved rows.
         CREATE TABLE IF NOT EXISTS playlist_tracks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
             playlist_id INTEGER NOT NULL,
             track_id INTEGER NOT NULL,
-            position INTEGER,
-            PRIMARY KEY (playlist_id, track_id),
+            position REAL,
+            added_at TEXT DEFAULT CURRENT_TIMESTAMP,
             FOREIGN KEY (playlist_id) REFERENCES playlists(id) ON DELETE CASCADE,
             FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
         );
@@ -73,6 +75,68 @@ pub fn init_schema(conn: &Connection) -> Result<()> {
             FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
         );
 
+        -- Star ratings (1-5; unrated tracks have no row)
+        CREATE TABLE IF NOT EXISTS track_ratings (
+            track_id INTEGER PRIMARY KEY,
+            rating INTEGER NOT NULL CHECK (rating BETWEEN 1 AND 5),
+            rated_at TEXT DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
+        );
+
+        -- Free-form user tags (many-to-many with tracks)
+        CREATE TABLE IF NOT EXISTS tags (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT UNIQUE NOT NULL COLLATE NOCASE,
+            created_at TEXT DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE IF NOT EXISTS track_tags (
+            track_id INTEGER NOT NULL,
+            tag_id INTEGER NOT NULL,
+            added_at TEXT DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (track_id, tag_id),
+            FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE,
+            FOREIGN KEY (tag_id) REFERENCES tags(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS idx_track_tags_tag ON track_tags(tag_id);
+
+        -- Files skipped on import because a track with identical audio exists
+        CREATE TABLE IF NOT EXISTS duplicate_files (
+            path TEXT PRIMARY KEY,
+            track_id INTEGER NOT NULL,
+            format TEXT,
+            bitrate INTEGER,
+            file_size INTEGER,
+            duration INTEGER,
+            detected_at TEXT DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS idx_duplicate_files_track ON duplicate_files(track_id);
+
+        -- Files that failed some step of a scan, one row per path and stage
+        CREATE TABLE IF NOT EXISTS scan_issues (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            path TEXT NOT NULL,
+            stage TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            message TEXT NOT NULL,
+            occurrences INTEGER NOT NULL DEFAULT 1,
+            first_seen TEXT DEFAULT CURRENT_TIMESTAMP,
+            last_seen TEXT DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(path, stage)
+        );
+
+        -- Destructive operations that can be undone; data is a JSON snapshot of
+        -- what they changed, backed-up files live in journal/{id}
+        CREATE TABLE IF NOT EXISTS operation_journal (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            description TEXT NOT NULL,
+            data TEXT NOT NULL,
+            created_at TEXT DEFAULT CURRENT_TIMESTAMP,
+            undone_at TEXT
+        );
+
         -- Play history table (one row per play event)
         CREATE TABLE IF NOT EXISTS play_history (
             id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -83,10 +147,13 @@ pub fn init_schema(conn: &Connection) -> Result<()> {
             FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
         );
 
-        -- Play history indexes for fast aggregation
-        CREATE INDEX IF NOT EXISTS idx_play_history_track ON play_history(track_id);
+        -- Play history indexes for fast aggregation. The time index covers
+        -- track_id and duration_played so range stats never touch the table rows.
+        DROP INDEX IF EXISTS idx_play_history_track;
+        DROP INDEX IF EXISTS idx_play_history_time;
+        CREATE INDEX IF NOT EXISTS idx_play_history_track_time ON play_history(track_id, played_at);
         CREATE INDEX IF NOT EXISTS idx_play_history_album ON play_history(album_id);
-        CREATE INDEX IF NOT EXISTS idx_play_history_time ON play_history(played_at);
+        CREATE INDEX IF NOT EXISTS idx_play_history_time_covering ON play_history(played_at, track_id, duration_played);
 
         -- Composite index
         -- This single index covers: ORDER BY artist, album, track_number, title
@@ -116,6 +183,15 @@ pub fn init_schema(conn: &Connection) -> Result<()> {
         ("date_added", "TEXT DEFAULT CURRENT_TIMESTAMP"),
         ("genre", "TEXT"),
         ("metadata_json", "TEXT"),
+        ("file_size", "INTEGER"),
+        ("file_mtime", "INTEGER"),
+        ("acoustic_fingerprint", "BLOB"),
+        ("metadata_fallback", "INTEGER NOT NULL DEFAULT 0"),
+        ("bpm", "REAL"),
+        ("musical_key", "TEXT"),
+        ("energy", "REAL"),
+        ("danceability", "REAL"),
+        ("features_analysed_at", "TEXT"),
     ];
 
     for (col_name, col_def) in tracks_columns {
@@ -169,11 +245,46 @@ pub fn init_schema(conn: &Connection) -> Result<()> {
         [],
     );
 
+    // Per-folder scan settings
+    let music_folders_columns = [
+        ("exclude_patterns", "TEXT"),
+        ("min_duration", "INTEGER"),
+        ("max_depth", "INTEGER"),
+        ("follow_symlinks", "INTEGER NOT NULL DEFAULT 1"),
+        ("cover_art_names", "TEXT"),
+        ("fetch_online_art", "INTEGER NOT NULL DEFAULT 0"),
+    ];
+    for (col_name, col_def) in music_folders_columns {
+        if !column_exists(conn, "music_folders", col_name)? {
+            println!("[DB] Adding missing column '{}' to music_folders table...", col_name);
+            let sql = format!("ALTER TABLE music_folders ADD COLUMN {} {}", col_name, col_def);
+            if let Err(e) = conn.execute(&sql, []) {
+                eprintln!("[DB] Failed to add column '{}': {}", col_name, e);
+            }
+        }
+    }
+
     // Verify or add columns to albums table
     if !column_exists(conn, "albums", "art_path")? {
         println!("[DB] Adding missing column 'art_path' to albums table...");
         let _ = conn.execute("ALTER TABLE albums ADD COLUMN art_path TEXT", []);
     }
+    if !column_exists(conn, "albums", "art_hash")? {
+        println!("[DB] Adding missing column 'art_hash' to albums table...");
+        let _ = conn.execute("ALTER TABLE albums ADD COLUMN art_hash TEXT", []);
+    }
+    if !column_exists(conn, "albums", "palette")? {
+        println!("[DB] Adding missing column 'palette' to albums table...");
+        let _ = conn.execute("ALTER TABLE albums ADD COLUMN palette TEXT", []);
+    }
+    // Release MBID found by the online art lookup, and when the lookup last ran
+    for col_name in ["musicbrainz_release_id", "art_lookup_at"] {
+        if !column_exists(conn, "albums", col_name)? {
+            println!("[DB] Adding missing column '{}' to albums table...", col_name);
+            let sql = format!("ALTER TABLE albums ADD COLUMN {} TEXT", col_name);
+            let _ = conn.execute(&sql, []);
+        }
+    }
 
     // ─── Sync infrastructure tables ──────────────────────────────────────────
     conn.execute_batch(
@@ -181,7 +292,7 @@ pub fn init_schema(conn: &Connection) -> Result<()> {
         -- Unified sync queue: all pending changes to push to server
         CREATE TABLE IF NOT EXISTS sync_queue (
             id INTEGER PRIMARY KEY AUTOINCREMENT,
-            entity_type TEXT NOT NULL,   -- 'playlist' | 'playlist_track' | 'liked_track' | 'settings'
+            entity_type TEXT NOT NULL,   -- 'playlist' | 'playlist_track' | 'liked_track' | 'track_rating' | 'track_tag' | 'settings'
             entity_id TEXT NOT NULL,     -- local ID or composite key
             operation TEXT NOT NULL,     -- 'create' | 'update' | 'delete'
             payload TEXT,               -- JSON snapshot of the entity at time of change
@@ -220,14 +331,135 @@ pub fn init_schema(conn: &Connection) -> Result<()> {
     );
     let _ = conn.execute("ALTER TABLE playlists ADD COLUMN folder_path TEXT", []);
     let _ = conn.execute("ALTER TABLE playlists ADD COLUMN cover_url TEXT", []);
+    // Smart playlists: JSON rule tree, NULL for regular playlists
+    let _ = conn.execute("ALTER TABLE playlists ADD COLUMN smart_rules TEXT", []);
+    // Playlist file kept in sync, with hashes of the file and the entries at the last sync
+    let _ = conn.execute("ALTER TABLE playlists ADD COLUMN linked_file TEXT", []);
+    let _ = conn.execute("ALTER TABLE playlists ADD COLUMN linked_file_hash TEXT", []);
+    let _ = conn.execute("ALTER TABLE playlists ADD COLUMN linked_entries_hash TEXT", []);
+    // Entries of the linked file that match no track (JSON), kept for writing back
+    let _ = conn.execute("ALTER TABLE playlists ADD COLUMN linked_unmatched TEXT", []);
+
+    // Playlist folders: a tree of folders holding playlists and subfolders.
+    // Folders and playlists with the same parent share one position order.
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS playlist_folders (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            parent_id INTEGER REFERENCES playlist_folders(id) ON DELETE SET NULL,
+            position REAL NOT NULL DEFAULT 0,
+            created_at TEXT DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE INDEX IF NOT EXISTS idx_playlist_folders_parent ON playlist_folders(parent_id);
+        ",
+    )?;
+    let _ = conn.execute(
+        "ALTER TABLE playlists ADD COLUMN folder_id INTEGER REFERENCES playlist_folders(id) ON DELETE SET NULL",
+        [],
+    );
+    let _ = conn.execute(
+        "ALTER TABLE playlists ADD COLUMN folder_position REAL NOT NULL DEFAULT 0",
+        [],
+    );
+    // Playlist details; modified_at is kept current by the triggers below
+    let _ = conn.execute("ALTER TABLE playlists ADD COLUMN description TEXT", []);
+    let _ = conn.execute("ALTER TABLE playlists ADD COLUMN modified_at TEXT", []);
+    let _ = conn.execute(
+        "ALTER TABLE playlists ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0",
+        [],
+    );
+    let _ = conn.execute(
+        "ALTER TABLE playlists ADD COLUMN sort_order TEXT NOT NULL DEFAULT 'manual'",
+        [],
+    );
     let _ = conn.execute(
         "CREATE UNIQUE INDEX IF NOT EXISTS idx_playlists_server_id ON playlists(server_id)",
         [],
     );
 
+    // Playlist entries: migrate the old (playlist_id, track_id) primary key table
+    migrate_playlist_entries(conn)?;
+    let _ = conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_playlist_tracks_order ON playlist_tracks(playlist_id, position)",
+        [],
+    );
+
     // Initialize playlist positions for existing playlists
     initialize_playlist_positions(conn)?;
 
+    // A playlist is modified when its details or entries change
+    conn.execute_batch(
+        "
+        CREATE TRIGGER IF NOT EXISTS playlists_modified_au
+        AFTER UPDATE OF name, cover_url, description, smart_rules ON playlists BEGIN
+            UPDATE playlists SET modified_at = CURRENT_TIMESTAMP WHERE id = new.id;
+        END;
+        CREATE TRIGGER IF NOT EXISTS playlist_tracks_modified_ai AFTER INSERT ON playlist_tracks BEGIN
+            UPDATE playlists SET modified_at = CURRENT_TIMESTAMP WHERE id = new.playlist_id;
+        END;
+        CREATE TRIGGER IF NOT EXISTS playlist_tracks_modified_ad AFTER DELETE ON playlist_tracks BEGIN
+            UPDATE playlists SET modified_at = CURRENT_TIMESTAMP WHERE id = old.playlist_id;
+        END;
+        CREATE TRIGGER IF NOT EXISTS playlist_tracks_modified_au
+        AFTER UPDATE OF position ON playlist_tracks BEGIN
+            UPDATE playlists SET modified_at = CURRENT_TIMESTAMP WHERE id = new.playlist_id;
+        END;
+        ",
+    )?;
+
+    // Related artists from MusicBrainz, kept so mixes can be built offline
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS artist_relations (
+            artist TEXT NOT NULL COLLATE NOCASE,
+            related_artist TEXT NOT NULL COLLATE NOCASE,
+            relation_type TEXT,
+            fetched_at TEXT DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (artist, related_artist)
+        );
+        CREATE INDEX IF NOT EXISTS idx_artist_relations_related ON artist_relations(related_artist);
+        ",
+    )?;
+
+    Ok(())
+}
+
+/// Rebuild `playlist_tracks` with a per-entry id if it still uses the old
+/// `PRIMARY KEY (playlist_id, track_id)` layout. Existing order is kept and
+/// positions are spread out by `PLAYLIST_POSITION_GAP`.
+fn migrate_playlist_entries(conn: &Connection) -> Result<()> {
+    if column_exists(conn, "playlist_tracks", "id")? {
+        return Ok(());
+    }
+
+    println!("[DB] Migrating playlist_tracks to per-entry ids...");
+    let tx = conn.unchecked_transaction()?;
+    tx.execute_batch(
+        "
+        ALTER TABLE playlist_tracks RENAME TO playlist_tracks_old;
+
+        CREATE TABLE playlist_tracks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            playlist_id INTEGER NOT NULL,
+            track_id INTEGER NOT NULL,
+            position REAL,
+            added_at TEXT DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (playlist_id) REFERENCES playlists(id) ON DELETE CASCADE,
+            FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
+        );
+
+        INSERT INTO playlist_tracks (playlist_id, track_id, position)
+        SELECT playlist_id, track_id, NULL
+        FROM playlist_tracks_old
+        ORDER BY playlist_id, position IS NULL, position, rowid;
+
+        DROP TABLE playlist_tracks_old;
+        ",
+    )?;
+    tx.commit()?;
+
+    // Positions are assigned by initialize_playlist_positions in insertion (id) order
     Ok(())
 }
 
@@ -249,25 +481,26 @@ fn initialize_playlist_positions(conn: &Connection) -> Result<()> {
 
     // For each playlist, assign sequential positions
     for playlist_id in playlist_ids {
-        // Get all track_ids in this playlist (in insertion order via rowid)
-        let mut track_stmt = conn.prepare(
-            "SELECT track_id 
+        // Get all entries in this playlist (in insertion order via id)
+        let mut entry_stmt = conn.prepare(
+            "SELECT id 
              FROM playlist_tracks 
              WHERE playlist_id = ?1 
-             ORDER BY rowid",
+             ORDER BY position IS NULL, position, id",
         )?;
 
-        let track_ids: Vec<i64> = track_stmt
+        let entry_ids: Vec<i64> = entry_stmt
             .query_map(params![playlist_id], |row| row.get(0))?
             .collect::<Result<Vec<_>>>()?;
 
-        // Assign sequential positions
-        for (pos, track_id) in track_ids.iter().enumerate() {
+        // Assign evenly spaced positions
+        for (pos, entry_id) in entry_ids.iter().enumerate() {
             conn.execute(
-                "UPDATE playlist_tracks 
-                 SET position = ?1 
-                 WHERE playlist_id = ?2 AND track_id = ?3",
-                params![pos as i64, playlist_id, track_id],
+                "UPDATE playlist_tracks SET position = ?1 WHERE id = ?2",
+                params![
+                    (pos as f64 + 1.0) * super::queries::PLAYLIST_POSITION_GAP,
+                    entry_id
+                ],
             )?;
         }
     }
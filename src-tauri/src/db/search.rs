@@ -0,0 +1,430 @@
+// Search query language parsed into FTS5 expressions and structured filters
+//
+// Supported syntax:
+//   daft punk            bare words, prefix matched ("daft"* AND "punk"*)
+//   "around the world"   quoted phrase, matched exactly
+//   -live                negation of any term or filter
+//   artist:daft  album:"discovery"  title:one    FTS column filters
+//   genre:house  format:flac  liked:yes  tag:workout  rating:>=4  untagged:yes
+//   year:1990..1999  year:2001  year:>2000  duration:>300  bitrate:>=320
+//
+// Anything the parser does not understand is searched as plain text, so user
+// input can never produce an FTS5 syntax error.
+use rusqlite::{params, Connection, Result};
+
+use super::queries::{SmartField, SmartOperator, SmartRuleNode};
+
+/// A text term matched against `tracks_fts`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchTerm {
+    /// FTS column to restrict to (`title`, `artist`, `album`), or all columns
+    pub column: Option<&'static str>,
+    pub text: String,
+    /// Quoted by the user: match as an exact phrase, no prefix expansion
+    pub phrase: bool,
+    pub negated: bool,
+    /// Close spellings from the index, OR-ed with the term (see `expand_typos`)
+    pub alternatives: Vec<String>,
+}
+
+/// A structured filter evaluated with the smart playlist rule compiler.
+#[derive(Debug, Clone)]
+pub struct SearchFilter {
+    pub rule: SmartRuleNode,
+    pub negated: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParsedQuery {
+    pub terms: Vec<SearchTerm>,
+    pub filters: Vec<SearchFilter>,
+}
+
+impl ParsedQuery {
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty() && self.filters.is_empty()
+    }
+
+    /// FTS5 MATCH expression for all positive terms, or None if there are none.
+    pub fn fts_match(&self) -> Option<String> {
+        let parts: Vec<String> = self
+            .terms
+            .iter()
+            .filter(|t| !t.negated)
+            .map(|t| t.fts_expr())
+            .collect();
+
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join(" AND "))
+        }
+    }
+}
+
+impl SearchTerm {
+    /// Render this term as a safe FTS5 expression. The text is always emitted as a
+    /// double-quoted string, so punctuation and FTS keywords are treated literally.
+    pub fn fts_expr(&self) -> String {
+        let quoted = fts_quote(&self.text);
+        let phrase = if self.phrase {
+            quoted
+        } else {
+            format!("{}*", quoted)
+        };
+        let expr = match self.column {
+            Some(col) => format!("{} : {}", col, phrase),
+            None => phrase,
+        };
+
+        if self.alternatives.is_empty() {
+            expr
+        } else {
+            let mut options = vec![expr];
+            options.extend(self.alternatives.iter().map(|w| fts_quote(w)));
+            format!("({})", options.join(" OR "))
+        }
+    }
+}
+
+fn fts_quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+/// Split input into raw tokens, keeping quoted sections together.
+/// Returns (token, was_quoted) pairs; an unterminated quote runs to the end of input.
+fn tokenize(input: &str) -> Vec<(String, bool)> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+
+    for c in input.chars() {
+        match c {
+            '"' => {
+                if in_quotes {
+                    in_quotes = false;
+                } else {
+                    in_quotes = true;
+                    quoted = true;
+                }
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    tokens.push((std::mem::take(&mut current), quoted));
+                }
+                quoted = false;
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push((current, quoted));
+    }
+
+    tokens
+}
+
+/// True if the FTS tokenizer would produce at least one token from this text.
+fn has_searchable_chars(text: &str) -> bool {
+    text.chars().any(|c| c.is_alphanumeric())
+}
+
+/// Parse a search string. Never fails: unknown or malformed parts become text terms.
+pub fn parse_query(input: &str) -> ParsedQuery {
+    let mut parsed = ParsedQuery::default();
+
+    for (raw, quoted) in tokenize(input) {
+        let (negated, body) = match raw.strip_prefix('-') {
+            Some(rest) if !rest.is_empty() => (true, rest.to_string()),
+            _ => (false, raw.clone()),
+        };
+
+        if let Some((key, value)) = body.split_once(':') {
+            if !value.is_empty() {
+                if let Some(column) = fts_column(key) {
+                    if has_searchable_chars(value) {
+                        parsed.terms.push(SearchTerm {
+                            column: Some(column),
+                            text: value.to_string(),
+                            phrase: quoted,
+                            negated,
+                            alternatives: Vec::new(),
+                        });
+                    }
+                    continue;
+                }
+                if let Some(rule) = parse_filter(key, value) {
+                    parsed.filters.push(SearchFilter { rule, negated });
+                    continue;
+                }
+            }
+        }
+
+        // Plain text (including unrecognised `key:value` tokens)
+        if has_searchable_chars(&body) {
+            parsed.terms.push(SearchTerm {
+                column: None,
+                text: body,
+                phrase: quoted,
+                negated,
+                alternatives: Vec::new(),
+            });
+        }
+    }
+
+    parsed
+}
+
+fn fts_column(key: &str) -> Option<&'static str> {
+    match key.to_lowercase().as_str() {
+        "title" => Some("title"),
+        "artist" => Some("artist"),
+        "album" => Some("album"),
+        _ => None,
+    }
+}
+
+fn parse_filter(key: &str, value: &str) -> Option<SmartRuleNode> {
+    let rule = |field, operator, value| SmartRuleNode::Rule {
+        field,
+        operator,
+        value,
+    };
+
+    match key.to_lowercase().as_str() {
+        "genre" => Some(rule(
+            SmartField::Genre,
+            SmartOperator::Contains,
+            serde_json::json!(value),
+        )),
+        "format" => Some(rule(
+            SmartField::Format,
+            SmartOperator::Is,
+            serde_json::json!(normalize_format(value)),
+        )),
+        "liked" => Some(rule(
+            SmartField::Liked,
+            SmartOperator::Is,
+            serde_json::json!(parse_yes_no(value)?),
+        )),
+        "untagged" => Some(rule(
+            SmartField::Untagged,
+            SmartOperator::Is,
+            serde_json::json!(parse_yes_no(value)?),
+        )),
+        "year" => parse_numeric_filter(SmartField::Year, value),
+        "duration" => parse_numeric_filter(SmartField::Duration, value),
+        "bitrate" => parse_numeric_filter(SmartField::Bitrate, value),
+        "track" => parse_numeric_filter(SmartField::TrackNumber, value),
+        "disc" => parse_numeric_filter(SmartField::DiscNumber, value),
+        "plays" => parse_numeric_filter(SmartField::PlayCount, value),
+        "rating" => parse_numeric_filter(SmartField::Rating, value),
+        "tag" => Some(rule(
+            SmartField::Tag,
+            SmartOperator::Is,
+            serde_json::json!(value),
+        )),
+        _ => None,
+    }
+}
+
+fn parse_yes_no(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "yes" | "true" | "1" | "y" => Some(true),
+        "no" | "false" | "0" | "n" => Some(false),
+        _ => None,
+    }
+}
+
+/// Map common extensions to the format names stored by the scanner (lofty `FileType`).
+fn normalize_format(value: &str) -> String {
+    match value.to_lowercase().as_str() {
+        "mp3" => "Mpeg".to_string(),
+        "m4a" | "mp4" | "alac" => "Mp4".to_string(),
+        "ogg" | "vorbis" => "Vorbis".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Parse `N`, `>N`, `<N`, `>=N`, `<=N`, `A..B`, `A..` and `..B` into a rule.
+fn parse_numeric_filter(field: SmartField, value: &str) -> Option<SmartRuleNode> {
+    let rule = |operator, value| {
+        Some(SmartRuleNode::Rule {
+            field,
+            operator,
+            value,
+        })
+    };
+    let num = |s: &str| s.trim().parse::<i64>().ok();
+
+    if let Some((low, high)) = value.split_once("..") {
+        return match (num(low), num(high)) {
+            (Some(l), Some(h)) => rule(
+                SmartOperator::Between,
+                serde_json::json!([l.min(h), l.max(h)]),
+            ),
+            (Some(l), None) if high.is_empty() => rule(
+                SmartOperator::GreaterThan,
+                serde_json::json!(l.checked_sub(1)?),
+            ),
+            (None, Some(h)) if low.is_empty() => rule(
+                SmartOperator::LessThan,
+                serde_json::json!(h.checked_add(1)?),
+            ),
+            _ => None,
+        };
+    }
+    if let Some(rest) = value.strip_prefix(">=") {
+        return rule(
+            SmartOperator::GreaterThan,
+            serde_json::json!(num(rest)?.checked_sub(1)?),
+        );
+    }
+    if let Some(rest) = value.strip_prefix("<=") {
+        return rule(
+            SmartOperator::LessThan,
+            serde_json::json!(num(rest)?.checked_add(1)?),
+        );
+    }
+    if let Some(rest) = value.strip_prefix('>') {
+        return rule(SmartOperator::GreaterThan, serde_json::json!(num(rest)?));
+    }
+    if let Some(rest) = value.strip_prefix('<') {
+        return rule(SmartOperator::LessThan, serde_json::json!(num(rest)?));
+    }
+    rule(SmartOperator::Is, serde_json::json!(num(value)?))
+}
+
+// ─── Typo tolerance ─────────────────────────────────────────────────────────
+
+/// Levenshtein edit distance between two strings (by char).
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b_chars.len()).collect();
+    let mut curr = vec![0; b_chars.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        curr[0] = i + 1;
+        for (j, cb) in b_chars.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j + 1] + 1).min(curr[j] + 1).min(prev[j] + cost);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+
+    prev[b_chars.len()]
+}
+
+/// Max edits allowed for a term of this length (short words must match exactly).
+fn max_typo_distance(len: usize) -> usize {
+    match len {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// Find indexed words within a small edit distance of `term`, closest and most
+/// common first. Uses the `tracks_fts_vocab` table created in `init_fts`.
+pub fn typo_candidates(conn: &Connection, term: &str) -> Result<Vec<String>> {
+    let term = term.to_lowercase();
+    let len = term.chars().count();
+    let max_distance = max_typo_distance(len);
+    if max_distance == 0 {
+        return Ok(Vec::new());
+    }
+
+    let first: String = term.chars().take(1).collect();
+    let mut stmt = conn.prepare(
+        "SELECT term, doc FROM tracks_fts_vocab
+         WHERE substr(term, 1, 1) = ?1 AND length(term) BETWEEN ?2 AND ?3",
+    )?;
+    let rows = stmt.query_map(
+        params![
+            first,
+            len.saturating_sub(max_distance) as i64,
+            (len + max_distance) as i64
+        ],
+        |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+    )?;
+
+    let mut candidates: Vec<(usize, i64, String)> = Vec::new();
+    for row in rows {
+        let (word, docs) = row?;
+        let distance = levenshtein(&term, &word);
+        if distance > 0 && distance <= max_distance {
+            candidates.push((distance, -docs, word));
+        }
+    }
+    candidates.sort();
+
+    Ok(candidates.into_iter().take(5).map(|(_, _, w)| w).collect())
+}
+
+/// Rewrite bare terms to also match close spellings found in the index.
+/// Returns None when no term could be expanded (retrying would not help).
+pub fn expand_typos(conn: &Connection, query: &ParsedQuery) -> Option<ParsedQuery> {
+    let mut result = query.clone();
+    let mut expanded = false;
+
+    for term in result.terms.iter_mut() {
+        if term.negated || term.phrase || term.column.is_some() {
+            continue;
+        }
+        term.alternatives = typo_candidates(conn, &term.text).unwrap_or_default();
+        expanded |= !term.alternatives.is_empty();
+    }
+
+    if expanded {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fields_negation_and_phrases() {
+        let q = parse_query(
+            r#"artist:"daft punk" -live year:1990..1999 "one more time" duration:>300"#,
+        );
+        assert_eq!(q.terms.len(), 3);
+        assert_eq!(q.terms[0].column, Some("artist"));
+        assert!(q.terms[0].phrase);
+        assert!(q.terms[1].negated);
+        assert_eq!(q.terms[2].text, "one more time");
+        assert_eq!(q.filters.len(), 2);
+    }
+
+    #[test]
+    fn test_malformed_input_is_quoted() {
+        let q = parse_query(r#"AC/DC "unterminated NOT OR ( foo:bar"#);
+        let expr = q.fts_match().unwrap();
+        assert_eq!(expr, r#""AC/DC"* AND "unterminated NOT OR ( foo:bar""#);
+
+        // Pure punctuation produces nothing to search for
+        assert!(parse_query("!!! ---").is_empty());
+    }
+
+    #[test]
+    fn test_numeric_filters() {
+        assert!(parse_numeric_filter(SmartField::Year, "1990..").is_some());
+        assert!(parse_numeric_filter(SmartField::Year, "abc").is_none());
+        assert!(parse_numeric_filter(SmartField::Duration, ">=300").is_some());
+        // Bounds at the edge of the integer range don't overflow
+        assert!(parse_numeric_filter(SmartField::Year, "-9223372036854775808..").is_none());
+        assert!(parse_numeric_filter(SmartField::Year, "<=9223372036854775807").is_none());
+        assert!(parse_numeric_filter(SmartField::Year, "..9223372036854775807").is_none());
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("metallica", "metalica"), 1);
+        assert_eq!(levenshtein("beatles", "beetles"), 1);
+        assert_eq!(levenshtein("abc", "abc"), 0);
+    }
+}
@@ -0,0 +1,665 @@
+// Listening statistics over a time range
+//
+// All range filters compare `play_history.played_at` (UTC, "YYYY-MM-DD HH:MM:SS")
+// against precomputed bounds so they stay index range scans. Day/hour buckets are
+// shifted into the caller's local time with `utc_offset_minutes`.
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+
+use super::queries::{AlbumWithCount, ArtistWithCount, Track, TrackWithCount};
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Seconds listened for a play: the recorded duration, or the track length for
+/// older events that were stored without one.
+const PLAY_SECONDS: &str =
+    "CASE WHEN ph.duration_played > 0 THEN ph.duration_played ELSE COALESCE(t.duration, 0) END";
+
+const GENRE: &str = "COALESCE(NULLIF(t.genre, ''), json_extract(t.metadata_json, '$.Genre'))";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StatsPeriod {
+    /// Last 7 days including today
+    Week,
+    /// Last 30 days including today
+    Month,
+    /// Last 365 days including today
+    Year,
+    AllTime,
+    /// Inclusive local dates, "YYYY-MM-DD"
+    Custom {
+        start: String,
+        end: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsRange {
+    pub period: StatsPeriod,
+    /// Minutes east of UTC for local day/hour bucketing (e.g. 120 for UTC+2)
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+}
+
+/// A range resolved to UTC bounds: `start <= played_at < end`.
+#[derive(Debug, Clone)]
+pub struct ResolvedRange {
+    pub start: String,
+    pub end: String,
+    /// SQLite datetime modifier that shifts UTC into local time, e.g. "+120 minutes"
+    pub local_modifier: String,
+}
+
+impl StatsRange {
+    pub fn resolve(&self) -> std::result::Result<ResolvedRange, String> {
+        let offset = Duration::minutes(self.utc_offset_minutes as i64);
+        let today = (Utc::now().naive_utc() + offset).date();
+
+        let (start_day, end_day) = match &self.period {
+            StatsPeriod::Week => (today - Duration::days(6), today),
+            StatsPeriod::Month => (today - Duration::days(29), today),
+            StatsPeriod::Year => (today - Duration::days(364), today),
+            StatsPeriod::AllTime => {
+                return Ok(ResolvedRange {
+                    start: "0000-01-01 00:00:00".to_string(),
+                    end: "9999-12-31 23:59:59".to_string(),
+                    local_modifier: self.local_modifier(),
+                })
+            }
+            StatsPeriod::Custom { start, end } => {
+                let parse = |s: &str| {
+                    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                        .map_err(|_| format!("Invalid date '{}', expected YYYY-MM-DD", s))
+                };
+                let (start, end) = (parse(start)?, parse(end)?);
+                if end < start {
+                    return Err("Range end is before its start".to_string());
+                }
+                (start, end)
+            }
+        };
+
+        // Local midnight -> UTC
+        let to_utc = |day: NaiveDate| -> NaiveDateTime {
+            day.and_hms_opt(0, 0, 0).unwrap_or_default() - offset
+        };
+
+        Ok(ResolvedRange {
+            start: to_utc(start_day).format(TIMESTAMP_FORMAT).to_string(),
+            end: to_utc(end_day + Duration::days(1))
+                .format(TIMESTAMP_FORMAT)
+                .to_string(),
+            local_modifier: self.local_modifier(),
+        })
+    }
+
+    fn local_modifier(&self) -> String {
+        format!("{:+} minutes", self.utc_offset_minutes)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DayBucket {
+    /// Local date, "YYYY-MM-DD"
+    pub date: String,
+    pub plays: i64,
+    pub seconds: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HourBucket {
+    /// 0 = Sunday .. 6 = Saturday (local time)
+    pub weekday: i64,
+    pub hour: i64,
+    pub plays: i64,
+    pub seconds: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenreWithCount {
+    pub genre: String,
+    pub play_count: i64,
+    pub seconds: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtistDiscovery {
+    pub artist: String,
+    /// First ever play (UTC)
+    pub first_played: String,
+    /// Plays within the range
+    pub play_count: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StreakInfo {
+    /// Consecutive days up to today (or yesterday) with at least one play
+    pub current_days: i64,
+    /// Longest run of consecutive listening days within the range
+    pub longest_days: i64,
+    pub longest_start: Option<String>,
+    pub longest_end: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListeningStats {
+    pub range_start: String,
+    pub range_end: String,
+    pub total_plays: i64,
+    pub total_seconds: i64,
+    pub unique_tracks: i64,
+    pub unique_artists: i64,
+    /// Only days with plays; the frontend fills gaps for calendar views
+    pub daily: Vec<DayBucket>,
+    /// Only weekday/hour cells with plays
+    pub hourly: Vec<HourBucket>,
+    pub top_tracks: Vec<TrackWithCount>,
+    pub top_artists: Vec<ArtistWithCount>,
+    pub top_albums: Vec<AlbumWithCount>,
+    pub top_genres: Vec<GenreWithCount>,
+    pub new_artists: Vec<ArtistDiscovery>,
+    pub streaks: StreakInfo,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonthBucket {
+    /// 1-12
+    pub month: u32,
+    pub plays: i64,
+    pub seconds: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct YearInReview {
+    pub year: i32,
+    pub stats: ListeningStats,
+    pub monthly: Vec<MonthBucket>,
+    pub busiest_day: Option<DayBucket>,
+    pub first_track: Option<Track>,
+}
+
+pub fn get_daily_listening(conn: &Connection, range: &ResolvedRange) -> Result<Vec<DayBucket>> {
+    let sql = format!(
+        "SELECT date(ph.played_at, ?3) AS day, COUNT(*), SUM({})
+         FROM play_history ph
+         LEFT JOIN tracks t ON t.id = ph.track_id
+         WHERE ph.played_at >= ?1 AND ph.played_at < ?2
+         GROUP BY day
+         ORDER BY day",
+        PLAY_SECONDS
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt
+        .query_map(
+            params![range.start, range.end, range.local_modifier],
+            |row| {
+                Ok(DayBucket {
+                    date: row.get(0)?,
+                    plays: row.get(1)?,
+                    seconds: row.get(2)?,
+                })
+            },
+        )?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+pub fn get_hourly_heatmap(conn: &Connection, range: &ResolvedRange) -> Result<Vec<HourBucket>> {
+    let sql = format!(
+        "SELECT CAST(strftime('%w', ph.played_at, ?3) AS INTEGER) AS weekday,
+                CAST(strftime('%H', ph.played_at, ?3) AS INTEGER) AS hour,
+                COUNT(*), SUM({})
+         FROM play_history ph
+         LEFT JOIN tracks t ON t.id = ph.track_id
+         WHERE ph.played_at >= ?1 AND ph.played_at < ?2
+         GROUP BY weekday, hour
+         ORDER BY weekday, hour",
+        PLAY_SECONDS
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt
+        .query_map(
+            params![range.start, range.end, range.local_modifier],
+            |row| {
+                Ok(HourBucket {
+                    weekday: row.get(0)?,
+                    hour: row.get(1)?,
+                    plays: row.get(2)?,
+                    seconds: row.get(3)?,
+                })
+            },
+        )?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+pub fn get_top_tracks_in_range(
+    conn: &Connection,
+    range: &ResolvedRange,
+    limit: i32,
+) -> Result<Vec<TrackWithCount>> {
+    let mut stmt = conn.prepare(
+        "SELECT t.id, t.path, t.title, t.artist, t.album, t.track_number, t.duration, t.album_id, t.format, t.bitrate, t.source_type, t.cover_url, t.external_id, t.local_src, t.track_cover_path, t.disc_number, t.metadata_json, t.date_added, p.play_count
+         FROM (
+             SELECT track_id, COUNT(*) AS play_count
+             FROM play_history
+             WHERE played_at >= ?1 AND played_at < ?2
+             GROUP BY track_id
+             ORDER BY play_count DESC
+             LIMIT ?3
+         ) p
+         INNER JOIN tracks t ON t.id = p.track_id
+         ORDER BY p.play_count DESC",
+    )?;
+
+    let results = stmt
+        .query_map(params![range.start, range.end, limit], |row| {
+            Ok(TrackWithCount {
+                track: Track {
+                    id: row.get(0)?,
+                    path: row.get(1)?,
+                    title: row.get(2)?,
+                    artist: row.get(3)?,
+                    album: row.get(4)?,
+                    track_number: row.get(5)?,
+                    duration: row.get(6)?,
+                    album_id: row.get(7)?,
+                    format: row.get(8)?,
+                    bitrate: row.get(9)?,
+                    source_type: row.get(10)?,
+                    cover_url: row.get(11)?,
+                    external_id: row.get(12)?,
+                    local_src: row.get(13)?,
+                    track_cover: None,
+                    track_cover_path: row.get(14)?,
+                    disc_number: row.get(15)?,
+                    metadata_json: row.get(16)?,
+                    date_added: row.get(17)?,
+                },
+                play_count: row.get(18)?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(results)
+}
+
+pub fn get_top_artists_in_range(
+    conn: &Connection,
+    range: &ResolvedRange,
+    limit: i32,
+) -> Result<Vec<ArtistWithCount>> {
+    let mut stmt = conn.prepare(
+        "SELECT t.artist, COUNT(*) AS play_count
+         FROM play_history ph
+         INNER JOIN tracks t ON t.id = ph.track_id
+         WHERE ph.played_at >= ?1 AND ph.played_at < ?2 AND t.artist IS NOT NULL
+         GROUP BY t.artist
+         ORDER BY play_count DESC
+         LIMIT ?3",
+    )?;
+    let results = stmt
+        .query_map(params![range.start, range.end, limit], |row| {
+            Ok(ArtistWithCount {
+                artist: row.get(0)?,
+                play_count: row.get(1)?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(results)
+}
+
+pub fn get_top_albums_in_range(
+    conn: &Connection,
+    range: &ResolvedRange,
+    limit: i32,
+) -> Result<Vec<AlbumWithCount>> {
+    let mut stmt = conn.prepare(
+        "SELECT a.id, a.name, a.artist, a.art_data, a.art_path, COUNT(*) AS play_count
+         FROM play_history ph
+         INNER JOIN albums a ON a.id = ph.album_id
+         WHERE ph.played_at >= ?1 AND ph.played_at < ?2
+         GROUP BY a.id
+         ORDER BY play_count DESC
+         LIMIT ?3",
+    )?;
+    let results = stmt
+        .query_map(params![range.start, range.end, limit], |row| {
+            Ok(AlbumWithCount {
+                album: super::queries::Album {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    artist: row.get(2)?,
+                    art_data: row.get(3)?,
+                    art_path: row.get(4)?,
+                },
+                play_count: row.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(results)
+}
+
+pub fn get_top_genres_in_range(
+    conn: &Connection,
+    range: &ResolvedRange,
+    limit: i32,
+) -> Result<Vec<GenreWithCount>> {
+    let sql = format!(
+        "SELECT {} AS genre, COUNT(*) AS play_count, SUM({})
+         FROM play_history ph
+         INNER JOIN tracks t ON t.id = ph.track_id
+         WHERE ph.played_at >= ?1 AND ph.played_at < ?2
+         GROUP BY genre COLLATE NOCASE
+         HAVING genre IS NOT NULL AND genre != ''
+         ORDER BY play_count DESC
+         LIMIT ?3",
+        GENRE, PLAY_SECONDS
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let results = stmt
+        .query_map(params![range.start, range.end, limit], |row| {
+            Ok(GenreWithCount {
+                genre: row.get(0)?,
+                play_count: row.get(1)?,
+                seconds: row.get(2)?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(results)
+}
+
+/// Artists played in the range that had never been played before it.
+/// The NOT EXISTS probe walks `idx_tracks_artist` + `idx_play_history_track_time`,
+/// so it does not rescan the whole history per artist.
+pub fn get_new_artists_in_range(
+    conn: &Connection,
+    range: &ResolvedRange,
+) -> Result<Vec<ArtistDiscovery>> {
+    let mut stmt = conn.prepare(
+        "SELECT t.artist, MIN(ph.played_at) AS first_played, COUNT(*) AS play_count
+         FROM play_history ph
+         INNER JOIN tracks t ON t.id = ph.track_id
+         WHERE ph.played_at >= ?1 AND ph.played_at < ?2 AND t.artist IS NOT NULL
+         GROUP BY t.artist
+         HAVING NOT EXISTS (
+             SELECT 1 FROM tracks t2
+             INNER JOIN play_history p2 ON p2.track_id = t2.id
+             WHERE t2.artist = t.artist AND p2.played_at < ?1
+         )
+         ORDER BY first_played",
+    )?;
+    let results = stmt
+        .query_map(params![range.start, range.end], |row| {
+            Ok(ArtistDiscovery {
+                artist: row.get(0)?,
+                first_played: row.get(1)?,
+                play_count: row.get(2)?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(results)
+}
+
+/// Longest run of consecutive dates in a sorted list, as (length, start, end).
+fn longest_run(days: &[NaiveDate]) -> (i64, Option<NaiveDate>, Option<NaiveDate>) {
+    let mut best = (0, None, None);
+    let mut run_start = None;
+    let mut run_len = 0;
+    let mut prev: Option<NaiveDate> = None;
+
+    for day in days {
+        if prev.map_or(false, |p| *day == p + Duration::days(1)) {
+            run_len += 1;
+        } else {
+            run_start = Some(*day);
+            run_len = 1;
+        }
+        if run_len > best.0 {
+            best = (run_len, run_start, Some(*day));
+        }
+        prev = Some(*day);
+    }
+
+    best
+}
+
+pub fn get_listening_streaks(conn: &Connection, range: &ResolvedRange) -> Result<StreakInfo> {
+    let parse_days = |stmt: &mut rusqlite::Statement, args: &[&dyn rusqlite::ToSql]| {
+        stmt.query_map(args, |row| row.get::<_, String>(0))?
+            .map(|r| r.map(|s| NaiveDate::parse_from_str(&s, "%Y-%m-%d").ok()))
+            .collect::<Result<Vec<_>>>()
+            .map(|days| days.into_iter().flatten().collect::<Vec<_>>())
+    };
+
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT date(played_at, ?3) AS day FROM play_history
+         WHERE played_at >= ?1 AND played_at < ?2
+         ORDER BY day",
+    )?;
+    let days = parse_days(
+        &mut stmt,
+        &[&range.start, &range.end, &range.local_modifier],
+    )?;
+    let (longest_days, longest_start, longest_end) = longest_run(&days);
+
+    // Current streak counts back from today regardless of the range; walk the
+    // most recent days until the first gap
+    let offset = range
+        .local_modifier
+        .trim_end_matches(" minutes")
+        .parse::<i64>()
+        .unwrap_or(0);
+    let today = (Utc::now().naive_utc() + Duration::minutes(offset)).date();
+    let mut recent = conn.prepare(
+        "SELECT DISTINCT date(played_at, ?1) AS day FROM play_history
+         WHERE played_at >= ?2
+         ORDER BY day DESC",
+    )?;
+    // Long streaks are rare; look back a year at most
+    let since = ((today - Duration::days(366))
+        .and_hms_opt(0, 0, 0)
+        .unwrap_or_default()
+        - Duration::minutes(offset))
+    .format(TIMESTAMP_FORMAT)
+    .to_string();
+    let recent_days = parse_days(&mut recent, &[&range.local_modifier, &since])?;
+
+    let mut current_days = 0;
+    let mut expected = match recent_days.first() {
+        // A streak is still alive if the last play was today or yesterday
+        Some(day) if *day >= today - Duration::days(1) => *day,
+        _ => today + Duration::days(2),
+    };
+    for day in &recent_days {
+        if *day != expected {
+            break;
+        }
+        current_days += 1;
+        expected = *day - Duration::days(1);
+    }
+
+    Ok(StreakInfo {
+        current_days,
+        longest_days,
+        longest_start: longest_start.map(|d| d.to_string()),
+        longest_end: longest_end.map(|d| d.to_string()),
+    })
+}
+
+/// Everything the stats page needs for one range.
+pub fn get_listening_stats(
+    conn: &Connection,
+    range: &ResolvedRange,
+    limit: i32,
+) -> Result<ListeningStats> {
+    let (total_plays, total_seconds, unique_tracks, unique_artists): (i64, i64, i64, i64) = conn
+        .query_row(
+            &format!(
+                "SELECT COUNT(*), COALESCE(SUM({}), 0), COUNT(DISTINCT ph.track_id), COUNT(DISTINCT t.artist)
+                 FROM play_history ph
+                 LEFT JOIN tracks t ON t.id = ph.track_id
+                 WHERE ph.played_at >= ?1 AND ph.played_at < ?2",
+                PLAY_SECONDS
+            ),
+            params![range.start, range.end],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+
+    Ok(ListeningStats {
+        range_start: range.start.clone(),
+        range_end: range.end.clone(),
+        total_plays,
+        total_seconds,
+        unique_tracks,
+        unique_artists,
+        daily: get_daily_listening(conn, range)?,
+        hourly: get_hourly_heatmap(conn, range)?,
+        top_tracks: get_top_tracks_in_range(conn, range, limit)?,
+        top_artists: get_top_artists_in_range(conn, range, limit)?,
+        top_albums: get_top_albums_in_range(conn, range, limit)?,
+        top_genres: get_top_genres_in_range(conn, range, limit)?,
+        new_artists: get_new_artists_in_range(conn, range)?,
+        streaks: get_listening_streaks(conn, range)?,
+    })
+}
+
+/// Calendar-year summary in local time.
+pub fn get_year_in_review(
+    conn: &Connection,
+    year: i32,
+    utc_offset_minutes: i32,
+    limit: i32,
+) -> std::result::Result<YearInReview, String> {
+    let range = StatsRange {
+        period: StatsPeriod::Custom {
+            start: format!("{:04}-01-01", year),
+            end: format!("{:04}-12-31", year),
+        },
+        utc_offset_minutes,
+    }
+    .resolve()?;
+
+    let stats = get_listening_stats(conn, &range, limit).map_err(|e| e.to_string())?;
+
+    let mut monthly: Vec<MonthBucket> = (1..=12)
+        .map(|month| MonthBucket {
+            month,
+            plays: 0,
+            seconds: 0,
+        })
+        .collect();
+    for day in &stats.daily {
+        if let Ok(date) = NaiveDate::parse_from_str(&day.date, "%Y-%m-%d") {
+            let bucket = &mut monthly[date.month0() as usize];
+            bucket.plays += day.plays;
+            bucket.seconds += day.seconds;
+        }
+    }
+
+    let busiest_day = stats.daily.iter().max_by_key(|d| d.seconds).cloned();
+
+    let first_track_id: Option<i64> = conn
+        .query_row(
+            "SELECT track_id FROM play_history
+             WHERE played_at >= ?1 AND played_at < ?2
+             ORDER BY played_at LIMIT 1",
+            params![range.start, range.end],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    let first_track = match first_track_id {
+        Some(id) => super::queries::get_track_by_id(conn, id).map_err(|e| e.to_string())?,
+        None => None,
+    };
+
+    Ok(YearInReview {
+        year,
+        stats,
+        monthly,
+        busiest_day,
+        first_track,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn test_custom_range_resolves_to_utc_bounds() {
+        let range = StatsRange {
+            period: StatsPeriod::Custom {
+                start: "2024-03-01".to_string(),
+                end: "2024-03-31".to_string(),
+            },
+            utc_offset_minutes: 120,
+        }
+        .resolve()
+        .unwrap();
+        assert_eq!(range.start, "2024-02-29 22:00:00");
+        assert_eq!(range.end, "2024-03-31 22:00:00");
+        assert_eq!(range.local_modifier, "+120 minutes");
+    }
+
+    #[test]
+    fn test_longest_run() {
+        let days = [
+            day("2024-01-01"),
+            day("2024-01-02"),
+            day("2024-01-05"),
+            day("2024-01-06"),
+            day("2024-01-07"),
+        ];
+        let (len, start, end) = longest_run(&days);
+        assert_eq!(len, 3);
+        assert_eq!(start, Some(day("2024-01-05")));
+        assert_eq!(end, Some(day("2024-01-07")));
+    }
+
+    #[test]
+    fn test_stats_for_range() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO tracks (id, path, title, artist, duration, genre) VALUES
+                 (1, '/a', 'A', 'Old Friend', 200, 'Rock'),
+                 (2, '/b', 'B', 'Newcomer', 100, 'Jazz');
+             INSERT INTO play_history (track_id, played_at, duration_played) VALUES
+                 (1, '2023-12-20 10:00:00', 200),
+                 (1, '2024-01-01 23:30:00', 0),
+                 (2, '2024-01-02 08:00:00', 50),
+                 (2, '2024-01-03 08:00:00', 100);",
+        )
+        .unwrap();
+
+        let range = StatsRange {
+            period: StatsPeriod::Custom {
+                start: "2024-01-01".to_string(),
+                end: "2024-01-31".to_string(),
+            },
+            utc_offset_minutes: 60,
+        }
+        .resolve()
+        .unwrap();
+        let stats = get_listening_stats(&conn, &range, 10).unwrap();
+
+        assert_eq!(stats.total_plays, 3);
+        // duration_played of 0 falls back to the track length
+        assert_eq!(stats.total_seconds, 350);
+        // 23:30 UTC on Jan 1 is Jan 2 in UTC+1
+        assert_eq!(stats.daily.len(), 2);
+        assert_eq!(stats.daily[0].date, "2024-01-02");
+        assert_eq!(stats.top_genres[0].genre, "Jazz");
+        assert_eq!(stats.new_artists.len(), 1);
+        assert_eq!(stats.new_artists[0].artist, "Newcomer");
+        assert_eq!(stats.streaks.longest_days, 2);
+    }
+}
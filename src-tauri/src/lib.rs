@@ -329,6 +329,7 @@ pub fn run() {
 
             app.manage(database);
             app.manage(commands::listenbrainz::ListenBrainzState::new());
+            app.manage(commands::watcher::WatcherState::new());
 
             // Initialize Discord RPC state (desktop only)
             #[cfg(desktop)]
@@ -347,6 +348,19 @@ pub fn run() {
                 audio::PlaybackStateSync::init_async(app.handle().clone());
             }
 
+            // =============================================================================
+            // LIBRARY WATCHER (desktop only)
+            // =============================================================================
+            // Picks up files added, changed, moved or deleted in the music folders
+            // while the app is running. Restarted whenever the folder list changes.
+            // =============================================================================
+            #[cfg(desktop)]
+            {
+                if let Err(e) = commands::watcher::restart_library_watcher(app.handle()) {
+                    tracing::warn!("Failed to start library watcher: {}", e);
+                }
+            }
+
             // =============================================================================
             // SYNC STATE INITIALIZATION
             // =============================================================================
@@ -499,7 +513,41 @@ pub fn run() {
                     commands::scan_music,
                     commands::add_folder,
                     commands::set_single_music_folder,
+                    commands::get_music_folder_settings,
+                    commands::set_music_folder_settings,
+                    commands::start_library_watcher,
+                    commands::stop_library_watcher,
+                    commands::get_library_watcher_status,
+                    commands::compute_acoustic_fingerprints,
+                    commands::fetch_missing_album_art,
+                    commands::edit_track_tags,
+                    commands::get_operation_journal,
+                    commands::undo_operation,
+                    commands::organise_files,
+                    commands::propose_album_tags,
+                    commands::apply_album_tags,
+                    commands::export_playlist,
+                    commands::import_playlist,
+                    commands::link_playlist_file,
+                    commands::unlink_playlist_file,
+                    commands::sync_playlist_file,
+                    commands::get_playlist_folders,
+                    commands::create_playlist_folder,
+                    commands::rename_playlist_folder,
+                    commands::delete_playlist_folder,
+                    commands::move_playlist_folder,
+                    commands::move_playlist,
+                    commands::update_playlist_description,
+                    commands::set_playlist_pinned,
+                    commands::set_playlist_sort_order,
+                    commands::generate_mix,
+                    commands::analyse_audio_features,
+                    commands::get_audio_features,
+                    commands::get_duplicates_report,
                     commands::rescan_music,
+                    commands::get_scan_issues,
+                    commands::retry_scan_issues,
+                    commands::dismiss_scan_issues,
                     commands::get_default_music_dirs,
                     commands::get_library,
                     commands::get_tracks_paginated,
@@ -521,6 +569,8 @@ pub fn run() {
                     commands::covers::get_track_cover_path,
                     commands::covers::get_batch_cover_paths,
                     commands::covers::get_album_art_path,
+                    commands::covers::get_album_palette,
+                    commands::covers::compute_album_palettes,
                     commands::covers::get_cover_as_asset_url,
                     commands::covers::preload_covers,
                     commands::covers::cleanup_orphaned_cover_files,
@@ -530,24 +580,43 @@ pub fn run() {
                     commands::create_playlist,
                     commands::get_playlists,
                     commands::get_playlist_tracks,
+                    commands::get_playlist_entries,
                     commands::add_track_to_playlist,
+                    commands::insert_tracks_into_playlist,
+                    commands::move_playlist_entries,
+                    commands::remove_playlist_entries,
                     commands::remove_track_from_playlist,
                     commands::delete_playlist,
                     commands::rename_playlist,
                     commands::update_playlist_cover,
                     commands::reorder_playlist_tracks,
+                    commands::create_smart_playlist,
+                    commands::update_smart_playlist_rules,
+                    commands::preview_smart_playlist,
                     // Activity commands (liked tracks + play history)
                     commands::like_track,
                     commands::unlike_track,
                     commands::is_track_liked,
                     commands::get_liked_track_ids,
                     commands::get_liked_tracks,
+                    commands::set_track_rating,
+                    commands::get_track_ratings,
+                    commands::import_ratings_from_files,
+                    commands::get_all_tags,
+                    commands::get_track_tags,
+                    commands::get_track_ids_by_tag,
+                    commands::add_tag_to_tracks,
+                    commands::remove_tag_from_tracks,
+                    commands::rename_tag,
+                    commands::delete_tag,
                     commands::record_play,
                     commands::get_top_tracks,
                     commands::get_top_albums,
                     commands::get_recently_played,
                     commands::get_top_artists,
                     commands::get_stats_summary,
+                    commands::get_listening_stats,
+                    commands::get_year_in_review,
                     // Lyrics commands
                     commands::save_source_lyrics_file,
                     commands::load_source_lyrics_file,
@@ -667,7 +736,41 @@ pub fn run() {
                     commands::scan_music,
                     commands::add_folder,
                     commands::set_single_music_folder,
+                    commands::get_music_folder_settings,
+                    commands::set_music_folder_settings,
+                    commands::start_library_watcher,
+                    commands::stop_library_watcher,
+                    commands::get_library_watcher_status,
+                    commands::compute_acoustic_fingerprints,
+                    commands::fetch_missing_album_art,
+                    commands::edit_track_tags,
+                    commands::get_operation_journal,
+                    commands::undo_operation,
+                    commands::organise_files,
+                    commands::propose_album_tags,
+                    commands::apply_album_tags,
+                    commands::export_playlist,
+                    commands::import_playlist,
+                    commands::link_playlist_file,
+                    commands::unlink_playlist_file,
+                    commands::sync_playlist_file,
+                    commands::get_playlist_folders,
+                    commands::create_playlist_folder,
+                    commands::rename_playlist_folder,
+                    commands::delete_playlist_folder,
+                    commands::move_playlist_folder,
+                    commands::move_playlist,
+                    commands::update_playlist_description,
+                    commands::set_playlist_pinned,
+                    commands::set_playlist_sort_order,
+                    commands::generate_mix,
+                    commands::analyse_audio_features,
+                    commands::get_audio_features,
+                    commands::get_duplicates_report,
                     commands::rescan_music,
+                    commands::get_scan_issues,
+                    commands::retry_scan_issues,
+                    commands::dismiss_scan_issues,
                     commands::get_default_music_dirs,
                     commands::get_library,
                     commands::get_tracks_paginated,
@@ -689,6 +792,8 @@ pub fn run() {
                     commands::covers::get_track_cover_path,
                     commands::covers::get_batch_cover_paths,
                     commands::covers::get_album_art_path,
+                    commands::covers::get_album_palette,
+                    commands::covers::compute_album_palettes,
                     commands::covers::get_cover_as_asset_url,
                     commands::covers::preload_covers,
                     commands::covers::cleanup_orphaned_cover_files,
@@ -698,24 +803,43 @@ pub fn run() {
                     commands::create_playlist,
                     commands::get_playlists,
                     commands::get_playlist_tracks,
+                    commands::get_playlist_entries,
                     commands::add_track_to_playlist,
+                    commands::insert_tracks_into_playlist,
+                    commands::move_playlist_entries,
+                    commands::remove_playlist_entries,
                     commands::remove_track_from_playlist,
                     commands::delete_playlist,
                     commands::rename_playlist,
                     commands::update_playlist_cover,
                     commands::reorder_playlist_tracks,
+                    commands::create_smart_playlist,
+                    commands::update_smart_playlist_rules,
+                    commands::preview_smart_playlist,
                     // Activity commands (liked tracks + play history)
                     commands::like_track,
                     commands::unlike_track,
                     commands::is_track_liked,
                     commands::get_liked_track_ids,
                     commands::get_liked_tracks,
+                    commands::set_track_rating,
+                    commands::get_track_ratings,
+                    commands::import_ratings_from_files,
+                    commands::get_all_tags,
+                    commands::get_track_tags,
+                    commands::get_track_ids_by_tag,
+                    commands::add_tag_to_tracks,
+                    commands::remove_tag_from_tracks,
+                    commands::rename_tag,
+                    commands::delete_tag,
                     commands::record_play,
                     commands::get_top_tracks,
                     commands::get_top_albums,
                     commands::get_recently_played,
                     commands::get_top_artists,
                     commands::get_stats_summary,
+                    commands::get_listening_stats,
+                    commands::get_year_in_review,
                     // Lyrics commands
                     commands::save_user_lyrics_file,
                     commands::save_source_lyrics_file,
@@ -0,0 +1,368 @@
+// Audio feature estimation: tempo, musical key, energy and danceability
+//
+// Everything works on the mono `ANALYSIS_RATE` signal the fingerprinter uses:
+//
+// - Tempo: autocorrelation of an onset envelope (rises in short-term loudness),
+//   summed over beat multiples and weighted towards common tempos.
+// - Key: a chromagram from Goertzel probes at every semitone between C2 and B5,
+//   matched against the Krumhansl-Schmuckler major and minor key profiles.
+// - Energy: loudness and onset density. Danceability: how clear the beat is,
+//   and how close the tempo is to typical dance tempos.
+use crate::db::queries::AudioFeatures;
+use crate::scanner::fingerprint::{decode, goertzel, Downmix, ANALYSIS_RATE};
+use lofty::config::{ParseOptions, ParsingMode};
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use std::f32::consts::PI;
+use std::path::Path;
+
+/// Seconds of audio analysed per track
+pub const ANALYSIS_SECONDS: u32 = 120;
+
+/// Onset envelope hop, ~11.6 ms at `ANALYSIS_RATE`
+const ENVELOPE_HOP: usize = 64;
+const MIN_BPM: f64 = 60.0;
+const MAX_BPM: f64 = 200.0;
+/// Tempos are weighted by a log-normal curve around this, to pick between
+/// half and double time
+const PREFERRED_BPM: f64 = 120.0;
+/// Beat multiples summed when scoring a tempo
+const BEAT_MULTIPLES: usize = 4;
+/// Below this average loudness rise per hop there is nothing to find a beat in
+const MIN_ONSET_STRENGTH: f64 = 0.005;
+/// Below this pulse clarity the track has no usable beat
+const MIN_PULSE_CLARITY: f64 = 0.05;
+
+/// Chroma frame, ~0.74 s, fine enough to separate semitones at C2
+const CHROMA_FRAME: usize = 4096;
+/// MIDI notes C2..B5
+const CHROMA_NOTES: std::ops::Range<i32> = 36..84;
+/// Below this correlation with the best key profile, no key is reported
+const MIN_KEY_CORRELATION: f64 = 0.3;
+
+const MAJOR_PROFILE: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+const PITCH_NAMES: [&str; 12] = [
+    "C", "Db", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+];
+
+/// Loudness (RMS, dBFS) mapped to energy 0 and 1
+const QUIET_DB: f64 = -35.0;
+const LOUD_DB: f64 = -8.0;
+/// Onsets per second counted as fully busy
+const BUSY_ONSET_RATE: f64 = 4.0;
+
+/// Estimate the features of the first `ANALYSIS_SECONDS` of `path`, or None
+/// if it can't be decoded
+pub fn analyse(path: &Path) -> Option<AudioFeatures> {
+    let mut downmix = Downmix::default();
+    decode::<f32, _>(path, Some(ANALYSIS_SECONDS), |samples, rate, channels| {
+        downmix.push(samples, rate, channels)
+    })?;
+    analyse_signal(&downmix.signal)
+}
+
+/// Features of a mono signal at `ANALYSIS_RATE`
+fn analyse_signal(signal: &[f32]) -> Option<AudioFeatures> {
+    if signal.len() < CHROMA_FRAME {
+        return None;
+    }
+    let envelope_rate = ANALYSIS_RATE as f64 / ENVELOPE_HOP as f64;
+    let onsets = onset_envelope(signal);
+    let tempo = estimate_tempo(&onsets, envelope_rate);
+
+    let rms =
+        (signal.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / signal.len() as f64).sqrt();
+    let loudness =
+        ((20.0 * rms.max(1e-9).log10() - QUIET_DB) / (LOUD_DB - QUIET_DB)).clamp(0.0, 1.0);
+    let onset_rate = count_onsets(&onsets) as f64 / (onsets.len() as f64 / envelope_rate);
+    let busyness = (onset_rate / BUSY_ONSET_RATE).clamp(0.0, 1.0);
+
+    let danceability = match tempo {
+        Some((bpm, clarity)) => {
+            let tempo_fit = (-0.5 * ((bpm - PREFERRED_BPM) / 30.0).powi(2)).exp();
+            0.7 * (clarity / 0.5).clamp(0.0, 1.0) + 0.3 * tempo_fit
+        }
+        None => 0.0,
+    };
+
+    Some(AudioFeatures {
+        bpm: tempo.map(|(bpm, _)| (bpm * 10.0).round() / 10.0),
+        key: estimate_key(signal),
+        energy: Some(round_score(0.7 * loudness + 0.3 * busyness)),
+        danceability: Some(round_score(danceability)),
+    })
+}
+
+fn round_score(score: f64) -> f64 {
+    (score * 1000.0).round() / 1000.0
+}
+
+/// Rise in compressed loudness per hop, zero where it falls
+fn onset_envelope(signal: &[f32]) -> Vec<f64> {
+    let loudness: Vec<f64> = signal
+        .chunks_exact(ENVELOPE_HOP)
+        .map(|hop| {
+            let power = hop.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / ENVELOPE_HOP as f64;
+            (1.0 + 1000.0 * power).ln()
+        })
+        .collect();
+    let mut onsets = vec![0.0];
+    onsets.extend(loudness.windows(2).map(|w| (w[1] - w[0]).max(0.0)));
+    onsets
+}
+
+/// Local peaks of the onset envelope that stand out from its average
+fn count_onsets(onsets: &[f64]) -> usize {
+    let mean = onsets.iter().sum::<f64>() / onsets.len().max(1) as f64;
+    onsets
+        .windows(3)
+        .filter(|w| w[1] > 3.0 * mean && w[1] > w[0] && w[1] >= w[2])
+        .count()
+}
+
+/// (BPM, pulse clarity in [0, 1]), or None when there's no clear beat
+fn estimate_tempo(onsets: &[f64], envelope_rate: f64) -> Option<(f64, f64)> {
+    let mean = onsets.iter().sum::<f64>() / onsets.len().max(1) as f64;
+    if mean < MIN_ONSET_STRENGTH {
+        return None;
+    }
+    let centred: Vec<f64> = onsets.iter().map(|o| o - mean).collect();
+    let autocorrelation = |lag: usize| -> f64 {
+        centred
+            .iter()
+            .zip(&centred[lag.min(centred.len())..])
+            .map(|(a, b)| a * b)
+            .sum::<f64>()
+            / (centred.len() - lag.min(centred.len())).max(1) as f64
+    };
+    let zero_lag = autocorrelation(0);
+    if zero_lag <= 0.0 {
+        return None;
+    }
+
+    let min_lag = (60.0 * envelope_rate / MAX_BPM).floor() as usize;
+    let max_lag = (60.0 * envelope_rate / MIN_BPM).ceil() as usize;
+    if max_lag * BEAT_MULTIPLES >= centred.len() {
+        return None;
+    }
+    let acf: Vec<f64> = (0..=max_lag * BEAT_MULTIPLES)
+        .map(autocorrelation)
+        .collect();
+
+    // Beat multiples fall between integer lags; take the better neighbour
+    let comb = |lag: f64| -> f64 {
+        (1..=BEAT_MULTIPLES)
+            .map(|k| {
+                let at = lag * k as f64;
+                acf[at.floor() as usize].max(acf[(at.ceil() as usize).min(acf.len() - 1)])
+                    / k as f64
+            })
+            .sum()
+    };
+    let weight = |lag: f64| -> f64 {
+        let bpm = 60.0 * envelope_rate / lag;
+        (-0.5 * (bpm / PREFERRED_BPM).log2().powi(2)).exp()
+    };
+
+    // Search in quarter-lag steps for the best weighted score
+    let steps = (min_lag * 4)..=(max_lag * 4);
+    let (best_lag, _) = steps
+        .map(|step| step as f64 / 4.0)
+        .map(|lag| (lag, comb(lag) * weight(lag)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+
+    let clarity = (acf[best_lag.round() as usize] / zero_lag).clamp(0.0, 1.0);
+    if clarity < MIN_PULSE_CLARITY {
+        return None;
+    }
+
+    // Refine on the longest multiple, where a lag step is finest
+    let span = best_lag * BEAT_MULTIPLES as f64;
+    let centre = span.round() as usize;
+    let refined = if centre >= 1 && centre + 1 < acf.len() {
+        let (a, b, c) = (acf[centre - 1], acf[centre], acf[centre + 1]);
+        let denominator = a - 2.0 * b + c;
+        let offset = if denominator < 0.0 {
+            (0.5 * (a - c) / denominator).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+        (centre as f64 + offset) / BEAT_MULTIPLES as f64
+    } else {
+        best_lag
+    };
+    Some((60.0 * envelope_rate / refined, clarity))
+}
+
+/// Key name such as "C" or "F#m", or None when no key fits well
+fn estimate_key(signal: &[f32]) -> Option<String> {
+    let window: Vec<f32> = (0..CHROMA_FRAME)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (CHROMA_FRAME - 1) as f32).cos())
+        .collect();
+    let coeffs: Vec<(usize, f32)> = CHROMA_NOTES
+        .map(|note| {
+            let freq = 440.0 * 2f32.powf((note - 69) as f32 / 12.0);
+            let coeff = 2.0 * (2.0 * PI * freq / ANALYSIS_RATE as f32).cos();
+            (note as usize % 12, coeff)
+        })
+        .collect();
+
+    let mut chroma = [0.0f64; 12];
+    let mut frame = vec![0.0f32; CHROMA_FRAME];
+    for chunk in signal.chunks_exact(CHROMA_FRAME) {
+        for (i, sample) in frame.iter_mut().enumerate() {
+            *sample = chunk[i] * window[i];
+        }
+        let mut frame_chroma = [0.0f64; 12];
+        for &(pitch, coeff) in &coeffs {
+            frame_chroma[pitch] += (goertzel(&frame, coeff).max(0.0) as f64).sqrt();
+        }
+        // Every frame counts the same, however loud
+        let total: f64 = frame_chroma.iter().sum();
+        if total > 1e-6 {
+            for (sum, value) in chroma.iter_mut().zip(frame_chroma) {
+                *sum += value / total;
+            }
+        }
+    }
+
+    let mut best: Option<(f64, usize, bool)> = None;
+    for tonic in 0..12 {
+        for (profile, minor) in [(&MAJOR_PROFILE, false), (&MINOR_PROFILE, true)] {
+            let rotated: [f64; 12] = std::array::from_fn(|i| profile[(i + 12 - tonic) % 12]);
+            let r = correlation(&chroma, &rotated);
+            if best.is_none_or(|(best_r, _, _)| r > best_r) {
+                best = Some((r, tonic, minor));
+            }
+        }
+    }
+    let (r, tonic, minor) = best?;
+    (r >= MIN_KEY_CORRELATION)
+        .then(|| format!("{}{}", PITCH_NAMES[tonic], if minor { "m" } else { "" }))
+}
+
+/// Pearson correlation
+fn correlation(a: &[f64; 12], b: &[f64; 12]) -> f64 {
+    let mean_a = a.iter().sum::<f64>() / 12.0;
+    let mean_b = b.iter().sum::<f64>() / 12.0;
+    let (mut cov, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        cov += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a).powi(2);
+        var_b += (y - mean_b).powi(2);
+    }
+    if var_a == 0.0 || var_b == 0.0 {
+        return 0.0;
+    }
+    cov / (var_a * var_b).sqrt()
+}
+
+/// The BPM and initial key tags of a file, as written. None for both when the
+/// file can't be read.
+pub fn read_tempo_tags(path: &Path) -> (Option<String>, Option<String>) {
+    let file = Probe::open(path).and_then(|probe| {
+        probe
+            .options(ParseOptions::new().parsing_mode(ParsingMode::Relaxed))
+            .read()
+    });
+    let Ok(file) = file else {
+        return (None, None);
+    };
+    match file.primary_tag().or_else(|| file.first_tag()) {
+        Some(tag) => (
+            tag.get_string(&ItemKey::Bpm).map(str::to_string),
+            tag.get_string(&ItemKey::InitialKey).map(str::to_string),
+        ),
+        None => (None, None),
+    }
+}
+
+/// BPM from a tag value such as "128" or "127.5"
+pub fn parse_bpm_tag(value: &str) -> Option<f64> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|bpm| bpm.is_finite() && *bpm > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = ANALYSIS_RATE as f32;
+
+    /// Notes (MIDI numbers) as summed sines, `seconds` long
+    fn chord(notes: &[i32], seconds: f32) -> Vec<f32> {
+        (0..(seconds * RATE) as usize)
+            .map(|i| {
+                let t = i as f32 / RATE;
+                notes
+                    .iter()
+                    .map(|&n| (2.0 * PI * 440.0 * 2f32.powf((n - 69) as f32 / 12.0) * t).sin())
+                    .sum::<f32>()
+                    * 0.2
+            })
+            .collect()
+    }
+
+    /// Decaying clicks of a low tone at `bpm`, `seconds` long
+    fn beat(bpm: f32, seconds: f32) -> Vec<f32> {
+        let period = (60.0 / bpm * RATE) as usize;
+        (0..(seconds * RATE) as usize)
+            .map(|i| {
+                let since = (i % period) as f32 / RATE;
+                (2.0 * PI * 80.0 * since).sin() * (-since * 30.0).exp() * 0.8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tempo_key_and_energy() {
+        for bpm in [95.0, 128.0, 174.0] {
+            let features = analyse_signal(&beat(bpm, 30.0)).unwrap();
+            let estimate = features.bpm.unwrap();
+            assert!(
+                (estimate - bpm as f64).abs() < 1.5,
+                "{} estimated as {}",
+                bpm,
+                estimate
+            );
+        }
+
+        // I-IV-V-I in C major and i-iv-V-i in A minor
+        let progression = |chords: [&[i32]; 4]| -> Vec<f32> {
+            chords.iter().flat_map(|notes| chord(notes, 2.0)).collect()
+        };
+        let c_major = progression([
+            &[48, 60, 64, 67],
+            &[53, 57, 60, 65],
+            &[55, 59, 62, 67],
+            &[48, 60, 64, 67],
+        ]);
+        let a_minor = progression([
+            &[45, 57, 60, 64],
+            &[50, 62, 65, 69],
+            &[52, 56, 59, 64],
+            &[45, 57, 60, 64],
+        ]);
+        assert_eq!(estimate_key(&c_major).as_deref(), Some("C"));
+        assert_eq!(estimate_key(&a_minor).as_deref(), Some("Am"));
+
+        // A loud beat is more energetic and danceable than a soft held note
+        let loud = analyse_signal(&beat(124.0, 30.0)).unwrap();
+        let soft: Vec<f32> = chord(&[60], 30.0).iter().map(|s| s * 0.05).collect();
+        let soft = analyse_signal(&soft).unwrap();
+        assert!(loud.energy > soft.energy);
+        assert!(loud.danceability > soft.danceability);
+        assert_eq!(soft.bpm, None);
+
+        assert_eq!(parse_bpm_tag(" 127.5 "), Some(127.5));
+        assert_eq!(parse_bpm_tag("fast"), None);
+    }
+}
@@ -1,10 +1,19 @@
 // Cover image storage and management
+use crate::db::queries::{self, AlbumPalette, ScanIssue, ScanIssueKind, ScanStage, TrackInsert};
+use crate::scanner::thumbnails::ThumbnailSize;
+use crate::scanner::walker::LibraryRules;
+use crate::scanner::{folder_art, palette, thumbnails};
 use base64::{engine::general_purpose::STANDARD, Engine};
-use rusqlite::{Connection, Result};
+use rayon::prelude::*;
+use rusqlite::{Connection, OptionalExtension, Result};
+use sha2::{Digest, Sha256};
 use std::collections::HashSet;
 use std::fs;
-use std::path::PathBuf;
-use std::sync::OnceLock;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// Album palettes computed per database write
+const PALETTE_CHUNK: usize = 64;
 
 /// App data directory set from Tauri's app.path().app_data_dir()
 /// This ensures cross-platform compatibility (desktop + Android/iOS)
@@ -59,9 +68,20 @@ impl ImageFormat {
 }
 
 /// Get the covers directory path
+pub fn get_covers_directory() -> std::result::Result<PathBuf, String> {
+    let covers_dir = get_app_data_directory()?.join("covers");
+
+    // Create directories if they don't exist
+    fs::create_dir_all(&covers_dir)
+        .map_err(|e| format!("Failed to create covers directory: {}", e))?;
+
+    Ok(covers_dir)
+}
+
+/// Get the app data directory
 /// Uses the app data dir set by Tauri (cross-platform),
 /// with fallback to APPDATA on Windows for backwards compatibility.
-pub fn get_covers_directory() -> std::result::Result<PathBuf, String> {
+pub fn get_app_data_directory() -> std::result::Result<PathBuf, String> {
     let base_dir = if let Some(dir) = APP_DATA_DIR.get() {
         // Use Tauri-provided app data dir (works on all platforms)
         dir.clone()
@@ -80,14 +100,7 @@ pub fn get_covers_directory() -> std::result::Result<PathBuf, String> {
                 .join("com.audion.app")
         }
     };
-
-    let covers_dir = base_dir.join("covers");
-
-    // Create directories if they don't exist
-    fs::create_dir_all(&covers_dir)
-        .map_err(|e| format!("Failed to create covers directory: {}", e))?;
-
-    Ok(covers_dir)
+    Ok(base_dir)
 }
 
 /// Get the tracks covers subdirectory
@@ -169,6 +182,187 @@ pub fn save_album_art_from_base64(album_id: i64, base64_data: &str) -> Result<St
     save_album_art(album_id, &image_bytes)
 }
 
+/// Hash of an image's contents, for sharing identical album art between albums
+fn art_hash(image_data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(image_data))
+}
+
+/// Save album art and point the album at it. Art identical to another album's
+/// reuses that album's file instead of writing a copy.
+pub fn store_album_art(
+    conn: &Connection,
+    album_id: i64,
+    image_data: &[u8],
+) -> Result<String, String> {
+    let hash = art_hash(image_data);
+    let existing = queries::find_album_art_by_hash(conn, &hash, album_id)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|path| Path::new(path).exists());
+    let path = match existing {
+        Some(path) => path,
+        None => save_album_art(album_id, image_data)?,
+    };
+    queries::set_album_art(conn, album_id, &path, &hash).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+/// The bigger of a track's embedded art and the art image in its folder
+fn best_album_art(track: &TrackInsert, rules: &LibraryRules) -> Option<Vec<u8>> {
+    let track_path = Path::new(&track.path);
+    let folder_art = track_path
+        .parent()
+        .and_then(|dir| folder_art::find_folder_art(dir, &rules.cover_art_names(track_path)))
+        .and_then(|path| fs::read(path).ok());
+
+    match (track.album_art.as_deref(), folder_art) {
+        (Some(embedded), Some(folder)) => {
+            if folder_art::image_area(&folder) > folder_art::image_area(embedded) {
+                Some(folder)
+            } else {
+                Some(embedded.to_vec())
+            }
+        }
+        (Some(embedded), None) => Some(embedded.to_vec()),
+        (None, folder) => folder,
+    }
+}
+
+/// Save a scanned track's artwork: its own embedded cover, plus art for its
+/// album if the album has none yet (embedded or from the track's folder,
+/// whichever is larger). Returns the track cover path and whatever failed.
+pub fn save_scanned_artwork(
+    conn: &Connection,
+    track_id: i64,
+    track: &TrackInsert,
+    rules: &LibraryRules,
+) -> (Option<String>, Vec<ScanIssue>) {
+    let mut issues = Vec::new();
+    let mut save = |image: &[u8], save_file: &dyn Fn(&[u8]) -> Result<String, String>| {
+        if ImageFormat::from_bytes(image).is_none() {
+            issues.push(ScanIssue::new(
+                track.path.clone(),
+                ScanStage::Cover,
+                ScanIssueKind::Unsupported,
+                "Picture is not a supported image",
+            ));
+            return None;
+        }
+        save_file(image)
+            .map_err(|e| {
+                issues.push(ScanIssue::new(
+                    track.path.clone(),
+                    ScanStage::Cover,
+                    ScanIssueKind::Io,
+                    e,
+                ))
+            })
+            .ok()
+    };
+
+    let cover_path = track
+        .track_cover
+        .as_deref()
+        .and_then(|bytes| save(bytes, &|b| save_track_cover(track_id, b)));
+
+    let album_without_art: Option<i64> = conn
+        .query_row(
+            "SELECT a.id FROM tracks t JOIN albums a ON a.id = t.album_id
+             WHERE t.id = ?1 AND a.art_path IS NULL",
+            [track_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .ok()
+        .flatten();
+    if let Some(album_id) = album_without_art {
+        if let Some(art) = best_album_art(track, rules) {
+            save(&art, &|b| store_album_art(conn, album_id, b));
+        }
+    }
+
+    if let Some(ref path) = cover_path {
+        if let Err(e) = queries::update_track_cover_path(conn, track_id, Some(path)) {
+            issues.push(ScanIssue::new(
+                track.path.clone(),
+                ScanStage::Db,
+                ScanIssueKind::Database,
+                e.to_string(),
+            ));
+        }
+    }
+
+    (cover_path, issues)
+}
+
+/// Give local albums that have no art the art image from their folder, for
+/// albums whose files were skipped as unchanged or had no embedded art when
+/// the folder image was added. Returns how many albums got art.
+pub fn fill_missing_album_art(conn: &Connection, rules: &LibraryRules) -> Result<usize, String> {
+    let albums = queries::get_local_albums_without_art(conn).map_err(|e| e.to_string())?;
+    let mut filled = 0;
+    for (album_id, track_path) in albums {
+        let track_path = Path::new(&track_path);
+        let Some(art_file) = track_path
+            .parent()
+            .and_then(|dir| folder_art::find_folder_art(dir, &rules.cover_art_names(track_path)))
+        else {
+            continue;
+        };
+        let stored = fs::read(&art_file)
+            .map_err(|e| e.to_string())
+            .and_then(|art| store_album_art(conn, album_id, &art));
+        match stored {
+            Ok(_) => filled += 1,
+            Err(e) => log::warn!("[Covers] Failed to store folder art {:?}: {}", art_file, e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Palette of a stored cover, decoded from its small thumbnail when possible
+pub fn cover_palette(art_path: &Path) -> Option<AlbumPalette> {
+    let source = thumbnails::get_thumbnail(art_path, ThumbnailSize::Small)
+        .unwrap_or_else(|_| art_path.to_path_buf());
+    let image = image::open(source).ok()?;
+    palette::extract_palette(&image)
+}
+
+/// Compute palettes for album art that doesn't have one yet (all of it when
+/// `force` is set), decoding outside the database lock. Art that can't be
+/// decoded is recorded as such so it isn't retried on every scan. Returns how
+/// many palettes were computed and how many failed.
+pub fn update_album_palettes(
+    db_conn: &Mutex<Connection>,
+    force: bool,
+) -> Result<(usize, usize), String> {
+    let albums = {
+        let conn = db_conn.lock().map_err(|e| e.to_string())?;
+        queries::get_albums_needing_palette(&conn, force).map_err(|e| e.to_string())?
+    };
+
+    let (mut computed, mut failed) = (0, 0);
+    for chunk in albums.chunks(PALETTE_CHUNK) {
+        let palettes: Vec<(i64, Option<AlbumPalette>)> = chunk
+            .par_iter()
+            .map(|(id, path)| (*id, cover_palette(Path::new(path))))
+            .collect();
+
+        let conn = db_conn.lock().map_err(|e| e.to_string())?;
+        let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+        for (id, palette) in &palettes {
+            queries::set_album_palette(&tx, *id, palette.as_ref()).map_err(|e| e.to_string())?;
+            if palette.is_some() {
+                computed += 1;
+            } else {
+                failed += 1;
+            }
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+    }
+    Ok((computed, failed))
+}
+
 /// Get cover file path for a track (verifies file exists)
 pub fn get_track_cover_file_path(conn: &Connection, track_id: i64) -> Result<Option<String>> {
     let path: Option<String> = conn
@@ -275,6 +469,23 @@ pub fn cleanup_orphaned_covers(conn: &Connection) -> Result<usize, String> {
         ids
     };
 
+    // Album art files can be shared, so one named after a deleted album may
+    // still be in use
+    let album_art_paths: HashSet<PathBuf> = {
+        let mut stmt = conn
+            .prepare("SELECT art_path FROM albums WHERE art_path IS NOT NULL")
+            .map_err(|e| format!("Failed to prepare album art query: {}", e))?;
+
+        let paths = stmt
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(|e| format!("Failed to query album art paths: {}", e))?
+            .map(|path| path.map(PathBuf::from))
+            .collect::<std::result::Result<HashSet<PathBuf>, _>>()
+            .map_err(|e| format!("Failed to collect album art paths: {}", e))?;
+
+        paths
+    };
+
     // 2: Clean up track covers
 
     let tracks_dir = get_tracks_covers_directory()?;
@@ -322,7 +533,7 @@ pub fn cleanup_orphaned_covers(conn: &Connection) -> Result<usize, String> {
                 if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
                     if let Ok(album_id) = stem.parse::<i64>() {
                         // Check against in-memory HashSet
-                        if !album_ids.contains(&album_id) {
+                        if !album_ids.contains(&album_id) && !album_art_paths.contains(&path) {
                             // Album doesn't exist, delete the art file
                             if let Err(e) = fs::remove_file(&path) {
                                 eprintln!("Failed to delete orphaned album art {:?}: {}", path, e);
@@ -336,6 +547,26 @@ pub fn cleanup_orphaned_covers(conn: &Connection) -> Result<usize, String> {
         }
     }
 
+    // 4: Clean up thumbnails of covers that are gone
+
+    let sources: Vec<String> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT track_cover_path FROM tracks WHERE track_cover_path IS NOT NULL
+                 UNION SELECT art_path FROM albums WHERE art_path IS NOT NULL",
+            )
+            .map_err(|e| format!("Failed to prepare cover paths query: {}", e))?;
+
+        let paths = stmt
+            .query_map([], |row| row.get(0))
+            .map_err(|e| format!("Failed to query cover paths: {}", e))?
+            .collect::<std::result::Result<Vec<String>, _>>()
+            .map_err(|e| format!("Failed to collect cover paths: {}", e))?;
+
+        paths
+    };
+    deleted_count += thumbnails::cleanup_thumbnails(&sources)?;
+
     Ok(deleted_count)
 }
 
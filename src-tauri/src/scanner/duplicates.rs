@@ -0,0 +1,330 @@
+// Duplicates report
+//
+// Groups copies of the same recording so the user can choose which to keep:
+// files with identical decoded audio (one is in the library, the others were
+// skipped on import) and, once acoustic fingerprints have been computed,
+// different encodings of the same recording.
+use crate::db::queries::{AudioIdentity, DuplicateFile};
+use crate::scanner::fingerprint::{fingerprint_from_blob, similarity, AUDIO_HASH_PREFIX};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Fingerprints at least this similar are treated as the same recording
+pub const ACOUSTIC_MATCH_THRESHOLD: f32 = 0.8;
+/// Largest duration difference (seconds) between acoustic matches
+const DURATION_TOLERANCE: i32 = 3;
+/// Leading sub-fingerprints per track used to find candidate pairs
+const INDEX_FRAMES: usize = 256;
+/// Sub-fingerprint values shared by more tracks than this (silence, hum) are
+/// useless for finding candidates
+const MAX_BUCKET: usize = 64;
+
+/// lofty file types that carry lossless audio
+const LOSSLESS_FORMATS: &[&str] = &["Flac", "Wav", "Aiff", "Ape", "WavPack"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateReason {
+    /// Every copy decodes to the same audio
+    IdenticalAudio,
+    /// At least one copy only matched by acoustic fingerprint (e.g. a re-encode)
+    AcousticMatch,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateCopy {
+    /// None for copies that were skipped on import and are not in the library
+    pub track_id: Option<i64>,
+    pub path: String,
+    pub format: Option<String>,
+    pub bitrate: Option<i32>,
+    pub file_size: Option<i64>,
+    pub duration: Option<i32>,
+    pub lossless: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateGroup {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub reason: DuplicateReason,
+    /// Lowest fingerprint similarity that linked the group (1.0 for identical audio)
+    pub similarity: f32,
+    /// Best quality first: lossless, then bitrate, then file size
+    pub copies: Vec<DuplicateCopy>,
+}
+
+fn is_lossless(format: Option<&str>) -> bool {
+    format.is_some_and(|f| LOSSLESS_FORMATS.iter().any(|l| l.eq_ignore_ascii_case(f)))
+}
+
+fn find_root(parent: &mut [usize], mut node: usize) -> usize {
+    while parent[node] != node {
+        parent[node] = parent[parent[node]];
+        node = parent[node];
+    }
+    node
+}
+
+/// Group `tracks` and `skipped` copies into duplicate sets. `skipped` entries
+/// whose original is not among `tracks` are ignored.
+pub fn find_duplicates(tracks: &[AudioIdentity], skipped: &[DuplicateFile]) -> Vec<DuplicateGroup> {
+    // One node per track, then one per skipped file that is not itself a track
+    let mut copies: Vec<DuplicateCopy> = tracks
+        .iter()
+        .map(|t| DuplicateCopy {
+            track_id: Some(t.id),
+            path: t.path.clone(),
+            format: t.format.clone(),
+            bitrate: t.bitrate,
+            file_size: t.file_size,
+            duration: t.duration,
+            lossless: is_lossless(t.format.as_deref()),
+        })
+        .collect();
+    let by_id: HashMap<i64, usize> = tracks.iter().enumerate().map(|(i, t)| (t.id, i)).collect();
+    let by_path: HashMap<&str, usize> = tracks
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.path.as_str(), i))
+        .collect();
+
+    // Edges carry the fingerprint similarity, or None for identical audio
+    let mut edges: Vec<(usize, usize, Option<f32>)> = Vec::new();
+
+    for file in skipped {
+        let Some(&original) = by_id.get(&file.track_id) else {
+            continue;
+        };
+        let node = match by_path.get(file.path.as_str()) {
+            Some(&node) => node,
+            None => {
+                copies.push(DuplicateCopy {
+                    track_id: None,
+                    path: file.path.clone(),
+                    format: file.format.clone(),
+                    bitrate: file.bitrate,
+                    file_size: file.file_size,
+                    duration: file.duration,
+                    lossless: is_lossless(file.format.as_deref()),
+                });
+                copies.len() - 1
+            }
+        };
+        if node != original {
+            edges.push((original, node, None));
+        }
+    }
+
+    // Tracks sharing an audio hash (imports never create these, but syncs and
+    // older databases can). Metadata hashes prove nothing and are ignored.
+    let mut by_hash: HashMap<&str, usize> = HashMap::new();
+    for (i, track) in tracks.iter().enumerate() {
+        let audio_hash = track
+            .content_hash
+            .as_deref()
+            .filter(|h| h.starts_with(AUDIO_HASH_PREFIX));
+        if let Some(hash) = audio_hash {
+            if let Some(&first) = by_hash.get(hash) {
+                edges.push((first, i, None));
+            } else {
+                by_hash.insert(hash, i);
+            }
+        }
+    }
+
+    let mut parent: Vec<usize> = (0..copies.len()).collect();
+    for &(a, b, _) in &edges {
+        let (ra, rb) = (find_root(&mut parent, a), find_root(&mut parent, b));
+        parent[ra] = rb;
+    }
+
+    // Acoustic matches. Candidates must share at least one exact sub-fingerprint
+    // near the start, which copies of one recording practically always do.
+    let fingerprints: Vec<Vec<u32>> = tracks
+        .iter()
+        .map(|t| {
+            t.acoustic_fingerprint
+                .as_deref()
+                .map(fingerprint_from_blob)
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, fp) in fingerprints.iter().enumerate() {
+        let values: HashSet<u32> = fp.iter().take(INDEX_FRAMES).copied().collect();
+        for value in values.into_iter().filter(|&v| v != 0) {
+            index.entry(value).or_default().push(i);
+        }
+    }
+
+    let mut candidates: HashSet<(usize, usize)> = HashSet::new();
+    for bucket in index
+        .values()
+        .filter(|b| b.len() > 1 && b.len() <= MAX_BUCKET)
+    {
+        for (n, &a) in bucket.iter().enumerate() {
+            for &b in &bucket[n + 1..] {
+                candidates.insert((a.min(b), a.max(b)));
+            }
+        }
+    }
+
+    let mut candidates: Vec<(usize, usize)> = candidates.into_iter().collect();
+    candidates.sort_unstable();
+    for (a, b) in candidates {
+        let durations_match = match (tracks[a].duration, tracks[b].duration) {
+            (Some(x), Some(y)) => (x - y).abs() <= DURATION_TOLERANCE,
+            _ => false,
+        };
+        if !durations_match || find_root(&mut parent, a) == find_root(&mut parent, b) {
+            continue;
+        }
+
+        let score = similarity(&fingerprints[a], &fingerprints[b]);
+        if score >= ACOUSTIC_MATCH_THRESHOLD {
+            edges.push((a, b, Some(score)));
+            let (ra, rb) = (find_root(&mut parent, a), find_root(&mut parent, b));
+            parent[ra] = rb;
+        }
+    }
+
+    // Collect the groups
+    let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+    for node in 0..copies.len() {
+        let root = find_root(&mut parent, node);
+        members.entry(root).or_default().push(node);
+    }
+    let mut lowest: HashMap<usize, f32> = HashMap::new();
+    for &(a, _, score) in &edges {
+        if let Some(score) = score {
+            let root = find_root(&mut parent, a);
+            let entry = lowest.entry(root).or_insert(score);
+            *entry = entry.min(score);
+        }
+    }
+
+    let mut groups: Vec<DuplicateGroup> = members
+        .into_iter()
+        .filter(|(_, nodes)| nodes.len() > 1)
+        .map(|(root, nodes)| {
+            // Nodes are in index order, so the first one is always a track
+            let named = &tracks[nodes[0]];
+            let mut group_copies: Vec<DuplicateCopy> =
+                nodes.iter().map(|&n| copies[n].clone()).collect();
+            group_copies.sort_by_key(|c| {
+                std::cmp::Reverse((c.lossless, c.bitrate.unwrap_or(0), c.file_size.unwrap_or(0)))
+            });
+
+            DuplicateGroup {
+                title: named.title.clone(),
+                artist: named.artist.clone(),
+                reason: if lowest.contains_key(&root) {
+                    DuplicateReason::AcousticMatch
+                } else {
+                    DuplicateReason::IdenticalAudio
+                },
+                similarity: lowest.get(&root).copied().unwrap_or(1.0),
+                copies: group_copies,
+            }
+        })
+        .collect();
+
+    groups.sort_by_cached_key(|g| {
+        (
+            g.artist.as_deref().unwrap_or("").to_lowercase(),
+            g.title.as_deref().unwrap_or("").to_lowercase(),
+            g.copies[0].path.clone(),
+        )
+    });
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::fingerprint::fingerprint_to_blob;
+
+    fn track(id: i64, format: &str, bitrate: i32, fingerprint: Option<&[u32]>) -> AudioIdentity {
+        AudioIdentity {
+            id,
+            path: format!("/music/{}.{}", id, format.to_lowercase()),
+            title: Some(format!("Song {}", id)),
+            artist: Some("Artist".to_string()),
+            format: Some(format.to_string()),
+            bitrate: Some(bitrate),
+            file_size: Some(1000),
+            duration: Some(200),
+            content_hash: Some(format!("a1:{}", id)),
+            acoustic_fingerprint: fingerprint.map(fingerprint_to_blob),
+        }
+    }
+
+    #[test]
+    fn test_find_duplicates_groups_by_audio_and_ranks_quality() {
+        let mut seed = 12345u32;
+        let recording: Vec<u32> = (0..300)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                seed
+            })
+            .collect();
+        // A re-encode: a few bits differ in some sub-fingerprints
+        let reencode: Vec<u32> = recording
+            .iter()
+            .enumerate()
+            .map(|(i, v)| if i % 3 == 0 { v ^ 0b1011 } else { *v })
+            .collect();
+        let unrelated: Vec<u32> = recording
+            .iter()
+            .map(|v| v.rotate_left(7) ^ 0x5555_5555)
+            .collect();
+
+        let tracks = vec![
+            track(1, "Mpeg", 192, Some(&reencode)),
+            track(2, "Flac", 900, Some(&recording)),
+            track(3, "Mpeg", 320, Some(&unrelated)),
+            track(4, "Mpeg", 256, None),
+        ];
+        let skipped = vec![
+            DuplicateFile {
+                path: "/music/copy of 4.mp3".to_string(),
+                track_id: 4,
+                format: Some("Mpeg".to_string()),
+                bitrate: Some(128),
+                file_size: Some(500),
+                duration: Some(200),
+            },
+            // Original no longer in the library
+            DuplicateFile {
+                path: "/music/orphan.mp3".to_string(),
+                track_id: 99,
+                format: None,
+                bitrate: None,
+                file_size: None,
+                duration: None,
+            },
+        ];
+
+        let groups = find_duplicates(&tracks, &skipped);
+        assert_eq!(groups.len(), 2);
+
+        let acoustic = groups
+            .iter()
+            .find(|g| g.reason == DuplicateReason::AcousticMatch)
+            .unwrap();
+        let ids: Vec<_> = acoustic.copies.iter().map(|c| c.track_id).collect();
+        assert_eq!(ids, vec![Some(2), Some(1)]); // lossless first
+        assert!(acoustic.similarity >= ACOUSTIC_MATCH_THRESHOLD && acoustic.similarity < 1.0);
+
+        let identical = groups
+            .iter()
+            .find(|g| g.reason == DuplicateReason::IdenticalAudio)
+            .unwrap();
+        assert_eq!(identical.similarity, 1.0);
+        assert_eq!(identical.copies[0].track_id, Some(4)); // higher bitrate
+        assert_eq!(identical.copies[1].track_id, None);
+        assert_eq!(identical.copies[1].path, "/music/copy of 4.mp3");
+    }
+}
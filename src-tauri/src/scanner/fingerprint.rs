@@ -0,0 +1,396 @@
+// Audio identity for duplicate detection
+//
+// Two measures, both computed from the decoded audio rather than the tags:
+//
+// - `audio_hash`: SHA-256 over the decoded PCM stream. Identical for every copy of
+//   the same encoded audio regardless of tags or container metadata, and stable
+//   across builds (unlike `DefaultHasher`). Stored as the track's content hash.
+// - `acoustic_fingerprint`: a compact perceptual fingerprint (one 32-bit
+//   sub-fingerprint per ~46 ms, after Haitsma & Kalker) that survives re-encoding,
+//   so an MP3 and a FLAC of the same recording compare as near-identical.
+use sha2::{Digest, Sha256};
+use std::f32::consts::PI;
+use std::fs::File;
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::conv::ConvertibleSample;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Prefix of content hashes computed from decoded audio
+pub const AUDIO_HASH_PREFIX: &str = "a1:";
+
+/// Seconds of audio analysed for the acoustic fingerprint
+pub const FINGERPRINT_SECONDS: u32 = 120;
+
+/// Fingerprints and audio features are computed on a mono signal resampled to this rate
+pub(crate) const ANALYSIS_RATE: u32 = 5512;
+const FRAME_SIZE: usize = 2048;
+const HOP_SIZE: usize = 256;
+/// 33 bands give 32 energy differences, i.e. one bit each of a `u32`
+const BANDS: usize = 33;
+const MIN_FREQ: f32 = 300.0;
+const MAX_FREQ: f32 = 2000.0;
+/// Goertzel probes per band; their energies are summed
+const PROBES_PER_BAND: usize = 3;
+
+/// Largest alignment shift (in sub-fingerprints) tried when comparing, to absorb
+/// encoder delay and slightly trimmed silence
+const MAX_OFFSET: isize = 8;
+/// Fewer overlapping sub-fingerprints than this are not worth comparing
+const MIN_OVERLAP: usize = 32;
+
+/// Decode the first audio track of `path`, passing interleaved sample blocks to
+/// `on_block(samples, sample_rate, channels)` until the stream ends or
+/// `max_seconds` have been decoded. Returns (sample rate, channels), or None if
+/// nothing could be decoded.
+pub(crate) fn decode<S, F>(
+    path: &Path,
+    max_seconds: Option<u32>,
+    mut on_block: F,
+) -> Option<(u32, usize)>
+where
+    S: ConvertibleSample,
+    F: FnMut(&[S], u32, usize),
+{
+    let file = File::open(path).ok()?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions {
+                limit_metadata_bytes: symphonia::core::meta::Limit::Maximum(0),
+                limit_visual_bytes: symphonia::core::meta::Limit::Maximum(0),
+            },
+        )
+        .ok()?;
+
+    let mut format = probed.format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .ok()?;
+
+    let mut sample_buf: Option<SampleBuffer<S>> = None;
+    let mut stream_spec = None;
+    let mut frames_decoded: u64 = 0;
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(p) => p,
+            Err(SymphoniaError::ResetRequired) => {
+                decoder.reset();
+                continue;
+            }
+            // End of stream
+            Err(_) => break,
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(d) => d,
+            // Corrupt packets are skipped, like the player does
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(_) => break,
+        };
+
+        let spec = *decoded.spec();
+        let channels = spec.channels.count().max(1);
+        let frames = decoded.capacity() as u64;
+        let buf = match sample_buf.as_mut() {
+            Some(buf) if buf.capacity() >= frames as usize * channels => buf,
+            _ => sample_buf.insert(SampleBuffer::new(frames, spec)),
+        };
+        buf.copy_interleaved_ref(decoded);
+
+        let (rate, _) = *stream_spec.get_or_insert((spec.rate, channels));
+        on_block(buf.samples(), spec.rate, channels);
+
+        frames_decoded += (buf.samples().len() / channels) as u64;
+        if let Some(max) = max_seconds {
+            if frames_decoded >= max as u64 * rate as u64 {
+                break;
+            }
+        }
+    }
+
+    stream_spec.filter(|_| frames_decoded > 0)
+}
+
+/// Stable hash of the decoded audio stream, or None if the file can't be decoded.
+/// Tags, cover art and container layout don't affect it.
+pub fn audio_hash(path: &Path) -> Option<String> {
+    let mut hasher = Sha256::new();
+    let mut bytes = Vec::new();
+
+    let (rate, channels) = decode::<i16, _>(path, None, |samples, _, _| {
+        bytes.clear();
+        bytes.extend(samples.iter().flat_map(|s| s.to_le_bytes()));
+        hasher.update(&bytes);
+    })?;
+    hasher.update(rate.to_le_bytes());
+    hasher.update((channels as u32).to_le_bytes());
+
+    let digest = hasher.finalize();
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    Some(format!("{}{}", AUDIO_HASH_PREFIX, hex))
+}
+
+/// Acoustic fingerprint of the first `FINGERPRINT_SECONDS` of `path`
+pub fn acoustic_fingerprint(path: &Path) -> Option<Vec<u32>> {
+    let mut downmix = Downmix::default();
+    decode::<f32, _>(
+        path,
+        Some(FINGERPRINT_SECONDS),
+        |samples, rate, channels| downmix.push(samples, rate, channels),
+    )?;
+
+    let fingerprint = sub_fingerprints(&downmix.signal);
+    (!fingerprint.is_empty()).then_some(fingerprint)
+}
+
+/// Mono signal resampled to `ANALYSIS_RATE` by averaging (or repeating) frames.
+/// Crude, but the same for every copy, which is all the fingerprint needs.
+#[derive(Default)]
+pub(crate) struct Downmix {
+    pub(crate) signal: Vec<f32>,
+    acc: f32,
+    acc_len: usize,
+    phase: u64,
+}
+
+impl Downmix {
+    pub(crate) fn push(&mut self, samples: &[f32], rate: u32, channels: usize) {
+        for frame in samples.chunks_exact(channels) {
+            self.acc += frame.iter().sum::<f32>() / channels as f32;
+            self.acc_len += 1;
+            self.phase += ANALYSIS_RATE as u64;
+            if self.phase < rate as u64 {
+                continue;
+            }
+            while self.phase >= rate as u64 {
+                self.signal.push(self.acc / self.acc_len as f32);
+                self.phase -= rate as u64;
+            }
+            self.acc = 0.0;
+            self.acc_len = 0;
+        }
+    }
+}
+
+/// Energy of `frame` at the frequency given by a Goertzel coefficient
+pub(crate) fn goertzel(frame: &[f32], coeff: f32) -> f32 {
+    let (mut s1, mut s2) = (0.0f32, 0.0f32);
+    for &x in frame {
+        let s = x + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s;
+    }
+    s1 * s1 + s2 * s2 - coeff * s1 * s2
+}
+
+/// One sub-fingerprint per hop: bit m is set when the energy difference between
+/// bands m and m+1 grew since the previous frame.
+fn sub_fingerprints(signal: &[f32]) -> Vec<u32> {
+    let window: Vec<f32> = (0..FRAME_SIZE)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (FRAME_SIZE - 1) as f32).cos())
+        .collect();
+
+    // Logarithmically spaced bands, each probed at a few frequencies
+    let ratio = (MAX_FREQ / MIN_FREQ).powf(1.0 / BANDS as f32);
+    let coeffs: Vec<[f32; PROBES_PER_BAND]> = (0..BANDS)
+        .map(|band| {
+            let low = MIN_FREQ * ratio.powi(band as i32);
+            std::array::from_fn(|probe| {
+                let freq = low * ratio.powf((probe as f32 + 0.5) / PROBES_PER_BAND as f32);
+                2.0 * (2.0 * PI * freq / ANALYSIS_RATE as f32).cos()
+            })
+        })
+        .collect();
+
+    let mut fingerprint = Vec::new();
+    let mut frame = vec![0.0f32; FRAME_SIZE];
+    let mut previous: Option<[f32; BANDS]> = None;
+
+    for start in (0..signal.len().saturating_sub(FRAME_SIZE - 1)).step_by(HOP_SIZE) {
+        for (i, sample) in frame.iter_mut().enumerate() {
+            *sample = signal[start + i] * window[i];
+        }
+        let energy: [f32; BANDS] =
+            std::array::from_fn(|band| coeffs[band].iter().map(|&c| goertzel(&frame, c)).sum());
+
+        if let Some(prev) = previous {
+            let mut bits = 0u32;
+            for m in 0..BANDS - 1 {
+                let delta = (energy[m] - energy[m + 1]) - (prev[m] - prev[m + 1]);
+                if delta > 0.0 {
+                    bits |= 1 << m;
+                }
+            }
+            fingerprint.push(bits);
+        }
+        previous = Some(energy);
+    }
+
+    fingerprint
+}
+
+/// Similarity of two fingerprints in [0, 1]: one minus the bit error rate at the
+/// best alignment. Copies of one recording typically score 0.85 or more,
+/// unrelated audio around 0.5.
+pub fn similarity(a: &[u32], b: &[u32]) -> f32 {
+    let mut best = 0.0f32;
+    for offset in -MAX_OFFSET..=MAX_OFFSET {
+        let (a, b) = if offset >= 0 {
+            (a.get(offset as usize..), Some(b))
+        } else {
+            (Some(a), b.get(offset.unsigned_abs()..))
+        };
+        let (Some(a), Some(b)) = (a, b) else {
+            continue;
+        };
+
+        let overlap = a.len().min(b.len());
+        if overlap < MIN_OVERLAP {
+            continue;
+        }
+        let errors: u32 = a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum();
+        best = best.max(1.0 - errors as f32 / (overlap * 32) as f32);
+    }
+    best
+}
+
+/// Fingerprints are stored as little-endian u32s in a BLOB column
+pub fn fingerprint_to_blob(fingerprint: &[u32]) -> Vec<u8> {
+    fingerprint.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+pub fn fingerprint_from_blob(blob: &[u8]) -> Vec<u32> {
+    blob.chunks_exact(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic white noise
+    fn noise(len: usize, mut seed: u32) -> Vec<f32> {
+        (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (seed >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+            })
+            .collect()
+    }
+
+    /// Minimal 16-bit PCM WAV, optionally with a LIST/INFO title chunk
+    fn write_wav(path: &Path, samples: &[i16], title: Option<&str>) {
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let mut list = Vec::new();
+        if let Some(title) = title {
+            let mut name = title.as_bytes().to_vec();
+            name.push(0);
+            if name.len() % 2 == 1 {
+                name.push(0);
+            }
+            list.extend(b"INFO");
+            list.extend(b"INAM");
+            list.extend((name.len() as u32).to_le_bytes());
+            list.extend(name);
+        }
+
+        let mut wav = Vec::new();
+        wav.extend(b"RIFF");
+        let list_len = if list.is_empty() { 0 } else { 8 + list.len() };
+        wav.extend(((4 + 24 + list_len + 8 + data.len()) as u32).to_le_bytes());
+        wav.extend(b"WAVEfmt ");
+        wav.extend(16u32.to_le_bytes());
+        wav.extend(1u16.to_le_bytes()); // PCM
+        wav.extend(1u16.to_le_bytes()); // mono
+        wav.extend(8000u32.to_le_bytes());
+        wav.extend(16000u32.to_le_bytes());
+        wav.extend(2u16.to_le_bytes());
+        wav.extend(16u16.to_le_bytes());
+        if !list.is_empty() {
+            wav.extend(b"LIST");
+            wav.extend((list.len() as u32).to_le_bytes());
+            wav.extend(list);
+        }
+        wav.extend(b"data");
+        wav.extend((data.len() as u32).to_le_bytes());
+        wav.extend(data);
+        std::fs::write(path, wav).unwrap();
+    }
+
+    #[test]
+    fn test_audio_hash_ignores_tags() {
+        let dir = std::env::temp_dir().join(format!("audion-fingerprint-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let samples: Vec<i16> = noise(8000, 7)
+            .iter()
+            .map(|s| (s * 20000.0) as i16)
+            .collect();
+        let mut other = samples.clone();
+        other[100] = other[100].wrapping_add(1);
+
+        let plain = dir.join("plain.wav");
+        let tagged = dir.join("tagged.wav");
+        let changed = dir.join("changed.wav");
+        write_wav(&plain, &samples, None);
+        write_wav(&tagged, &samples, Some("Some Title"));
+        write_wav(&changed, &other, None);
+
+        let plain_hash = audio_hash(&plain);
+        let tagged_hash = audio_hash(&tagged);
+        let changed_hash = audio_hash(&changed);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let plain_hash = plain_hash.expect("wav should decode");
+        assert!(plain_hash.starts_with(AUDIO_HASH_PREFIX));
+        assert_eq!(Some(&plain_hash), tagged_hash.as_ref());
+        assert_ne!(Some(&plain_hash), changed_hash.as_ref());
+    }
+
+    #[test]
+    fn test_fingerprint_similarity() {
+        let original = noise(ANALYSIS_RATE as usize * 20, 1);
+        // Same recording, quieter, with a little added noise and 30 ms of delay
+        let hiss = noise(original.len(), 2);
+        let delay = ANALYSIS_RATE as usize * 30 / 1000;
+        let mut copy = vec![0.0; delay];
+        copy.extend(original.iter().zip(&hiss).map(|(s, n)| s * 0.7 + n * 0.02));
+        let unrelated = noise(original.len(), 3);
+
+        let a = sub_fingerprints(&original);
+        let b = sub_fingerprints(&copy);
+        let c = sub_fingerprints(&unrelated);
+
+        assert_eq!(fingerprint_from_blob(&fingerprint_to_blob(&a)), a);
+        assert!(similarity(&a, &b) > 0.8, "copy: {}", similarity(&a, &b));
+        assert!(
+            similarity(&a, &c) < 0.7,
+            "unrelated: {}",
+            similarity(&a, &c)
+        );
+    }
+}
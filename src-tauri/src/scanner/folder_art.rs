@@ -0,0 +1,119 @@
+// Folder art discovery
+//
+// Album folders often carry their art as an image file next to the audio
+// (`cover.jpg`, `folder.png`, Windows Media Player's `AlbumArt_{...}_Large.jpg`).
+// Which names count, and in which order, is a per-folder scan setting.
+use globset::{GlobBuilder, GlobMatcher};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+
+/// Pixel dimensions read from the image header, without decoding it
+pub fn image_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    image::ImageReader::new(std::io::Cursor::new(data))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+fn pixel_count((width, height): (u32, u32)) -> u64 {
+    width as u64 * height as u64
+}
+
+/// Pixel count of an image, 0 when it can't be read
+pub fn image_area(data: &[u8]) -> u64 {
+    image_dimensions(data).map_or(0, pixel_count)
+}
+
+/// The art image in `dir`: files matching an earlier name in `names` win,
+/// and among those the one with the most pixels (then the largest file).
+/// Images whose header can't be read are ignored.
+pub fn find_folder_art(dir: &Path, names: &[String]) -> Option<PathBuf> {
+    let matchers: Vec<GlobMatcher> = names
+        .iter()
+        .filter_map(|name| {
+            GlobBuilder::new(name)
+                .case_insensitive(true)
+                .literal_separator(true)
+                .build()
+                .ok()
+        })
+        .map(|glob| glob.compile_matcher())
+        .collect();
+    if matchers.is_empty() {
+        return None;
+    }
+
+    // (priority, pixels, file size, path)
+    let mut best: Option<(usize, u64, u64, PathBuf)> = None;
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        let is_image = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| IMAGE_EXTENSIONS.iter().any(|e| e.eq_ignore_ascii_case(ext)));
+        if !is_image || !path.is_file() {
+            continue;
+        }
+        let Some(priority) = path
+            .file_stem()
+            .and_then(|stem| matchers.iter().position(|m| m.is_match(Path::new(stem))))
+        else {
+            continue;
+        };
+        if best.as_ref().is_some_and(|b| b.0 < priority) {
+            continue;
+        }
+        let Ok(pixels) = image::image_dimensions(&path).map(pixel_count) else {
+            continue;
+        };
+        let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+
+        let better = match &best {
+            None => true,
+            Some((best_priority, best_pixels, best_size, best_path)) => {
+                priority < *best_priority
+                    || (pixels, size, std::cmp::Reverse(&path))
+                        > (*best_pixels, *best_size, std::cmp::Reverse(best_path))
+            }
+        };
+        if better {
+            best = Some((priority, pixels, size, path));
+        }
+    }
+    best.map(|(_, _, _, path)| path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_png(path: &Path, width: u32, height: u32) {
+        image::RgbImage::new(width, height).save(path).unwrap();
+    }
+
+    #[test]
+    fn test_find_folder_art_priority_then_size() {
+        let dir = std::env::temp_dir().join(format!("audion_folder_art_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_png(&dir.join("Folder.png"), 50, 50);
+        write_png(&dir.join("AlbumArtSmall.png"), 10, 10);
+        write_png(&dir.join("AlbumArt_{ABC}_Large.png"), 40, 40);
+        write_png(&dir.join("back.png"), 500, 500);
+        std::fs::write(dir.join("cover.jpg"), b"not an image").unwrap();
+
+        let names = crate::db::queries::default_cover_art_names();
+        let found = find_folder_art(&dir, &names);
+        let without_folder = find_folder_art(&dir, &names[2..]);
+        let nothing = find_folder_art(&dir, &["scan*".to_string()]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // The unreadable cover.jpg is skipped, folder beats albumart*
+        assert_eq!(found, Some(dir.join("Folder.png")));
+        // Largest of the albumart* matches
+        assert_eq!(without_folder, Some(dir.join("AlbumArt_{ABC}_Large.png")));
+        assert_eq!(nothing, None);
+    }
+}
@@ -3,22 +3,39 @@ use lofty::prelude::*;
 use lofty::probe::Probe;
 use lofty::tag::Tag as LoftyTag;
 use lofty::config::{ParseOptions, ParsingMode};
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use lofty::error::{ErrorKind as LoftyErrorKind, LoftyError};
+use lofty::picture::PictureType;
+use sha2::{Digest, Sha256};
 use std::path::Path;
 
-use crate::db::queries::TrackInsert;
+use crate::db::queries::{ScanIssue, ScanIssueKind, ScanStage, TrackInsert};
+use crate::scanner::folder_art::image_area;
+
+/// Prefix of content hashes computed from tags (used when the audio can't be decoded)
+pub const METADATA_HASH_PREFIX: &str = "m1:";
+
+/// The front cover (the largest when several pictures are tagged as one),
+/// falling back to the first picture
+fn pick_cover<'a>(pictures: impl Iterator<Item = (bool, &'a [u8])>) -> Option<Vec<u8>> {
+    let pictures: Vec<(bool, &[u8])> = pictures.collect();
+    pictures
+        .iter()
+        .filter(|(front, _)| *front)
+        .max_by_key(|(_, data)| (image_area(data), data.len()))
+        .or_else(|| pictures.first())
+        .map(|(_, data)| data.to_vec())
+}
 
-/// Generate a content hash based on metadata for duplicate detection
-fn generate_content_hash(
+/// Generate a content hash from normalised metadata. Only a fallback for files
+/// whose audio can't be decoded and for remote tracks; local files are identified
+/// by `fingerprint::audio_hash`.
+pub fn generate_content_hash(
     title: Option<&str>,
     artist: Option<&str>,
     album: Option<&str>,
     duration: Option<i32>,
 ) -> String {
-    let mut hasher = DefaultHasher::new();
-
-    // Normalize and hash metadata fields
+    // Normalize metadata fields
     let title_normalized = title.unwrap_or("").trim().to_lowercase();
     let artist_normalized = artist.unwrap_or("").trim().to_lowercase();
     let album_normalized = album.unwrap_or("").trim().to_lowercase();
@@ -30,17 +47,66 @@ fn generate_content_hash(
         title_normalized, artist_normalized, album_normalized, duration_str
     );
 
-    combined.hash(&mut hasher);
-    format!("{:016x}", hasher.finish())
+    // SHA-256 rather than DefaultHasher, whose output may change between Rust releases
+    let digest = Sha256::digest(combined.as_bytes());
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}{}", METADATA_HASH_PREFIX, hex)
 }
 
 pub fn extract_metadata(path: &str) -> Option<TrackInsert> {
+    extract_metadata_with_issues(path).0
+}
+
+/// Read a file's tags and identity, along with whatever went wrong on the way.
+/// Files whose tags can't be read still produce a track, named after the file
+/// and flagged with `metadata_fallback`.
+pub fn extract_metadata_with_issues(path: &str) -> (Option<TrackInsert>, Vec<ScanIssue>) {
+    let (mut track, issues) = read_metadata_with_issues(path);
+    if let Some(ref mut track) = track {
+        identify_audio(track);
+    }
+    (track, issues)
+}
+
+/// `extract_metadata_with_issues` without decoding the audio, so the content
+/// hash is still the one made from the tags. For passes that only need the
+/// audio identity of a few files.
+pub fn read_metadata_with_issues(path: &str) -> (Option<TrackInsert>, Vec<ScanIssue>) {
     let path = Path::new(path);
+    let mut issues = Vec::new();
+    let Some(mut track) = read_tags(path, &mut issues) else {
+        return (None, issues);
+    };
+
+    // Remember the file's size/mtime so later rescans can skip it if unchanged
+    if let Some((size, mtime)) = super::walker::file_fingerprint(path) {
+        track.file_size = Some(size);
+        track.file_mtime = Some(mtime);
+    }
+    (Some(track), issues)
+}
 
-    // Try to read the file
-    // Try to read the file with default options first
-    let tagged_file_result = Probe::open(path)
-    .and_then(|probe| {
+/// Identify the file by its decoded audio, so differently-tagged copies of the
+/// same recording are recognised as duplicates (and same-tagged different
+/// recordings are not). Files that can't be decoded keep the tag hash.
+pub fn identify_audio(track: &mut TrackInsert) {
+    if let Some(hash) = super::fingerprint::audio_hash(Path::new(&track.path)) {
+        track.content_hash = Some(hash);
+    }
+}
+
+fn probe_issue_kind(error: &LoftyError) -> ScanIssueKind {
+    match error.kind() {
+        LoftyErrorKind::UnknownFormat => ScanIssueKind::Unsupported,
+        LoftyErrorKind::Io(e) => ScanIssueKind::from_io(e),
+        _ => ScanIssueKind::Corrupt,
+    }
+}
+
+fn read_tags(path: &Path, issues: &mut Vec<ScanIssue>) -> Option<TrackInsert> {
+    let path_str = path.to_string_lossy().to_string();
+
+    let tagged_file_result = Probe::open(path).and_then(|probe| {
         probe
             .options(ParseOptions::new().parsing_mode(ParsingMode::Relaxed))
             .read()
@@ -49,38 +115,22 @@ pub fn extract_metadata(path: &str) -> Option<TrackInsert> {
     let tagged_file = match tagged_file_result {
         Ok(file) => file,
         Err(e) => {
-            // Check if it's a FLAC file that failed
-            if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
-                if ext.to_lowercase() == "flac" {
-                    eprintln!(
-                        "[Scanner] Lofty failed for FLAC {:?}: {}. Trying metaflac fallback...",
-                        path, e
-                    );
-                    return extract_flac_metadata_fallback(path, None);
-                }
-            }
+            issues.push(ScanIssue::new(
+                path_str,
+                ScanStage::Probe,
+                probe_issue_kind(&e),
+                e.to_string(),
+            ));
 
-            // Try relaxed parsing as a general fallback
-            match Probe::open(path) {
-                Ok(mut probe) => {
-                    // Configure allowed tag types to be more permissive if possible,
-                    // but lofty's read() is already quite permissive.
-                    // We can try to explicitly specific options if the API allows,
-                    // but for now we'll rely on the specific FLAC fallback.
-                    eprintln!(
-                        "[Scanner] Failed to read audio file {:?}: {}. Returning fallback.",
-                        path, e
-                    );
-                    return Some(create_fallback_metadata(path));
-                }
-                Err(e) => {
-                    eprintln!(
-                        "[Scanner] Failed to open audio file {:?}: {}. Returning fallback.",
-                        path, e
-                    );
-                    return Some(create_fallback_metadata(path));
-                }
+            // metaflac copes with some FLAC files lofty rejects
+            let is_flac = path
+                .extension()
+                .and_then(|s| s.to_str())
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("flac"));
+            if is_flac {
+                return extract_flac_metadata_fallback(path, None, issues);
             }
+            return Some(create_fallback_metadata(path));
         }
     };
 
@@ -125,10 +175,14 @@ pub fn extract_metadata(path: &str) -> Option<TrackInsert> {
             });
 
             // Extract album art as raw bytes (NOT base64)
-            let album_art = tag.pictures().first().map(|pic| pic.data().to_vec());
+            let album_art = pick_cover(
+                tag.pictures()
+                    .iter()
+                    .map(|pic| (pic.pic_type() == PictureType::CoverFront, pic.data())),
+            );
 
             // Extract track cover as raw bytes (same as album art, but stored per-track)
-            let track_cover = tag.pictures().first().map(|pic| pic.data().to_vec());
+            let track_cover = album_art.clone();
 
             // Generate content hash for duplicate detection
             let content_hash = Some(generate_content_hash(
@@ -165,10 +219,19 @@ pub fn extract_metadata(path: &str) -> Option<TrackInsert> {
                 local_src: None,
                 musicbrainz_recording_id,
                 metadata_json,
+                file_size: None,
+                file_mtime: None,
+                metadata_fallback: false,
             })
         }
         None => {
             // No tags found, use fallback
+            issues.push(ScanIssue::new(
+                path_str,
+                ScanStage::Tags,
+                ScanIssueKind::MissingTags,
+                "No tags found",
+            ));
             let mut track = create_fallback_metadata(path);
             track.duration = Some(duration);
             track.format = format;
@@ -203,6 +266,7 @@ fn collect_all_metadata(tag: &LoftyTag) -> Option<String> {
         ItemKey::DiscTotal,
         ItemKey::Year,
         ItemKey::Bpm,
+        ItemKey::InitialKey,
         ItemKey::Isrc,
         ItemKey::Label,
         ItemKey::CatalogNumber,
@@ -212,6 +276,7 @@ fn collect_all_metadata(tag: &LoftyTag) -> Option<String> {
         ItemKey::Language,
         ItemKey::Publisher,
         ItemKey::EncoderSettings,
+        ItemKey::MusicBrainzReleaseId,
     ];
 
     for key in keys {
@@ -247,6 +312,9 @@ fn create_fallback_metadata(path: &Path) -> TrackInsert {
         local_src: None,
         musicbrainz_recording_id: None,
         metadata_json: None,
+        file_size: None,
+        file_mtime: None,
+        metadata_fallback: true,
     }
 }
 
@@ -256,7 +324,11 @@ fn get_filename_without_ext(path: &Path) -> Option<String> {
         .map(|s| s.to_string())
 }
 
-fn extract_flac_metadata_fallback(path: &Path, _duration_hint: Option<i32>) -> Option<TrackInsert> {
+fn extract_flac_metadata_fallback(
+    path: &Path,
+    _duration_hint: Option<i32>,
+    issues: &mut Vec<ScanIssue>,
+) -> Option<TrackInsert> {
     use metaflac::Tag;
 
     // We still need the format
@@ -276,7 +348,12 @@ fn extract_flac_metadata_fallback(path: &Path, _duration_hint: Option<i32>) -> O
                 vorbis.and_then(|v| v.get("DISCNUMBER").and_then(|d| d[0].parse::<i32>().ok()));
 
             // Extract picture
-            let album_art = tag.pictures().next().map(|p| p.data.clone());
+            let album_art = pick_cover(tag.pictures().map(|p| {
+                (
+                    p.picture_type == metaflac::block::PictureType::CoverFront,
+                    p.data.as_slice(),
+                )
+            }));
 
             // Calculate duration from StreamInfo
             let duration = tag
@@ -317,10 +394,18 @@ fn extract_flac_metadata_fallback(path: &Path, _duration_hint: Option<i32>) -> O
                 local_src: None,
                 musicbrainz_recording_id: None,
                 metadata_json: None,
+                file_size: None,
+                file_mtime: None,
+                metadata_fallback: false,
             })
         }
         Err(e) => {
-            eprintln!("[Scanner] Metaflac also failed for {:?}: {}", path, e);
+            issues.push(ScanIssue::new(
+                path.to_string_lossy(),
+                ScanStage::Tags,
+                ScanIssueKind::Corrupt,
+                e.to_string(),
+            ));
             let mut track = create_fallback_metadata(path);
             track.duration = _duration_hint; // Use hint if available (probably None)
             track.format = format;
@@ -1,7 +1,17 @@
 // Scanner module for file walking, metadata extraction, and cover storage
 pub mod walker;
 pub mod metadata;
+pub mod organiser;
+pub mod audio_features;
 pub mod cover_storage;
+pub mod duplicates;
+pub mod fingerprint;
+pub mod folder_art;
+pub mod palette;
+pub mod playlist_formats;
+pub mod rating;
+pub mod relink;
+pub mod thumbnails;
+pub mod watcher;
 
-pub use walker::scan_directory;
 pub use metadata::extract_metadata;
\ No newline at end of file
@@ -0,0 +1,780 @@
+// File organiser
+//
+// Renames and moves library files to paths built from their tags with a
+// template such as `{albumartist}/{year} - {album}/{disc}-{track:02} {title}.{ext}`.
+// Templates are relative to the music folder a file is in, so files never leave
+// their folder. Parts in square brackets are dropped when a field in them is
+// empty: `[{year} - ]{album}` is just the album name for untagged years. Every
+// path component is sanitised for the target filesystem.
+use crate::db::queries::OrganiseTrack;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Lyrics and per-track art that move with an audio file of the same name
+const SIDECAR_EXTENSIONS: &[&str] = &["lrc", "txt", "jpg", "jpeg", "png", "webp"];
+
+/// Folder art that follows an album when all of its folder's audio moves
+const FOLDER_IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "gif", "bmp"];
+
+/// Longest file or folder name most filesystems accept, in bytes
+const MAX_COMPONENT_BYTES: usize = 255;
+
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+const FIELDS: &[&str] = &[
+    "title",
+    "artist",
+    "album",
+    "albumartist",
+    "year",
+    "genre",
+    "composer",
+    "track",
+    "tracktotal",
+    "disc",
+    "disctotal",
+    "filename",
+    "ext",
+];
+
+/// Which characters and names file names may use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Filesystem {
+    /// NTFS, FAT and exFAT; also the safe choice for drives shared with Windows
+    Windows,
+    /// APFS and HFS+
+    Mac,
+    /// ext4, btrfs and other Unix filesystems
+    Linux,
+}
+
+impl Filesystem {
+    pub fn current() -> Self {
+        if cfg!(target_os = "windows") {
+            Self::Windows
+        } else if cfg!(target_os = "macos") {
+            Self::Mac
+        } else {
+            Self::Linux
+        }
+    }
+
+    fn is_case_insensitive(self) -> bool {
+        self != Self::Linux
+    }
+
+    fn is_forbidden(self, c: char) -> bool {
+        c.is_control()
+            || c == '/'
+            || match self {
+                Self::Windows => matches!(c, '<' | '>' | ':' | '"' | '\\' | '|' | '?' | '*'),
+                Self::Mac => c == ':',
+                Self::Linux => false,
+            }
+    }
+}
+
+/// What to do when a file's new path is taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionPolicy {
+    /// Add " (2)", " (3)"... to the file name
+    #[default]
+    Number,
+    /// Leave the file where it is
+    Skip,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Text(String),
+    Field { name: String, width: usize },
+    Optional(Vec<Token>),
+}
+
+/// A parsed path template, one list of tokens per path component
+#[derive(Debug, Clone)]
+pub struct Template {
+    components: Vec<Vec<Token>>,
+}
+
+fn parse_field(spec: &str) -> Result<Token, String> {
+    let (name, width) = match spec.split_once(':') {
+        Some((name, width)) => {
+            let width = width
+                .parse()
+                .map_err(|_| format!("Invalid width in {{{}}}", spec))?;
+            (name, width)
+        }
+        None => (spec, 0),
+    };
+    let name = name.trim().to_lowercase();
+    if !FIELDS.contains(&name.as_str()) {
+        return Err(format!(
+            "Unknown field {{{}}}; available: {}",
+            name,
+            FIELDS.join(", ")
+        ));
+    }
+    Ok(Token::Field { name, width })
+}
+
+fn parse_component(component: &str) -> Result<Vec<Token>, String> {
+    // Tokens of the enclosing groups, innermost last
+    let mut stack: Vec<Vec<Token>> = vec![Vec::new()];
+    let mut chars = component.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                let spec: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                stack.last_mut().unwrap().push(parse_field(&spec)?);
+            }
+            '}' => return Err("Unmatched '}' in template".to_string()),
+            '[' => stack.push(Vec::new()),
+            ']' => {
+                if stack.len() < 2 {
+                    return Err("Unmatched ']' in template".to_string());
+                }
+                let group = stack.pop().unwrap();
+                stack.last_mut().unwrap().push(Token::Optional(group));
+            }
+            _ => match stack.last_mut().unwrap().last_mut() {
+                Some(Token::Text(text)) => text.push(c),
+                _ => stack.last_mut().unwrap().push(Token::Text(c.to_string())),
+            },
+        }
+    }
+    if stack.len() > 1 {
+        return Err("Unmatched '[' in template (optional parts can't span folders)".to_string());
+    }
+    Ok(stack.pop().unwrap())
+}
+
+fn has_field(tokens: &[Token], field: &str) -> bool {
+    tokens.iter().any(|token| match token {
+        Token::Field { name, .. } => name == field,
+        Token::Optional(group) => has_field(group, field),
+        Token::Text(_) => false,
+    })
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let template = template.trim().replace('\\', "/");
+        let mut components = template
+            .split('/')
+            .filter(|component| !component.trim().is_empty())
+            .map(parse_component)
+            .collect::<Result<Vec<_>, _>>()?;
+        let Some(file_name) = components.last_mut() else {
+            return Err("Template is empty".to_string());
+        };
+        // Files always keep their extension
+        if !has_field(file_name, "ext") {
+            file_name.push(Token::Text(".".to_string()));
+            file_name.push(Token::Field {
+                name: "ext".to_string(),
+                width: 0,
+            });
+        }
+        Ok(Template { components })
+    }
+
+    /// The path for `track` relative to its music folder
+    fn render(&self, track: &OrganiseTrack, filesystem: Filesystem) -> PathBuf {
+        let last = self.components.len() - 1;
+        self.components
+            .iter()
+            .enumerate()
+            .map(|(i, tokens)| {
+                let text = render_tokens(tokens, track, false).unwrap_or_default();
+                if i == last {
+                    sanitize_file_name(&text, &field_value(track, "ext"), filesystem)
+                } else {
+                    sanitize_component(&text, filesystem, 0)
+                }
+            })
+            .collect()
+    }
+}
+
+fn field_value(track: &OrganiseTrack, name: &str) -> String {
+    let path = Path::new(&track.path);
+    let value = match name {
+        "filename" => path.file_stem().map(|s| s.to_string_lossy().to_string()),
+        "ext" => path.extension().map(|e| e.to_string_lossy().to_lowercase()),
+        _ => track.tags.get(name).cloned(),
+    };
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_default()
+}
+
+/// Rendered text, None inside an optional group when a field is empty.
+/// Outside groups, empty names fall back to placeholders.
+fn render_tokens(tokens: &[Token], track: &OrganiseTrack, optional: bool) -> Option<String> {
+    let mut out = String::new();
+    for token in tokens {
+        match token {
+            Token::Text(text) => out.push_str(text),
+            Token::Field { name, width } => {
+                let mut value = field_value(track, name);
+                if value.is_empty() {
+                    if optional {
+                        return None;
+                    }
+                    value = match name.as_str() {
+                        "artist" | "albumartist" => "Unknown Artist".to_string(),
+                        "album" => "Unknown Album".to_string(),
+                        "title" => field_value(track, "filename"),
+                        _ => String::new(),
+                    };
+                }
+                if !value.is_empty() && value.chars().all(|c| c.is_ascii_digit()) {
+                    out.push_str(&format!("{:0>width$}", value, width = width));
+                } else {
+                    out.push_str(&value);
+                }
+            }
+            Token::Optional(group) => {
+                if let Some(text) = render_tokens(group, track, true) {
+                    out.push_str(&text);
+                }
+            }
+        }
+    }
+    Some(out)
+}
+
+/// A valid file or folder name for `filesystem`, leaving `reserve` bytes spare
+fn sanitize_component(name: &str, filesystem: Filesystem, reserve: usize) -> String {
+    let replaced: String = name
+        .chars()
+        .map(|c| if filesystem.is_forbidden(c) { '_' } else { c })
+        .collect();
+    let mut name = replaced.trim().to_string();
+
+    let max = MAX_COMPONENT_BYTES.saturating_sub(reserve);
+    if name.len() > max {
+        let mut end = max;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name.truncate(end);
+    }
+
+    if filesystem == Filesystem::Windows {
+        // Windows drops trailing dots and spaces, and reserves device names
+        // even with an extension
+        name = name.trim_end_matches(['.', ' ']).to_string();
+        let stem = name.split('.').next().unwrap_or_default();
+        if WINDOWS_RESERVED_NAMES
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(stem.trim_end()))
+        {
+            name.insert(0, '_');
+        }
+    }
+    if name.is_empty() || name == "." || name == ".." {
+        name = "_".to_string();
+    }
+    name
+}
+
+/// Like [`sanitize_component`], keeping the extension intact when the name is shortened
+fn sanitize_file_name(name: &str, ext: &str, filesystem: Filesystem) -> String {
+    let suffix = format!(".{}", ext);
+    match name.strip_suffix(&suffix) {
+        Some(stem) if !ext.is_empty() => {
+            format!(
+                "{}{}",
+                sanitize_component(stem, filesystem, suffix.len()),
+                suffix
+            )
+        }
+        _ => sanitize_component(name, filesystem, 0),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MoveStatus {
+    /// Already where the template puts it
+    Unchanged,
+    /// Moves (or, in a dry run, would move)
+    Move,
+    /// The target was taken and the collision policy is to skip
+    Skipped,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileMove {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedMove {
+    pub track_id: i64,
+    pub from: String,
+    pub to: String,
+    pub status: MoveStatus,
+    /// A number was added to the name because the target was taken
+    pub numbered: bool,
+    /// Lyrics, art and other files moving along with this one
+    pub sidecars: Vec<FileMove>,
+    pub error: Option<String>,
+}
+
+impl PlannedMove {
+    fn failed(track: &OrganiseTrack, error: String) -> Self {
+        PlannedMove {
+            track_id: track.id,
+            from: track.path.clone(),
+            to: track.path.clone(),
+            status: MoveStatus::Failed,
+            numbered: false,
+            sidecars: Vec::new(),
+            error: Some(error),
+        }
+    }
+}
+
+fn path_key(path: &Path, filesystem: Filesystem) -> String {
+    let path = path.to_string_lossy();
+    if filesystem.is_case_insensitive() {
+        path.to_lowercase()
+    } else {
+        path.to_string()
+    }
+}
+
+fn lowercase_extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// `path` with " (n)" added to its file name
+fn numbered_path(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{} ({}).{}", stem, n, ext.to_string_lossy()),
+        None => format!("{} ({})", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+/// Paths claimed by planned moves, plus existing files
+struct Claims {
+    filesystem: Filesystem,
+    claimed: HashSet<String>,
+}
+
+impl Claims {
+    /// Whether `path` is free for a file now at `from`
+    fn is_free(&self, path: &Path, from: &Path) -> bool {
+        let key = path_key(path, self.filesystem);
+        // A rename that only changes case is the same file on case-insensitive filesystems
+        let same_file = key == path_key(from, self.filesystem);
+        !self.claimed.contains(&key) && (same_file || !path.exists())
+    }
+
+    fn claim(&mut self, path: &Path) {
+        self.claimed.insert(path_key(path, self.filesystem));
+    }
+}
+
+/// Files in `dir`, listed once per directory
+fn dir_files<'a>(cache: &'a mut HashMap<PathBuf, Vec<PathBuf>>, dir: &Path) -> &'a [PathBuf] {
+    cache.entry(dir.to_path_buf()).or_insert_with(|| {
+        let mut files: Vec<PathBuf> = fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| path.is_file())
+                    .collect()
+            })
+            .unwrap_or_default();
+        files.sort();
+        files
+    })
+}
+
+/// Work out where each track goes. Nothing is touched on disk.
+pub fn plan(
+    tracks: &[OrganiseTrack],
+    roots: &[PathBuf],
+    template: &Template,
+    filesystem: Filesystem,
+    collisions: CollisionPolicy,
+) -> Vec<PlannedMove> {
+    let mut tracks: Vec<&OrganiseTrack> = tracks.iter().collect();
+    tracks.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut claims = Claims {
+        filesystem,
+        claimed: HashSet::new(),
+    };
+    let mut listings = HashMap::new();
+    let mut moves = Vec::with_capacity(tracks.len());
+
+    for track in tracks {
+        let from = Path::new(&track.path);
+        let Some(root) = roots
+            .iter()
+            .filter(|root| from.starts_with(root))
+            .max_by_key(|root| root.as_os_str().len())
+        else {
+            moves.push(PlannedMove::failed(
+                track,
+                "Not inside a music folder".to_string(),
+            ));
+            continue;
+        };
+
+        let target = root.join(template.render(track, filesystem));
+        if target == from {
+            claims.claim(&target);
+            moves.push(PlannedMove {
+                status: MoveStatus::Unchanged,
+                ..PlannedMove::failed(track, String::new())
+            });
+            continue;
+        }
+
+        let mut to = target.clone();
+        let mut numbered = false;
+        if !claims.is_free(&to, from) {
+            if collisions == CollisionPolicy::Skip {
+                moves.push(PlannedMove {
+                    status: MoveStatus::Skipped,
+                    error: Some(format!("{} is taken", target.display())),
+                    ..PlannedMove::failed(track, String::new())
+                });
+                continue;
+            }
+            to = (2..)
+                .map(|n| numbered_path(&target, n))
+                .find(|candidate| claims.is_free(candidate, from))
+                .unwrap();
+            numbered = true;
+        }
+        claims.claim(&to);
+
+        // Files next to the track with the same name
+        let (Some(stem), Some(source_dir), Some(to_dir), Some(to_stem)) =
+            (from.file_stem(), from.parent(), to.parent(), to.file_stem())
+        else {
+            continue;
+        };
+        let mut sidecars = Vec::new();
+        for file in dir_files(&mut listings, source_dir) {
+            let is_sidecar = file != from
+                && file.file_stem() == Some(stem)
+                && SIDECAR_EXTENSIONS.contains(&lowercase_extension(file).as_str());
+            if !is_sidecar {
+                continue;
+            }
+            let mut name = to_stem.to_os_string();
+            name.push(".");
+            name.push(file.extension().unwrap_or_default());
+            let sidecar_to = to_dir.join(name);
+            if claims.is_free(&sidecar_to, file) {
+                claims.claim(&sidecar_to);
+                sidecars.push(FileMove {
+                    from: file.to_string_lossy().to_string(),
+                    to: sidecar_to.to_string_lossy().to_string(),
+                });
+            }
+        }
+
+        moves.push(PlannedMove {
+            track_id: track.id,
+            from: track.path.clone(),
+            to: to.to_string_lossy().to_string(),
+            status: MoveStatus::Move,
+            numbered,
+            sidecars,
+            error: None,
+        });
+    }
+
+    plan_folder_images(&mut moves, &mut claims, &mut listings);
+    moves
+}
+
+/// When every audio file of a folder moves to the same new folder, its
+/// remaining images (cover.jpg, folder.png...) go along
+fn plan_folder_images(
+    moves: &mut [PlannedMove],
+    claims: &mut Claims,
+    listings: &mut HashMap<PathBuf, Vec<PathBuf>>,
+) {
+    // Source folder -> indexes of the moves out of it
+    let mut by_folder: HashMap<PathBuf, Vec<usize>> = HashMap::new();
+    for (i, planned) in moves.iter().enumerate() {
+        if let Some(dir) = Path::new(&planned.from).parent() {
+            by_folder.entry(dir.to_path_buf()).or_default().push(i);
+        }
+    }
+
+    let mut folders: Vec<_> = by_folder.into_iter().collect();
+    folders.sort();
+    for (dir, indexes) in folders {
+        let targets: HashSet<Option<&Path>> = indexes
+            .iter()
+            .map(|&i| match moves[i].status {
+                MoveStatus::Move => Path::new(&moves[i].to).parent(),
+                _ => None,
+            })
+            .collect();
+        let target_dir = match targets.into_iter().collect::<Vec<_>>()[..] {
+            [Some(target)] if target != dir => target.to_path_buf(),
+            _ => continue,
+        };
+
+        let moving: HashSet<&str> = indexes
+            .iter()
+            .flat_map(|&i| {
+                std::iter::once(moves[i].from.as_str())
+                    .chain(moves[i].sidecars.iter().map(|s| s.from.as_str()))
+            })
+            .collect();
+        let files = dir_files(listings, &dir);
+        let other_audio = files.iter().any(|file| {
+            super::walker::is_supported_audio_file(file)
+                && !moving.contains(file.to_string_lossy().as_ref())
+        });
+        if other_audio {
+            continue;
+        }
+
+        let images: Vec<FileMove> = files
+            .iter()
+            .filter(|file| {
+                FOLDER_IMAGE_EXTENSIONS.contains(&lowercase_extension(file).as_str())
+                    && !moving.contains(file.to_string_lossy().as_ref())
+            })
+            .filter_map(|file| {
+                let to = target_dir.join(file.file_name()?);
+                claims.is_free(&to, file).then(|| {
+                    claims.claim(&to);
+                    FileMove {
+                        from: file.to_string_lossy().to_string(),
+                        to: to.to_string_lossy().to_string(),
+                    }
+                })
+            })
+            .collect();
+        moves[indexes[0]].sidecars.extend(images);
+    }
+}
+
+/// Move a file without overwriting anything, copying across devices
+fn move_file(from: &Path, to: &Path) -> Result<(), String> {
+    // A rename that only changes case finds the file itself at `to`
+    let same_file = to.exists() && from.canonicalize().ok() == to.canonicalize().ok();
+    if to.exists() && !same_file {
+        return Err(format!("{} already exists", to.display()));
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    match fs::rename(from, to) {
+        Ok(()) => return Ok(()),
+        Err(e) if same_file => {
+            return Err(format!("Failed to rename {}: {}", from.display(), e));
+        }
+        Err(_) => {}
+    }
+    fs::copy(from, to)
+        .and_then(|_| fs::remove_file(from))
+        .map_err(|e| format!("Failed to move {}: {}", from.display(), e))
+}
+
+/// Carry out planned moves. A file that can't be moved is marked failed;
+/// sidecars that can't be moved are reported in the move's error.
+pub fn execute(moves: &mut [PlannedMove]) {
+    for planned in moves.iter_mut() {
+        if planned.status != MoveStatus::Move {
+            continue;
+        }
+        if let Err(e) = move_file(Path::new(&planned.from), Path::new(&planned.to)) {
+            planned.status = MoveStatus::Failed;
+            planned.error = Some(e);
+            continue;
+        }
+        let mut sidecars = Vec::new();
+        for sidecar in std::mem::take(&mut planned.sidecars) {
+            match move_file(Path::new(&sidecar.from), Path::new(&sidecar.to)) {
+                Ok(()) => sidecars.push(sidecar),
+                Err(e) => planned.error = Some(e),
+            }
+        }
+        planned.sidecars = sidecars;
+    }
+}
+
+/// Move executed files back, e.g. when the library couldn't be updated
+pub fn revert(moves: &mut [PlannedMove], reason: &str) {
+    for planned in moves.iter_mut() {
+        if planned.status != MoveStatus::Move {
+            continue;
+        }
+        for sidecar in &planned.sidecars {
+            let _ = move_file(Path::new(&sidecar.to), Path::new(&sidecar.from));
+        }
+        let _ = move_file(Path::new(&planned.to), Path::new(&planned.from));
+        planned.status = MoveStatus::Failed;
+        planned.error = Some(reason.to_string());
+    }
+}
+
+/// Delete folders left empty by moves, up to (not including) the music folders
+pub fn remove_empty_dirs(moves: &[PlannedMove], roots: &[PathBuf]) {
+    let mut dirs: Vec<&Path> = moves
+        .iter()
+        .filter(|planned| planned.status == MoveStatus::Move)
+        .filter_map(|planned| Path::new(&planned.from).parent())
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    for dir in dirs {
+        let mut dir = Some(dir);
+        while let Some(current) = dir {
+            if roots.iter().any(|root| root == current) || fs::remove_dir(current).is_err() {
+                break;
+            }
+            dir = current.parent();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(id: i64, path: &Path, tags: &[(&str, &str)]) -> OrganiseTrack {
+        OrganiseTrack {
+            id,
+            path: path.to_string_lossy().to_string(),
+            tags: tags
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_template_rendering_and_sanitising() {
+        let template =
+            Template::parse("{albumartist}/[{year} - ]{album}/[{disc}-]{track:02} {title}")
+                .unwrap();
+        let tagged = track(
+            1,
+            Path::new("/m/x.FLAC"),
+            &[
+                ("albumartist", "AC/DC"),
+                ("year", "1980"),
+                ("album", "Back in Black?"),
+                ("track", "3"),
+                ("title", "CON"),
+            ],
+        );
+        assert_eq!(
+            template.render(&tagged, Filesystem::Windows),
+            PathBuf::from("AC_DC/1980 - Back in Black_/03 CON.flac")
+        );
+        assert_eq!(
+            template.render(&tagged, Filesystem::Linux),
+            PathBuf::from("AC_DC/1980 - Back in Black?/03 CON.flac")
+        );
+        let reserved = Template::parse("{title}").unwrap();
+        assert_eq!(
+            reserved.render(&tagged, Filesystem::Windows),
+            PathBuf::from("_CON.flac")
+        );
+
+        let untagged = track(2, Path::new("/m/song.mp3"), &[]);
+        assert_eq!(
+            template.render(&untagged, Filesystem::Linux),
+            PathBuf::from("Unknown Artist/Unknown Album/song.mp3")
+        );
+
+        let long = "x".repeat(300);
+        let long_title = track(3, Path::new("/m/a.ogg"), &[("title", long.as_str())]);
+        let name = reserved.render(&long_title, Filesystem::Linux);
+        let name = name.to_string_lossy();
+        assert_eq!(name.len(), MAX_COMPONENT_BYTES);
+        assert!(name.ends_with("x.ogg"));
+
+        assert!(Template::parse("{nope}").is_err());
+        assert!(Template::parse("[{album}/{title}]").is_err());
+        assert!(Template::parse("").is_err());
+    }
+
+    #[test]
+    fn test_plan_and_execute_moves() {
+        let root = std::env::temp_dir().join(format!("audion_organise_{}", std::process::id()));
+        let old_dir = root.join("old");
+        fs::create_dir_all(&old_dir).unwrap();
+        for name in ["a.mp3", "a.lrc", "b.mp3", "cover.jpg"] {
+            fs::write(old_dir.join(name), name).unwrap();
+        }
+        fs::create_dir_all(root.join("Artist/Album")).unwrap();
+        fs::write(root.join("Artist/Album/Song.mp3"), "taken").unwrap();
+
+        let tags = [("artist", "Artist"), ("album", "Album"), ("title", "Song")];
+        let tracks = vec![
+            track(1, &old_dir.join("a.mp3"), &tags),
+            track(2, &old_dir.join("b.mp3"), &tags),
+            track(3, Path::new("/elsewhere/c.mp3"), &tags),
+        ];
+        let template = Template::parse("{artist}/{album}/{title}.{ext}").unwrap();
+        let roots = vec![root.clone()];
+
+        let skipped = plan(
+            &tracks,
+            &roots,
+            &template,
+            Filesystem::Linux,
+            CollisionPolicy::Skip,
+        );
+        let mut moves = plan(
+            &tracks,
+            &roots,
+            &template,
+            Filesystem::Linux,
+            CollisionPolicy::Number,
+        );
+        execute(&mut moves);
+        remove_empty_dirs(&moves, &roots);
+
+        let album = root.join("Artist/Album");
+        let moved_a = album.join("Song (2).mp3").exists() && album.join("Song (2).lrc").exists();
+        let moved_b = album.join("Song (3).mp3").exists();
+        let moved_cover = album.join("cover.jpg").exists();
+        let old_removed = !old_dir.exists();
+        fs::remove_dir_all(&root).unwrap();
+
+        let status =
+            |moves: &[PlannedMove], id| moves.iter().find(|m| m.track_id == id).unwrap().status;
+        assert_eq!(status(&skipped, 1), MoveStatus::Skipped);
+        assert_eq!(status(&moves, 1), MoveStatus::Move);
+        assert!(moves
+            .iter()
+            .all(|m| m.numbered == (m.status == MoveStatus::Move)));
+        assert_eq!(status(&moves, 3), MoveStatus::Failed);
+        assert!(moved_a && moved_b && moved_cover && old_removed);
+    }
+}
@@ -0,0 +1,139 @@
+// Colour palettes of album covers
+//
+// Pixels are grouped into coarse colour buckets (4 bits per channel); each
+// bucket's average colour is a candidate, weighted by how many pixels fell in it.
+use crate::db::queries::AlbumPalette;
+use image::DynamicImage;
+use std::collections::HashMap;
+
+/// Covers are scaled down to at most this many pixels per side first
+const SAMPLE_SIZE: u32 = 64;
+
+/// Saturation separating vibrant from muted colours
+const VIBRANT_SATURATION: f32 = 0.35;
+
+#[derive(Default)]
+struct Bucket {
+    sum: [u64; 3],
+    count: u64,
+}
+
+impl Bucket {
+    fn colour(&self) -> [u8; 3] {
+        self.sum.map(|channel| (channel / self.count) as u8)
+    }
+}
+
+/// (saturation, lightness), both 0..=1
+fn saturation_lightness([r, g, b]: [u8; 3]) -> (f32, f32) {
+    let max = r.max(g).max(b) as f32 / 255.0;
+    let min = r.min(g).min(b) as f32 / 255.0;
+    let lightness = (max + min) / 2.0;
+    let saturation = if max == min {
+        0.0
+    } else {
+        (max - min) / (1.0 - (2.0 * lightness - 1.0).abs())
+    };
+    (saturation, lightness)
+}
+
+/// WCAG relative luminance
+fn luminance(colour: [u8; 3]) -> f32 {
+    let linear = colour.map(|c| {
+        let c = c as f32 / 255.0;
+        if c <= 0.03928 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    });
+    0.2126 * linear[0] + 0.7152 * linear[1] + 0.0722 * linear[2]
+}
+
+fn hex([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Palette of a cover image, None if it has no opaque pixels
+pub fn extract_palette(image: &DynamicImage) -> Option<AlbumPalette> {
+    let sample = if image.width() > SAMPLE_SIZE || image.height() > SAMPLE_SIZE {
+        image.thumbnail(SAMPLE_SIZE, SAMPLE_SIZE).to_rgba8()
+    } else {
+        image.to_rgba8()
+    };
+
+    let mut buckets: HashMap<[u8; 3], Bucket> = HashMap::new();
+    for pixel in sample.pixels().filter(|p| p[3] >= 128) {
+        let bucket = buckets
+            .entry([pixel[0] >> 4, pixel[1] >> 4, pixel[2] >> 4])
+            .or_default();
+        for channel in 0..3 {
+            bucket.sum[channel] += pixel[channel] as u64;
+        }
+        bucket.count += 1;
+    }
+
+    // (colour, pixel count, saturation, lightness), most common first
+    let mut candidates: Vec<([u8; 3], u64, f32, f32)> = buckets
+        .values()
+        .map(|bucket| {
+            let colour = bucket.colour();
+            let (saturation, lightness) = saturation_lightness(colour);
+            (colour, bucket.count, saturation, lightness)
+        })
+        .collect();
+    candidates.sort_by_key(|&(colour, count, _, _)| (std::cmp::Reverse(count), colour));
+
+    let dominant = candidates.first()?.0;
+    let vibrant = candidates
+        .iter()
+        .filter(|c| c.2 >= VIBRANT_SATURATION && (0.25..=0.75).contains(&c.3))
+        .max_by(|a, b| (a.1 as f32 * a.2).total_cmp(&(b.1 as f32 * b.2)))
+        .map_or(dominant, |c| c.0);
+    let muted = candidates
+        .iter()
+        .find(|c| c.2 < VIBRANT_SATURATION && (0.15..=0.85).contains(&c.3))
+        .map_or(dominant, |c| c.0);
+
+    // Whichever of black and white contrasts more with the dominant colour
+    let background = luminance(dominant);
+    let text = if 1.05 / (background + 0.05) >= (background + 0.05) / 0.05 {
+        [255, 255, 255]
+    } else {
+        [0, 0, 0]
+    };
+
+    Some(AlbumPalette {
+        dominant: hex(dominant),
+        vibrant: hex(vibrant),
+        muted: hex(muted),
+        text: hex(text),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_palette() {
+        // Mostly grey with a red stripe and a little near-black
+        let image = image::RgbImage::from_fn(50, 50, |x, _| match x {
+            0..=9 => image::Rgb([200, 30, 30]),
+            10..=12 => image::Rgb([10, 10, 10]),
+            _ => image::Rgb([128, 128, 128]),
+        });
+        let palette = extract_palette(&DynamicImage::ImageRgb8(image)).unwrap();
+
+        assert_eq!(palette.dominant, "#808080");
+        assert_eq!(palette.vibrant, "#c81e1e");
+        assert_eq!(palette.muted, "#808080");
+        assert_eq!(palette.text, "#000000");
+
+        let dark = image::RgbImage::from_pixel(10, 10, image::Rgb([20, 30, 60]));
+        let palette = extract_palette(&DynamicImage::ImageRgb8(dark)).unwrap();
+        assert_eq!(palette.text, "#ffffff");
+        // No candidate qualifies, so both fall back to the dominant colour
+        assert_eq!(palette.vibrant, palette.dominant);
+    }
+}
@@ -0,0 +1,675 @@
+// Playlist files
+//
+// Reads and writes M3U/M3U8 (with #EXTINF), PLS, XSPF and a JSON format of our
+// own. Once read, locations are absolute paths or URLs; when writing, local
+// paths can be made relative to the playlist file's folder. Entries also keep
+// what the file says about each track, so a track can be matched by artist,
+// title and length when its path leads nowhere. The JSON format is lossless:
+// it carries the playlist's cover, description, pinning, sort order and smart
+// rules, and each track's identity.
+use crate::db::queries::{PlaylistSortOrder, SmartPlaylistRules};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use url::Url;
+
+/// Identifies Audion's JSON playlists
+const JSON_FORMAT_NAME: &str = "audion-playlist";
+const JSON_FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaylistFormat {
+    M3u,
+    M3u8,
+    Pls,
+    Xspf,
+    Json,
+}
+
+impl PlaylistFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_string_lossy().to_lowercase();
+        match ext.as_str() {
+            "m3u" => Some(Self::M3u),
+            "m3u8" => Some(Self::M3u8),
+            "pls" => Some(Self::Pls),
+            "xspf" => Some(Self::Xspf),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// How local paths are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PathMode {
+    #[default]
+    Absolute,
+    /// Relative to the playlist file's folder, where both are on the same drive
+    Relative,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistFileEntry {
+    /// Absolute path or URL
+    pub location: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    /// Length in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_number: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disc_number: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PlaylistFile {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub cover_url: Option<String>,
+    #[serde(default)]
+    pub smart_rules: Option<SmartPlaylistRules>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub sort_order: PlaylistSortOrder,
+    #[serde(default)]
+    pub entries: Vec<PlaylistFileEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonPlaylist {
+    format: String,
+    version: u32,
+    #[serde(flatten)]
+    playlist: PlaylistFile,
+}
+
+/// Remove `.` and `..` without touching the filesystem
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push(component);
+                }
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// `path` relative to `base`, or None when they don't share a root
+fn relative_path(path: &Path, base: &Path) -> Option<PathBuf> {
+    let (path, base) = (normalize_path(path), normalize_path(base));
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    if path.first() != base.first() {
+        return None;
+    }
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in common..base.len() {
+        relative.push("..");
+    }
+    for component in &path[common..] {
+        relative.push(component);
+    }
+    Some(relative)
+}
+
+/// A file URI, a URI relative to `base_dir`, or another URL as-is
+fn resolve_uri(uri: &str, base_dir: &Path) -> String {
+    let url = match Url::parse(uri) {
+        Ok(url) => url,
+        Err(url::ParseError::RelativeUrlWithoutBase) => {
+            match Url::from_directory_path(base_dir).map(|base| base.join(uri)) {
+                Ok(Ok(url)) => url,
+                _ => return resolve_location(uri, base_dir),
+            }
+        }
+        Err(_) => return uri.to_string(),
+    };
+    if url.scheme() != "file" {
+        return uri.to_string();
+    }
+    url.to_file_path()
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or_else(|_| uri.to_string())
+}
+
+/// A path as written in an M3U, PLS or JSON playlist, made absolute
+fn resolve_location(location: &str, base_dir: &Path) -> String {
+    if location.starts_with("file:") {
+        return resolve_uri(location, base_dir);
+    }
+    if location.contains("://") {
+        return location.to_string();
+    }
+    // Playlists written on Windows use backslashes
+    #[cfg(not(windows))]
+    let location = location.replace('\\', "/");
+    normalize_path(&base_dir.join(location))
+        .to_string_lossy()
+        .to_string()
+}
+
+/// A location as it should be written with `mode`
+fn write_location(location: &str, base_dir: &Path, mode: PathMode) -> String {
+    if mode == PathMode::Absolute || location.contains("://") {
+        return location.to_string();
+    }
+    relative_path(Path::new(location), base_dir)
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or_else(|| location.to_string())
+}
+
+fn percent_encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// A location as an XSPF URI
+fn write_uri(location: &str, base_dir: &Path, mode: PathMode) -> String {
+    if location.contains("://") {
+        return location.to_string();
+    }
+    if mode == PathMode::Relative {
+        if let Some(relative) = relative_path(Path::new(location), base_dir) {
+            return relative
+                .components()
+                .map(|c| percent_encode(&c.as_os_str().to_string_lossy()))
+                .collect::<Vec<_>>()
+                .join("/");
+        }
+    }
+    Url::from_file_path(location)
+        .map(|url| url.to_string())
+        .unwrap_or_else(|_| location.to_string())
+}
+
+fn non_empty(text: &str) -> Option<String> {
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// "Artist - Title" as (artist, title)
+fn split_display(display: &str) -> (Option<String>, Option<String>) {
+    match display.split_once(" - ") {
+        Some((artist, title)) => (non_empty(artist), non_empty(title)),
+        None => (None, non_empty(display)),
+    }
+}
+
+/// "Artist - Title", the title, or the file name
+fn display_name(entry: &PlaylistFileEntry) -> String {
+    let name = match (&entry.artist, &entry.title) {
+        (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+        (None, Some(title)) => title.clone(),
+        _ => Path::new(&entry.location)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    };
+    name.replace(['\r', '\n'], " ")
+}
+
+/// Seconds from a length field; -1 means unknown
+fn parse_length(text: &str) -> Option<i32> {
+    text.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|seconds| *seconds >= 0.0)
+        .map(|seconds| seconds.round() as i32)
+}
+
+fn parse_m3u(content: &str, base_dir: &Path) -> PlaylistFile {
+    let mut playlist = PlaylistFile::default();
+    let mut info: Option<PlaylistFileEntry> = None;
+    for line in content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        if let Some(rest) = line.strip_prefix("#EXTINF:") {
+            let (length, display) = rest.split_once(',').unwrap_or((rest, ""));
+            // Attributes such as tvg-id="..." may follow the length
+            let length = length.split_whitespace().next().unwrap_or_default();
+            let (artist, title) = split_display(display);
+            info = Some(PlaylistFileEntry {
+                duration: parse_length(length),
+                artist,
+                title,
+                ..Default::default()
+            });
+        } else if let Some(name) = line.strip_prefix("#PLAYLIST:") {
+            playlist.name = non_empty(name);
+        } else if !line.starts_with('#') {
+            let mut entry = info.take().unwrap_or_default();
+            entry.location = resolve_location(line, base_dir);
+            playlist.entries.push(entry);
+        }
+    }
+    playlist
+}
+
+fn render_m3u(playlist: &PlaylistFile, base_dir: &Path, mode: PathMode) -> String {
+    let mut out = String::from("#EXTM3U\n");
+    if let Some(ref name) = playlist.name {
+        out.push_str(&format!("#PLAYLIST:{}\n", name.replace(['\r', '\n'], " ")));
+    }
+    for entry in &playlist.entries {
+        out.push_str(&format!(
+            "#EXTINF:{},{}\n{}\n",
+            entry.duration.unwrap_or(-1),
+            display_name(entry),
+            write_location(&entry.location, base_dir, mode)
+        ));
+    }
+    out
+}
+
+fn parse_pls(content: &str, base_dir: &Path) -> PlaylistFile {
+    let mut entries: BTreeMap<u32, PlaylistFileEntry> = BTreeMap::new();
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let split = key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len());
+        let (field, index) = key.split_at(split);
+        let Ok(index) = index.parse::<u32>() else {
+            continue;
+        };
+        let entry = entries.entry(index).or_default();
+        match field {
+            "file" => entry.location = resolve_location(value.trim(), base_dir),
+            "title" => (entry.artist, entry.title) = split_display(value),
+            "length" => entry.duration = parse_length(value),
+            _ => {}
+        }
+    }
+    PlaylistFile {
+        entries: entries
+            .into_values()
+            .filter(|entry| !entry.location.is_empty())
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn render_pls(playlist: &PlaylistFile, base_dir: &Path, mode: PathMode) -> String {
+    let mut out = String::from("[playlist]\n");
+    for (i, entry) in playlist.entries.iter().enumerate() {
+        let n = i + 1;
+        out.push_str(&format!(
+            "File{n}={}\nTitle{n}={}\nLength{n}={}\n",
+            write_location(&entry.location, base_dir, mode),
+            display_name(entry),
+            entry.duration.unwrap_or(-1),
+        ));
+    }
+    out.push_str(&format!(
+        "NumberOfEntries={}\nVersion=2\n",
+        playlist.entries.len()
+    ));
+    out
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn xml_unescape(text: &str) -> String {
+    let text = text.trim();
+    if let Some(data) = text
+        .strip_prefix("<![CDATA[")
+        .and_then(|t| t.strip_suffix("]]>"))
+    {
+        return data.to_string();
+    }
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let decoded = match &rest[1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            entity => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Contents of each `<tag>` element in `xml`, outermost first
+fn xml_elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{}", tag);
+    let close = format!("</{}>", tag);
+    let mut elements = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        // Skip longer tag names that start the same, e.g. <trackList> for <track>
+        if !rest.starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace()) {
+            continue;
+        }
+        let Some(head_end) = rest.find('>') else {
+            break;
+        };
+        if rest[..head_end].ends_with('/') {
+            elements.push("");
+            rest = &rest[head_end + 1..];
+            continue;
+        }
+        rest = &rest[head_end + 1..];
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        elements.push(&rest[..end]);
+        rest = &rest[end + close.len()..];
+    }
+    elements
+}
+
+fn xml_text(xml: &str, tag: &str) -> Option<String> {
+    xml_elements(xml, tag)
+        .first()
+        .and_then(|text| non_empty(&xml_unescape(text)))
+}
+
+fn parse_xspf(content: &str, base_dir: &Path) -> Result<PlaylistFile, String> {
+    if !content.contains("<playlist") {
+        return Err("Not an XSPF playlist".to_string());
+    }
+    let (head, body) = content.split_at(content.find("<trackList").unwrap_or(content.len()));
+    let entries = xml_elements(body, "track")
+        .into_iter()
+        .filter_map(|track| {
+            Some(PlaylistFileEntry {
+                location: resolve_uri(&xml_text(track, "location")?, base_dir),
+                title: xml_text(track, "title"),
+                artist: xml_text(track, "creator"),
+                album: xml_text(track, "album"),
+                duration: xml_text(track, "duration")
+                    .and_then(|ms| ms.parse::<i64>().ok())
+                    .map(|ms| ((ms + 500) / 1000) as i32),
+                track_number: xml_text(track, "trackNum").and_then(|n| n.parse().ok()),
+                ..Default::default()
+            })
+        })
+        .collect();
+    Ok(PlaylistFile {
+        name: xml_text(head, "title"),
+        entries,
+        ..Default::default()
+    })
+}
+
+fn render_xspf(playlist: &PlaylistFile, base_dir: &Path, mode: PathMode) -> String {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+    );
+    if let Some(ref name) = playlist.name {
+        out.push_str(&format!("  <title>{}</title>\n", xml_escape(name)));
+    }
+    out.push_str("  <trackList>\n");
+    for entry in &playlist.entries {
+        out.push_str("    <track>\n");
+        out.push_str(&format!(
+            "      <location>{}</location>\n",
+            xml_escape(&write_uri(&entry.location, base_dir, mode))
+        ));
+        for (tag, value) in [
+            ("title", &entry.title),
+            ("creator", &entry.artist),
+            ("album", &entry.album),
+        ] {
+            if let Some(value) = value {
+                out.push_str(&format!("      <{0}>{1}</{0}>\n", tag, xml_escape(value)));
+            }
+        }
+        if let Some(number) = entry.track_number {
+            out.push_str(&format!("      <trackNum>{}</trackNum>\n", number));
+        }
+        if let Some(seconds) = entry.duration {
+            out.push_str(&format!(
+                "      <duration>{}</duration>\n",
+                seconds as i64 * 1000
+            ));
+        }
+        out.push_str("    </track>\n");
+    }
+    out.push_str("  </trackList>\n</playlist>\n");
+    out
+}
+
+fn parse_json(content: &str, base_dir: &Path) -> Result<PlaylistFile, String> {
+    let json: JsonPlaylist =
+        serde_json::from_str(content).map_err(|e| format!("Invalid playlist JSON: {}", e))?;
+    if json.format != JSON_FORMAT_NAME {
+        return Err("Not an Audion playlist".to_string());
+    }
+    if json.version > JSON_FORMAT_VERSION {
+        return Err("This playlist was exported by a newer version of Audion".to_string());
+    }
+    let mut playlist = json.playlist;
+    for entry in &mut playlist.entries {
+        entry.location = resolve_location(&entry.location, base_dir);
+    }
+    Ok(playlist)
+}
+
+fn render_json(playlist: &PlaylistFile, base_dir: &Path, mode: PathMode) -> Result<String, String> {
+    let mut playlist = playlist.clone();
+    for entry in &mut playlist.entries {
+        entry.location = write_location(&entry.location, base_dir, mode);
+    }
+    let json = JsonPlaylist {
+        format: JSON_FORMAT_NAME.to_string(),
+        version: JSON_FORMAT_VERSION,
+        playlist,
+    };
+    serde_json::to_string_pretty(&json).map_err(|e| e.to_string())
+}
+
+/// Parse a playlist whose relative paths are relative to `base_dir`
+pub fn parse_playlist(
+    content: &str,
+    format: PlaylistFormat,
+    base_dir: &Path,
+) -> Result<PlaylistFile, String> {
+    match format {
+        PlaylistFormat::M3u | PlaylistFormat::M3u8 => Ok(parse_m3u(content, base_dir)),
+        PlaylistFormat::Pls => Ok(parse_pls(content, base_dir)),
+        PlaylistFormat::Xspf => parse_xspf(content, base_dir),
+        PlaylistFormat::Json => parse_json(content, base_dir),
+    }
+}
+
+/// Render a playlist to be saved in `base_dir`
+pub fn render_playlist(
+    playlist: &PlaylistFile,
+    format: PlaylistFormat,
+    base_dir: &Path,
+    mode: PathMode,
+) -> Result<String, String> {
+    match format {
+        PlaylistFormat::M3u | PlaylistFormat::M3u8 => Ok(render_m3u(playlist, base_dir, mode)),
+        PlaylistFormat::Pls => Ok(render_pls(playlist, base_dir, mode)),
+        PlaylistFormat::Xspf => Ok(render_xspf(playlist, base_dir, mode)),
+        PlaylistFormat::Json => render_json(playlist, base_dir, mode),
+    }
+}
+
+/// Read a playlist file, in the format its extension names
+pub fn read_playlist_file(path: &Path) -> Result<PlaylistFile, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    parse_playlist_file(path, &bytes)
+}
+
+/// Parse the contents of the playlist file at `path`, in the format its
+/// extension names
+pub fn parse_playlist_file(path: &Path, bytes: &[u8]) -> Result<PlaylistFile, String> {
+    let format = PlaylistFormat::from_path(path)
+        .ok_or_else(|| format!("Unsupported playlist file: {}", path.display()))?;
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    // Plain .m3u files are often Latin-1
+    let content = match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    };
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    parse_playlist(&content, format, base_dir)
+}
+
+/// Write a playlist file in `format`, replacing any file at `path` in one step
+pub fn write_playlist_file(
+    playlist: &PlaylistFile,
+    path: &Path,
+    format: PlaylistFormat,
+    mode: PathMode,
+) -> Result<(), String> {
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    let content = render_playlist(playlist, format, base_dir, mode)?;
+    let temp = path.with_extension("audion-tmp");
+    fs::write(&temp, content)
+        .and_then(|_| fs::rename(&temp, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&temp);
+            format!("Failed to write {}: {}", path.display(), e)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_playlist_formats_round_trip() {
+        let base = std::env::temp_dir().join(format!("audion_playlists_{}", std::process::id()));
+        let dir = base.join("lists");
+        let entry = |location: PathBuf, title: &str, artist: Option<&str>| PlaylistFileEntry {
+            location: location.to_string_lossy().to_string(),
+            title: Some(title.to_string()),
+            artist: artist.map(str::to_string),
+            duration: Some(215),
+            ..Default::default()
+        };
+        let playlist = PlaylistFile {
+            name: Some("Road trip".to_string()),
+            description: Some("For the A1".to_string()),
+            pinned: true,
+            sort_order: PlaylistSortOrder::Artist,
+            entries: vec![
+                entry(
+                    base.join("Music/AC & DC/01 Highway.flac"),
+                    "Highway",
+                    Some("AC & DC"),
+                ),
+                entry(dir.join("local.mp3"), "Local <1>", None),
+                PlaylistFileEntry {
+                    location: "https://example.com/stream.mp3".to_string(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let formats = [
+            PlaylistFormat::M3u8,
+            PlaylistFormat::Pls,
+            PlaylistFormat::Xspf,
+            PlaylistFormat::Json,
+        ];
+        for format in formats {
+            for mode in [PathMode::Absolute, PathMode::Relative] {
+                let text = render_playlist(&playlist, format, &dir, mode).unwrap();
+                let parsed = parse_playlist(&text, format, &dir).unwrap();
+                let locations: Vec<&str> =
+                    parsed.entries.iter().map(|e| e.location.as_str()).collect();
+                let expected: Vec<&str> = playlist
+                    .entries
+                    .iter()
+                    .map(|e| e.location.as_str())
+                    .collect();
+                assert_eq!(locations, expected, "{:?} {:?}", format, mode);
+                assert_eq!(parsed.entries[0].title.as_deref(), Some("Highway"));
+                assert_eq!(parsed.entries[0].artist.as_deref(), Some("AC & DC"));
+                assert_eq!(parsed.entries[1].title.as_deref(), Some("Local <1>"));
+                assert_eq!(parsed.entries[0].duration, Some(215));
+                if format == PlaylistFormat::Json {
+                    assert_eq!(parsed.description.as_deref(), Some("For the A1"));
+                    assert!(parsed.pinned);
+                    assert_eq!(parsed.sort_order, PlaylistSortOrder::Artist);
+                }
+                if mode == PathMode::Relative && format != PlaylistFormat::Xspf {
+                    assert!(text.contains("local.mp3") && !text.contains(&*dir.to_string_lossy()));
+                }
+            }
+        }
+
+        let m3u = "#EXTM3U\r\n#EXTINF:-1 tvg-id=\"x\",Just A Title\r\n..\\other\\song.mp3\r\nplain.ogg\r\n";
+        let parsed = parse_playlist(m3u, PlaylistFormat::M3u, &dir).unwrap();
+        assert_eq!(parsed.entries.len(), 2);
+        assert_eq!(parsed.entries[0].title.as_deref(), Some("Just A Title"));
+        assert_eq!(parsed.entries[0].duration, None);
+        #[cfg(not(windows))]
+        assert_eq!(
+            parsed.entries[0].location,
+            base.join("other/song.mp3").to_string_lossy()
+        );
+        assert_eq!(parsed.entries[1].title, None);
+    }
+}
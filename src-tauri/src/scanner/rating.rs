@@ -0,0 +1,141 @@
+// Star ratings stored in audio file tags
+//
+// MP3 uses ID3v2 POPM frames (0-255, Windows Media Player scale); Vorbis comments
+// (FLAC/OGG/Opus) use RATING and MP4 uses `rate`, both written as 0-100.
+use lofty::config::{ParseOptions, WriteOptions};
+use lofty::file::FileType;
+use lofty::id3::v2::{Frame, Id3v2Tag, PopularimeterFrame};
+use lofty::mpeg::MpegFile;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use std::fs::File;
+use std::path::Path;
+
+/// POPM email used by Windows Media Player, which most players read
+const POPM_EMAIL: &str = "Windows Media Player 9 Series";
+
+/// POPM byte written for 0-5 stars
+const POPM_VALUES: [u8; 6] = [0, 1, 64, 128, 196, 255];
+
+/// Convert a POPM byte (1-255) to stars using the common WMP ranges.
+fn popm_to_stars(value: u8) -> u8 {
+    match value {
+        0 => 0,
+        1..=31 => 1,
+        32..=95 => 2,
+        96..=159 => 3,
+        160..=223 => 4,
+        _ => 5,
+    }
+}
+
+/// Parse a text rating: 0-5 is taken as stars, larger values as a 0-100 scale.
+fn text_to_stars(value: &str) -> Option<u8> {
+    let n: f64 = value.trim().parse().ok()?;
+    if !(0.0..=100.0).contains(&n) {
+        return None;
+    }
+    let stars = if n <= 5.0 { n } else { n / 20.0 };
+    Some(stars.round() as u8)
+}
+
+fn read_popm(path: &Path) -> Option<u8> {
+    let mut file = File::open(path).ok()?;
+    let mpeg = MpegFile::read_from(&mut file, ParseOptions::new()).ok()?;
+    let tag = mpeg.id3v2()?;
+
+    tag.into_iter().find_map(|frame| match frame {
+        Frame::Popularimeter(popm) if popm.rating > 0 => Some(popm_to_stars(popm.rating)),
+        _ => None,
+    })
+}
+
+/// Read a star rating (1-5) from a file's tags. Returns None when unrated or unreadable.
+pub fn read_file_rating(path: &Path) -> Option<u8> {
+    let file_type = FileType::from_path(path);
+    if file_type == Some(FileType::Mpeg) {
+        return read_popm(path);
+    }
+
+    let tagged_file = Probe::open(path).ok()?.read().ok()?;
+    let tag = tagged_file
+        .primary_tag()
+        .or_else(|| tagged_file.first_tag())?;
+    tag.get_string(&ItemKey::Popularimeter)
+        .and_then(text_to_stars)
+        .filter(|stars| *stars > 0)
+}
+
+/// Write a 0-5 star rating to a file's tags (0 removes it).
+pub fn write_file_rating(path: &Path, stars: u8) -> Result<(), String> {
+    let stars = stars.min(5);
+
+    if FileType::from_path(path) == Some(FileType::Mpeg) {
+        let mut file = File::open(path).map_err(|e| e.to_string())?;
+        let mpeg = MpegFile::read_from(&mut file, ParseOptions::new()).map_err(|e| e.to_string())?;
+        drop(file);
+
+        let mut tag = mpeg.id3v2().cloned().unwrap_or_else(Id3v2Tag::new);
+        // Keep the play counter of an existing WMP frame, drop any other raters
+        let counter = (&tag)
+            .into_iter()
+            .find_map(|frame| match frame {
+                Frame::Popularimeter(popm) if popm.email == POPM_EMAIL => Some(popm.counter),
+                _ => None,
+            })
+            .unwrap_or(0);
+        tag.retain(|frame| !matches!(frame, Frame::Popularimeter(_)));
+        if stars > 0 {
+            tag.insert(Frame::Popularimeter(PopularimeterFrame::new(
+                POPM_EMAIL.to_string(),
+                POPM_VALUES[stars as usize],
+                counter,
+            )));
+        }
+
+        return tag
+            .save_to_path(path, WriteOptions::default())
+            .map_err(|e| e.to_string());
+    }
+
+    let mut tagged_file = Probe::open(path)
+        .map_err(|e| e.to_string())?
+        .read()
+        .map_err(|e| e.to_string())?;
+
+    let tag = match tagged_file.primary_tag_mut() {
+        Some(tag) => tag,
+        None => {
+            let tag_type = tagged_file.primary_tag_type();
+            tagged_file.insert_tag(lofty::tag::Tag::new(tag_type));
+            tagged_file
+                .primary_tag_mut()
+                .ok_or_else(|| "File does not support tags".to_string())?
+        }
+    };
+
+    if stars == 0 {
+        tag.remove_key(&ItemKey::Popularimeter);
+    } else if !tag.insert_text(ItemKey::Popularimeter, (stars as u32 * 20).to_string()) {
+        return Err("This format does not support ratings".to_string());
+    }
+
+    tag.save_to_path(path, WriteOptions::default())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rating_scales() {
+        for stars in 0..=5u8 {
+            assert_eq!(popm_to_stars(POPM_VALUES[stars as usize]), stars);
+        }
+        assert_eq!(text_to_stars("80"), Some(4));
+        assert_eq!(text_to_stars("3"), Some(3));
+        assert_eq!(text_to_stars("abc"), None);
+        assert_eq!(text_to_stars("255"), None);
+    }
+}
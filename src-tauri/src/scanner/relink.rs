@@ -0,0 +1,223 @@
+// Relinking tracks whose files were moved or renamed
+//
+// A track's identity in the database is its id, but scans find files by path. When a
+// folder is moved, the old rows would be deleted (taking likes, play history and
+// playlist entries with them) and the files re-imported as new tracks. Before
+// anything is deleted, missing tracks are matched against files that are not in the
+// library yet and simply pointed at their new location.
+use crate::db::queries::{self, MissingTrack, ScanIssue, TrackInsert};
+use crate::scanner::metadata::{identify_audio, read_metadata_with_issues};
+use crate::scanner::walker::file_fingerprint;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Largest duration difference (seconds) accepted for a content hash match
+const DURATION_TOLERANCE: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelinkMatch {
+    /// Same size and modification time: the file was moved without being touched
+    FileFingerprint,
+    /// Same content hash and duration
+    ContentHash,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelinkedTrack {
+    pub track_id: i64,
+    pub old_path: String,
+    pub new_path: String,
+    pub matched_by: RelinkMatch,
+}
+
+fn extension(path: &str) -> Option<String> {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+}
+
+fn file_name(path: &str) -> Option<&std::ffi::OsStr> {
+    Path::new(path).file_name()
+}
+
+/// Files whose tags and audio were read while matching, with the issues seen,
+/// so the import that follows doesn't read them again
+pub type ExtractedFiles = HashMap<String, (TrackInsert, Vec<ScanIssue>)>;
+
+/// Match missing tracks to files that are not in the library yet. Only touches
+/// the filesystem, so it runs without the database lock. Matched tracks are
+/// removed from `missing`; whatever is left there is really gone.
+pub fn plan_relinks(
+    missing: &mut Vec<MissingTrack>,
+    new_files: &[String],
+) -> (Vec<RelinkedTrack>, ExtractedFiles) {
+    let mut relinked = Vec::new();
+    let mut extracted = ExtractedFiles::new();
+    if missing.is_empty() || new_files.is_empty() {
+        return (relinked, extracted);
+    }
+
+    let mut matched = vec![false; missing.len()];
+    let mut link = |i: usize, new_path: &str, matched_by, matched: &mut Vec<bool>| {
+        matched[i] = true;
+        relinked.push(RelinkedTrack {
+            track_id: missing[i].id,
+            old_path: missing[i].path.clone(),
+            new_path: new_path.to_string(),
+            matched_by,
+        });
+    };
+
+    // 1. Size + mtime, a single stat per file. Ambiguous keys are left to step 2.
+    let mut by_stat: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (i, track) in missing.iter().enumerate() {
+        if let (Some(size), Some(mtime)) = (track.file_size, track.file_mtime) {
+            by_stat.entry((size, mtime)).or_default().push(i);
+        }
+    }
+
+    let mut unmatched_files = Vec::new();
+    for file in new_files {
+        let candidate = file_fingerprint(Path::new(file))
+            .and_then(|fp| by_stat.get(&fp))
+            .filter(|candidates| candidates.len() == 1)
+            .map(|candidates| candidates[0])
+            .filter(|&i| !matched[i] && extension(&missing[i].path) == extension(file));
+
+        match candidate {
+            Some(i) => link(i, file, RelinkMatch::FileFingerprint, &mut matched),
+            None => unmatched_files.push(file),
+        }
+    }
+
+    // 2. Content hash + duration. Needs the tags of the remaining new files,
+    //    and their decoded audio only when a track of about the same length is
+    //    still waiting for a match.
+    let mut by_hash: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, track) in missing.iter().enumerate() {
+        if let (false, Some(hash)) = (matched[i], track.content_hash.as_ref()) {
+            by_hash.entry(hash.clone()).or_default().push(i);
+        }
+    }
+
+    if !by_hash.is_empty() {
+        for file in unmatched_files {
+            let (Some(mut track_data), issues) = read_metadata_with_issues(file) else {
+                continue;
+            };
+            let duration = track_data.duration;
+            let near = |i: usize, matched: &[bool]| {
+                !matched[i]
+                    && match (missing[i].duration, duration) {
+                        (Some(a), Some(b)) => (a - b).abs() <= DURATION_TOLERANCE,
+                        _ => false,
+                    }
+            };
+            if !by_hash.values().flatten().any(|&i| near(i, &matched)) {
+                continue;
+            }
+            identify_audio(&mut track_data);
+
+            let best = track_data
+                .content_hash
+                .as_ref()
+                .and_then(|hash| by_hash.get(hash))
+                .and_then(|candidates| {
+                    candidates
+                        .iter()
+                        .copied()
+                        .filter(|&i| near(i, &matched))
+                        // Prefer the same file name, e.g. when only a folder was renamed
+                        .max_by_key(|&i| file_name(&missing[i].path) == file_name(file))
+                });
+            if let Some(i) = best {
+                link(i, file, RelinkMatch::ContentHash, &mut matched);
+            }
+            extracted.insert(file.clone(), (track_data, issues));
+        }
+    }
+
+    let mut index = 0;
+    missing.retain(|_| {
+        let keep = !matched[index];
+        index += 1;
+        keep
+    });
+
+    (relinked, extracted)
+}
+
+/// Point relinked tracks at their new files, then delete the tracks that are
+/// still missing. Returns the number of deleted tracks.
+pub fn apply_relinks(
+    conn: &Connection,
+    relinked: &[RelinkedTrack],
+    still_missing: &[MissingTrack],
+) -> rusqlite::Result<usize> {
+    for track in relinked {
+        queries::relocate_track(conn, track.track_id, &track.new_path)?;
+        log::info!(
+            "[Scanner] Relinked track {}: {} -> {}",
+            track.track_id,
+            track.old_path,
+            track.new_path
+        );
+    }
+
+    let mut deleted = 0;
+    for track in still_missing {
+        if queries::delete_track(conn, track.id)? {
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relink_by_file_fingerprint() {
+        let dir = std::env::temp_dir().join(format!("audion-relink-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let new_file = dir.join("moved.mp3");
+        std::fs::write(&new_file, b"not really audio").unwrap();
+        let new_path = new_file.to_string_lossy().to_string();
+        let (size, mtime) = file_fingerprint(&new_file).unwrap();
+
+        let mut missing = vec![
+            MissingTrack {
+                id: 1,
+                path: "/old/place/moved.mp3".to_string(),
+                content_hash: None,
+                duration: Some(100),
+                file_size: Some(size),
+                file_mtime: Some(mtime),
+            },
+            MissingTrack {
+                id: 2,
+                path: "/old/place/deleted.mp3".to_string(),
+                content_hash: None,
+                duration: Some(100),
+                file_size: Some(size + 1),
+                file_mtime: Some(mtime),
+            },
+        ];
+
+        let (relinked, extracted) = plan_relinks(&mut missing, &[new_path.clone()]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(relinked.len(), 1);
+        assert_eq!(relinked[0].track_id, 1);
+        assert_eq!(relinked[0].new_path, new_path);
+        assert_eq!(relinked[0].matched_by, RelinkMatch::FileFingerprint);
+        assert!(extracted.is_empty());
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].id, 2);
+    }
+}
@@ -0,0 +1,205 @@
+// Cover thumbnails
+//
+// Stored covers are the original embedded or folder images, often several
+// megabytes. Views that show them small ask for a thumbnail instead, generated
+// on first use and cached under `covers/thumbs/{size}`. A thumbnail is keyed by
+// the hash of its source path and regenerated once the source is newer.
+use crate::scanner::cover_storage::get_covers_directory;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::DynamicImage;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+const JPEG_QUALITY: u8 = 85;
+
+/// Thumbnail files are JPEG, or lossless WebP for images with transparency
+const THUMBNAIL_EXTENSIONS: &[&str] = &["jpg", "webp"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailSize {
+    /// List rows and mini player
+    Small,
+    /// Grid tiles
+    Medium,
+    /// Album and now-playing headers
+    Large,
+}
+
+impl ThumbnailSize {
+    pub const ALL: [ThumbnailSize; 3] = [Self::Small, Self::Medium, Self::Large];
+
+    /// Longest side in pixels
+    pub fn pixels(self) -> u32 {
+        match self {
+            Self::Small => 96,
+            Self::Medium => 256,
+            Self::Large => 600,
+        }
+    }
+}
+
+/// Cache file name (without extension) for a source image
+fn thumbnail_stem(source: &Path) -> String {
+    let digest = Sha256::digest(source.to_string_lossy().as_bytes());
+    format!("{:x}", digest)[..32].to_string()
+}
+
+fn is_fresh(thumbnail: &Path, source_modified: std::time::SystemTime) -> bool {
+    fs::metadata(thumbnail)
+        .and_then(|m| m.modified())
+        .is_ok_and(|modified| modified >= source_modified)
+}
+
+/// Whether any pixel is see-through (many PNG covers carry an opaque alpha channel)
+fn has_transparency(image: &DynamicImage) -> bool {
+    image.color().has_alpha() && image.to_rgba8().pixels().any(|p| p[3] < u8::MAX)
+}
+
+fn encode(image: &DynamicImage, path: &Path) -> Result<(), String> {
+    let file = fs::File::create(path).map_err(|e| format!("Failed to create thumbnail: {}", e))?;
+    let writer = BufWriter::new(file);
+    let result = if has_transparency(image) {
+        image
+            .to_rgba8()
+            .write_with_encoder(WebPEncoder::new_lossless(writer))
+    } else {
+        image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(writer, JPEG_QUALITY))
+    };
+    result.map_err(|e| {
+        let _ = fs::remove_file(path);
+        format!("Failed to encode thumbnail: {}", e)
+    })
+}
+
+/// Thumbnail of `source` in `cache_dir`, (re)generated if missing or older
+/// than the source. Sources already no larger than `size` are returned as is.
+fn thumbnail_in(cache_dir: &Path, source: &Path, size: ThumbnailSize) -> Result<PathBuf, String> {
+    let source_modified = fs::metadata(source)
+        .and_then(|m| m.modified())
+        .map_err(|e| format!("Cover not readable: {}", e))?;
+
+    let max = size.pixels();
+    let (width, height) =
+        image::image_dimensions(source).map_err(|e| format!("Unsupported cover image: {}", e))?;
+    if width <= max && height <= max {
+        return Ok(source.to_path_buf());
+    }
+
+    let dir = cache_dir.join(size.pixels().to_string());
+    let stem = thumbnail_stem(source);
+    let cached = THUMBNAIL_EXTENSIONS
+        .iter()
+        .map(|ext| dir.join(format!("{}.{}", stem, ext)))
+        .find(|path| is_fresh(path, source_modified));
+    if let Some(path) = cached {
+        return Ok(path);
+    }
+
+    let image = image::open(source).map_err(|e| format!("Failed to decode cover: {}", e))?;
+    let thumbnail = image.thumbnail(max, max);
+    let ext = if has_transparency(&thumbnail) {
+        "webp"
+    } else {
+        "jpg"
+    };
+
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create thumbnail directory: {}", e))?;
+    // A stale thumbnail in the other format would otherwise shadow this one
+    for other in THUMBNAIL_EXTENSIONS.iter().filter(|e| **e != ext) {
+        let _ = fs::remove_file(dir.join(format!("{}.{}", stem, other)));
+    }
+    let path = dir.join(format!("{}.{}", stem, ext));
+    encode(&thumbnail, &path)?;
+    Ok(path)
+}
+
+pub fn get_thumbnails_directory() -> Result<PathBuf, String> {
+    Ok(get_covers_directory()?.join("thumbs"))
+}
+
+/// Thumbnail of a stored cover at `size`, generating it if needed
+pub fn get_thumbnail(source: &Path, size: ThumbnailSize) -> Result<PathBuf, String> {
+    thumbnail_in(&get_thumbnails_directory()?, source, size)
+}
+
+/// Delete thumbnails whose source is not among `sources`. Returns how many
+/// files were removed.
+pub fn cleanup_thumbnails(sources: &[String]) -> Result<usize, String> {
+    let thumbs_dir = get_thumbnails_directory()?;
+    let keep: HashSet<String> = sources
+        .iter()
+        .map(|source| thumbnail_stem(Path::new(source)))
+        .collect();
+
+    let mut deleted = 0;
+    for size in ThumbnailSize::ALL {
+        let Ok(entries) = fs::read_dir(thumbs_dir.join(size.pixels().to_string())) else {
+            continue;
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let orphaned = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(|stem| !keep.contains(stem));
+            if orphaned && path.is_file() {
+                if let Err(e) = fs::remove_file(&path) {
+                    eprintln!("Failed to delete orphaned thumbnail {:?}: {}", path, e);
+                } else {
+                    deleted += 1;
+                }
+            }
+        }
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thumbnails_are_cached_and_regenerated() {
+        let dir = std::env::temp_dir().join(format!("audion_thumbs_{}", std::process::id()));
+        let cache = dir.join("thumbs");
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("1.png");
+        let small = dir.join("2.png");
+        image::RgbImage::new(1000, 500).save(&source).unwrap();
+        image::RgbImage::new(80, 80).save(&small).unwrap();
+
+        let large = thumbnail_in(&cache, &source, ThumbnailSize::Large).unwrap();
+        let large_dims = image::image_dimensions(&large).unwrap();
+        let cached = thumbnail_in(&cache, &source, ThumbnailSize::Large).unwrap();
+        let cached_modified = fs::metadata(&cached).unwrap().modified().unwrap();
+        let unscaled = thumbnail_in(&cache, &small, ThumbnailSize::Small).unwrap();
+
+        // Replacing the source invalidates the thumbnail
+        image::RgbaImage::new(2000, 2000).save(&source).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_modified(cached_modified + std::time::Duration::from_secs(5))
+            .unwrap();
+        let regenerated = thumbnail_in(&cache, &source, ThumbnailSize::Large).unwrap();
+        let regenerated_dims = image::image_dimensions(&regenerated).unwrap();
+        let old_removed = !large.exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(large.extension().unwrap(), "jpg");
+        assert_eq!(large_dims, (600, 300));
+        assert_eq!(cached, large);
+        assert_eq!(unscaled, small);
+        assert_eq!(regenerated.extension().unwrap(), "webp");
+        assert_eq!(regenerated_dims, (600, 600));
+        assert!(old_removed);
+    }
+}
@@ -1,39 +1,344 @@
 // Directory walking and file discovery
-use std::path::Path;
+//
+// Each music folder has its own `FolderScanSettings`. On top of those, a directory
+// containing a `.nomedia` file is skipped entirely, and a `.audionignore` file
+// lists glob patterns (relative to its directory) to skip; an empty one skips the
+// whole directory.
+use crate::db::queries::{
+    default_cover_art_names, FolderScanSettings, ScanIssue, ScanIssueKind, ScanStage,
+};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use walkdir::WalkDir;
 
 const SUPPORTED_EXTENSIONS: &[&str] = &["flac", "mp3", "wav", "ogg", "m4a", "aac"];
 
+const NOMEDIA_FILE: &str = ".nomedia";
+const IGNORE_FILE: &str = ".audionignore";
+
 pub struct ScanResult {
     pub audio_files: Vec<String>,
     pub total_scanned: usize,
-    pub errors: Vec<String>,
+    pub errors: Vec<ScanIssue>,
+}
+
+/// Check user-supplied exclude patterns, reporting the first invalid one
+pub fn validate_exclude_patterns(patterns: &[String]) -> Result<(), String> {
+    for pattern in patterns {
+        Glob::new(pattern).map_err(|e| format!("Invalid exclude pattern '{}': {}", pattern, e))?;
+    }
+    Ok(())
+}
+
+/// Check user-supplied folder art names, reporting the first invalid one
+pub fn validate_cover_art_names(names: &[String]) -> Result<(), String> {
+    for name in names {
+        if name.contains(['/', '\\']) {
+            return Err(format!("Cover art name '{}' must not contain a path", name));
+        }
+        Glob::new(name).map_err(|e| format!("Invalid cover art name '{}': {}", name, e))?;
+    }
+    Ok(())
+}
+
+/// Compile patterns, skipping (and logging) invalid ones
+fn build_glob_set<'a>(patterns: impl IntoIterator<Item = &'a str>) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        match Glob::new(pattern) {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(e) => log::warn!("[Scanner] Ignoring invalid pattern '{}': {}", pattern, e),
+        }
+    }
+    builder.build().unwrap_or_else(|_| GlobSet::empty())
+}
+
+/// Whether `path` or any of its ancestors below `base` matches `set`
+fn matches_below(set: &GlobSet, base: &Path, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(base) else {
+        return false;
+    };
+    relative
+        .ancestors()
+        .filter(|p| !p.as_os_str().is_empty())
+        .any(|p| set.is_match(p))
+}
+
+/// What a directory's marker files say about its contents
+enum DirIgnore {
+    Nothing,
+    Everything,
+    Patterns(GlobSet),
+}
+
+fn read_dir_ignore(dir: &Path) -> DirIgnore {
+    if dir.join(NOMEDIA_FILE).exists() {
+        return DirIgnore::Everything;
+    }
+    match std::fs::read_to_string(dir.join(IGNORE_FILE)) {
+        Ok(contents) => {
+            let patterns: Vec<&str> = contents
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .collect();
+            if patterns.is_empty() {
+                DirIgnore::Everything
+            } else {
+                DirIgnore::Patterns(build_glob_set(patterns))
+            }
+        }
+        Err(_) => DirIgnore::Nothing,
+    }
 }
 
-pub fn scan_directory(path: &str) -> ScanResult {
+/// Compiled scan rules for one music folder
+pub struct FolderRules {
+    root: PathBuf,
+    settings: FolderScanSettings,
+    excludes: GlobSet,
+    /// Marker files seen so far, per directory
+    dir_ignores: Mutex<HashMap<PathBuf, Arc<DirIgnore>>>,
+}
+
+impl FolderRules {
+    pub fn new(root: &str, settings: FolderScanSettings) -> Self {
+        let excludes = build_glob_set(settings.exclude_patterns.iter().map(String::as_str));
+        Self {
+            root: PathBuf::from(root),
+            settings,
+            excludes,
+            dir_ignores: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn dir_ignore(&self, dir: &Path) -> Arc<DirIgnore> {
+        let mut cache = self.dir_ignores.lock().unwrap_or_else(|e| e.into_inner());
+        cache
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| Arc::new(read_dir_ignore(dir)))
+            .clone()
+    }
+
+    /// Read the marker files of `dir` and the directories below it again
+    pub fn forget_markers(&self, dir: &Path) {
+        let mut cache = self.dir_ignores.lock().unwrap_or_else(|e| e.into_inner());
+        cache.retain(|cached, _| !cached.starts_with(dir));
+    }
+
+    /// Whether the settings or marker files exclude `path`, which must lie
+    /// under the folder
+    pub fn excludes(&self, path: &Path, is_dir: bool) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        let components = relative.components().count();
+        if components == 0 {
+            return false;
+        }
+
+        if let Some(max_depth) = self.settings.max_depth {
+            let depth = if is_dir { components } else { components - 1 };
+            if depth > max_depth {
+                return true;
+            }
+        }
+
+        if matches_below(&self.excludes, &self.root, path) {
+            return true;
+        }
+
+        // Marker files in the folder itself and every directory down to `path`
+        let mut dirs: Vec<&Path> = path
+            .ancestors()
+            .skip(if is_dir { 0 } else { 1 })
+            .take_while(|dir| dir.starts_with(&self.root))
+            .collect();
+        dirs.reverse();
+        dirs.into_iter().any(|dir| match &*self.dir_ignore(dir) {
+            DirIgnore::Nothing => false,
+            DirIgnore::Everything => true,
+            DirIgnore::Patterns(set) => matches_below(set, dir, path),
+        })
+    }
+
+    /// Whether a track of this length should be skipped
+    pub fn too_short(&self, duration: Option<i32>) -> bool {
+        match (self.settings.min_duration, duration) {
+            (Some(min), Some(duration)) => duration < min,
+            _ => false,
+        }
+    }
+}
+
+/// Scan rules for every music folder, matched to paths by their deepest root
+pub struct LibraryRules {
+    folders: Vec<FolderRules>,
+}
+
+impl LibraryRules {
+    pub fn new(folders: Vec<(String, FolderScanSettings)>) -> Self {
+        let mut folders: Vec<FolderRules> = folders
+            .into_iter()
+            .map(|(root, settings)| FolderRules::new(&root, settings))
+            .collect();
+        // Deepest first, so nested folders take precedence
+        folders.sort_by_key(|f| std::cmp::Reverse(f.root.components().count()));
+        Self { folders }
+    }
+
+    pub fn folders(&self) -> Vec<String> {
+        let mut roots: Vec<String> = self
+            .folders
+            .iter()
+            .map(|f| f.root.to_string_lossy().to_string())
+            .collect();
+        roots.sort();
+        roots
+    }
+
+    pub fn for_path(&self, path: &Path) -> Option<&FolderRules> {
+        self.folders.iter().find(|f| path.starts_with(&f.root))
+    }
+
+    /// Whether the rules of the folder containing `path` exclude this file
+    pub fn excludes_file(&self, path: &Path) -> bool {
+        self.for_path(path).is_some_and(|f| f.excludes(path, false))
+    }
+
+    pub fn too_short(&self, path: &Path, duration: Option<i32>) -> bool {
+        self.for_path(path).is_some_and(|f| f.too_short(duration))
+    }
+
+    /// Read the marker files of `dir` and the directories below it again,
+    /// e.g. after one of them changed
+    pub fn forget_markers(&self, dir: &Path) {
+        for folder in self.folders.iter() {
+            if dir.starts_with(&folder.root) || folder.root.starts_with(dir) {
+                folder.forget_markers(dir);
+            }
+        }
+    }
+
+    /// Whether the folder containing `path` looks up missing art online
+    pub fn fetch_online_art(&self, path: &Path) -> bool {
+        self.for_path(path).is_some_and(|f| f.settings.fetch_online_art)
+    }
+
+    /// Folder art file names for the folder containing `path`
+    pub fn cover_art_names(&self, path: &Path) -> Vec<String> {
+        self.for_path(path)
+            .map_or_else(default_cover_art_names, |f| {
+                f.settings.cover_art_names.clone()
+            })
+    }
+
+    /// Walk `dir` with the rules of the folder containing it (defaults outside
+    /// any music folder, e.g. for folder playlists)
+    pub fn scan(&self, dir: &str) -> ScanResult {
+        match self.for_path(Path::new(dir)) {
+            Some(rules) => scan_directory(dir, rules),
+            None => scan_directory(dir, &FolderRules::new(dir, FolderScanSettings::default())),
+        }
+    }
+}
+
+/// Walk `path` (the folder's root or a directory below it) for audio files
+pub fn scan_directory(path: &str, rules: &FolderRules) -> ScanResult {
     let mut audio_files = Vec::new();
     let mut errors = Vec::new();
     let mut total_scanned = 0;
 
-    for entry in WalkDir::new(path)
-        .follow_links(true)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
+    let follow = rules.settings.follow_symlinks;
+    let root = rules
+        .root
+        .canonicalize()
+        .unwrap_or_else(|_| rules.root.clone());
+    // Real locations reached through symlinks. walkdir reports links back into
+    // their own ancestry as loops; this also catches several links to one place.
+    let mut linked_targets: HashSet<PathBuf> = HashSet::new();
+    let mut skipped_links = Vec::new();
+
+    let walker = WalkDir::new(path).follow_links(follow).into_iter();
+    let entries = walker.filter_entry(|entry| {
+        let is_dir = entry.file_type().is_dir();
+        if entry.depth() > 0 && rules.excludes(entry.path(), is_dir) {
+            return false;
+        }
+        if follow && entry.depth() > 0 && entry.path_is_symlink() {
+            let Ok(target) = entry.path().canonicalize() else {
+                return false;
+            };
+            // Targets inside the folder are walked (or were) at their real path
+            if target.starts_with(&root) || !linked_targets.insert(target) {
+                skipped_links.push(entry.path().to_path_buf());
+                return false;
+            }
+        }
+        true
+    });
+
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let failed = e.path().unwrap_or(Path::new(path)).to_string_lossy();
+                let issue = match (e.loop_ancestor(), e.io_error()) {
+                    (Some(ancestor), _) => ScanIssue::new(
+                        failed,
+                        ScanStage::Walk,
+                        ScanIssueKind::SymlinkCycle,
+                        format!("Skipped symlink cycle back to {:?}", ancestor),
+                    ),
+                    (None, Some(io)) => ScanIssue::new(
+                        failed,
+                        ScanStage::Walk,
+                        ScanIssueKind::from_io(io),
+                        io.to_string(),
+                    ),
+                    (None, None) => {
+                        ScanIssue::new(failed, ScanStage::Walk, ScanIssueKind::Other, e.to_string())
+                    }
+                };
+                errors.push(issue);
+                continue;
+            }
+        };
         let path = entry.path();
-        
-        if path.is_file() {
+
+        if entry.file_type().is_file() {
             total_scanned += 1;
-            
+
             if is_supported_audio_file(path) {
                 match path.to_str() {
                     Some(path_str) => audio_files.push(path_str.to_string()),
-                    None => errors.push(format!("Invalid path encoding: {:?}", path)),
+                    None => errors.push(ScanIssue::new(
+                        path.to_string_lossy(),
+                        ScanStage::Walk,
+                        ScanIssueKind::InvalidPath,
+                        "Path is not valid UTF-8",
+                    )),
                 }
             }
         }
     }
 
+    if !skipped_links.is_empty() {
+        log::info!(
+            "[Scanner] Skipped {} symlink(s) to already scanned locations under {}",
+            skipped_links.len(),
+            path
+        );
+    }
+
     ScanResult {
         audio_files,
         total_scanned,
@@ -41,7 +346,26 @@ pub fn scan_directory(path: &str) -> ScanResult {
     }
 }
 
-fn is_supported_audio_file(path: &Path) -> bool {
+/// (size in bytes, mtime in ms since the Unix epoch) for change detection.
+/// A single stat call, so much cheaper than re-reading tags on network shares.
+pub fn file_fingerprint(path: &Path) -> Option<(i64, i64)> {
+    let meta = std::fs::metadata(path).ok()?;
+    let mtime = meta
+        .modified()
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_millis() as i64;
+    Some((meta.len() as i64, mtime))
+}
+
+/// Whether `path` is a `.nomedia` or `.audionignore` file
+pub fn is_marker_file(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name == NOMEDIA_FILE || name == IGNORE_FILE)
+}
+
+pub fn is_supported_audio_file(path: &Path) -> bool {
     path.extension()
         .and_then(|ext| ext.to_str())
         .map(|ext| SUPPORTED_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
@@ -66,4 +390,75 @@ mod tests {
         assert!(!is_supported_audio_file(Path::new("song.mp4")));
         assert!(!is_supported_audio_file(Path::new("song.txt")));
     }
+
+    #[test]
+    fn test_folder_rules() {
+        let root = std::env::temp_dir().join(format!("audion-walker-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let files = [
+            "a.mp3",
+            "sub/b.mp3",
+            "sub/deep/c.mp3",
+            "Samples/s.mp3",
+            "hidden/.nomedia",
+            "hidden/h.mp3",
+            "ign/.audionignore",
+            "ign/x.wav",
+            "ign/y.mp3",
+        ];
+        for file in files {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let contents = if file.ends_with(IGNORE_FILE) {
+                "# no wavs\n*.wav\n"
+            } else {
+                ""
+            };
+            std::fs::write(path, contents).unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&root, root.join("sub/loop")).unwrap();
+
+        let root_str = root.to_string_lossy().to_string();
+        let rules = FolderRules::new(
+            &root_str,
+            FolderScanSettings {
+                exclude_patterns: vec!["Samples".to_string()],
+                min_duration: Some(30),
+                max_depth: Some(1),
+                follow_symlinks: true,
+                ..Default::default()
+            },
+        );
+        let result = scan_directory(&root_str, &rules);
+        let excluded_deep = rules.excludes(&root.join("sub/deep/c.mp3"), false);
+        let excluded_sample = rules.excludes(&root.join("Samples/s.mp3"), false);
+        // Marker files are read once until forgotten
+        std::fs::write(root.join("sub/.nomedia"), "").unwrap();
+        let excluded_cached = rules.excludes(&root.join("sub/b.mp3"), false);
+        rules.forget_markers(&root.join("sub"));
+        let excluded_marked = rules.excludes(&root.join("sub/b.mp3"), false);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let mut found: Vec<String> = result
+            .audio_files
+            .iter()
+            .map(|p| {
+                Path::new(p)
+                    .strip_prefix(&root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect();
+        found.sort();
+        assert_eq!(found, vec!["a.mp3", "ign/y.mp3", "sub/b.mp3"]);
+        assert!(excluded_deep);
+        assert!(excluded_sample);
+        assert!(!excluded_cached);
+        assert!(excluded_marked);
+        assert!(rules.too_short(Some(10)));
+        assert!(!rules.too_short(Some(30)));
+        assert!(!rules.too_short(None));
+    }
 }
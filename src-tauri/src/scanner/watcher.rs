@@ -0,0 +1,695 @@
+// Filesystem watcher for live library updates
+//
+// Events from all music folders are collected until the folders go quiet and are
+// then applied as one batch, so copying in an album causes a single update.
+// Moves keep the track id (and with it play history and playlist entries): paired
+// rename events are applied directly, and a delete + create pair is recognised by
+// the new file having the same content hash as a track whose file has vanished.
+use crate::db::queries::{self, ScanIssue, ScanIssueKind, ScanStage};
+use crate::scanner::cover_storage;
+use crate::scanner::metadata::extract_metadata_with_issues;
+use crate::scanner::walker::{is_marker_file, is_supported_audio_file, LibraryRules};
+use notify::event::{EventKind, ModifyKind, RenameMode};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Quiet period after the last event before a batch is applied
+const DEBOUNCE: Duration = Duration::from_millis(1500);
+
+/// Upper bound on how long a folder that keeps changing can hold back a batch
+const MAX_BATCH_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MovedTrack {
+    pub track_id: i64,
+    pub old_path: String,
+    pub new_path: String,
+}
+
+/// Payload of the `library-changed` event
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LibraryChangedEvent {
+    pub added: Vec<queries::Track>,
+    pub updated: Vec<queries::Track>,
+    pub removed: Vec<i64>,
+    pub moved: Vec<MovedTrack>,
+    pub errors: Vec<ScanIssue>,
+}
+
+impl LibraryChangedEvent {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.updated.is_empty()
+            && self.removed.is_empty()
+            && self.moved.is_empty()
+            && self.errors.is_empty()
+    }
+}
+
+/// Raw filesystem changes collected during the debounce window
+#[derive(Debug, Default)]
+pub struct PendingChanges {
+    /// Files created, modified or removed; existence is checked when applied
+    files: HashSet<PathBuf>,
+    /// Directories created or moved in, which are walked when applied
+    dirs: HashSet<PathBuf>,
+    /// Paired renames (from, to)
+    renames: Vec<(PathBuf, PathBuf)>,
+}
+
+impl PendingChanges {
+    pub fn push(&mut self, event: Event) {
+        match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                self.renames
+                    .push((event.paths[0].clone(), event.paths[1].clone()));
+            }
+            EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_)) => {
+                for path in event.paths {
+                    if path.is_dir() {
+                        self.dirs.insert(path);
+                    } else {
+                        self.files.insert(path);
+                    }
+                }
+            }
+            EventKind::Remove(_)
+            | EventKind::Modify(ModifyKind::Data(_))
+            | EventKind::Modify(ModifyKind::Any)
+            | EventKind::Any => {
+                // Directory "modified" events just mean a child changed, which is
+                // reported separately
+                self.files
+                    .extend(event.paths.into_iter().filter(|p| !p.is_dir()));
+            }
+            // Access and metadata-only (permissions, atime) changes
+            _ => {}
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.dirs.is_empty() && self.renames.is_empty()
+    }
+
+    /// Resolve paths and read tags without touching the database, so the
+    /// connection is only locked for the short apply step. Files the folders'
+    /// scan settings exclude are ignored.
+    pub fn prepare(self, rules: &LibraryRules) -> PreparedChanges {
+        let PendingChanges {
+            mut files,
+            mut dirs,
+            renames,
+        } = self;
+
+        // A marker file that appeared, changed or went away changes what its
+        // directory leaves out, so the directory is walked again
+        let marked: Vec<PathBuf> = files
+            .iter()
+            .chain(renames.iter().flat_map(|(from, to)| [from, to]))
+            .filter(|path| is_marker_file(path))
+            .filter_map(|path| path.parent().map(Path::to_path_buf))
+            .collect();
+        dirs.extend(marked.into_iter().filter(|dir| dir.is_dir()));
+
+        let mut present = Vec::new();
+        let mut gone = Vec::new();
+        let mut walked = Vec::new();
+        let mut errors = Vec::new();
+
+        // A renamed file is read like any new file; whether it moves a track
+        // is decided against the library when applied. Renames to a file the
+        // folders' settings leave out just remove the old one.
+        let mut kept_renames = Vec::new();
+        for (from, to) in renames {
+            if to.is_dir() {
+                rules.forget_markers(&from);
+                rules.forget_markers(&to);
+                kept_renames.push((from, to));
+                continue;
+            }
+            if !to.is_file() {
+                // Gone again already; settle both ends by what exists now
+                files.insert(from);
+                files.insert(to);
+                continue;
+            }
+            files.remove(&from);
+            files.remove(&to);
+            if is_supported_audio_file(&to) && !rules.excludes_file(&to) {
+                files.insert(to.clone());
+                kept_renames.push((from, to));
+            } else if let Some(from) = from.to_str() {
+                gone.push(from.to_string());
+            }
+        }
+
+        for dir in dirs {
+            // Its marker files may differ from the ones seen last time
+            rules.forget_markers(&dir);
+            let dir = dir.to_string_lossy().to_string();
+            let result = rules.scan(&dir);
+            present.extend(result.audio_files);
+            errors.extend(result.errors);
+            walked.push(dir);
+        }
+        for file in files {
+            let Some(path) = file.to_str().map(str::to_string) else {
+                errors.push(ScanIssue::new(
+                    file.to_string_lossy(),
+                    ScanStage::Walk,
+                    ScanIssueKind::InvalidPath,
+                    "Path is not valid UTF-8",
+                ));
+                continue;
+            };
+            if file.is_file() {
+                if is_supported_audio_file(&file) && !rules.excludes_file(&file) {
+                    present.push(path);
+                }
+            } else if !file.exists() {
+                // A deleted file or a whole deleted directory
+                gone.push(path);
+            }
+        }
+        present.sort();
+        present.dedup();
+
+        let mut tracks = Vec::new();
+        for path in present {
+            let (track_data, issues) = extract_metadata_with_issues(&path);
+            match track_data {
+                Some(track_data) if rules.too_short(Path::new(&path), track_data.duration) => {}
+                Some(track_data) => {
+                    tracks.push(track_data);
+                    errors.extend(issues);
+                }
+                None => errors.extend(issues),
+            }
+        }
+
+        PreparedChanges {
+            renames: kept_renames
+                .into_iter()
+                .filter_map(|(from, to)| {
+                    let is_dir = to.is_dir();
+                    Some((from.to_str()?.to_string(), to.to_str()?.to_string(), is_dir))
+                })
+                .collect(),
+            tracks,
+            gone,
+            walked,
+            errors,
+        }
+    }
+}
+
+/// A debounced batch ready to be written to the database
+#[derive(Debug, Default)]
+pub struct PreparedChanges {
+    /// (from, to, is_dir). The tags of a renamed file are in `tracks`.
+    pub renames: Vec<(String, String, bool)>,
+    /// Tags of files that were added or changed
+    pub tracks: Vec<queries::TrackInsert>,
+    /// Paths (files or directories) that no longer exist
+    pub gone: Vec<String>,
+    /// Directories that were walked for new files
+    pub walked: Vec<String>,
+    pub errors: Vec<ScanIssue>,
+}
+
+/// A library track with the same content whose file is gone, i.e. the source
+/// of a move that was reported as delete + create.
+fn find_vanished_duplicate(conn: &Connection, hash: &str, path: &str) -> Option<(i64, String)> {
+    let mut stmt = conn
+        .prepare("SELECT id, path FROM tracks WHERE content_hash = ?1 AND path != ?2")
+        .ok()?;
+    let candidates = stmt
+        .query_map(params![hash, path], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })
+        .ok()?
+        .filter_map(|r| r.ok())
+        .collect::<Vec<_>>();
+    candidates
+        .into_iter()
+        .find(|(_, old_path)| !Path::new(old_path).exists())
+}
+
+/// Delete a track whose file is gone, along with its cover
+fn remove_track(conn: &Connection, track_id: i64, path: &str, event: &mut LibraryChangedEvent) {
+    let cover_path: Option<String> = conn
+        .query_row(
+            "SELECT track_cover_path FROM tracks WHERE id = ?1",
+            [track_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .ok()
+        .flatten()
+        .flatten();
+    match queries::delete_track(conn, track_id) {
+        Ok(true) => {
+            let _ = cover_storage::delete_track_cover_file(cover_path.as_deref());
+            event.removed.push(track_id);
+        }
+        Ok(false) => {}
+        Err(e) => event.errors.push(ScanIssue::database(path, e)),
+    }
+}
+
+/// Write a prepared batch to the library.
+pub fn apply_changes(
+    conn: &Connection,
+    changes: PreparedChanges,
+    rules: &LibraryRules,
+) -> LibraryChangedEvent {
+    let mut event = LibraryChangedEvent {
+        errors: changes.errors,
+        ..Default::default()
+    };
+
+    // Files a track was moved to, whose tags are refreshed without reporting
+    // an update
+    let mut moved_to = HashSet::new();
+
+    // 1. Paired renames
+    for (from, to, is_dir) in changes.renames {
+        if is_dir {
+            match queries::relocate_tracks_under(conn, &from, &to) {
+                Ok(moved) => {
+                    event
+                        .moved
+                        .extend(moved.into_iter().map(|(track_id, old_path, new_path)| {
+                            MovedTrack {
+                                track_id,
+                                old_path,
+                                new_path,
+                            }
+                        }))
+                }
+                Err(e) => event.errors.push(ScanIssue::database(
+                    &from,
+                    format!("Failed to move to {}: {}", to, e),
+                )),
+            }
+            continue;
+        }
+
+        // A file that wasn't a track (a finished download, or the temp file
+        // of an atomic save) is added or updated from `tracks` like any other
+        let track_id = match queries::get_track_id_by_path(conn, &from) {
+            Ok(Some(track_id)) => track_id,
+            Ok(None) => continue,
+            Err(e) => {
+                event.errors.push(ScanIssue::database(&from, e));
+                continue;
+            }
+        };
+        // A track moved over another one replaces it
+        match queries::get_track_id_by_path(conn, &to) {
+            Ok(Some(replaced)) => remove_track(conn, replaced, &to, &mut event),
+            Ok(None) => {}
+            Err(e) => {
+                event.errors.push(ScanIssue::database(&to, e));
+                continue;
+            }
+        }
+        match queries::relocate_track(conn, track_id, &to) {
+            Ok(()) => {
+                moved_to.insert(to.clone());
+                event.moved.push(MovedTrack {
+                    track_id,
+                    old_path: from,
+                    new_path: to,
+                });
+            }
+            Err(e) => event.errors.push(ScanIssue::database(
+                &from,
+                format!("Failed to move to {}: {}", to, e),
+            )),
+        }
+    }
+
+    // 2. Added or changed files
+    let folder_playlists = queries::get_folder_playlists(conn).unwrap_or_default();
+    for track_data in &changes.tracks {
+        let mut result = queries::insert_or_update_track(conn, track_data);
+
+        // Duplicate of another track: if that track's file is gone this is a move
+        if let (Ok((0, _)), Some(hash)) = (&result, track_data.content_hash.as_deref()) {
+            if let Some((track_id, old_path)) =
+                find_vanished_duplicate(conn, hash, &track_data.path)
+            {
+                if let Err(e) = queries::relocate_track(conn, track_id, &track_data.path) {
+                    event.errors.push(ScanIssue::database(
+                        &track_data.path,
+                        format!("Failed to move from {}: {}", old_path, e),
+                    ));
+                    continue;
+                }
+                event.moved.push(MovedTrack {
+                    track_id,
+                    old_path,
+                    new_path: track_data.path.clone(),
+                });
+                // Refresh tags at the new location; reported as a move, not an update
+                result =
+                    queries::insert_or_update_track(conn, track_data).map(|(id, _)| (id, false));
+                if let Ok((id, _)) = result {
+                    let (_, issues) =
+                        cover_storage::save_scanned_artwork(conn, id, track_data, rules);
+                    event.errors.extend(issues);
+                }
+                continue;
+            }
+        }
+
+        match result {
+            Ok((track_id, was_new)) if track_id > 0 => {
+                let (_, issues) =
+                    cover_storage::save_scanned_artwork(conn, track_id, track_data, rules);
+                event.errors.extend(issues);
+                if was_new {
+                    for (playlist_id, folder) in &folder_playlists {
+                        if Path::new(&track_data.path).starts_with(folder) {
+                            let _ = queries::ensure_track_in_playlist(conn, *playlist_id, track_id);
+                        }
+                    }
+                }
+                match queries::get_track_by_id(conn, track_id) {
+                    Ok(Some(track)) if was_new => event.added.push(track),
+                    Ok(Some(_)) if moved_to.contains(&track_data.path) => {}
+                    Ok(Some(track)) => event.updated.push(track),
+                    _ => {}
+                }
+            }
+            // Duplicate of a track that still exists elsewhere
+            Ok(_) => {}
+            Err(e) => event.errors.push(ScanIssue::database(&track_data.path, e)),
+        }
+    }
+
+    // 3. Deleted files and directories. Runs last so tracks that were moved
+    //    above now point at their new path and are kept.
+    for path in &changes.gone {
+        let tracks = match queries::get_tracks_under_path(conn, path) {
+            Ok(tracks) => tracks,
+            Err(e) => {
+                event.errors.push(ScanIssue::database(path, e));
+                continue;
+            }
+        };
+        for (track_id, track_path, _) in tracks {
+            if Path::new(&track_path).exists() {
+                continue;
+            }
+            remove_track(conn, track_id, &track_path, &mut event);
+        }
+    }
+
+    if !event.removed.is_empty() || !event.moved.is_empty() {
+        let _ = queries::cleanup_empty_albums(conn);
+    }
+
+    // Files read (or found gone) in this batch have only the issues seen now
+    let processed: HashSet<String> = changes
+        .tracks
+        .iter()
+        .map(|t| t.path.clone())
+        .chain(changes.gone.iter().cloned())
+        .collect();
+    if let Err(e) = queries::record_scan_results(conn, &changes.walked, &processed, &event.errors) {
+        log::warn!("[Watcher] Failed to record scan issues: {}", e);
+    }
+
+    event
+}
+
+fn flush<F>(
+    db_conn: &Arc<Mutex<Connection>>,
+    rules: &LibraryRules,
+    pending: PendingChanges,
+    on_change: &F,
+) where
+    F: Fn(LibraryChangedEvent),
+{
+    let prepared = pending.prepare(rules);
+
+    let event = {
+        let conn = match db_conn.lock() {
+            Ok(c) => c,
+            Err(e) => {
+                log::error!("[Watcher] Failed to acquire DB lock: {}", e);
+                return;
+            }
+        };
+        let tx = match conn.unchecked_transaction() {
+            Ok(tx) => tx,
+            Err(e) => {
+                log::error!("[Watcher] Failed to begin transaction: {}", e);
+                return;
+            }
+        };
+        let event = apply_changes(&tx, prepared, rules);
+        if let Err(e) = tx.commit() {
+            log::error!("[Watcher] Failed to commit changes: {}", e);
+            return;
+        }
+        event
+    };
+
+    if !event.is_empty() {
+        log::info!(
+            "[Watcher] Library updated: {} added, {} updated, {} removed, {} moved",
+            event.added.len(),
+            event.updated.len(),
+            event.removed.len(),
+            event.moved.len()
+        );
+        on_change(event);
+    }
+}
+
+fn run_event_loop<F>(
+    rx: mpsc::Receiver<notify::Result<Event>>,
+    db_conn: Arc<Mutex<Connection>>,
+    rules: LibraryRules,
+    on_change: F,
+) where
+    F: Fn(LibraryChangedEvent),
+{
+    let mut pending = PendingChanges::default();
+    let mut batch_started: Option<Instant> = None;
+
+    loop {
+        let disconnected = match rx.recv_timeout(DEBOUNCE) {
+            Ok(Ok(event)) => {
+                pending.push(event);
+                let started = *batch_started.get_or_insert_with(Instant::now);
+                if started.elapsed() < MAX_BATCH_DELAY {
+                    continue;
+                }
+                false
+            }
+            Ok(Err(e)) => {
+                log::warn!("[Watcher] {}", e);
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => false,
+            // The watcher was dropped
+            Err(RecvTimeoutError::Disconnected) => true,
+        };
+
+        if !pending.is_empty() {
+            batch_started = None;
+            flush(&db_conn, &rules, std::mem::take(&mut pending), &on_change);
+        }
+        if disconnected {
+            break;
+        }
+    }
+}
+
+/// Watches the music folders until dropped.
+pub struct LibraryWatcher {
+    _watcher: RecommendedWatcher,
+    folders: Vec<String>,
+}
+
+impl LibraryWatcher {
+    /// Start watching the folders of `rules` recursively. `on_change` runs on
+    /// the watcher thread after each applied batch.
+    pub fn start<F>(
+        db_conn: Arc<Mutex<Connection>>,
+        rules: LibraryRules,
+        on_change: F,
+    ) -> Result<Self, String>
+    where
+        F: Fn(LibraryChangedEvent) + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |res| {
+            let _ = tx.send(res);
+        })
+        .map_err(|e| format!("Failed to create watcher: {}", e))?;
+
+        let mut watched = Vec::new();
+        for folder in rules.folders() {
+            match watcher.watch(Path::new(&folder), RecursiveMode::Recursive) {
+                Ok(()) => watched.push(folder),
+                Err(e) => log::warn!("[Watcher] Cannot watch {}: {}", folder, e),
+            }
+        }
+
+        std::thread::spawn(move || run_event_loop(rx, db_conn, rules, on_change));
+
+        Ok(Self {
+            _watcher: watcher,
+            folders: watched,
+        })
+    }
+
+    pub fn folders(&self) -> &[String] {
+        &self.folders
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track_insert(path: &str, hash: &str) -> queries::TrackInsert {
+        queries::TrackInsert {
+            path: path.to_string(),
+            title: Some("Song".to_string()),
+            artist: Some("Artist".to_string()),
+            album: None,
+            track_number: None,
+            disc_number: None,
+            duration: Some(180),
+            album_art: None,
+            track_cover: None,
+            format: None,
+            bitrate: None,
+            source_type: None,
+            cover_url: None,
+            external_id: None,
+            content_hash: Some(hash.to_string()),
+            local_src: None,
+            musicbrainz_recording_id: None,
+            metadata_json: None,
+            file_size: None,
+            file_mtime: None,
+            metadata_fallback: false,
+        }
+    }
+
+    #[test]
+    fn test_unpaired_move_keeps_track_id() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&conn).unwrap();
+
+        let (old_id, _) =
+            queries::insert_or_update_track(&conn, &track_insert("/gone/a.mp3", "h1")).unwrap();
+        let (deleted_id, _) =
+            queries::insert_or_update_track(&conn, &track_insert("/gone/b.mp3", "h2")).unwrap();
+        conn.execute("INSERT INTO play_history (track_id) VALUES (?1)", [old_id])
+            .unwrap();
+
+        let event = apply_changes(
+            &conn,
+            PreparedChanges {
+                tracks: vec![track_insert("/new/a.mp3", "h1")],
+                gone: vec!["/gone".to_string()],
+                ..Default::default()
+            },
+            &LibraryRules::new(Vec::new()),
+        );
+
+        assert_eq!(event.moved.len(), 1);
+        assert_eq!(event.moved[0].track_id, old_id);
+        assert!(event.added.is_empty());
+        assert_eq!(event.removed, vec![deleted_id]);
+
+        let track = queries::get_track_by_id(&conn, old_id).unwrap().unwrap();
+        assert_eq!(track.path, "/new/a.mp3");
+        let plays: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM play_history WHERE track_id = ?1",
+                [old_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(plays, 1);
+    }
+
+    fn renamed_over(from: &str, to: &str, hash: &str) -> PreparedChanges {
+        PreparedChanges {
+            renames: vec![(from.to_string(), to.to_string(), false)],
+            tracks: vec![track_insert(to, hash)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rename_over_existing_track_updates_it() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&conn).unwrap();
+        let (song_id, _) =
+            queries::insert_or_update_track(&conn, &track_insert("/m/song.mp3", "h1")).unwrap();
+        let (other_id, _) =
+            queries::insert_or_update_track(&conn, &track_insert("/m/other.mp3", "h2")).unwrap();
+        let rules = LibraryRules::new(Vec::new());
+
+        // A tag editor saves through a temp file renamed over the original
+        let mut changes = renamed_over("/m/.song.mp3.tmp", "/m/song.mp3", "h3");
+        changes.tracks[0].title = Some("Retagged".to_string());
+        let event = apply_changes(&conn, changes, &rules);
+        assert!(event.errors.is_empty() && event.moved.is_empty());
+        assert_eq!(event.updated.len(), 1);
+        let track = queries::get_track_by_id(&conn, song_id).unwrap().unwrap();
+        assert_eq!(track.title.as_deref(), Some("Retagged"));
+
+        // A track moved over another replaces it
+        let event = apply_changes(
+            &conn,
+            renamed_over("/m/other.mp3", "/m/song.mp3", "h2"),
+            &rules,
+        );
+        assert!(event.errors.is_empty());
+        assert_eq!(event.removed, vec![song_id]);
+        assert_eq!(event.moved[0].track_id, other_id);
+        let track = queries::get_track_by_id(&conn, other_id).unwrap().unwrap();
+        assert_eq!(track.path, "/m/song.mp3");
+    }
+
+    #[test]
+    fn test_rename_to_audio_file_adds_it() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&conn).unwrap();
+        let rules = LibraryRules::new(Vec::new());
+
+        // A finished download
+        let event = apply_changes(
+            &conn,
+            renamed_over("/m/x.mp3.part", "/m/x.mp3", "h1"),
+            &rules,
+        );
+        assert!(event.errors.is_empty() && event.moved.is_empty());
+        assert_eq!(event.added.len(), 1);
+        assert_eq!(event.added[0].path, "/m/x.mp3");
+
+        // A track renamed to a new name keeps its ID
+        let id = event.added[0].id;
+        let event = apply_changes(&conn, renamed_over("/m/x.mp3", "/m/y.mp3", "h1"), &rules);
+        assert_eq!(event.moved.len(), 1);
+        assert_eq!(event.moved[0].track_id, id);
+        assert!(event.added.is_empty() && event.updated.is_empty());
+    }
+}
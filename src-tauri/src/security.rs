@@ -53,12 +53,28 @@ pub fn is_safe_path(path: &Path) -> Result<bool, String> {
     Ok(false)
 }
 
+/// What [`delete_to_trash`] did with a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeleteOutcome {
+    /// The file didn't exist
+    Missing,
+    /// Moved to the trash, so it can be restored
+    Trashed,
+    /// Deleted permanently (no trash on this platform, or trashing failed)
+    Deleted,
+}
+
 /// Safely delete a file by moving it to trash instead of permanent deletion
 /// Returns Ok(true) if successfully trashed, Ok(false) if file didn't exist
 pub fn safe_delete_file(path: &Path) -> Result<bool, String> {
+    Ok(delete_to_trash(path)? != DeleteOutcome::Missing)
+}
+
+/// Like [`safe_delete_file`], but reports whether the file went to the trash
+pub fn delete_to_trash(path: &Path) -> Result<DeleteOutcome, String> {
     if !path.exists() {
         log::debug!("[SECURITY] File does not exist, skipping deletion: {:?}", path);
-        return Ok(false);
+        return Ok(DeleteOutcome::Missing);
     }
 
     // Validate path is within allowed directories
@@ -81,7 +97,7 @@ pub fn safe_delete_file(path: &Path) -> Result<bool, String> {
         match trash::delete(path) {
             Ok(()) => {
                 log::info!("[AUDIT] File successfully moved to trash: {:?}", path);
-                Ok(true)
+                Ok(DeleteOutcome::Trashed)
             }
             Err(e) => {
                 log::error!("[AUDIT] Failed to move file to trash: {:?} - {}", path, e);
@@ -91,7 +107,7 @@ pub fn safe_delete_file(path: &Path) -> Result<bool, String> {
                     format!("Failed to delete file {:?}: {}", path, e)
                 })?;
                 log::info!("[AUDIT] File permanently deleted (trash unavailable): {:?}", path);
-                Ok(true)
+                Ok(DeleteOutcome::Deleted)
             }
         }
     }
@@ -103,8 +119,50 @@ pub fn safe_delete_file(path: &Path) -> Result<bool, String> {
             format!("Failed to delete file {:?}: {}", path, e)
         })?;
         log::info!("[AUDIT] File permanently deleted: {:?}", path);
-        Ok(true)
+        Ok(DeleteOutcome::Deleted)
+    }
+}
+
+/// Move a file that [`delete_to_trash`] trashed back to `path`. If it was
+/// trashed more than once, the most recent copy is restored.
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+pub fn restore_from_trash(path: &Path) -> Result<(), String> {
+    if path.exists() {
+        return Err(format!(
+            "Cannot restore {:?}: a file is already there",
+            path
+        ));
     }
+    // The trash records canonical paths
+    let canonical = path
+        .parent()
+        .and_then(|parent| parent.canonicalize().ok())
+        .zip(path.file_name())
+        .map(|(parent, name)| parent.join(name));
+
+    let items = trash::os_limited::list().map_err(|e| format!("Failed to read trash: {}", e))?;
+    let item = items
+        .into_iter()
+        .filter(|item| {
+            let original = item.original_path();
+            original == path || canonical.as_ref() == Some(&original)
+        })
+        .max_by_key(|item| item.time_deleted)
+        .ok_or_else(|| format!("{:?} is no longer in the trash", path))?;
+
+    trash::os_limited::restore_all([item])
+        .map_err(|e| format!("Failed to restore {:?} from trash: {}", path, e))?;
+    log::info!("[AUDIT] File restored from trash: {:?}", path);
+    Ok(())
+}
+
+/// The trash can't be read on this platform
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+pub fn restore_from_trash(path: &Path) -> Result<(), String> {
+    Err(format!(
+        "Restoring from the trash is not supported on this platform; restore {:?} manually",
+        path
+    ))
 }
 
 /// Batch validate multiple paths
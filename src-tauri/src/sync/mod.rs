@@ -86,6 +86,8 @@ struct ServerChange {
 struct SyncFullResponse {
     cursor: i64,
     playlists: Vec<serde_json::Value>,
+    #[serde(default, rename = "playlistFolders")]
+    playlist_folders: Vec<serde_json::Value>,
     #[serde(rename = "likedTracks")]
     liked_tracks: Vec<serde_json::Value>,
     #[serde(rename = "libraryTracks")]
@@ -367,6 +369,9 @@ async fn perform_full_sync_inner(
         apply_settings_from_server(db, settings)?;
     }
 
+    // Apply playlist folders first so playlists can be placed in them
+    apply_full_sync_playlist_folders(db, &response.playlist_folders)?;
+
     // Apply playlists (with their tracks) from server
     apply_full_sync_playlists(db, &response.playlists, sync_state)?;
 
@@ -428,10 +433,12 @@ async fn push_local_data_to_server(db: &Database, sync_state: &SyncState) -> Res
         let conn = db.conn.lock().map_err(|e| e.to_string())?;
         let playlists = queries::get_all_playlists(&conn).map_err(|e| e.to_string())?;
 
-        let mut playlist_tracks_map: Vec<(queries::Playlist, Vec<queries::Track>)> = Vec::new();
+        let mut playlist_tracks_map: Vec<(queries::Playlist, Vec<queries::PlaylistEntry>)> =
+            Vec::new();
         for pl in &playlists {
-            let tracks = queries::get_playlist_tracks(&conn, pl.id).map_err(|e| e.to_string())?;
-            playlist_tracks_map.push((pl.clone(), tracks));
+            let entries =
+                queries::get_playlist_entries(&conn, pl.id).map_err(|e| e.to_string())?;
+            playlist_tracks_map.push((pl.clone(), entries));
         }
 
         playlist_tracks_map
@@ -440,8 +447,59 @@ async fn push_local_data_to_server(db: &Database, sync_state: &SyncState) -> Res
     // 2. Build changes list
     let mut changes: Vec<ClientChange> = Vec::new();
 
+    // Playlist folders, parents before their subfolders
+    {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        let mut folders = queries::get_playlist_folders(&conn).map_err(|e| e.to_string())?;
+        let parents: std::collections::HashMap<i64, Option<i64>> =
+            folders.iter().map(|f| (f.id, f.parent_id)).collect();
+        let depth = |mut id: i64| {
+            let mut depth = 0;
+            while let Some(Some(parent)) = parents.get(&id) {
+                depth += 1;
+                id = *parent;
+                if depth > parents.len() {
+                    break;
+                }
+            }
+            depth
+        };
+        folders.sort_by_key(|f| depth(f.id));
+
+        for folder in &folders {
+            let folder_local_id = folder.id.to_string();
+            if queries::get_server_id(&conn, &folder_local_id, "playlist_folder")
+                .unwrap_or(None)
+                .is_some()
+            {
+                continue;
+            }
+            let folder_server_id =
+                queries::get_or_create_server_id(&conn, &folder_local_id, "playlist_folder")
+                    .map_err(|e| e.to_string())?;
+            let parent_server_id = match folder.parent_id {
+                Some(parent) => Some(
+                    queries::get_or_create_server_id(&conn, &parent.to_string(), "playlist_folder")
+                        .map_err(|e| e.to_string())?,
+                ),
+                None => None,
+            };
+
+            changes.push(ClientChange {
+                entity_type: "playlist_folder".to_string(),
+                entity_id: folder_server_id,
+                operation: "create".to_string(),
+                payload: serde_json::json!({
+                    "name": folder.name,
+                    "parentId": parent_server_id,
+                    "position": folder.position,
+                }),
+            });
+        }
+    }
+
     // Playlists
-    for (playlist, tracks) in &playlist_tracks_map {
+    for (playlist, entries) in &playlist_tracks_map {
         let conn = db.conn.lock().map_err(|e| e.to_string())?;
         let playlist_local_id = playlist.id.to_string();
 
@@ -456,6 +514,13 @@ async fn push_local_data_to_server(db: &Database, sync_state: &SyncState) -> Res
                 .map_err(|e| e.to_string())?;
         // Also store on the playlists table for apply_server_changes lookups
         let _ = queries::set_playlist_server_id(&conn, playlist.id, &playlist_server_id);
+        let folder_server_id = match playlist.folder_id {
+            Some(folder) => Some(
+                queries::get_or_create_server_id(&conn, &folder.to_string(), "playlist_folder")
+                    .map_err(|e| e.to_string())?,
+            ),
+            None => None,
+        };
         drop(conn);
 
         changes.push(ClientChange {
@@ -466,13 +531,25 @@ async fn push_local_data_to_server(db: &Database, sync_state: &SyncState) -> Res
                 "name": playlist.name,
                 "coverUrl": playlist.cover_url,
                 "createdAt": playlist.created_at,
+                "smartRules": playlist.smart_rules,
+                "folderId": folder_server_id,
+                "position": playlist.folder_position,
+                "description": playlist.description,
+                "pinned": playlist.pinned,
+                "sortOrder": playlist.sort_order,
             }),
         });
 
-        // Tracks in this playlist
-        for (position, track) in tracks.iter().enumerate() {
+        // Smart playlist tracks are computed from rules on each device
+        if playlist.smart_rules.is_some() {
+            continue;
+        }
+
+        // Entries in this playlist (keyed per entry so duplicates are kept)
+        for (position, entry) in entries.iter().enumerate() {
+            let track = &entry.track;
             let track_hash = build_track_hash(track);
-            let pt_local_id = format!("{}_{}", playlist.id, track.id);
+            let pt_local_id = format!("entry_{}", entry.entry_id);
             let conn = db.conn.lock().map_err(|e| e.to_string())?;
             let pt_server_id =
                 queries::get_or_create_server_id(&conn, &pt_local_id, "playlist_track")
@@ -487,6 +564,7 @@ async fn push_local_data_to_server(db: &Database, sync_state: &SyncState) -> Res
                     "playlistId": playlist_server_id,
                     "trackHash": track_hash,
                     "position": position,
+                    "sortKey": entry.position,
                     "title": track.title,
                     "artist": track.artist,
                     "album": track.album,
@@ -537,6 +615,73 @@ async fn push_local_data_to_server(db: &Database, sync_state: &SyncState) -> Res
         }
     }
 
+    // Ratings and user tags
+    {
+        let conn = db.conn.lock().map_err(|e| e.to_string())?;
+        let ratings = queries::get_track_ratings(&conn).map_err(|e| e.to_string())?;
+        for (track_id, rating) in &ratings {
+            let rating_local_id = format!("rating_{}", track_id);
+            if queries::get_server_id(&conn, &rating_local_id, "track_rating")
+                .unwrap_or(None)
+                .is_some()
+            {
+                continue;
+            }
+            let Ok(Some(track)) = queries::get_track_by_id(&conn, *track_id) else {
+                continue;
+            };
+            let rating_server_id =
+                queries::get_or_create_server_id(&conn, &rating_local_id, "track_rating")
+                    .map_err(|e| e.to_string())?;
+
+            changes.push(ClientChange {
+                entity_type: "track_rating".to_string(),
+                entity_id: rating_server_id,
+                operation: "update".to_string(),
+                payload: serde_json::json!({
+                    "trackHash": build_track_hash(&track),
+                    "title": track.title,
+                    "artist": track.artist,
+                    "album": track.album,
+                    "duration": track.duration,
+                    "rating": rating,
+                }),
+            });
+        }
+
+        for tag in queries::get_all_tags(&conn).map_err(|e| e.to_string())? {
+            for track_id in queries::get_track_ids_by_tag(&conn, &tag.name).map_err(|e| e.to_string())? {
+                let tag_local_id = format!("tag_{}_{}", track_id, tag.name.to_lowercase());
+                if queries::get_server_id(&conn, &tag_local_id, "track_tag")
+                    .unwrap_or(None)
+                    .is_some()
+                {
+                    continue;
+                }
+                let Ok(Some(track)) = queries::get_track_by_id(&conn, track_id) else {
+                    continue;
+                };
+                let tag_server_id =
+                    queries::get_or_create_server_id(&conn, &tag_local_id, "track_tag")
+                        .map_err(|e| e.to_string())?;
+
+                changes.push(ClientChange {
+                    entity_type: "track_tag".to_string(),
+                    entity_id: tag_server_id,
+                    operation: "create".to_string(),
+                    payload: serde_json::json!({
+                        "trackHash": build_track_hash(&track),
+                        "title": track.title,
+                        "artist": track.artist,
+                        "album": track.album,
+                        "duration": track.duration,
+                        "tag": tag.name,
+                    }),
+                });
+            }
+        }
+    }
+
     // Full track library
     {
         let conn = db.conn.lock().map_err(|e| e.to_string())?;
@@ -671,6 +816,56 @@ async fn push_local_data_to_server(db: &Database, sync_state: &SyncState) -> Res
 
 // ─── Apply full sync data from server ────────────────────────────────────────
 
+/// Apply playlist folders from the full sync response to the local DB.
+fn apply_full_sync_playlist_folders(
+    db: &Database,
+    folders: &[serde_json::Value],
+) -> Result<(), String> {
+    if folders.is_empty() {
+        return Ok(());
+    }
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+
+    // Create unknown folders first, so parents resolve whatever the order
+    let mut local_ids = Vec::with_capacity(folders.len());
+    for folder in folders {
+        let server_id = folder
+            .get("id")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default();
+        if server_id.is_empty() {
+            tracing::warn!("Skipping playlist folder with missing server_id");
+            continue;
+        }
+        let local_id = match local_folder_from_server(&conn, server_id) {
+            Some(id) => id,
+            None => {
+                let name = folder
+                    .get("name")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("Untitled Folder");
+                let id = queries::create_playlist_folder(&conn, name, None)
+                    .map_err(|e| e.to_string())?;
+                let _ =
+                    queries::store_id_mapping(&conn, &id.to_string(), "playlist_folder", server_id);
+                id
+            }
+        };
+        local_ids.push((local_id, folder));
+    }
+    for (local_id, folder) in local_ids {
+        apply_playlist_tree_place(
+            &conn,
+            queries::PlaylistTreeItem::Folder(local_id),
+            folder,
+            "parentId",
+        );
+    }
+
+    tracing::info!("Imported {} playlist folders from server", folders.len());
+    Ok(())
+}
+
 /// Apply playlists (with their tracks) from the full sync response to the local DB.
 fn apply_full_sync_playlists(
     db: &Database,
@@ -699,6 +894,7 @@ fn apply_full_sync_playlists(
             .unwrap_or("Untitled Playlist");
         let cover_url = pl.get("coverUrl").and_then(|v| v.as_str());
         let tracks = pl.get("tracks").and_then(|v| v.as_array());
+        let smart_rules = parse_smart_rules(pl);
 
         if server_id.is_empty() {
             tracing::warn!("Skipping playlist with missing server_id");
@@ -718,6 +914,16 @@ fn apply_full_sync_playlists(
             let id = queries::create_playlist(&conn, name, cover_url).map_err(|e| e.to_string())?;
             queries::set_playlist_server_id(&conn, id, server_id).map_err(|e| e.to_string())?;
             let _ = queries::store_id_mapping(&conn, &id.to_string(), "playlist", server_id);
+            if let Some(Some(rules)) = &smart_rules {
+                let _ = queries::set_playlist_smart_rules(&conn, id, Some(rules));
+            }
+            apply_playlist_tree_place(
+                &conn,
+                queries::PlaylistTreeItem::Playlist(id),
+                pl,
+                "folderId",
+            );
+            apply_playlist_details(&conn, id, pl);
             tracing::info!(
                 "Created playlist '{}' (local_id={}, server_id={})",
                 name,
@@ -757,8 +963,36 @@ fn apply_full_sync_playlists(
                     cover_url,
                 );
 
+                // Entries already imported (or created here) are mapped by server ID.
+                // Without an ID, only fill playlists created by this import so a
+                // repeated full sync does not duplicate entries.
+                let entry_server_id = track_val.get("id").and_then(|v| v.as_str());
+                let already_imported = match entry_server_id {
+                    Some(sid) => queries::get_local_id_from_server(&conn, sid, "playlist_track")
+                        .unwrap_or(None)
+                        .is_some(),
+                    None => existing.is_some(),
+                };
+                if already_imported {
+                    continue;
+                }
+
                 if let Ok(Some(track_id)) = local_track_id {
-                    let _ = queries::add_track_to_playlist(&conn, local_playlist_id, track_id);
+                    if let Ok(entry_id) =
+                        queries::add_track_to_playlist(&conn, local_playlist_id, track_id)
+                    {
+                        if let Some(sort_key) = track_val.get("sortKey").and_then(|v| v.as_f64()) {
+                            let _ = queries::set_playlist_entry_position(&conn, entry_id, sort_key);
+                        }
+                        if let Some(sid) = entry_server_id {
+                            let _ = queries::store_id_mapping(
+                                &conn,
+                                &format!("entry_{}", entry_id),
+                                "playlist_track",
+                                sid,
+                            );
+                        }
+                    }
                 }
             }
         }
@@ -850,6 +1084,15 @@ fn apply_full_sync_liked_tracks(
     Ok(())
 }
 
+/// Resolve a "title|artist|album" track hash to a local track ID.
+fn local_track_from_hash(conn: &rusqlite::Connection, track_hash: &str) -> Option<i64> {
+    let parts: Vec<&str> = track_hash.splitn(3, '|').collect();
+    if parts.len() != 3 || parts[0].is_empty() || parts[1].is_empty() {
+        return None;
+    }
+    find_local_track_by_metadata(conn, parts[0], parts[1]).ok()
+}
+
 /// Find an existing local track by metadata, or create a placeholder track.
 /// Returns the local track ID, or None if the track can't be created.
 fn find_or_create_synced_track(
@@ -908,6 +1151,9 @@ fn find_or_create_synced_track(
         local_src: None,
         musicbrainz_recording_id: None,
         metadata_json: None,
+        file_size: None,
+        file_mtime: None,
+        metadata_fallback: false,
     };
 
     match queries::insert_or_update_track(conn, &track) {
@@ -1033,6 +1279,9 @@ fn apply_full_sync_library_tracks(
             local_src: None,
             musicbrainz_recording_id: None,
             metadata_json: None,
+            file_size: None,
+            file_mtime: None,
+            metadata_fallback: false,
         };
 
         match queries::insert_or_update_track(&conn, &track) {
@@ -1110,6 +1359,7 @@ fn apply_single_server_change(db: &Database, change: &ServerChange) -> Result<()
                         .and_then(|v| v.as_str())
                         .unwrap_or("Untitled Playlist");
                     let cover_url = change.payload.get("coverUrl").and_then(|v| v.as_str());
+                    let smart_rules = parse_smart_rules(&change.payload);
 
                     // Check if a playlist with this server_id already exists locally
                     let existing = queries::find_playlist_by_server_id(&conn, &change.entity_id)
@@ -1129,6 +1379,16 @@ fn apply_single_server_change(db: &Database, change: &ServerChange) -> Result<()
                             "playlist",
                             &change.entity_id,
                         );
+                        if let Some(Some(rules)) = &smart_rules {
+                            let _ = queries::set_playlist_smart_rules(&conn, local_id, Some(rules));
+                        }
+                        apply_playlist_tree_place(
+                            &conn,
+                            queries::PlaylistTreeItem::Playlist(local_id),
+                            &change.payload,
+                            "folderId",
+                        );
+                        apply_playlist_details(&conn, local_id, &change.payload);
                         tracing::info!(
                             "Created local playlist {} (server_id={})",
                             local_id,
@@ -1150,6 +1410,17 @@ fn apply_single_server_change(db: &Database, change: &ServerChange) -> Result<()
                             queries::update_playlist_cover(&conn, local_id, Some(cover))
                                 .map_err(|e| e.to_string())?;
                         }
+                        if let Some(rules) = parse_smart_rules(&change.payload) {
+                            queries::set_playlist_smart_rules(&conn, local_id, rules.as_ref())
+                                .map_err(|e| e.to_string())?;
+                        }
+                        apply_playlist_tree_place(
+                            &conn,
+                            queries::PlaylistTreeItem::Playlist(local_id),
+                            &change.payload,
+                            "folderId",
+                        );
+                        apply_playlist_details(&conn, local_id, &change.payload);
                         tracing::info!("Updated local playlist {} from server", local_id);
                     }
                 }
@@ -1168,6 +1439,54 @@ fn apply_single_server_change(db: &Database, change: &ServerChange) -> Result<()
                 }
             }
         }
+        "playlist_folder" => {
+            let local_id = local_folder_from_server(&conn, &change.entity_id);
+            match change.operation.as_str() {
+                "create" | "update" => {
+                    let name = change.payload.get("name").and_then(|v| v.as_str());
+                    let folder_id = match local_id {
+                        Some(id) => {
+                            if let Some(name) = name {
+                                queries::rename_playlist_folder(&conn, id, name)
+                                    .map_err(|e| e.to_string())?;
+                            }
+                            id
+                        }
+                        None => {
+                            let id = queries::create_playlist_folder(
+                                &conn,
+                                name.unwrap_or("Untitled Folder"),
+                                None,
+                            )
+                            .map_err(|e| e.to_string())?;
+                            let _ = queries::store_id_mapping(
+                                &conn,
+                                &id.to_string(),
+                                "playlist_folder",
+                                &change.entity_id,
+                            );
+                            id
+                        }
+                    };
+                    apply_playlist_tree_place(
+                        &conn,
+                        queries::PlaylistTreeItem::Folder(folder_id),
+                        &change.payload,
+                        "parentId",
+                    );
+                    tracing::info!("Applied playlist folder {} from server", folder_id);
+                }
+                "delete" => {
+                    if let Some(id) = local_id {
+                        queries::delete_playlist_folder(&conn, id).map_err(|e| e.to_string())?;
+                        tracing::info!("Deleted local playlist folder {} from server", id);
+                    }
+                }
+                _ => {
+                    tracing::warn!("Unknown playlist folder operation: {}", change.operation);
+                }
+            }
+        }
         "playlist_track" => {
             let playlist_server_id = change
                 .payload
@@ -1179,52 +1498,92 @@ fn apply_single_server_change(db: &Database, change: &ServerChange) -> Result<()
                 .get("trackHash")
                 .and_then(|v| v.as_str())
                 .unwrap_or("");
+            let sort_key = change.payload.get("sortKey").and_then(|v| v.as_f64());
 
-            if !playlist_server_id.is_empty() && !track_hash.is_empty() {
-                // 1. Find local playlist ID
-                if let Ok(Some(local_playlist_id)) =
-                    queries::find_playlist_by_server_id(&conn, playlist_server_id)
-                {
-                    // 2. Find local track ID
-                    let parts: Vec<&str> = track_hash.splitn(3, '|').collect();
-                    if parts.len() == 3 {
-                        if let (Some(t), Some(a)) = (Some(parts[0]), Some(parts[1])) {
-                            if !t.is_empty() && !a.is_empty() {
-                                if let Ok(local_track_id) =
-                                    find_local_track_by_metadata(&conn, t, a)
-                                {
-                                    match change.operation.as_str() {
-                                        "create" | "update" => {
-                                            let _ = queries::add_track_to_playlist(
-                                                &conn,
-                                                local_playlist_id,
-                                                local_track_id,
-                                            );
-                                            tracing::info!(
-                                                "Added track {} to playlist {} via sync",
-                                                local_track_id,
-                                                local_playlist_id
-                                            );
-                                        }
-                                        "delete" => {
-                                            let _ = queries::remove_track_from_playlist(
-                                                &conn,
-                                                local_playlist_id,
-                                                local_track_id,
-                                            );
-                                            tracing::info!(
-                                                "Removed track {} from playlist {} via sync",
-                                                local_track_id,
-                                                local_playlist_id
-                                            );
-                                        }
-                                        _ => {}
-                                    }
-                                }
+            // Entry already known locally (created here or applied before)
+            let local_entry_id = queries::get_local_id_from_server(
+                &conn,
+                &change.entity_id,
+                "playlist_track",
+            )
+            .unwrap_or(None)
+            .and_then(|local| local.strip_prefix("entry_").and_then(|id| id.parse::<i64>().ok()));
+
+            // Resolve playlist + track for entries we have not seen yet
+            let resolve_local = || -> Option<(i64, i64)> {
+                if playlist_server_id.is_empty() || track_hash.is_empty() {
+                    return None;
+                }
+                let local_playlist_id =
+                    queries::find_playlist_by_server_id(&conn, playlist_server_id).ok()??;
+                let parts: Vec<&str> = track_hash.splitn(3, '|').collect();
+                if parts.len() != 3 || parts[0].is_empty() || parts[1].is_empty() {
+                    return None;
+                }
+                let local_track_id = find_local_track_by_metadata(&conn, parts[0], parts[1]).ok()?;
+                Some((local_playlist_id, local_track_id))
+            };
+
+            match change.operation.as_str() {
+                "create" | "update" => {
+                    if let Some(entry_id) = local_entry_id {
+                        if let Some(sort_key) = sort_key {
+                            let _ = queries::set_playlist_entry_position(&conn, entry_id, sort_key);
+                            tracing::info!("Moved playlist entry {} via sync", entry_id);
+                        }
+                    } else if let Some((local_playlist_id, local_track_id)) = resolve_local() {
+                        if let Ok(entry_id) =
+                            queries::add_track_to_playlist(&conn, local_playlist_id, local_track_id)
+                        {
+                            if let Some(sort_key) = sort_key {
+                                let _ =
+                                    queries::set_playlist_entry_position(&conn, entry_id, sort_key);
                             }
+                            let _ = queries::store_id_mapping(
+                                &conn,
+                                &format!("entry_{}", entry_id),
+                                "playlist_track",
+                                &change.entity_id,
+                            );
+                            tracing::info!(
+                                "Added track {} to playlist {} via sync",
+                                local_track_id,
+                                local_playlist_id
+                            );
                         }
                     }
                 }
+                "delete" => {
+                    if let Some(entry_id) = local_entry_id {
+                        if let Ok(Some((local_playlist_id, _, _))) =
+                            queries::get_playlist_entry(&conn, entry_id)
+                        {
+                            let _ = queries::remove_playlist_entries(
+                                &conn,
+                                local_playlist_id,
+                                &[entry_id],
+                            );
+                            tracing::info!(
+                                "Removed entry {} from playlist {} via sync",
+                                entry_id,
+                                local_playlist_id
+                            );
+                        }
+                    } else if let Some((local_playlist_id, local_track_id)) = resolve_local() {
+                        // Legacy change keyed by track rather than entry
+                        let _ = queries::remove_track_from_playlist(
+                            &conn,
+                            local_playlist_id,
+                            local_track_id,
+                        );
+                        tracing::info!(
+                            "Removed track {} from playlist {} via sync",
+                            local_track_id,
+                            local_playlist_id
+                        );
+                    }
+                }
+                _ => {}
             }
         }
         "liked_track" => {
@@ -1300,6 +1659,58 @@ fn apply_single_server_change(db: &Database, change: &ServerChange) -> Result<()
                 _ => {}
             }
         }
+        "track_rating" => {
+            let track_hash = change
+                .payload
+                .get("trackHash")
+                .and_then(|v| v.as_str())
+                .unwrap_or("");
+            let rating = match change.operation.as_str() {
+                "delete" => Some(0),
+                _ => change
+                    .payload
+                    .get("rating")
+                    .and_then(|v| v.as_u64())
+                    .map(|r| r.min(5) as u8),
+            };
+
+            if let (Some(track_id), Some(rating)) = (local_track_from_hash(&conn, track_hash), rating) {
+                let _ = queries::set_track_rating(&conn, track_id, rating);
+                tracing::info!("Rated local track {} ({}) from server sync", track_id, rating);
+            }
+        }
+        "track_tag" => {
+            let track_hash = change
+                .payload
+                .get("trackHash")
+                .and_then(|v| v.as_str())
+                .unwrap_or("");
+            let tag = change
+                .payload
+                .get("tag")
+                .and_then(|v| v.as_str())
+                .unwrap_or("");
+
+            if let Some(track_id) = local_track_from_hash(&conn, track_hash) {
+                if !tag.is_empty() {
+                    match change.operation.as_str() {
+                        "create" | "update" => {
+                            let _ = queries::add_tag_to_tracks(&conn, tag, &[track_id]);
+                        }
+                        "delete" => {
+                            let _ = queries::remove_tag_from_tracks(&conn, tag, &[track_id]);
+                        }
+                        _ => {}
+                    }
+                    tracing::info!(
+                        "Applied tag '{}' {} on local track {} from server sync",
+                        tag,
+                        change.operation,
+                        track_id
+                    );
+                }
+            }
+        }
         "library_track" => {
             tracing::info!(
                 "Server change: library_track {} {} (track library synced)",
@@ -1355,6 +1766,99 @@ fn apply_single_server_change(db: &Database, change: &ServerChange) -> Result<()
     Ok(())
 }
 
+/// Read the `smartRules` field of a playlist payload.
+/// Outer None: field absent. Inner None: explicitly null (regular playlist).
+fn parse_smart_rules(payload: &serde_json::Value) -> Option<Option<queries::SmartPlaylistRules>> {
+    let value = payload.get("smartRules")?;
+    if value.is_null() {
+        return Some(None);
+    }
+    match serde_json::from_value::<queries::SmartPlaylistRules>(value.clone()) {
+        Ok(rules) => Some(Some(rules)),
+        Err(e) => {
+            tracing::warn!("Ignoring invalid smart playlist rules from server: {}", e);
+            None
+        }
+    }
+}
+
+/// Apply the description, pinning and sort order of a playlist payload.
+/// Fields missing from the payload are kept.
+fn apply_playlist_details(
+    conn: &rusqlite::Connection,
+    playlist_id: i64,
+    payload: &serde_json::Value,
+) {
+    if let Some(description) = payload.get("description") {
+        let _ = queries::set_playlist_description(conn, playlist_id, description.as_str());
+    }
+    if let Some(pinned) = payload.get("pinned").and_then(|v| v.as_bool()) {
+        let _ = queries::set_playlist_pinned(conn, playlist_id, pinned);
+    }
+    if let Some(sort_order) = payload.get("sortOrder").and_then(|v| v.as_str()) {
+        let _ = queries::set_playlist_sort_order(
+            conn,
+            playlist_id,
+            queries::PlaylistSortOrder::parse(sort_order),
+        );
+    }
+}
+
+/// Local ID of a playlist folder known by its server ID
+fn local_folder_from_server(conn: &rusqlite::Connection, server_id: &str) -> Option<i64> {
+    queries::get_local_id_from_server(conn, server_id, "playlist_folder")
+        .ok()
+        .flatten()
+        .and_then(|local| local.parse().ok())
+}
+
+/// Place a folder or playlist by the parent folder (`parent_key`) and
+/// position of its payload. Fields missing from the payload are kept, and an
+/// unknown parent puts the item at the top level.
+fn apply_playlist_tree_place(
+    conn: &rusqlite::Connection,
+    item: queries::PlaylistTreeItem,
+    payload: &serde_json::Value,
+    parent_key: &str,
+) {
+    let parent = payload.get(parent_key).map(|value| {
+        let local = value
+            .as_str()
+            .and_then(|sid| local_folder_from_server(conn, sid));
+        if local.is_none() && !value.is_null() {
+            tracing::warn!("Unknown playlist folder from server: {}", value);
+        }
+        local
+    });
+    let position = payload.get("position").and_then(|v| v.as_f64());
+    if parent.is_none() && position.is_none() {
+        return;
+    }
+    let Ok((current_parent, current_position)) = queries::get_playlist_tree_place(conn, item)
+    else {
+        return;
+    };
+    let mut parent = parent.unwrap_or(current_parent);
+    // Moves on both sides can cross (A into B here, B into A there); a folder
+    // that would end up inside itself goes to the top level instead
+    if let (queries::PlaylistTreeItem::Folder(folder_id), Some(parent_id)) = (item, parent) {
+        let ancestors = queries::get_playlist_folder_ancestors(conn, parent_id).unwrap_or_default();
+        if parent_id == folder_id || ancestors.contains(&folder_id) {
+            tracing::warn!(
+                "Playlist folder {} from server would be inside itself; moving it to the top level",
+                folder_id
+            );
+            parent = None;
+        }
+    }
+    let _ = queries::set_playlist_tree_position(
+        conn,
+        item,
+        parent,
+        position.unwrap_or(current_position),
+    );
+}
+
 /// Find a local track by title and artist metadata.
 fn find_local_track_by_metadata(
     conn: &rusqlite::Connection,
@@ -1399,11 +1903,20 @@ fn translate_entity_id(
             if let Ok(id) = raw_id.parse::<i64>() {
                 let _ = queries::set_playlist_server_id(conn, id, &server_id);
             }
+            translate_folder_ref(conn, payload, "folderId");
 
             server_id
         }
+        "playlist_folder" => {
+            let raw_id = local_entity_id
+                .strip_prefix("local_")
+                .unwrap_or(local_entity_id);
+            translate_folder_ref(conn, payload, "parentId");
+            queries::get_or_create_server_id(conn, raw_id, "playlist_folder")
+                .unwrap_or_else(|_| local_entity_id.to_string())
+        }
         "playlist_track" => {
-            // entity_id format: "local_{playlist_id}_{track_id}" or "local_{playlist_id}_reorder"
+            // entity_id format: "local_entry_{entry_id}" (older queues: "local_{playlist_id}_{track_id}")
             let stripped = local_entity_id
                 .strip_prefix("local_")
                 .unwrap_or(local_entity_id);
@@ -1450,6 +1963,14 @@ fn translate_entity_id(
             queries::get_or_create_server_id(conn, &lib_local_key, "library_track")
                 .unwrap_or_else(|_| local_entity_id.to_string())
         }
+        "track_rating" | "track_tag" => {
+            // entity_id format: "local_rating_{track_id}" / "local_tag_{track_id}_{tag}"
+            let stripped = local_entity_id
+                .strip_prefix("local_")
+                .unwrap_or(local_entity_id);
+            queries::get_or_create_server_id(conn, stripped, entity_type)
+                .unwrap_or_else(|_| local_entity_id.to_string())
+        }
         "play_history" => local_entity_id.to_string(),
         _ => {
             // Unknown entity type — pass through as-is
@@ -1458,6 +1979,23 @@ fn translate_entity_id(
     }
 }
 
+/// Replace a local folder reference ("local_{id}") in `payload[key]` with the
+/// folder's server UUID. Null (the top level) is left as is.
+fn translate_folder_ref(conn: &rusqlite::Connection, payload: &mut serde_json::Value, key: &str) {
+    let Some(obj) = payload.as_object_mut() else {
+        return;
+    };
+    let Some(local) = obj.get(key).and_then(|v| v.as_str()).map(|s| s.to_string()) else {
+        return;
+    };
+    let Some(raw_id) = local.strip_prefix("local_") else {
+        return;
+    };
+    if let Ok(server_id) = queries::get_or_create_server_id(conn, raw_id, "playlist_folder") {
+        obj.insert(key.to_string(), serde_json::json!(server_id));
+    }
+}
+
 /// Build a stable track hash for identifying tracks across devices.
 /// Uses content_hash if available, otherwise falls back to "title|artist|album".
 fn build_track_hash(track: &queries::Track) -> String {
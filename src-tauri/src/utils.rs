@@ -57,3 +57,14 @@ pub fn resolve_path(base_dir: &Path, unsafe_path: &str) -> Result<PathBuf, Strin
         Ok(normalized)
     }
 }
+
+/// Lowercase words without punctuation, for comparing titles and names
+/// ("Moment's Notice!" is "moments notice")
+pub fn normalize_words(text: &str) -> String {
+    text.to_lowercase()
+        .replace(['\'', '\u{2019}'], "")
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -150,6 +150,13 @@ export interface Track {
     date_added?: string | null;
 }
 
+// A playlist slot: the track plus a stable entry id (tracks may repeat)
+export interface PlaylistEntry extends Track {
+    entry_id: number;
+    position: number;
+    added_at: string | null;
+}
+
 export interface Album {
     id: number;
     name: string;
@@ -170,6 +177,57 @@ export interface Playlist {
     created_at: string | null;
     folder_path?: string | null;
     cover_url?: string | null;
+    smart_rules?: SmartPlaylistRules | null;
+    /** Playlist file kept in sync with this playlist */
+    linked_file?: string | null;
+    /** Folder holding this playlist; null at the top level */
+    folder_id?: number | null;
+    /** Order among the folders and playlists of the same parent */
+    folder_position?: number;
+    description?: string | null;
+    /** Last change to the details or tracks; the creation time until then */
+    modified_at?: string | null;
+    /** Pinned playlists are listed first */
+    pinned?: boolean;
+    sort_order?: PlaylistSortOrder;
+    /** null for smart playlists, whose tracks depend on the library */
+    track_count?: number | null;
+    /** Total length in seconds; null for smart playlists */
+    duration?: number | null;
+}
+
+export type PlaylistSortOrder = 'manual' | 'date_added' | 'title' | 'artist';
+
+export interface PlaylistFolder {
+    id: number;
+    name: string;
+    /** null at the top level */
+    parent_id: number | null;
+    /** Order among the folders and playlists of the same parent */
+    position: number;
+    created_at: string | null;
+}
+
+// Smart playlist rule tree (compiled to SQL on the backend)
+export type SmartField =
+    | 'title' | 'artist' | 'album' | 'genre' | 'format' | 'path' | 'year'
+    | 'duration' | 'bitrate' | 'track_number' | 'disc_number'
+    | 'play_count' | 'last_played' | 'liked' | 'date_added' | 'rating' | 'tag'
+    | 'untagged' | 'bpm' | 'key' | 'energy' | 'danceability';
+
+export type SmartOperator =
+    | 'is' | 'is_not' | 'contains' | 'not_contains' | 'starts_with' | 'ends_with'
+    | 'greater_than' | 'less_than' | 'between' | 'in_last' | 'not_in_last'
+    | 'is_empty' | 'is_not_empty';
+
+export type SmartRuleNode =
+    | { type: 'rule'; field: SmartField; operator: SmartOperator; value?: unknown }
+    | { type: 'group'; combinator: 'and' | 'or'; rules: SmartRuleNode[] };
+
+export interface SmartPlaylistRules {
+    root: SmartRuleNode;
+    sort?: { field: SmartField; descending?: boolean } | null;
+    limit?: number | null;
 }
 
 export interface Library {
@@ -182,7 +240,49 @@ export interface ScanResult {
     tracks_added: number;
     tracks_updated: number;
     tracks_deleted: number;
-    errors: string[];
+    /** Files skipped because their size and modification time are unchanged */
+    tracks_skipped: number;
+    /** Tracks kept because their file was found at a new location */
+    relinked: RelinkedTrack[];
+    /** Files that failed some step; also listed by getScanIssues */
+    errors: ScanIssue[];
+}
+
+export type ScanStage = 'walk' | 'probe' | 'tags' | 'cover' | 'db';
+
+export type ScanIssueKind =
+    | 'not_found' | 'permission_denied' | 'io' | 'invalid_path' | 'symlink_cycle'
+    | 'unsupported' | 'corrupt' | 'missing_tags' | 'database' | 'other';
+
+export interface ScanIssue {
+    path: string;
+    stage: ScanStage;
+    kind: ScanIssueKind;
+    message: string;
+}
+
+export interface ScanIssueRecord extends ScanIssue {
+    id: number;
+    /** Number of scans that ran into it */
+    occurrences: number;
+    first_seen: string | null;
+    last_seen: string | null;
+    track_id: number | null;
+    /** The track was imported with filename-only metadata */
+    metadata_fallback: boolean;
+}
+
+export interface ScanRetryResult {
+    resolved: number;
+    remaining: ScanIssue[];
+    tracks: Track[];
+}
+
+export interface RelinkedTrack {
+    track_id: number;
+    old_path: string;
+    new_path: string;
+    matched_by: 'file_fingerprint' | 'content_hash';
 }
 
 // Progressive scan types
@@ -201,6 +301,93 @@ export interface ScanBatchEvent {
     progress: ScanProgress;
 }
 
+// Live updates from the music folder watcher
+export interface MovedTrack {
+    track_id: number;
+    old_path: string;
+    new_path: string;
+}
+
+export interface LibraryChangedEvent {
+    added: Track[];
+    updated: Track[];
+    removed: number[];
+    moved: MovedTrack[];
+    errors: ScanIssue[];
+}
+
+export interface FolderScanSettings {
+    /** Glob patterns relative to the folder, e.g. "**/Samples" or "*.wav" */
+    exclude_patterns: string[];
+    /** Files shorter than this many seconds are skipped */
+    min_duration: number | null;
+    /** Subfolder levels walked below the folder (0 = only its own files) */
+    max_depth: number | null;
+    follow_symlinks: boolean;
+    /** Folder art names without extension, highest priority first, e.g. "cover" or "albumart*" */
+    cover_art_names: string[];
+    /** Look up art for albums without any on the Cover Art Archive after scans */
+    fetch_online_art: boolean;
+}
+
+export interface MusicFolderSettings {
+    path: string;
+    settings: FolderScanSettings;
+}
+
+export interface WatcherStatus {
+    running: boolean;
+    folders: string[];
+}
+
+export interface FingerprintResult {
+    fingerprinted: number;
+    failed: number;
+}
+
+// Estimated from the audio, or read from BPM/key tags
+export interface AudioFeatures {
+    bpm: number | null;
+    /** e.g. "C", "F#m", "Bbm" */
+    key: string | null;
+    /** 0 (calm) to 1 (intense) */
+    energy: number | null;
+    /** 0 to 1: how steady and clear the beat is */
+    danceability: number | null;
+}
+
+export interface AudioAnalysisResult {
+    analysed: number;
+    from_tags: number;
+    failed: number;
+}
+
+// Payload of 'audio-analysis-progress'
+export interface AudioAnalysisProgress {
+    processed: number;
+    total: number;
+}
+
+export interface DuplicateCopy {
+    /** null for copies that were skipped on import and are not in the library */
+    track_id: number | null;
+    path: string;
+    format: string | null;
+    bitrate: number | null;
+    file_size: number | null;
+    duration: number | null;
+    lossless: boolean;
+}
+
+export interface DuplicateGroup {
+    title: string | null;
+    artist: string | null;
+    reason: 'identical_audio' | 'acoustic_match';
+    similarity: number;
+    /** Best quality first */
+    copies: DuplicateCopy[];
+}
+
 export interface MigrationProgress {
     total: number;
     processed: number;
@@ -229,8 +416,165 @@ export async function setSingleMusicFolder(path: string): Promise<void> {
     return await invoke('set_single_music_folder', { path });
 }
 
-export async function rescanMusic(): Promise<ScanResult> {
-    return await invoke('rescan_music');
+export async function getMusicFolderSettings(): Promise<MusicFolderSettings[]> {
+    return await invoke('get_music_folder_settings');
+}
+
+/** Takes effect for live changes immediately and for existing files on the next rescan */
+export async function setMusicFolderSettings(
+    path: string,
+    settings: FolderScanSettings
+): Promise<void> {
+    return await invoke('set_music_folder_settings', { path, settings });
+}
+
+/**
+ * Rescan all music folders. Unchanged files are skipped unless `forceFull` is set.
+ */
+export async function rescanMusic(forceFull: boolean = false): Promise<ScanResult> {
+    return await invoke('rescan_music', { forceFull });
+}
+
+export async function getScanIssues(): Promise<ScanIssueRecord[]> {
+    return await invoke('get_scan_issues');
+}
+
+/**
+ * Re-read the files of the given scan issues (all of them when `ids` is omitted).
+ */
+export async function retryScanIssues(ids?: number[]): Promise<ScanRetryResult> {
+    return await invoke('retry_scan_issues', { ids: ids ?? null });
+}
+
+export async function dismissScanIssues(ids: number[]): Promise<number> {
+    return await invoke('dismiss_scan_issues', { ids });
+}
+
+export async function startLibraryWatcher(): Promise<WatcherStatus> {
+    return await invoke('start_library_watcher');
+}
+
+export async function stopLibraryWatcher(): Promise<void> {
+    return await invoke('stop_library_watcher');
+}
+
+export async function getLibraryWatcherStatus(): Promise<WatcherStatus> {
+    return await invoke('get_library_watcher_status');
+}
+
+export async function computeAcousticFingerprints(force = false): Promise<FingerprintResult> {
+    return await invoke('compute_acoustic_fingerprints', { force });
+}
+
+// Estimate BPM, key, energy and danceability for tracks not analysed yet.
+// Tracks with BPM and key tags keep them unless force is set.
+export async function analyseAudioFeatures(force = false): Promise<AudioAnalysisResult> {
+    return await invoke('analyse_audio_features', { force });
+}
+
+export async function getAudioFeatures(): Promise<Record<number, AudioFeatures>> {
+    return await invoke('get_audio_features');
+}
+
+export async function getDuplicatesReport(): Promise<DuplicateGroup[]> {
+    return await invoke('get_duplicates_report');
+}
+
+export interface CoverArtLookupResult {
+    fetched: number;
+    /** No matching release, or the release has no front cover */
+    not_found: number;
+    /** Network or storage errors; retried on the next run */
+    failed: number;
+}
+
+/** Payload of the 'album-art-fetched' event */
+export interface AlbumArtFetched {
+    album_id: number;
+    art_path: string;
+}
+
+// Look up missing album art on the Cover Art Archive (albums checked in the
+// last 30 days are skipped unless force is set)
+export async function fetchMissingAlbumArt(force = false): Promise<CoverArtLookupResult> {
+    return await invoke('fetch_missing_album_art', { force });
+}
+
+export type CoverEdit =
+    /** Base64-encoded JPEG or PNG */
+    | { action: 'set'; data: string }
+    | { action: 'remove' };
+
+export interface TagEdit {
+    track_id: number;
+    /** Field name to values: several values write a multi-value field, none remove it */
+    fields?: Record<string, string[]>;
+    cover?: CoverEdit;
+}
+
+export interface TagEditResult {
+    track_id: number;
+    path: string | null;
+    success: boolean;
+    error: string | null;
+    /** Fields the file's tag format has no place for */
+    skipped_fields: string[];
+}
+
+// Write tags to library files; each file succeeds or fails on its own
+export async function editTrackTags(edits: TagEdit[]): Promise<TagEditResult[]> {
+    return await invoke('edit_track_tags', { edits });
+}
+
+// MusicBrainz auto-tagging
+
+export interface ReleaseCandidate {
+    id: string;
+    title: string;
+    artist: string;
+    date: string | null;
+    country: string | null;
+    track_count: number;
+    /** How well the tracklist fits the files, out of 100 */
+    score: number;
+}
+
+export interface FieldChange {
+    field: string;
+    /** The file's current values joined with "; ", null if unset */
+    current: string | null;
+    proposed: string;
+}
+
+export interface TrackProposal {
+    track_id: number;
+    path: string;
+    matched: boolean;
+    /** Only the fields that differ from the file */
+    changes: FieldChange[];
+    error: string | null;
+}
+
+export interface AlbumProposal {
+    folder: string;
+    album: string | null;
+    artist: string | null;
+    /** Best first; the proposal is for the first one when it scores high enough */
+    candidates: ReleaseCandidate[];
+    release_id: string | null;
+    tracks: TrackProposal[];
+    error: string | null;
+}
+
+// Match the albums of these tracks to MusicBrainz releases; nothing is written.
+// Pass releaseId to propose tags from a specific release instead.
+export async function proposeAlbumTags(trackIds: number[], releaseId?: string): Promise<AlbumProposal[]> {
+    return await invoke('propose_album_tags', { trackIds, releaseId: releaseId ?? null });
+}
+
+// Write the accepted changes (as tag edits) and remember each album's release
+export async function applyAlbumTags(edits: TagEdit[]): Promise<TagEditResult[]> {
+    return await invoke('apply_album_tags', { edits });
 }
 
 export async function getDefaultMusicDirs(): Promise<string[]> {
@@ -298,6 +642,80 @@ export async function deleteAlbum(albumId: number): Promise<boolean> {
     return await invoke('delete_album', { albumId });
 }
 
+export interface JournalEntry {
+    id: number;
+    kind: 'tag_edit' | 'delete' | 'cover_merge';
+    description: string;
+    created_at: string | null;
+    undone_at: string | null;
+}
+
+export interface UndoResult {
+    entry: JournalEntry;
+    /** Files or tracks put back */
+    restored: number;
+    /** Parts that couldn't be undone; the entry stays undoable if there are any */
+    errors: string[];
+}
+
+// Recent tag edits, deletions and cover merges, most recent first
+export async function getOperationJournal(limit?: number): Promise<JournalEntry[]> {
+    return await invoke('get_operation_journal', { limit: limit ?? null });
+}
+
+// Undo a journal entry (the most recent one not undone yet by default)
+export async function undoOperation(entryId?: number): Promise<UndoResult> {
+    return await invoke('undo_operation', { entryId: entryId ?? null });
+}
+
+// File organiser
+
+export type OrganiseFilesystem = 'windows' | 'mac' | 'linux';
+
+export interface OrganiseRequest {
+    /** e.g. '{albumartist}/[{year} - ]{album}/{disc}-{track:02} {title}.{ext}' */
+    template: string;
+    /** Tracks to organise; the whole library when absent */
+    track_ids?: number[] | null;
+    /** Naming rules to follow; the current OS's when absent */
+    filesystem?: OrganiseFilesystem | null;
+    /** What to do when a target is taken: add " (2)" (default) or leave the file */
+    collisions?: 'number' | 'skip';
+    dry_run?: boolean;
+}
+
+export interface FileMove {
+    from: string;
+    to: string;
+}
+
+export interface PlannedMove {
+    track_id: number;
+    from: string;
+    to: string;
+    status: 'unchanged' | 'move' | 'skipped' | 'failed';
+    /** A number was added to the name because the target was taken */
+    numbered: boolean;
+    /** Lyrics, art and other files moving along with this one */
+    sidecars: FileMove[];
+    error: string | null;
+}
+
+export interface OrganiseResult {
+    dry_run: boolean;
+    /** Every track that moves, is skipped or failed */
+    moves: PlannedMove[];
+    unchanged: number;
+    moved: number;
+    skipped: number;
+    failed: number;
+}
+
+// Rename and move library files by a tag template; use dry_run for a preview
+export async function organiseFiles(request: OrganiseRequest): Promise<OrganiseResult> {
+    return await invoke('organise_files', { request });
+}
+
 export async function resetDatabase(): Promise<void> {
     return await invoke('reset_database');
 }
@@ -317,10 +735,16 @@ export async function getTrackCoverPath(trackId: number): Promise<string | null>
     return await invoke('get_track_cover_path', { trackId });
 }
 
+// Thumbnail sizes: small = 96px, medium = 256px, large = 600px (longest side)
+export type CoverSize = 'small' | 'medium' | 'large';
+
 // Get cover paths for multiple tracks in a single batch operation
-// Returns a map of trackId -> coverPath
-export async function getBatchCoverPaths(trackIds: number[]): Promise<Record<number, string>> {
-    return await invoke('get_batch_cover_paths', { trackIds });
+// Returns a map of trackId -> coverPath (a cached thumbnail when size is given)
+export async function getBatchCoverPaths(
+    trackIds: number[],
+    size?: CoverSize
+): Promise<Record<number, string>> {
+    return await invoke('get_batch_cover_paths', { trackIds, size: size ?? null });
 }
 
 export async function importAudioBytes(filename: string, base64Data: string, overwrite: boolean): Promise<Track | 'duplicate' | string> {
@@ -332,10 +756,34 @@ export async function importAudioBytes(filename: string, base64Data: string, ove
     }
 }
 
-// Get the file path for an album's art
+// Get the file path for an album's art (a cached thumbnail when size is given)
 // Returns null if no art exists
-export async function getAlbumArtPath(albumId: number): Promise<string | null> {
-    return await invoke('get_album_art_path', { albumId });
+export async function getAlbumArtPath(albumId: number, size?: CoverSize): Promise<string | null> {
+    return await invoke('get_album_art_path', { albumId, size: size ?? null });
+}
+
+// Colours picked from an album cover, as #rrggbb
+export interface AlbumPalette {
+    dominant: string;
+    vibrant: string;
+    muted: string;
+    /** Black or white, whichever reads better on the dominant colour */
+    text: string;
+}
+
+export interface PaletteResult {
+    computed: number;
+    failed: number;
+}
+
+// Get an album's cover palette (null if it has no usable art)
+export async function getAlbumPalette(albumId: number): Promise<AlbumPalette | null> {
+    return await invoke('get_album_palette', { albumId });
+}
+
+// Compute missing album palettes (all of them when force is set)
+export async function computeAlbumPalettes(force = false): Promise<PaletteResult> {
+    return await invoke('compute_album_palettes', { force });
 }
 
 // Convert a file path to an asset URL for browser use
@@ -453,10 +901,26 @@ export async function getPlaylistTracks(playlistId: number): Promise<Track[]> {
     return await invoke('get_playlist_tracks', { playlistId });
 }
 
-export async function addTrackToPlaylist(playlistId: number, trackId: number): Promise<void> {
+export async function getPlaylistEntries(playlistId: number): Promise<PlaylistEntry[]> {
+    return await invoke('get_playlist_entries', { playlistId });
+}
+
+export async function addTrackToPlaylist(playlistId: number, trackId: number): Promise<number> {
     return await invoke('add_track_to_playlist', { playlistId, trackId });
 }
 
+export async function insertTracksIntoPlaylist(playlistId: number, trackIds: number[], index?: number | null): Promise<number[]> {
+    return await invoke('insert_tracks_into_playlist', { playlistId, trackIds, index });
+}
+
+export async function movePlaylistEntries(playlistId: number, entryIds: number[], toIndex: number): Promise<void> {
+    return await invoke('move_playlist_entries', { playlistId, entryIds, toIndex });
+}
+
+export async function removePlaylistEntries(playlistId: number, entryIds: number[]): Promise<number> {
+    return await invoke('remove_playlist_entries', { playlistId, entryIds });
+}
+
 export async function removeTrackFromPlaylist(playlistId: number, trackId: number): Promise<void> {
     return await invoke('remove_track_from_playlist', { playlistId, trackId });
 }
@@ -473,10 +937,121 @@ export async function reorderPlaylistTracks(playlistId: number, fromIndex: numbe
     return await invoke('reorder_playlist_tracks', { playlistId, fromIndex, toIndex });
 }
 
+export async function updatePlaylistDescription(playlistId: number, description: string | null): Promise<void> {
+    return await invoke('update_playlist_description', { playlistId, description });
+}
+
+export async function setPlaylistPinned(playlistId: number, pinned: boolean): Promise<void> {
+    return await invoke('set_playlist_pinned', { playlistId, pinned });
+}
+
+// How getPlaylistTracks lists the playlist; reordering needs 'manual'
+export async function setPlaylistSortOrder(playlistId: number, sortOrder: PlaylistSortOrder): Promise<void> {
+    return await invoke('set_playlist_sort_order', { playlistId, sortOrder });
+}
+
+// Playlist folders: folders and playlists with the same parent share one order
+
+export async function getPlaylistFolders(): Promise<PlaylistFolder[]> {
+    return await invoke('get_playlist_folders');
+}
+
+export async function createPlaylistFolder(name: string, parentId?: number | null): Promise<number> {
+    return await invoke('create_playlist_folder', { name, parentId: parentId ?? null });
+}
+
+export async function renamePlaylistFolder(folderId: number, newName: string): Promise<void> {
+    return await invoke('rename_playlist_folder', { folderId, newName });
+}
+
+// Its playlists and subfolders take its place in the parent
+export async function deletePlaylistFolder(folderId: number): Promise<void> {
+    return await invoke('delete_playlist_folder', { folderId });
+}
+
+// Move into parentId (null for the top level) at index among the items there
+export async function movePlaylistFolder(folderId: number, parentId: number | null, index: number): Promise<void> {
+    return await invoke('move_playlist_folder', { folderId, parentId, index });
+}
+
+export async function movePlaylist(playlistId: number, folderId: number | null, index: number): Promise<void> {
+    return await invoke('move_playlist', { playlistId, folderId, index });
+}
+
+export async function createSmartPlaylist(name: string, rules: SmartPlaylistRules, coverUrl?: string | null): Promise<number> {
+    return await invoke('create_smart_playlist', { name, rules, coverUrl });
+}
+
+export async function updateSmartPlaylistRules(playlistId: number, rules: SmartPlaylistRules): Promise<void> {
+    return await invoke('update_smart_playlist_rules', { playlistId, rules });
+}
+
+export async function previewSmartPlaylist(rules: SmartPlaylistRules): Promise<Track[]> {
+    return await invoke('preview_smart_playlist', { rules });
+}
+
 export async function beginFolderImport(folderPath: string): Promise<number> {
     return await invoke('begin_folder_import', { folderPath });
 }
 
+export type PlaylistFileFormat = 'm3u' | 'm3u8' | 'pls' | 'xspf' | 'json';
+export type PlaylistPathMode = 'absolute' | 'relative';
+
+export interface UnmatchedPlaylistEntry {
+    /** Position in the playlist file */
+    index: number;
+    location: string;
+    title: string | null;
+    artist: string | null;
+    duration: number | null;
+}
+
+export interface PlaylistImportReport {
+    playlist_id: number;
+    name: string;
+    total: number;
+    matched_by_path: number;
+    matched_by_metadata: number;
+    unmatched: UnmatchedPlaylistEntry[];
+}
+
+// Write a playlist to a file; the format follows the extension unless given
+export async function exportPlaylist(playlistId: number, path: string, format?: PlaylistFileFormat | null, pathMode?: PlaylistPathMode | null): Promise<number> {
+    return await invoke('export_playlist', { playlistId, path, format: format ?? null, pathMode: pathMode ?? null });
+}
+
+// Create a playlist from an M3U/M3U8, PLS, XSPF or JSON file
+export async function importPlaylist(path: string, name?: string | null): Promise<PlaylistImportReport> {
+    return await invoke('import_playlist', { path, name: name ?? null });
+}
+
+export type PlaylistSyncResolution = 'keep_playlist' | 'keep_file';
+export type PlaylistSyncOutcome = 'unchanged' | 'written' | 'read' | 'conflict' | 'missing' | 'failed';
+
+export interface PlaylistSyncReport {
+    playlist_id: number;
+    path: string;
+    outcome: PlaylistSyncOutcome;
+    /** Entries of a read file that match no library track */
+    unmatched: number;
+    error: string | null;
+}
+
+// Keep a playlist in sync with a playlist file; `resolution` picks the side to
+// keep when both have tracks. Rescans emit 'playlist-files-synced' with reports.
+export async function linkPlaylistFile(playlistId: number, path: string, resolution?: PlaylistSyncResolution | null): Promise<PlaylistSyncReport> {
+    return await invoke('link_playlist_file', { playlistId, path, resolution: resolution ?? null });
+}
+
+export async function unlinkPlaylistFile(playlistId: number): Promise<void> {
+    return await invoke('unlink_playlist_file', { playlistId });
+}
+
+// Sync a linked playlist now, e.g. to settle a conflict
+export async function syncPlaylistFile(playlistId: number, resolution?: PlaylistSyncResolution | null): Promise<PlaylistSyncReport> {
+    return await invoke('sync_playlist_file', { playlistId, resolution: resolution ?? null });
+}
+
 
 // Activity commands (liked tracks + play history)
 
@@ -502,6 +1077,82 @@ export interface StatsSummary {
     top_genre: string | null;
 }
 
+export type StatsPeriod =
+    | { kind: 'week' }
+    | { kind: 'month' }
+    | { kind: 'year' }
+    | { kind: 'all_time' }
+    | { kind: 'custom'; start: string; end: string };
+
+export interface StatsRange {
+    period: StatsPeriod;
+    /** Minutes east of UTC, e.g. -new Date().getTimezoneOffset() */
+    utc_offset_minutes?: number;
+}
+
+export interface DayBucket {
+    date: string;
+    plays: number;
+    seconds: number;
+}
+
+export interface HourBucket {
+    weekday: number;
+    hour: number;
+    plays: number;
+    seconds: number;
+}
+
+export interface GenreWithCount {
+    genre: string;
+    play_count: number;
+    seconds: number;
+}
+
+export interface ArtistDiscovery {
+    artist: string;
+    first_played: string;
+    play_count: number;
+}
+
+export interface StreakInfo {
+    current_days: number;
+    longest_days: number;
+    longest_start: string | null;
+    longest_end: string | null;
+}
+
+export interface ListeningStats {
+    range_start: string;
+    range_end: string;
+    total_plays: number;
+    total_seconds: number;
+    unique_tracks: number;
+    unique_artists: number;
+    daily: DayBucket[];
+    hourly: HourBucket[];
+    top_tracks: TrackWithCount[];
+    top_artists: ArtistWithCount[];
+    top_albums: AlbumWithCount[];
+    top_genres: GenreWithCount[];
+    new_artists: ArtistDiscovery[];
+    streaks: StreakInfo;
+}
+
+export interface MonthBucket {
+    month: number;
+    plays: number;
+    seconds: number;
+}
+
+export interface YearInReview {
+    year: number;
+    stats: ListeningStats;
+    monthly: MonthBucket[];
+    busiest_day: DayBucket | null;
+    first_track: Track | null;
+}
+
 export async function likeTrack(trackId: number): Promise<void> {
     return await invoke('like_track', { trackId });
 }
@@ -522,6 +1173,54 @@ export async function getLikedTracks(): Promise<Track[]> {
     return await invoke('get_liked_tracks');
 }
 
+// Ratings & user tags
+
+export interface TagInfo {
+    id: number;
+    name: string;
+    track_count: number;
+}
+
+export async function setTrackRating(trackId: number, rating: number, writeToFile?: boolean): Promise<void> {
+    return await invoke('set_track_rating', { trackId, rating, writeToFile });
+}
+
+export async function getTrackRatings(): Promise<Record<number, number>> {
+    return await invoke('get_track_ratings');
+}
+
+export async function importRatingsFromFiles(overwrite?: boolean): Promise<number> {
+    return await invoke('import_ratings_from_files', { overwrite });
+}
+
+export async function getAllTags(): Promise<TagInfo[]> {
+    return await invoke('get_all_tags');
+}
+
+export async function getTrackTags(trackId: number): Promise<string[]> {
+    return await invoke('get_track_tags', { trackId });
+}
+
+export async function getTrackIdsByTag(name: string): Promise<number[]> {
+    return await invoke('get_track_ids_by_tag', { name });
+}
+
+export async function addTagToTracks(name: string, trackIds: number[]): Promise<void> {
+    return await invoke('add_tag_to_tracks', { name, trackIds });
+}
+
+export async function removeTagFromTracks(name: string, trackIds: number[]): Promise<void> {
+    return await invoke('remove_tag_from_tracks', { name, trackIds });
+}
+
+export async function renameTag(tagId: number, newName: string): Promise<void> {
+    return await invoke('rename_tag', { tagId, newName });
+}
+
+export async function deleteTag(tagId: number): Promise<void> {
+    return await invoke('delete_tag', { tagId });
+}
+
 export async function recordPlay(trackId: number, albumId: number | null, durationPlayed: number): Promise<void> {
     return await invoke('record_play', { trackId, albumId, durationPlayed });
 }
@@ -546,6 +1245,14 @@ export async function getStatsSummary(): Promise<StatsSummary> {
     return await invoke('get_stats_summary');
 }
 
+export async function getListeningStats(range: StatsRange, limit?: number): Promise<ListeningStats> {
+    return await invoke('get_listening_stats', { range, limit });
+}
+
+export async function getYearInReview(year: number, utcOffsetMinutes?: number): Promise<YearInReview> {
+    return await invoke('get_year_in_review', { year, utcOffsetMinutes });
+}
+
 
 // File dialog
 
@@ -1005,11 +1712,33 @@ export async function getReleaseMbInfo(
 /**
  * Find artists related to `artistName` on MusicBrainz.
  * Each result includes whether the artist is present in the local library.
+ * The relations are cached for `generateMix`.
  */
 export async function getSimilarArtistsMb(artistName: string): Promise<MbSimilarArtist[]> {
     return await invoke('get_similar_artists_mb', { artistName });
 }
 
+/**
+ * Build a queue from seed tracks, artists and/or a genre using local data only
+ * (cached related artists, genres and play history). Seed tracks come first;
+ * pass the same `seed` to get the same mix again.
+ */
+export async function generateMix(
+    seedTrackIds: number[],
+    seedArtists: string[],
+    genre?: string | null,
+    length?: number | null,
+    seed?: number | null,
+): Promise<Track[]> {
+    return await invoke('generate_mix', {
+        seedTrackIds,
+        seedArtists,
+        genre: genre ?? null,
+        length: length ?? null,
+        seed: seed ?? null,
+    });
+}
+
 /**
  * Fetch the full MusicBrainz discography (release groups) for an artist,
  * sorted newest-first.
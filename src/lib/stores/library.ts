@@ -1,7 +1,7 @@
 // Library store - manages music library state
 import { writable, derived, get } from 'svelte/store';
-import type { Track, Album, Artist, Playlist, ScanBatchEvent } from '$lib/api/tauri';
-import { getLibrary, getPlaylists, getAlbumCoverSrc, getAlbumArtSrc, getTracksPaginated, getAlbumsPaginated, searchLibrary, convertFileSrc } from '$lib/api/tauri';
+import type { Track, Album, Artist, Playlist, ScanBatchEvent, LibraryChangedEvent } from '$lib/api/tauri';
+import { getLibrary, getPlaylists, getAlbumCoverSrc, getAlbumArtSrc, getTracksPaginated, getAlbumsPaginated, searchLibrary, convertFileSrc, listen } from '$lib/api/tauri';
 import { customArtworks, getCustomArtworkSync } from './customArtwork';
 import { playlistCovers, getPlaylistCoverSync } from './playlistCovers';
 
@@ -749,6 +749,46 @@ export function ingestScanBatch(event: ScanBatchEvent): void {
     trackCount.set(event.progress.total);
 }
 
+/**
+ * Apply a live update from the music folder watcher
+ */
+export function applyLibraryChange(event: LibraryChangedEvent): void {
+    const removed = new Set(event.removed);
+    const changed = new Map(ingestTracks([...event.added, ...event.updated]).map(t => [t.id, t]));
+    const moved = new Map(event.moved.map(m => [m.track_id, m.new_path]));
+
+    tracks.update(current => {
+        const next = current
+            .filter(t => !removed.has(t.id))
+            .map(t => {
+                const replacement = changed.get(t.id);
+                if (replacement) {
+                    changed.delete(t.id);
+                    return replacement;
+                }
+                const newPath = moved.get(t.id);
+                return newPath ? { ...t, path: newPath } : t;
+            });
+        // Whatever is left was not loaded yet: show new tracks at the top
+        return [...changed.values(), ...next];
+    });
+
+    trackCount.update(n => Math.max(0, n + event.added.length - event.removed.length));
+
+    if (event.added.length || event.updated.length || event.removed.length) {
+        loadAlbumsAndArtists();
+    }
+}
+
+/**
+ * Subscribe to `library-changed` events. Returns the unlisten function.
+ */
+export async function watchLibraryChanges(): Promise<() => void> {
+    return await listen<LibraryChangedEvent>('library-changed', (event) => {
+        applyLibraryChange(event.payload);
+    });
+}
+
 /**
  * Load only albums and artists (tracks already populated).
  */
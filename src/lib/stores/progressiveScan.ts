@@ -72,7 +72,7 @@ function createProgressiveScanStore() {
             update(state => ({
                 ...state,
                 isScanning: false,
-                errors: result.errors,
+                errors: result.errors.map(issue => `${issue.path}: ${issue.message}`),
             }));
 
             cleanup();